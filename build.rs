@@ -0,0 +1,4 @@
+// Embedded migrations need a rebuild whenever one is added
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
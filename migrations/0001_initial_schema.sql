@@ -0,0 +1,87 @@
+-- Tables are created in foreign key order. IF NOT EXISTS lets databases
+-- initialized before migrations were introduced adopt them.
+CREATE TABLE IF NOT EXISTS schema_version (
+    version bigint PRIMARY KEY,
+    description text NOT NULL,
+    applied_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS run (
+    run_uuid uuid PRIMARY KEY,
+    begin timestamptz NOT NULL,
+    finish timestamptz NOT NULL,
+    benchmark text,
+    email text,
+    name text,
+    description text,
+    source text
+);
+
+CREATE TABLE IF NOT EXISTS tag (
+    run_uuid uuid REFERENCES run ON DELETE CASCADE,
+    name text,
+    val text,
+    PRIMARY KEY (run_uuid, name)
+);
+
+CREATE TABLE IF NOT EXISTS iteration (
+    iteration_uuid uuid PRIMARY KEY,
+    run_uuid uuid REFERENCES run ON DELETE CASCADE,
+    num bigint NOT NULL,
+    status text,
+    path text,
+    primary_metric text NOT NULL,
+    primary_period text NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS param (
+    iteration_uuid uuid REFERENCES iteration ON DELETE CASCADE,
+    arg text,
+    val text,
+    PRIMARY KEY (iteration_uuid, arg)
+);
+
+CREATE TABLE IF NOT EXISTS sample (
+    sample_uuid uuid PRIMARY KEY,
+    iteration_uuid uuid REFERENCES iteration ON DELETE CASCADE,
+    num bigint,
+    status text,
+    path text
+);
+
+CREATE TABLE IF NOT EXISTS period (
+    period_uuid uuid PRIMARY KEY,
+    sample_uuid uuid REFERENCES sample ON DELETE CASCADE,
+    begin timestamptz NOT NULL,
+    finish timestamptz NOT NULL,
+    name text
+);
+
+CREATE TABLE IF NOT EXISTS metric_desc (
+    metric_desc_uuid uuid PRIMARY KEY,
+    period_uuid uuid REFERENCES period ON DELETE CASCADE,
+    class text NOT NULL,
+    metric_type text NOT NULL,
+    source text NOT NULL,
+    names_list text,
+    names text
+);
+
+CREATE TABLE IF NOT EXISTS name (
+    metric_desc_uuid uuid REFERENCES metric_desc ON DELETE CASCADE,
+    name text NOT NULL,
+    val text NOT NULL,
+    PRIMARY KEY (metric_desc_uuid, name)
+);
+
+CREATE TABLE IF NOT EXISTS metric_data (
+    metric_data_id bigserial,
+    metric_desc_uuid uuid REFERENCES metric_desc ON DELETE CASCADE,
+    value double precision NOT NULL,
+    begin timestamptz NOT NULL,
+    finish timestamptz NOT NULL,
+    duration bigint NOT NULL,
+    PRIMARY KEY (metric_data_id, metric_desc_uuid)
+);
+
+INSERT INTO schema_version (version, description) VALUES (1, 'initial schema');
@@ -0,0 +1,4 @@
+-- Databases initialized before metric_desc had a unit need the column added
+ALTER TABLE metric_desc ADD COLUMN IF NOT EXISTS unit text;
+
+INSERT INTO schema_version (version, description) VALUES (2, 'metric_desc unit');
@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS metric_data_rollup (
+    metric_desc_uuid uuid REFERENCES metric_desc ON DELETE CASCADE,
+    interval_ms bigint NOT NULL,
+    begin timestamptz NOT NULL,
+    finish timestamptz NOT NULL,
+    min double precision NOT NULL,
+    max double precision NOT NULL,
+    avg double precision NOT NULL,
+    sum double precision NOT NULL,
+    sum_sq double precision NOT NULL,
+    count bigint NOT NULL,
+    PRIMARY KEY (metric_desc_uuid, interval_ms, begin)
+);
+
+-- The newest metric_data row each rollup interval covers. A rollup is only
+-- used for queries once it has caught up with everything ingested.
+CREATE TABLE IF NOT EXISTS metric_data_rollup_state (
+    interval_ms bigint PRIMARY KEY,
+    max_metric_data_id bigint NOT NULL,
+    rolled_up_at timestamptz NOT NULL
+);
+
+INSERT INTO schema_version (version, description) VALUES (3, 'metric_data rollups');
@@ -0,0 +1,19 @@
+-- Foreign keys that aren't already the leading column of a primary key
+CREATE INDEX IF NOT EXISTS iteration_run_uuid_idx ON iteration (run_uuid);
+CREATE INDEX IF NOT EXISTS sample_iteration_uuid_idx ON sample (iteration_uuid);
+CREATE INDEX IF NOT EXISTS period_sample_uuid_idx ON period (sample_uuid);
+CREATE INDEX IF NOT EXISTS metric_desc_period_uuid_idx ON metric_desc (period_uuid);
+CREATE INDEX IF NOT EXISTS metric_data_metric_desc_uuid_begin_idx ON metric_data (metric_desc_uuid, begin);
+
+-- Columns metric queries filter and break out on
+CREATE INDEX IF NOT EXISTS metric_desc_metric_type_idx ON metric_desc (metric_type);
+CREATE INDEX IF NOT EXISTS name_name_val_idx ON name (name, val);
+CREATE INDEX IF NOT EXISTS tag_name_val_idx ON tag (name, val);
+
+-- Time ranges
+CREATE INDEX IF NOT EXISTS run_begin_idx ON run (begin);
+CREATE INDEX IF NOT EXISTS period_begin_finish_idx ON period (begin, finish);
+CREATE INDEX IF NOT EXISTS metric_data_begin_finish_idx ON metric_data (begin, finish);
+CREATE INDEX IF NOT EXISTS metric_data_rollup_interval_ms_begin_idx ON metric_data_rollup (interval_ms, begin);
+
+INSERT INTO schema_version (version, description) VALUES (4, 'supporting indexes');
@@ -0,0 +1,5 @@
+-- names held serialized JSON as text, store it as jsonb so it can be indexed
+ALTER TABLE metric_desc ALTER COLUMN names TYPE jsonb USING names::jsonb;
+CREATE INDEX IF NOT EXISTS metric_desc_names_idx ON metric_desc USING gin (names);
+
+INSERT INTO schema_version (version, description) VALUES (5, 'metric_desc names as jsonb');
@@ -0,0 +1,5 @@
+-- names_list was bound as an array but stored as its text representation
+ALTER TABLE metric_desc ALTER COLUMN names_list TYPE text[] USING names_list::text[];
+CREATE INDEX IF NOT EXISTS metric_desc_names_list_idx ON metric_desc USING gin (names_list);
+
+INSERT INTO schema_version (version, description) VALUES (6, 'metric_desc names_list as text[]');
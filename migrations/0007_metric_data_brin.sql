@@ -0,0 +1,6 @@
+-- Metric data arrives roughly time ordered, so a BRIN index covers the
+-- window overlap predicates at a fraction of the B-tree's size
+DROP INDEX IF EXISTS metric_data_begin_finish_idx;
+CREATE INDEX IF NOT EXISTS metric_data_begin_finish_brin_idx ON metric_data USING brin (begin, finish);
+
+INSERT INTO schema_version (version, description) VALUES (7, 'metric_data BRIN index');
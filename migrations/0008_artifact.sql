@@ -0,0 +1,18 @@
+-- Files attached to a run, either referenced by path or URL, or stored
+-- inline when they're small
+CREATE TABLE IF NOT EXISTS artifact (
+    artifact_uuid uuid PRIMARY KEY,
+    run_uuid uuid NOT NULL REFERENCES run ON DELETE CASCADE,
+    name text NOT NULL,
+    location text,
+    checksum text,
+    content_type text,
+    size bigint,
+    content bytea,
+    added timestamptz NOT NULL DEFAULT now(),
+    CHECK (location IS NOT NULL OR content IS NOT NULL)
+);
+
+CREATE INDEX IF NOT EXISTS artifact_run_uuid_idx ON artifact (run_uuid);
+
+INSERT INTO schema_version (version, description) VALUES (8, 'run artifacts');
@@ -0,0 +1,14 @@
+-- How runs relate to each other, read as "from is <relation> to", ex: a
+-- run that's a rerun-of another
+CREATE TABLE IF NOT EXISTS run_link (
+    from_run_uuid uuid NOT NULL REFERENCES run ON DELETE CASCADE,
+    to_run_uuid uuid NOT NULL REFERENCES run ON DELETE CASCADE,
+    relation text NOT NULL CHECK (relation IN ('rerun-of', 'baseline-for', 'tuned-from')),
+    created timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (from_run_uuid, to_run_uuid, relation),
+    CHECK (from_run_uuid <> to_run_uuid)
+);
+
+CREATE INDEX IF NOT EXISTS run_link_to_run_uuid_idx ON run_link (to_run_uuid);
+
+INSERT INTO schema_version (version, description) VALUES (9, 'run lineage links');
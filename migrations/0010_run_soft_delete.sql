@@ -0,0 +1,6 @@
+-- Soft deleted runs are hidden from queries until they're restored or purged
+ALTER TABLE run ADD COLUMN IF NOT EXISTS deleted_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS run_deleted_at_idx ON run (deleted_at) WHERE deleted_at IS NOT NULL;
+
+INSERT INTO schema_version (version, description) VALUES (10, 'run soft delete');
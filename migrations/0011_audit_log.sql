@@ -0,0 +1,14 @@
+-- Every command that changes or removes data leaves an entry here
+CREATE TABLE IF NOT EXISTS audit_log (
+    audit_id bigserial PRIMARY KEY,
+    at timestamptz NOT NULL DEFAULT now(),
+    db_user text NOT NULL DEFAULT current_user,
+    os_user text,
+    command text NOT NULL,
+    filters jsonb,
+    rows_affected bigint
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_at_idx ON audit_log (at);
+
+INSERT INTO schema_version (version, description) VALUES (11, 'audit log');
@@ -0,0 +1,8 @@
+-- Bad collector clocks shouldn't reach the aggregates. NOT VALID leaves rows
+-- loaded before these checks existed alone, while new rows are held to them.
+ALTER TABLE run ADD CONSTRAINT run_begin_finish_check CHECK (begin <= finish) NOT VALID;
+ALTER TABLE period ADD CONSTRAINT period_begin_finish_check CHECK (begin <= finish) NOT VALID;
+ALTER TABLE metric_data ADD CONSTRAINT metric_data_begin_finish_check CHECK (begin <= finish) NOT VALID;
+ALTER TABLE metric_data ADD CONSTRAINT metric_data_duration_check CHECK (duration >= 0) NOT VALID;
+
+INSERT INTO schema_version (version, description) VALUES (12, 'timestamp checks');
@@ -0,0 +1,24 @@
+-- How long `scdm prune` keeps each tier of data. There is at most one policy.
+CREATE TABLE IF NOT EXISTS retention_tiers (
+    only_row boolean PRIMARY KEY DEFAULT true CHECK (only_row),
+    raw_for interval NOT NULL,
+    rollup_for interval NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    CHECK (rollup_for >= raw_for)
+);
+
+-- The primary metric of iterations whose raw data was downsampled away, so
+-- it outlives the data mv_iteration_primary_metric is computed from
+CREATE TABLE IF NOT EXISTS iteration_summary (
+    iteration_uuid uuid PRIMARY KEY REFERENCES iteration ON DELETE CASCADE,
+    primary_metric text NOT NULL,
+    primary_period text NOT NULL,
+    samples bigint NOT NULL,
+    mean double precision,
+    stddev double precision,
+    min double precision,
+    max double precision,
+    summarized_at timestamptz NOT NULL DEFAULT now()
+);
+
+INSERT INTO schema_version (version, description) VALUES (13, 'retention tiers');
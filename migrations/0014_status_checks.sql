@@ -0,0 +1,24 @@
+-- Free form statuses make pass/fail reporting unreliable, so they're limited
+-- to pass, fail, skip and error. Existing statuses are mapped the way ingest
+-- maps them, with anything unrecognized becoming error.
+UPDATE iteration SET status = CASE
+        WHEN lower(trim(status)) IN ('pass', 'passed', 'ok', 'success', 'succeeded') THEN 'pass'
+        WHEN lower(trim(status)) IN ('fail', 'failed', 'failure') THEN 'fail'
+        WHEN lower(trim(status)) IN ('skip', 'skipped') THEN 'skip'
+        ELSE 'error'
+    END
+    WHERE status NOT IN ('pass', 'fail', 'skip', 'error');
+UPDATE sample SET status = CASE
+        WHEN lower(trim(status)) IN ('pass', 'passed', 'ok', 'success', 'succeeded') THEN 'pass'
+        WHEN lower(trim(status)) IN ('fail', 'failed', 'failure') THEN 'fail'
+        WHEN lower(trim(status)) IN ('skip', 'skipped') THEN 'skip'
+        ELSE 'error'
+    END
+    WHERE status NOT IN ('pass', 'fail', 'skip', 'error');
+
+ALTER TABLE iteration ADD CONSTRAINT iteration_status_check
+    CHECK (status IN ('pass', 'fail', 'skip', 'error'));
+ALTER TABLE sample ADD CONSTRAINT sample_status_check
+    CHECK (status IN ('pass', 'fail', 'skip', 'error'));
+
+INSERT INTO schema_version (version, description) VALUES (14, 'status checks');
@@ -0,0 +1,4 @@
+-- How long each ingest took, so a slowdown shows up before it becomes a backlog
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS duration_ms bigint;
+
+INSERT INTO schema_version (version, description) VALUES (15, 'audit duration');
@@ -0,0 +1,17 @@
+-- How long `scdm prune` keeps the runs of a benchmark, or of every benchmark
+-- without a benchmark. A policy with a tag covers only the runs tagged with
+-- it, over the policies without one. No keep means the runs are kept forever
+CREATE TABLE IF NOT EXISTS retention_policy (
+    benchmark text,
+    tag_name text,
+    tag_val text,
+    keep interval CHECK (keep > interval '0'),
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    CHECK ((tag_name IS NULL) = (tag_val IS NULL))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS retention_policy_scope_idx ON retention_policy (
+    COALESCE(benchmark, ''), COALESCE(tag_name, ''), COALESCE(tag_val, '')
+);
+
+INSERT INTO schema_version (version, description) VALUES (16, 'retention policies');
@@ -0,0 +1,6 @@
+-- Who a run belongs to, whose deletes and restores of it aren't refused
+ALTER TABLE run ADD COLUMN IF NOT EXISTS owner text;
+
+UPDATE run SET owner = NULLIF(email, '') WHERE owner IS NULL;
+
+INSERT INTO schema_version (version, description) VALUES (17, 'run owner');
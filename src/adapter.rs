@@ -0,0 +1,374 @@
+use crate::add::{self, IterationNode, MetricNode, PeriodNode, Point, RunNode, SampleNode};
+use crate::args::AdapterOpts;
+use crate::cdm;
+use crate::parser::{self, BodyJson};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::builder::PossibleValue;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+use uuid::Uuid;
+
+mod fio;
+mod iperf3;
+mod jmeter;
+mod kube_burner;
+mod locust;
+mod pcp;
+mod perf_stat;
+mod sar;
+mod sysbench;
+mod uperf;
+mod vegeta;
+
+#[derive(Error, Debug)]
+pub enum AdapterError {
+    #[error("Unknown format {0}")]
+    UnknownFormat(String),
+    #[error("Couldn't read {0}, {1}")]
+    ReadFailed(String, String),
+    #[error("Couldn't parse {0}, {1}")]
+    ParseFailed(String, String),
+    #[error("{0} adds to an existing run, pass its --run-uuid and --period-uuid")]
+    NoPeriod(&'static str),
+    #[error("Invalid {0} {1}, expected NAME=VALUE")]
+    InvalidPair(&'static str, String),
+}
+
+/// Converts the output of a tool into the documents scdm ingests
+pub trait Adapter: Sync {
+    /// The name --format takes
+    fn name(&self) -> &'static str;
+    /// What the adapter reads, shown by --help
+    fn description(&self) -> &'static str;
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>>;
+}
+
+/// A directory of CDM ndjson, as crucible writes it
+struct Cdm;
+
+impl Adapter for Cdm {
+    fn name(&self) -> &'static str {
+        "cdm"
+    }
+
+    fn description(&self) -> &'static str {
+        "A directory of CDM ndjson, as crucible writes it"
+    }
+
+    fn parse(&self, path: &Path, _opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        parser::read_ndjson_dir(path)
+    }
+}
+
+/// The JSON results `scdm add` takes
+struct RunJson;
+
+impl Adapter for RunJson {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn description(&self) -> &'static str {
+        "JSON results like `scdm add` takes"
+    }
+
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        add::read_run_nodes(path, opts.uuid_v7)
+    }
+}
+
+/// Every format `scdm parse` reads, new ones only need adding here
+const ADAPTERS: &[&dyn Adapter] = &[
+    &Cdm,
+    &RunJson,
+    &fio::Fio,
+    &iperf3::Iperf3,
+    &jmeter::Jmeter,
+    &kube_burner::KubeBurner,
+    &locust::Locust,
+    &pcp::Pcp,
+    &perf_stat::PerfStat,
+    &sar::Sar,
+    &sysbench::Sysbench,
+    &uperf::Uperf,
+    &vegeta::Vegeta,
+];
+
+/// The formats for --format, with their descriptions for --help
+pub fn possible_values() -> Vec<PossibleValue> {
+    ADAPTERS
+        .iter()
+        .map(|adapter| PossibleValue::new(adapter.name()).help(adapter.description()))
+        .collect()
+}
+
+pub fn find(format: &str) -> Result<&'static dyn Adapter, AdapterError> {
+    ADAPTERS
+        .iter()
+        .find(|adapter| adapter.name() == format)
+        .copied()
+        .ok_or(AdapterError::UnknownFormat(format.to_string()))
+}
+
+/// The NAME=VALUE pairs of --tag or --param
+fn pairs(option: &'static str, pairs: &[String]) -> Result<HashMap<String, String>, AdapterError> {
+    pairs
+        .iter()
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(name, val)| (name.to_string(), val.to_string()))
+                .ok_or(AdapterError::InvalidPair(option, pair.clone()))
+        })
+        .collect()
+}
+
+/// A run of the tool's output, with the details from the command line that
+/// the output doesn't carry. The UUIDs the output doesn't have are made up
+pub fn run_node(
+    opts: &AdapterOpts,
+    benchmark: &str,
+    begin: DateTime<Utc>,
+    finish: DateTime<Utc>,
+    mut iterations: Vec<IterationNode>,
+) -> Result<RunNode, AdapterError> {
+    let tags = pairs("tag", &opts.tag)?;
+    let params = pairs("param", &opts.param)?;
+    for iteration in &mut iterations {
+        for (arg, val) in &params {
+            iteration
+                .params
+                .entry(arg.clone())
+                .or_insert_with(|| val.clone());
+        }
+    }
+    let mut run = RunNode {
+        run_uuid: opts.run_uuid.unwrap_or_default(),
+        begin,
+        finish,
+        benchmark: opts
+            .benchmark
+            .clone()
+            .unwrap_or_else(|| benchmark.to_string()),
+        email: opts.email.clone(),
+        name: opts.user_name.clone(),
+        description: opts.description.clone(),
+        source: benchmark.to_string(),
+        tags,
+        iterations,
+        metrics: vec![],
+    };
+    run.make_up_uuids(opts.uuid_v7);
+    Ok(run)
+}
+
+/// A metric of the tool's output, named by its breakouts. Its UUID is made
+/// up along with the run's, or once it's attached to a period
+pub fn metric_node(
+    class: &str,
+    metric_type: &str,
+    source: &str,
+    unit: Option<&str>,
+    names: &[(&str, &str)],
+    data: Vec<Point>,
+) -> MetricNode {
+    MetricNode {
+        metric_desc_uuid: Uuid::nil(),
+        class: class.to_string(),
+        metric_type: metric_type.to_string(),
+        source: source.to_string(),
+        unit: unit.map(str::to_string),
+        names: names
+            .iter()
+            .map(|(name, val)| (name.to_string(), val.to_string()))
+            .collect(),
+        data,
+    }
+}
+
+/// An iteration with a single sample, whose measurement period holds the
+/// metrics, as the tools that aren't run by crucible measure once. The UUIDs
+/// are made up along with the run's
+pub fn iteration_node(
+    num: i64,
+    status: &str,
+    primary_metric: &str,
+    params: HashMap<String, String>,
+    begin: DateTime<Utc>,
+    finish: DateTime<Utc>,
+    metrics: Vec<MetricNode>,
+) -> IterationNode {
+    IterationNode {
+        iteration_uuid: Uuid::nil(),
+        num,
+        status: status.to_string(),
+        path: None,
+        primary_metric: primary_metric.to_string(),
+        primary_period: "measurement".to_string(),
+        params,
+        samples: vec![SampleNode {
+            sample_uuid: Uuid::nil(),
+            num: 1,
+            status: status.to_string(),
+            path: None,
+            periods: vec![PeriodNode {
+                period_uuid: Uuid::nil(),
+                begin,
+                finish,
+                name: "measurement".to_string(),
+                metrics,
+            }],
+        }],
+    }
+}
+
+/// A setting of the tool's output as a param, which are strings
+pub fn param_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// One value over the whole of a period
+pub fn point(begin: DateTime<Utc>, finish: DateTime<Utc>, value: f64) -> Point {
+    Point {
+        begin,
+        finish,
+        value,
+    }
+}
+
+/// The documents of telemetry, like sar's, that adds to the period of an
+/// existing run rather than making a run of its own. Only the metrics and
+/// time range asked for are kept
+pub fn attached_documents(
+    format: &'static str,
+    opts: &AdapterOpts,
+    metrics: Vec<MetricNode>,
+) -> Result<Vec<BodyJson>, AdapterError> {
+    let (Some(run_uuid), Some(period_uuid)) = (opts.run_uuid, opts.period_uuid) else {
+        return Err(AdapterError::NoPeriod(format));
+    };
+    let wanted = |metric: &MetricNode| {
+        opts.metric.is_empty()
+            || opts
+                .metric
+                .iter()
+                .any(|prefix| metric.metric_type.starts_with(prefix.as_str()))
+    };
+    let in_range = |point: &Point| {
+        opts.begin.is_none_or(|begin| point.begin >= begin)
+            && opts.finish.is_none_or(|finish| point.finish <= finish)
+    };
+    Ok(metrics
+        .into_iter()
+        .filter(wanted)
+        .filter_map(|mut metric| {
+            cdm::make_up_uuid(&mut metric.metric_desc_uuid, opts.uuid_v7);
+            metric.data.retain(in_range);
+            (!metric.data.is_empty()).then_some(metric)
+        })
+        .flat_map(|metric| {
+            add::metric_to_body_jsons(run_uuid, None, None, Some(period_uuid), metric)
+        })
+        .collect())
+}
+
+/// The contents of the tool's output file
+pub fn read(path: &Path) -> Result<String, AdapterError> {
+    std::fs::read_to_string(path)
+        .map_err(|e| AdapterError::ReadFailed(path.display().to_string(), e.to_string()))
+}
+
+pub fn documents(runs: Vec<RunNode>) -> Vec<BodyJson> {
+    runs.into_iter().flat_map(add::run_to_body_jsons).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// The (begin, end, value) of each point of a metric
+    type Points = Vec<(DateTime<Utc>, DateTime<Utc>, f64)>;
+
+    /// Where a test writes its sample of a tool's output, unique to the test
+    pub(super) fn sample_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("scdm-{}-{}", std::process::id(), name))
+    }
+
+    /// Writes the sample of a tool's output to a file and parses it
+    pub(super) fn parse_sample(
+        adapter: &dyn Adapter,
+        name: &str,
+        sample: &str,
+        opts: &AdapterOpts,
+    ) -> Vec<BodyJson> {
+        let path = sample_path(name);
+        std::fs::write(&path, sample).unwrap();
+        let docs = adapter.parse(&path, opts);
+        std::fs::remove_file(&path).unwrap();
+        docs.unwrap()
+    }
+
+    /// The points of the metric of this type and these breakouts, none when
+    /// there's no such metric
+    pub(super) fn points(
+        docs: &[BodyJson],
+        metric_type: &str,
+        names: &[(&str, &str)],
+    ) -> Option<Points> {
+        let desc = docs.iter().find_map(|doc| match doc {
+            BodyJson::MetricDesc(desc)
+                if desc.metric_desc.metric_type == metric_type
+                    && desc.metric_desc.names.len() == names.len()
+                    && names.iter().all(|(name, val)| {
+                        desc.metric_desc.names.get(*name) == Some(&Value::from(*val))
+                    }) =>
+            {
+                Some(desc.metric_desc.metric_desc_uuid)
+            }
+            _ => None,
+        })?;
+        Some(
+            docs.iter()
+                .filter_map(|doc| match doc {
+                    BodyJson::MetricData(data) if data.metric_desc.metric_desc_uuid == desc => {
+                        Some((
+                            data.metric_data.begin,
+                            data.metric_data.end,
+                            data.metric_data.value,
+                        ))
+                    }
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// The value of the metric that's one point over the whole period
+    pub(super) fn value(docs: &[BodyJson], metric_type: &str, names: &[(&str, &str)]) -> f64 {
+        match points(docs, metric_type, names).as_deref() {
+            Some([(_, _, value)]) => *value,
+            other => panic!("no single {} {:?} point, {:?}", metric_type, names, other),
+        }
+    }
+
+    /// The (begin, end) of each period
+    pub(super) fn periods(docs: &[BodyJson]) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        docs.iter()
+            .filter_map(|doc| match doc {
+                BodyJson::Period(period) => Some((period.period.begin, period.period.end)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Parses an RFC 3339 timestamp
+    pub(super) fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+}
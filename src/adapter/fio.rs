@@ -0,0 +1,238 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, param_value, point, run_node};
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The output of `fio --output-format=json`
+pub struct Fio;
+
+#[derive(Deserialize)]
+struct FioOutput {
+    /// When fio finished
+    timestamp_ms: i64,
+    #[serde(rename = "global options", default)]
+    global_options: HashMap<String, Value>,
+    jobs: Vec<Job>,
+}
+
+#[derive(Deserialize)]
+struct Job {
+    jobname: String,
+    #[serde(default)]
+    error: i64,
+    #[serde(rename = "job options", default)]
+    job_options: HashMap<String, Value>,
+    /// How long the job ran, in ms
+    #[serde(default)]
+    job_runtime: i64,
+    read: Option<Op>,
+    write: Option<Op>,
+    trim: Option<Op>,
+}
+
+#[derive(Deserialize)]
+struct Op {
+    #[serde(default)]
+    total_ios: u64,
+    bw_bytes: f64,
+    iops: f64,
+    lat_ns: Option<Latency>,
+    clat_ns: Option<Latency>,
+}
+
+#[derive(Deserialize)]
+struct Latency {
+    min: f64,
+    max: f64,
+    mean: f64,
+    stddev: f64,
+    #[serde(default)]
+    percentile: BTreeMap<String, f64>,
+}
+
+impl Adapter for Fio {
+    fn name(&self) -> &'static str {
+        "fio"
+    }
+
+    fn description(&self) -> &'static str {
+        "The JSON output of fio, an iteration per job"
+    }
+
+    /// Each job is an iteration with its options for params. The IOPS,
+    /// bandwidth and latency of each op the job did are broken out by job
+    /// and op, and the latency by stat and percentile too
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let contents = super::read(path)?;
+        // fio prints its notes before the JSON
+        let json = &contents[contents.find('{').unwrap_or(0)..];
+        let output: FioOutput = serde_json::from_str(json)
+            .map_err(|e| AdapterError::ParseFailed(path.display().to_string(), e.to_string()))?;
+
+        let finish = DateTime::from_timestamp_millis(output.timestamp_ms).ok_or(
+            AdapterError::ParseFailed(
+                path.display().to_string(),
+                format!("invalid timestamp_ms {}", output.timestamp_ms),
+            ),
+        )?;
+        let runtime = output.jobs.iter().map(|job| job.job_runtime).max();
+        let begin = finish - Duration::milliseconds(runtime.unwrap_or_default());
+
+        let mut iterations = vec![];
+        for (num, job) in output.jobs.iter().enumerate() {
+            let params = output
+                .global_options
+                .iter()
+                .chain(&job.job_options)
+                .map(|(arg, val)| (arg.clone(), param_value(val)))
+                .collect();
+            let mut metrics = vec![];
+            let ops = [
+                ("read", &job.read),
+                ("write", &job.write),
+                ("trim", &job.trim),
+            ];
+            for (op, stats) in ops {
+                let Some(stats) = stats.as_ref().filter(|stats| stats.total_ios > 0) else {
+                    continue;
+                };
+                let names = [("job", job.jobname.as_str()), ("op", op)];
+                let over_run = |value| vec![point(begin, finish, value)];
+                metrics.push(metric_node(
+                    "throughput",
+                    "iops",
+                    "fio",
+                    None,
+                    &names,
+                    over_run(stats.iops),
+                ));
+                metrics.push(metric_node(
+                    "throughput",
+                    "bandwidth",
+                    "fio",
+                    Some("B/s"),
+                    &names,
+                    over_run(stats.bw_bytes),
+                ));
+                let latencies = [
+                    ("latency", &stats.lat_ns),
+                    ("completion-latency", &stats.clat_ns),
+                ];
+                for (metric_type, latency) in latencies {
+                    let Some(latency) = latency else {
+                        continue;
+                    };
+                    let mut values = vec![
+                        ("min".to_string(), latency.min),
+                        ("mean".to_string(), latency.mean),
+                        ("max".to_string(), latency.max),
+                        ("stddev".to_string(), latency.stddev),
+                    ];
+                    for (percentile, value) in &latency.percentile {
+                        let percentile = percentile.parse::<f64>().map_err(|_| {
+                            AdapterError::ParseFailed(
+                                path.display().to_string(),
+                                format!("invalid percentile {}", percentile),
+                            )
+                        })?;
+                        values.push((format!("p{}", percentile), *value));
+                    }
+                    for (stat, value) in values {
+                        metrics.push(metric_node(
+                            "count",
+                            metric_type,
+                            "fio",
+                            Some("ns"),
+                            &[names[0], names[1], ("stat", &stat)],
+                            over_run(value),
+                        ));
+                    }
+                }
+            }
+            let status = if job.error == 0 { "pass" } else { "fail" };
+            iterations.push(iteration_node(
+                num as i64 + 1,
+                status,
+                "iops",
+                params,
+                begin,
+                finish,
+                metrics,
+            ));
+        }
+
+        let run = run_node(opts, "fio", begin, finish, iterations)?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, parse_sample, periods, points, sample_path, value};
+
+    /// A job that only read, after the notes fio prints before its JSON
+    const SAMPLE: &str = r#"note: both iodepth >= 1 and synchronous I/O engine are selected, queue depth will be capped at 1
+{
+  "fio version" : "fio-3.36",
+  "timestamp" : 1700000010,
+  "timestamp_ms" : 1700000010000,
+  "global options" : { "ioengine" : "psync" },
+  "jobs" : [
+    {
+      "jobname" : "randread",
+      "error" : 0,
+      "job options" : { "rw" : "randread", "bs" : "4k" },
+      "job_runtime" : 10000,
+      "read" : {
+        "total_ios" : 25000,
+        "bw_bytes" : 10240000,
+        "iops" : 2500.0,
+        "lat_ns" : { "min" : 1000, "max" : 90000, "mean" : 4000.5, "stddev" : 300.0 },
+        "clat_ns" : {
+          "min" : 900, "max" : 89000, "mean" : 3900.0, "stddev" : 290.0,
+          "percentile" : { "50.000000" : 3800, "99.900000" : 12000 }
+        }
+      },
+      "write" : { "total_ios" : 0, "bw_bytes" : 0, "iops" : 0.0 }
+    }
+  ]
+}
+"#;
+
+    #[test]
+    fn parses_the_ops_of_each_job() {
+        let docs = parse_sample(&Fio, "fio.json", SAMPLE, &AdapterOpts::default());
+        let names = [("job", "randread"), ("op", "read")];
+        assert_eq!(value(&docs, "iops", &names), 2500.0);
+        assert_eq!(value(&docs, "bandwidth", &names), 10240000.0);
+        let stat = |stat| [names[0], names[1], ("stat", stat)];
+        assert_eq!(value(&docs, "latency", &stat("mean")), 4000.5);
+        assert_eq!(value(&docs, "completion-latency", &stat("p50")), 3800.0);
+        assert_eq!(value(&docs, "completion-latency", &stat("p99.9")), 12000.0);
+        assert!(points(&docs, "iops", &[names[0], ("op", "write")]).is_none());
+        assert_eq!(
+            periods(&docs),
+            [(at("2023-11-14T22:13:20Z"), at("2023-11-14T22:13:30Z"))]
+        );
+    }
+
+    #[test]
+    fn refuses_an_invalid_percentile() {
+        let sample = SAMPLE.replace("99.900000", "99.9th");
+        let path = sample_path("fio-percentile.json");
+        std::fs::write(&path, sample).unwrap();
+        let err = Fio.parse(&path, &AdapterOpts::default()).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            err.to_string().ends_with("invalid percentile 99.9th"),
+            "{}",
+            err
+        );
+    }
+}
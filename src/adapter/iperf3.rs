@@ -0,0 +1,213 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, param_value, point, run_node};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The output of `iperf3 --json`
+pub struct Iperf3;
+
+#[derive(Deserialize)]
+struct Iperf3Output {
+    start: Start,
+    intervals: Vec<Interval>,
+    /// Set when iperf3 failed
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Start {
+    #[serde(default)]
+    connected: Vec<Connection>,
+    timestamp: Timestamp,
+    test_start: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct Connection {
+    socket: i64,
+    remote_host: String,
+}
+
+#[derive(Deserialize)]
+struct Timestamp {
+    timesecs: i64,
+}
+
+#[derive(Deserialize)]
+struct Interval {
+    streams: Vec<Stream>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    socket: i64,
+    /// Seconds since the test started
+    start: f64,
+    end: f64,
+    bits_per_second: f64,
+    /// Only TCP senders count them
+    retransmits: Option<f64>,
+    /// Only UDP receivers count them
+    lost_packets: Option<f64>,
+}
+
+impl Adapter for Iperf3 {
+    fn name(&self) -> &'static str {
+        "iperf3"
+    }
+
+    fn description(&self) -> &'static str {
+        "The JSON output of iperf3"
+    }
+
+    /// The throughput, retransmits and lost packets of each interval, broken
+    /// out by stream. The test's settings are the iteration's params
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let contents = super::read(path)?;
+        let output: Iperf3Output = serde_json::from_str(&contents)
+            .map_err(|e| AdapterError::ParseFailed(path.display().to_string(), e.to_string()))?;
+
+        let started = DateTime::from_timestamp(output.start.timestamp.timesecs, 0).ok_or(
+            AdapterError::ParseFailed(
+                path.display().to_string(),
+                format!("invalid timesecs {}", output.start.timestamp.timesecs),
+            ),
+        )?;
+        let at = |secs: f64| started + Duration::milliseconds((secs * 1000.0) as i64);
+        let hosts: HashMap<i64, &str> = output
+            .start
+            .connected
+            .iter()
+            .map(|connection| (connection.socket, connection.remote_host.as_str()))
+            .collect();
+
+        // The series of each stream, by socket
+        let mut series: BTreeMap<(i64, &str), Vec<Point>> = BTreeMap::new();
+        let mut finish = started;
+        for interval in &output.intervals {
+            for stream in &interval.streams {
+                let (begin, end) = (at(stream.start), at(stream.end));
+                finish = finish.max(end);
+                let values = [
+                    ("throughput", Some(stream.bits_per_second)),
+                    ("retransmits", stream.retransmits),
+                    ("lost-packets", stream.lost_packets),
+                ];
+                for (metric_type, value) in values {
+                    if let Some(value) = value {
+                        series
+                            .entry((stream.socket, metric_type))
+                            .or_default()
+                            .push(point(begin, end - Duration::milliseconds(1), value));
+                    }
+                }
+            }
+        }
+
+        let metrics = series
+            .into_iter()
+            .map(|((socket, metric_type), data)| {
+                let (class, unit) = match metric_type {
+                    "throughput" => ("throughput", Some("bps")),
+                    _ => ("count", None),
+                };
+                let stream = socket.to_string();
+                let host = hosts.get(&socket).copied().unwrap_or_default();
+                metric_node(
+                    class,
+                    metric_type,
+                    "iperf3",
+                    unit,
+                    &[("stream", &stream), ("host", host)],
+                    data,
+                )
+            })
+            .collect();
+        let params = output
+            .start
+            .test_start
+            .iter()
+            .map(|(arg, val)| (arg.clone(), param_value(val)))
+            .collect();
+        let status = if output.error.is_none() {
+            "pass"
+        } else {
+            "fail"
+        };
+        let iteration = iteration_node(1, status, "throughput", params, started, finish, metrics);
+
+        let run = run_node(opts, "iperf3", started, finish, vec![iteration])?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, parse_sample, periods, points};
+
+    /// Two seconds of a TCP stream, trimmed of the fields that aren't read
+    const SAMPLE: &str = r#"{
+  "start": {
+    "connected": [
+      { "socket": 5, "local_host": "10.0.0.1", "local_port": 40000, "remote_host": "10.0.0.2", "remote_port": 5201 }
+    ],
+    "version": "iperf 3.16",
+    "timestamp": { "time": "Tue, 14 Nov 2023 22:13:20 GMT", "timesecs": 1700000000 },
+    "test_start": { "protocol": "TCP", "num_streams": 1, "blksize": 131072, "duration": 2, "reverse": 0 }
+  },
+  "intervals": [
+    {
+      "streams": [
+        { "socket": 5, "start": 0, "end": 1.000041, "seconds": 1.000041, "bytes": 117440512, "bits_per_second": 939524096, "retransmits": 2, "omitted": false, "sender": true }
+      ],
+      "sum": { "start": 0, "end": 1.000041, "bits_per_second": 939524096 }
+    },
+    {
+      "streams": [
+        { "socket": 5, "start": 1.000041, "end": 2.000032, "seconds": 0.999991, "bytes": 117440512, "bits_per_second": 939533000, "retransmits": 0, "omitted": false, "sender": true }
+      ],
+      "sum": { "start": 1.000041, "end": 2.000032, "bits_per_second": 939533000 }
+    }
+  ],
+  "end": {}
+}"#;
+
+    #[test]
+    fn parses_each_interval_of_each_stream() {
+        let docs = parse_sample(&Iperf3, "iperf3.json", SAMPLE, &AdapterOpts::default());
+        let names = [("stream", "5"), ("host", "10.0.0.2")];
+        assert_eq!(
+            points(&docs, "throughput", &names).unwrap(),
+            [
+                (
+                    at("2023-11-14T22:13:20Z"),
+                    at("2023-11-14T22:13:20.999Z"),
+                    939524096.0
+                ),
+                (
+                    at("2023-11-14T22:13:21Z"),
+                    at("2023-11-14T22:13:21.999Z"),
+                    939533000.0
+                ),
+            ]
+        );
+        let retransmits: Vec<f64> = points(&docs, "retransmits", &names)
+            .unwrap()
+            .iter()
+            .map(|(_, _, value)| *value)
+            .collect();
+        assert_eq!(retransmits, [2.0, 0.0]);
+        assert!(points(&docs, "lost-packets", &names).is_none());
+        assert_eq!(
+            periods(&docs),
+            [(at("2023-11-14T22:13:20Z"), at("2023-11-14T22:13:22Z"))]
+        );
+    }
+}
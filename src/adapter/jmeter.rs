@@ -0,0 +1,187 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, point, run_node};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// A JMeter JTL results file in its default CSV form, a row per sample with
+/// the epoch millis it was taken at
+pub struct Jmeter;
+
+/// The samples of a label within a second
+#[derive(Default)]
+struct Bucket {
+    requests: f64,
+    errors: f64,
+    elapsed: f64,
+    latency: f64,
+    connect: f64,
+    bytes: f64,
+}
+
+impl Adapter for Jmeter {
+    fn name(&self) -> &'static str {
+        "jmeter"
+    }
+
+    fn description(&self) -> &'static str {
+        "JMeter JTL results saved as CSV with a header"
+    }
+
+    /// The samples are summed per second and label: the request and error
+    /// rates, the mean response time, latency and connect time, and the
+    /// received throughput, broken out by endpoint
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let perr = |e: String| AdapterError::ParseFailed(path.display().to_string(), e);
+        let mut reader = csv::Reader::from_path(path).map_err(|e| perr(e.to_string()))?;
+        let headers = reader.headers().map_err(|e| perr(e.to_string()))?.clone();
+        let column = |name: &str| headers.iter().position(|header| header == name);
+        let (Some(timestamp), Some(elapsed), Some(label)) =
+            (column("timeStamp"), column("elapsed"), column("label"))
+        else {
+            return Err(perr("no timeStamp, elapsed and label columns".to_string()).into());
+        };
+        let (success, latency, connect, bytes) = (
+            column("success"),
+            column("Latency"),
+            column("Connect"),
+            column("bytes"),
+        );
+
+        let mut buckets: BTreeMap<(String, i64), Bucket> = BTreeMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| perr(e.to_string()))?;
+            let millis: i64 = record
+                .get(timestamp)
+                .and_then(|millis| millis.parse().ok())
+                .ok_or_else(|| perr("timeStamp isn't epoch millis".to_string()))?;
+            let number = |column: Option<usize>| {
+                column
+                    .and_then(|column| record.get(column))
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .unwrap_or_default()
+            };
+            let bucket = buckets
+                .entry((record[label].to_string(), millis.div_euclid(1000)))
+                .or_default();
+            bucket.requests += 1.0;
+            if success.and_then(|column| record.get(column)) == Some("false") {
+                bucket.errors += 1.0;
+            }
+            bucket.elapsed += number(Some(elapsed));
+            bucket.latency += number(latency);
+            bucket.connect += number(connect);
+            bucket.bytes += number(bytes);
+        }
+        let (Some(first), Some(last)) = (
+            buckets.keys().map(|(_, secs)| *secs).min(),
+            buckets.keys().map(|(_, secs)| *secs).max(),
+        ) else {
+            return Err(perr("no samples".to_string()).into());
+        };
+        let begin = DateTime::from_timestamp(first, 0).unwrap_or_default();
+        let finish = DateTime::from_timestamp(last + 1, 0).unwrap_or_default();
+
+        // The series by metric type and endpoint
+        let mut series: BTreeMap<(&str, String), Vec<Point>> = BTreeMap::new();
+        for ((endpoint, secs), bucket) in buckets {
+            let begin: DateTime<Utc> = DateTime::from_timestamp(secs, 0).unwrap_or_default();
+            let end = begin + Duration::milliseconds(999);
+            let mut push = |metric_type, value| {
+                series
+                    .entry((metric_type, endpoint.clone()))
+                    .or_default()
+                    .push(point(begin, end, value));
+            };
+            push("requests-sec", bucket.requests);
+            push("errors-sec", bucket.errors);
+            push("response-time", bucket.elapsed / bucket.requests);
+            if latency.is_some() {
+                push("latency", bucket.latency / bucket.requests);
+            }
+            if connect.is_some() {
+                push("connect-time", bucket.connect / bucket.requests);
+            }
+            if bytes.is_some() {
+                push("throughput", bucket.bytes * 8.0);
+            }
+        }
+
+        let metrics = series
+            .into_iter()
+            .map(|((metric_type, endpoint), data)| {
+                let (class, unit) = match metric_type {
+                    "requests-sec" | "errors-sec" => ("throughput", None),
+                    "throughput" => ("throughput", Some("bps")),
+                    _ => ("count", Some("ms")),
+                };
+                let names = [("endpoint", endpoint.as_str())];
+                metric_node(class, metric_type, "jmeter", unit, &names, data)
+            })
+            .collect();
+        let iteration = iteration_node(
+            1,
+            "pass",
+            "requests-sec",
+            HashMap::new(),
+            begin,
+            finish,
+            metrics,
+        );
+
+        let run = run_node(opts, "jmeter", begin, finish, vec![iteration])?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, parse_sample, periods, points, value};
+
+    const SAMPLE: &str = "\
+timeStamp,elapsed,label,responseCode,responseMessage,threadName,dataType,success,failureMessage,bytes,sentBytes,grpThreads,allThreads,URL,Latency,IdleTime,Connect
+1700000000123,100,GET /,200,OK,Thread Group 1-1,text,true,,1000,120,2,2,http://app/,90,0,10
+1700000000456,120,GET /,200,OK,Thread Group 1-2,text,true,,1200,120,2,2,http://app/,110,0,20
+1700000000789,300,POST /login,500,Internal Server Error,Thread Group 1-1,text,false,,200,300,2,2,http://app/login,290,0,5
+1700000001100,80,GET /,200,OK,Thread Group 1-2,text,true,,1000,120,2,2,http://app/,70,0,0
+";
+
+    #[test]
+    fn samples_are_summed_per_second_and_label() {
+        let docs = parse_sample(&Jmeter, "jmeter.jtl", SAMPLE, &AdapterOpts::default());
+        let get = [("endpoint", "GET /")];
+        assert_eq!(
+            points(&docs, "requests-sec", &get).unwrap(),
+            [
+                (
+                    at("2023-11-14T22:13:20Z"),
+                    at("2023-11-14T22:13:20.999Z"),
+                    2.0
+                ),
+                (
+                    at("2023-11-14T22:13:21Z"),
+                    at("2023-11-14T22:13:21.999Z"),
+                    1.0
+                ),
+            ]
+        );
+        let first = |metric_type, names| points(&docs, metric_type, names).unwrap()[0].2;
+        assert_eq!(first("response-time", &get), 110.0);
+        assert_eq!(first("latency", &get), 100.0);
+        assert_eq!(first("connect-time", &get), 15.0);
+        assert_eq!(first("throughput", &get), 17600.0);
+        assert_eq!(first("errors-sec", &get), 0.0);
+        assert_eq!(
+            value(&docs, "errors-sec", &[("endpoint", "POST /login")]),
+            1.0
+        );
+        assert_eq!(
+            periods(&docs),
+            [(at("2023-11-14T22:13:20Z"), at("2023-11-14T22:13:22Z"))]
+        );
+    }
+}
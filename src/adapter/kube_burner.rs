@@ -0,0 +1,336 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, param_value, point, run_node};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The measurements kube-burner's local indexer writes to its metrics
+/// directory, a JSON array of documents per file
+pub struct KubeBurner;
+
+/// The conditions of podLatencyMeasurement, by its field
+const POD_CONDITIONS: [(&str, &str); 4] = [
+    ("schedulingLatency", "PodScheduled"),
+    ("initializedLatency", "Initialized"),
+    ("containersReadyLatency", "ContainersReady"),
+    ("podReadyLatency", "Ready"),
+];
+
+const QUANTILES: [&str; 5] = ["P99", "P95", "P50", "max", "avg"];
+
+/// A series by metric type and breakout names
+type Series = BTreeMap<(String, Vec<(String, String)>), Vec<Point>>;
+
+/// What's known of a job, from its summary or else its measurements
+#[derive(Default)]
+struct Job {
+    begin: Option<DateTime<Utc>>,
+    finish: Option<DateTime<Utc>>,
+    passed: Option<bool>,
+    params: HashMap<String, String>,
+    series: Series,
+}
+
+impl Job {
+    fn extend(&mut self, at: DateTime<Utc>) {
+        self.begin = Some(self.begin.map_or(at, |begin| begin.min(at)));
+        self.finish = Some(self.finish.map_or(at, |finish| finish.max(at)));
+    }
+
+    fn push(
+        &mut self,
+        metric_type: &str,
+        names: Vec<(&str, String)>,
+        at: DateTime<Utc>,
+        value: f64,
+    ) {
+        let names = names
+            .into_iter()
+            .map(|(name, val)| (name.to_string(), val))
+            .collect();
+        // A measurement is taken at an instant, given the shortest span
+        self.series
+            .entry((metric_type.to_string(), names))
+            .or_default()
+            .push(point(at, at + Duration::milliseconds(1), value));
+        self.extend(at);
+    }
+}
+
+fn timestamp(document: &Map<String, Value>, field: &str) -> Option<DateTime<Utc>> {
+    let timestamp = document.get(field)?.as_str()?;
+    Some(DateTime::parse_from_rfc3339(timestamp).ok()?.to_utc())
+}
+
+fn string(document: &Map<String, Value>, field: &str) -> String {
+    document.get(field).map(param_value).unwrap_or_default()
+}
+
+fn add_document(jobs: &mut BTreeMap<String, Job>, document: &Map<String, Value>) {
+    let metric_name = string(document, "metricName");
+    let job_name = match string(document, "jobName") {
+        name if name.is_empty() => document
+            .get("jobConfig")
+            .and_then(|config| config.get("name"))
+            .map(param_value)
+            .unwrap_or_default(),
+        name => name,
+    };
+    let Some(at) = timestamp(document, "timestamp") else {
+        return;
+    };
+    let job = jobs.entry(job_name.clone()).or_default();
+    let number = |field: &str| document.get(field).and_then(Value::as_f64);
+    match metric_name.as_str() {
+        "jobSummary" => {
+            job.begin = Some(at);
+            job.finish = timestamp(document, "endTimestamp").or(job.finish);
+            job.passed = document.get("passed").and_then(Value::as_bool);
+            if let Some(Value::Object(config)) = document.get("jobConfig") {
+                for (arg, val) in config {
+                    if !val.is_object() && !val.is_array() {
+                        job.params.insert(arg.clone(), param_value(val));
+                    }
+                }
+            }
+        }
+        "podLatencyQuantilesMeasurement" => {
+            let condition = string(document, "quantileName");
+            for quantile in QUANTILES {
+                if let Some(value) = number(quantile) {
+                    let names = vec![
+                        ("job", job_name.clone()),
+                        ("condition", condition.clone()),
+                        ("quantile", quantile.to_string()),
+                    ];
+                    job.push("pod-latency-quantile", names, at, value);
+                }
+            }
+        }
+        "podLatencyMeasurement" => {
+            for (field, condition) in POD_CONDITIONS {
+                if let Some(value) = number(field) {
+                    let names = vec![
+                        ("job", job_name.clone()),
+                        ("namespace", string(document, "namespace")),
+                        ("condition", condition.to_string()),
+                    ];
+                    job.push("pod-latency", names, at, value);
+                }
+            }
+        }
+        // The metrics of the metrics profile, like the API call latencies,
+        // broken out by their Prometheus labels
+        _ => {
+            let Some(value) = number("value") else {
+                return;
+            };
+            let mut names = vec![("job", job_name.clone())];
+            if let Some(Value::Object(labels)) = document.get("labels") {
+                names.extend(
+                    labels
+                        .iter()
+                        .map(|(name, val)| (name.as_str(), param_value(val))),
+                );
+            }
+            job.push(&metric_name, names, at, value);
+        }
+    }
+}
+
+/// The JSON files of the metrics directory, or the file itself
+fn json_files(path: &Path) -> Result<Vec<PathBuf>, AdapterError> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| AdapterError::ReadFailed(path.display().to_string(), e.to_string()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+impl Adapter for KubeBurner {
+    fn name(&self) -> &'static str {
+        "kube-burner"
+    }
+
+    fn description(&self) -> &'static str {
+        "The metrics directory of kube-burner's local indexer, an iteration per job"
+    }
+
+    /// Each job is an iteration, with its jobConfig for params. Pod latency
+    /// quantiles are broken out by job, condition and quantile, the latency
+    /// of each pod by job, namespace and condition, and the metrics profile's
+    /// series, like API call latencies, by job and their labels
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let mut jobs: BTreeMap<String, Job> = BTreeMap::new();
+        for file in json_files(path)? {
+            let contents = super::read(&file)?;
+            let documents: Vec<Map<String, Value>> =
+                serde_json::from_str(&contents).map_err(|e| {
+                    AdapterError::ParseFailed(file.display().to_string(), e.to_string())
+                })?;
+            for document in &documents {
+                add_document(&mut jobs, document);
+            }
+        }
+
+        let mut iterations = vec![];
+        let mut span: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+        for (num, (name, job)) in jobs.into_iter().enumerate() {
+            let (Some(begin), Some(finish)) = (job.begin, job.finish) else {
+                continue;
+            };
+            let finish = finish.max(begin + Duration::milliseconds(1));
+            span = Some(span.map_or((begin, finish), |(b, f)| (b.min(begin), f.max(finish))));
+            let metrics = job
+                .series
+                .into_iter()
+                .map(|((metric_type, names), data)| {
+                    let names: Vec<(&str, &str)> = names
+                        .iter()
+                        .map(|(name, val)| (name.as_str(), val.as_str()))
+                        .collect();
+                    let unit = metric_type.starts_with("pod-latency").then_some("ms");
+                    metric_node("count", &metric_type, "kube-burner", unit, &names, data)
+                })
+                .collect();
+            let mut params = job.params;
+            params.entry("name".to_string()).or_insert(name);
+            let status = if job.passed == Some(false) {
+                "fail"
+            } else {
+                "pass"
+            };
+            iterations.push(iteration_node(
+                num as i64 + 1,
+                status,
+                "pod-latency-quantile",
+                params,
+                begin,
+                finish,
+                metrics,
+            ));
+        }
+        let Some((begin, finish)) = span else {
+            return Err(AdapterError::ParseFailed(
+                path.display().to_string(),
+                "no kube-burner measurements".to_string(),
+            )
+            .into());
+        };
+
+        let run = run_node(opts, "kube-burner", begin, finish, iterations)?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, periods, points, sample_path, value};
+
+    const JOB_SUMMARY: &str = r#"[
+  {
+    "timestamp": "2024-01-02T03:04:00Z",
+    "endTimestamp": "2024-01-02T03:06:00Z",
+    "elapsedTime": 120,
+    "uuid": "0b8d1b4c-2a7a-4f32-9d35-8c2b8f6c8d1e",
+    "metricName": "jobSummary",
+    "passed": true,
+    "jobConfig": { "name": "cluster-density", "jobIterations": 10, "qps": 20, "burst": 20, "namespacedIterations": true, "objects": [] }
+  }
+]"#;
+
+    const POD_LATENCY_QUANTILES: &str = r#"[
+  {
+    "quantileName": "Ready",
+    "uuid": "0b8d1b4c-2a7a-4f32-9d35-8c2b8f6c8d1e",
+    "P99": 4200, "P95": 3900, "P50": 2100, "max": 4500, "avg": 2300,
+    "timestamp": "2024-01-02T03:06:00Z",
+    "metricName": "podLatencyQuantilesMeasurement",
+    "jobName": "cluster-density"
+  },
+  {
+    "quantileName": "PodScheduled",
+    "uuid": "0b8d1b4c-2a7a-4f32-9d35-8c2b8f6c8d1e",
+    "P99": 12, "P95": 9, "P50": 3, "max": 15, "avg": 4,
+    "timestamp": "2024-01-02T03:06:00Z",
+    "metricName": "podLatencyQuantilesMeasurement",
+    "jobName": "cluster-density"
+  }
+]"#;
+
+    const API_LATENCY: &str = r#"[
+  {
+    "timestamp": "2024-01-02T03:05:00Z",
+    "labels": { "resource": "pods", "verb": "POST" },
+    "value": 0.025,
+    "uuid": "0b8d1b4c-2a7a-4f32-9d35-8c2b8f6c8d1e",
+    "query": "histogram_quantile(0.99, ...)",
+    "metricName": "API99thLatency",
+    "jobName": "cluster-density"
+  }
+]"#;
+
+    #[test]
+    fn parses_a_metrics_directory() {
+        let dir = sample_path("kube-burner");
+        fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("jobSummary.json", JOB_SUMMARY),
+            (
+                "podLatencyQuantilesMeasurement-cluster-density.json",
+                POD_LATENCY_QUANTILES,
+            ),
+            ("API99thLatency.json", API_LATENCY),
+            ("README.txt", "not a measurement"),
+        ];
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        let docs = KubeBurner.parse(&dir, &AdapterOpts::default());
+        fs::remove_dir_all(&dir).unwrap();
+        let docs = docs.unwrap();
+
+        let job = ("job", "cluster-density");
+        let quantile =
+            |condition, quantile| [job, ("condition", condition), ("quantile", quantile)];
+        assert_eq!(
+            points(&docs, "pod-latency-quantile", &quantile("Ready", "P99")).unwrap(),
+            [(
+                at("2024-01-02T03:06:00Z"),
+                at("2024-01-02T03:06:00.001Z"),
+                4200.0
+            )]
+        );
+        assert_eq!(
+            value(
+                &docs,
+                "pod-latency-quantile",
+                &quantile("PodScheduled", "avg")
+            ),
+            4.0
+        );
+        assert_eq!(
+            value(
+                &docs,
+                "API99thLatency",
+                &[job, ("resource", "pods"), ("verb", "POST")]
+            ),
+            0.025
+        );
+        assert_eq!(
+            periods(&docs),
+            [(at("2024-01-02T03:04:00Z"), at("2024-01-02T03:06:00Z"))]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, point, run_node};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The <prefix>_stats_history.csv Locust writes with --csv, a row per
+/// endpoint every few seconds when run with --csv-full-history, otherwise
+/// only the Aggregated rows
+pub struct Locust;
+
+/// The row covering everything, which endpoint breakouts would count twice
+const AGGREGATED: &str = "Aggregated";
+
+impl Adapter for Locust {
+    fn name(&self) -> &'static str {
+        "locust"
+    }
+
+    fn description(&self) -> &'static str {
+        "The stats history CSV Locust writes with --csv"
+    }
+
+    /// The request and failure rates and response time percentiles of each
+    /// row, broken out by endpoint and method, and the user count
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let perr = |e: String| AdapterError::ParseFailed(path.display().to_string(), e);
+        let mut reader = csv::Reader::from_path(path).map_err(|e| perr(e.to_string()))?;
+        let headers = reader.headers().map_err(|e| perr(e.to_string()))?.clone();
+        let column = |name: &str| headers.iter().position(|header| header == name);
+        let (Some(timestamp), Some(name), Some(method)) =
+            (column("Timestamp"), column("Name"), column("Type"))
+        else {
+            return Err(perr(
+                "no Timestamp, Name and Type columns, is it the _stats_history.csv?".to_string(),
+            )
+            .into());
+        };
+        let percentiles: Vec<(usize, String)> = headers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, header)| Some((i, format!("p{}", header.strip_suffix('%')?))))
+            .collect();
+
+        let mut rows = vec![];
+        for record in reader.records() {
+            let record = record.map_err(|e| perr(e.to_string()))?;
+            let at = record
+                .get(timestamp)
+                .and_then(|secs| secs.parse().ok())
+                .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                .ok_or_else(|| perr("Timestamp isn't epoch seconds".to_string()))?;
+            rows.push((at, record));
+        }
+        let endpoints = rows.iter().any(|(_, record)| &record[name] != AGGREGATED);
+        let (Some(begin), Some(finish)) = (
+            rows.iter().map(|(at, _)| *at).min(),
+            rows.iter().map(|(at, _)| *at).max(),
+        ) else {
+            return Err(perr("no rows".to_string()).into());
+        };
+
+        // The series by metric type, endpoint, method and stat, and when
+        // each endpoint's last row was
+        let mut series: BTreeMap<(&str, String, String, String), Vec<Point>> = BTreeMap::new();
+        let mut last: HashMap<(String, String), DateTime<Utc>> = HashMap::new();
+        let mut users: Vec<Point> = vec![];
+        for (at, record) in &rows {
+            let (endpoint, method) = (&record[name], &record[method]);
+            let since = last.insert((endpoint.to_string(), method.to_string()), *at);
+            // The first row of each covers nothing before it
+            let Some(since) = since.filter(|since| since < at) else {
+                continue;
+            };
+            let end = *at - Duration::milliseconds(1);
+            let number = |column: Option<usize>| {
+                column
+                    .and_then(|column| record.get(column))
+                    .and_then(|value| value.parse::<f64>().ok())
+            };
+            if endpoint == AGGREGATED {
+                if let Some(count) = number(column("User Count")) {
+                    users.push(point(since, end, count));
+                }
+                if endpoints {
+                    continue;
+                }
+            }
+            let mut push = |metric_type, stat: &str, value: Option<f64>| {
+                if let Some(value) = value {
+                    series
+                        .entry((
+                            metric_type,
+                            endpoint.to_string(),
+                            method.to_string(),
+                            stat.to_string(),
+                        ))
+                        .or_default()
+                        .push(point(since, end, value));
+                }
+            };
+            push("requests-sec", "", number(column("Requests/s")));
+            push("failures-sec", "", number(column("Failures/s")));
+            // Percentiles are N/A while an endpoint has no requests
+            for (column, stat) in &percentiles {
+                push("response-time", stat, number(Some(*column)));
+            }
+        }
+
+        let mut metrics: Vec<_> = series
+            .into_iter()
+            .map(|((metric_type, endpoint, method, stat), data)| {
+                let (class, unit) = match metric_type {
+                    "response-time" => ("count", Some("ms")),
+                    _ => ("throughput", None),
+                };
+                let mut names = vec![];
+                if endpoint != AGGREGATED {
+                    names.extend([("endpoint", endpoint.as_str()), ("method", method.as_str())]);
+                }
+                if !stat.is_empty() {
+                    names.push(("stat", stat.as_str()));
+                }
+                metric_node(class, metric_type, "locust", unit, &names, data)
+            })
+            .collect();
+        if !users.is_empty() {
+            metrics.push(metric_node("count", "users", "locust", None, &[], users));
+        }
+        let iteration = iteration_node(
+            1,
+            "pass",
+            "requests-sec",
+            HashMap::new(),
+            begin,
+            finish,
+            metrics,
+        );
+
+        let run = run_node(opts, "locust", begin, finish, vec![iteration])?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, parse_sample, periods, points, value};
+
+    /// Two rows of an endpoint and the aggregate, from --csv-full-history
+    const SAMPLE: &str = "\
+Timestamp,User Count,Type,Name,Requests/s,Failures/s,50%,66%,75%,80%,90%,95%,98%,99%,99.9%,99.99%,100%,Total Request Count,Total Failure Count,Total Median Response Time,Total Average Response Time,Total Min Response Time,Total Max Response Time,Total Average Content Size
+1700000000,10,GET,/,0.000000,0.000000,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,0,0,0,0.0,0,0,0.0
+1700000000,10,,Aggregated,0.000000,0.000000,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,N/A,0,0,0,0.0,0,0,0.0
+1700000002,10,GET,/,5.000000,0.500000,40,45,50,55,70,80,95,110,150,150,150,10,1,40,47.5,12,150,1024.0
+1700000002,10,,Aggregated,5.000000,0.500000,40,45,50,55,70,80,95,110,150,150,150,10,1,40,47.5,12,150,1024.0
+";
+
+    #[test]
+    fn rows_are_broken_out_by_endpoint() {
+        let docs = parse_sample(
+            &Locust,
+            "locust_stats_history.csv",
+            SAMPLE,
+            &AdapterOpts::default(),
+        );
+        let endpoint = [("endpoint", "/"), ("method", "GET")];
+        assert_eq!(
+            points(&docs, "requests-sec", &endpoint).unwrap(),
+            [(
+                at("2023-11-14T22:13:20Z"),
+                at("2023-11-14T22:13:21.999Z"),
+                5.0
+            )]
+        );
+        assert_eq!(value(&docs, "failures-sec", &endpoint), 0.5);
+        let stat = |stat| [endpoint[0], endpoint[1], ("stat", stat)];
+        assert_eq!(value(&docs, "response-time", &stat("p50")), 40.0);
+        assert_eq!(value(&docs, "response-time", &stat("p99.9")), 150.0);
+        assert_eq!(value(&docs, "users", &[]), 10.0);
+        assert!(points(&docs, "requests-sec", &[]).is_none());
+        assert_eq!(
+            periods(&docs),
+            [(at("2023-11-14T22:13:20Z"), at("2023-11-14T22:13:22Z"))]
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use super::{Adapter, AdapterError, attached_documents, metric_node, point};
+use crate::add::{MetricNode, Point};
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use crate::render::Timezone;
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The JSON pmrep writes with `-o json`, or the CSV it writes with `-o csv`,
+/// ex: `pmrep -a <archive> -o json -Z UTC -S <start> -T <end> kernel.all.load disk.dev.read`.
+/// Times without an offset are taken to be UTC unless --timezone says pmrep
+/// wrote them in another
+pub struct Pcp;
+
+/// A PCP metric and its instance, if it has them, ex: disk.dev.read of sda
+type Series = (String, Option<String>);
+
+/// The values pmrep sampled at a time
+type Row = (DateTime<Utc>, Vec<(Series, f64)>);
+
+/// The metric and instance of a CSV column, "disk.dev.read-sda" or "mem.util.free".
+/// It's split on the last '-', as derived metrics can have one in their
+/// name, so instances with a '-' in theirs need `-o json`
+fn metric_instance(column: &str) -> Series {
+    match column.rsplit_once('-') {
+        Some((metric, instance)) => (metric.to_string(), Some(instance.to_string())),
+        None => (column.to_string(), None),
+    }
+}
+
+fn row_time(time: &str, timezone: Timezone) -> Option<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(time) {
+        return Some(at.to_utc());
+    }
+    let naive = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").ok()?;
+    timezone.to_utc(naive)
+}
+
+fn csv_rows(contents: &[u8], timezone: Timezone) -> Result<Vec<Row>, String> {
+    let mut reader = csv::Reader::from_reader(contents);
+    let columns: Vec<Series> = reader
+        .headers()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(metric_instance)
+        .collect();
+
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let time = record.get(0).unwrap_or_default();
+        let at = row_time(time, timezone).ok_or_else(|| format!("invalid time {}", time))?;
+        let values = record
+            .iter()
+            .zip(&columns)
+            .skip(1)
+            .filter_map(|(value, series)| Some((series.clone(), value.parse().ok()?)))
+            .collect();
+        rows.push((at, values));
+    }
+    Ok(rows)
+}
+
+/// pmrep writes the values as strings
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// The values under a sample's metric tree, whose path of keys is the
+/// metric's name. A metric without instances holds its value, one with
+/// them a list of the instances and their values
+fn json_values(metric: &str, node: &Value, values: &mut Vec<(Series, f64)>) {
+    match node {
+        Value::Object(fields) if fields.contains_key("value") => {
+            if let Some(value) = fields.get("value").and_then(number) {
+                values.push(((metric.to_string(), None), value));
+            }
+        }
+        Value::Object(fields) => match fields.get("@instances") {
+            Some(instances) => json_values(metric, instances, values),
+            None => {
+                for (key, child) in fields.iter().filter(|(key, _)| !key.starts_with('@')) {
+                    let child_metric = match metric {
+                        "" => key.clone(),
+                        _ => format!("{}.{}", metric, key),
+                    };
+                    json_values(&child_metric, child, values);
+                }
+            }
+        },
+        Value::Array(instances) => {
+            for instance in instances {
+                let name = ["@id", "name", "@name"]
+                    .iter()
+                    .find_map(|key| instance.get(key))
+                    .map(|name| match name {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    });
+                if let Some(value) = instance.get("value").and_then(number) {
+                    values.push(((metric.to_string(), name), value));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The samples of each host under "@pcp"."@hosts"
+fn json_hosts(contents: &[u8], timezone: Timezone) -> Result<Vec<(String, Vec<Row>)>, String> {
+    let output: Value = serde_json::from_slice(contents).map_err(|e| e.to_string())?;
+    let hosts = output
+        .pointer("/@pcp/@hosts")
+        .and_then(Value::as_array)
+        .ok_or("no @pcp.@hosts")?;
+
+    let mut parsed = vec![];
+    for host in hosts {
+        let hostname = host
+            .get("@host")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let mut rows = vec![];
+        for sample in host
+            .get("@metrics")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let time = sample
+                .get("@timestamp")
+                .and_then(Value::as_str)
+                .ok_or("a sample without an @timestamp")?;
+            let at = row_time(time, timezone).ok_or_else(|| format!("invalid time {}", time))?;
+            let mut values = vec![];
+            json_values("", sample, &mut values);
+            rows.push((at, values));
+        }
+        parsed.push((hostname, rows));
+    }
+    Ok(parsed)
+}
+
+/// Each series of the host's rows, broken out by the hostname and the
+/// instance. A row covers the time since the one before it
+fn host_metrics(hostname: &str, rows: &[Row]) -> Result<Vec<MetricNode>, String> {
+    if rows.len() < 2 {
+        return Err(format!(
+            "pmrep wrote fewer than two samples of {:?}",
+            hostname
+        ));
+    }
+
+    let mut series: BTreeMap<&Series, Vec<Point>> = BTreeMap::new();
+    for (i, (at, values)) in rows.iter().enumerate() {
+        // The first row covers as long as the second does
+        let begin = match i {
+            0 => *at - (rows[1].0 - *at),
+            _ => rows[i - 1].0,
+        };
+        for (key, value) in values {
+            series.entry(key).or_default().push(point(
+                begin,
+                *at - Duration::milliseconds(1),
+                *value,
+            ));
+        }
+    }
+
+    Ok(series
+        .into_iter()
+        .map(|((metric, instance), data)| {
+            let mut names = vec![("hostname", hostname)];
+            if let Some(instance) = instance {
+                names.push(("instance", instance.as_str()));
+            }
+            metric_node("count", metric, "pcp", None, &names, data)
+        })
+        .collect())
+}
+
+impl Adapter for Pcp {
+    fn name(&self) -> &'static str {
+        "pcp"
+    }
+
+    fn description(&self) -> &'static str {
+        "PCP archive metrics from pmrep -o json or -o csv, added to an existing run's period"
+    }
+
+    /// Each metric and instance is a series of the PCP metric, broken out by
+    /// the host, which --hostname overrides, and the instance. The JSON is
+    /// told apart from the CSV by its opening brace
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let perr = |e: String| AdapterError::ParseFailed(path.display().to_string(), e);
+        let contents = std::fs::read(path).map_err(|e| perr(e.to_string()))?;
+        let is_json = contents
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|b| *b == b'{');
+
+        let timezone = opts.timezone.unwrap_or_default();
+        let hosts = match is_json {
+            true => json_hosts(&contents, timezone).map_err(perr)?,
+            false => vec![(String::new(), csv_rows(&contents, timezone).map_err(perr)?)],
+        };
+        let mut metrics = vec![];
+        for (hostname, rows) in &hosts {
+            let hostname = opts.hostname.as_deref().unwrap_or(hostname);
+            metrics.extend(host_metrics(hostname, rows).map_err(perr)?);
+        }
+        Ok(attached_documents("pcp", opts, metrics)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::at;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn csv_columns_split_on_the_last_dash() {
+        let series = |metric: &str, instance: Option<&str>| {
+            (metric.to_string(), instance.map(str::to_string))
+        };
+        assert_eq!(
+            metric_instance("mem.util.free"),
+            series("mem.util.free", None)
+        );
+        assert_eq!(
+            metric_instance("disk.dev.read-sda"),
+            series("disk.dev.read", Some("sda"))
+        );
+        assert_eq!(
+            metric_instance("kernel.all.load-1 minute"),
+            series("kernel.all.load", Some("1 minute"))
+        );
+        assert_eq!(
+            metric_instance("disk.dev.read-rate-sda"),
+            series("disk.dev.read-rate", Some("sda"))
+        );
+    }
+
+    #[test]
+    fn the_first_row_covers_as_long_as_the_second() {
+        let series = ("kernel.all.load".to_string(), None);
+        let rows = vec![
+            (at("2024-01-02T03:04:10Z"), vec![(series.clone(), 1.0)]),
+            (at("2024-01-02T03:04:20Z"), vec![(series.clone(), 2.0)]),
+        ];
+        let metrics = host_metrics("host1", &rows).unwrap();
+        let points: Vec<_> = metrics[0]
+            .data
+            .iter()
+            .map(|point| (point.begin, point.finish, point.value))
+            .collect();
+        assert_eq!(
+            points,
+            [
+                (
+                    at("2024-01-02T03:04:00Z"),
+                    at("2024-01-02T03:04:09.999Z"),
+                    1.0
+                ),
+                (
+                    at("2024-01-02T03:04:10Z"),
+                    at("2024-01-02T03:04:19.999Z"),
+                    2.0
+                ),
+            ]
+        );
+        assert!(host_metrics("host1", &rows[..1]).is_err());
+    }
+
+    #[test]
+    fn json_instances_are_named_by_id_or_name() {
+        let sample: Value = serde_json::json!({
+            "@timestamp": "2024-01-02 03:04:10",
+            "mem": { "util": { "free": { "value": "1024" } } },
+            "disk": { "dev": { "read": { "@unit": "count/s", "@instances": [
+                { "name": "sda", "value": "5" },
+                { "@id": 2, "name": "sdb", "value": 6 },
+            ] } } },
+        });
+        let mut values = vec![];
+        json_values("", &sample, &mut values);
+        let series = |metric: &str, instance: Option<&str>, value: f64| {
+            ((metric.to_string(), instance.map(str::to_string)), value)
+        };
+        assert_eq!(
+            values,
+            [
+                series("mem.util.free", None, 1024.0),
+                series("disk.dev.read", Some("sda"), 5.0),
+                series("disk.dev.read", Some("2"), 6.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn naive_times_are_in_the_timezone_and_offsets_are_kept() {
+        let plus_two = Timezone::Offset("+02:00".parse::<FixedOffset>().unwrap());
+        assert_eq!(
+            row_time("2024-01-02 03:04:05", Timezone::Utc),
+            Some(at("2024-01-02T03:04:05Z"))
+        );
+        assert_eq!(
+            row_time("2024-01-02 03:04:05", plus_two),
+            Some(at("2024-01-02T01:04:05Z"))
+        );
+        assert_eq!(
+            row_time("2024-01-02T03:04:05-05:00", plus_two),
+            Some(at("2024-01-02T08:04:05Z"))
+        );
+        assert_eq!(row_time("yesterday", Timezone::Utc), None);
+    }
+}
@@ -0,0 +1,226 @@
+use super::{Adapter, AdapterError, attached_documents, metric_node, point};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The CSV of `perf stat -x, -I <ms>`, ex:
+/// `1.001145606,1234567,,instructions,1001049632,100.00,0.52,insn per cycle`.
+/// Its times are seconds since perf started, which is the "# started on" line
+/// perf writes with -o, otherwise the file is taken to have been last written
+/// when perf stopped
+pub struct PerfStat;
+
+/// A series by metric type, the event or derived metric, and the CPU, core
+/// or socket of --per-cpu, --per-core or --per-socket
+type Series = BTreeMap<(&'static str, String, String), Vec<Point>>;
+
+/// A counter of an interval, with the aggregate perf printed it for if any
+struct Counter<'a> {
+    secs: f64,
+    aggregate: &'a str,
+    value: Option<f64>,
+    event: &'a str,
+    /// The metric perf derives from the counter, ex: "insn per cycle"
+    derived: Option<(f64, &'a str)>,
+}
+
+fn parse_line(line: &str) -> Option<Counter<'_>> {
+    let mut fields: Vec<&str> = line.split(',').collect();
+    let secs = fields.first()?.trim().parse().ok()?;
+    // The aggregate, "CPU0" or "S0-D0-C1" or "S0" followed by its CPU count
+    let mut aggregate = "";
+    if fields.len() > 2 && fields[1].parse::<f64>().is_err() && !fields[1].starts_with('<') {
+        aggregate = fields[1];
+        let cpus = fields
+            .get(2)
+            .is_some_and(|cpus| cpus.parse::<u64>().is_ok())
+            && fields
+                .get(3)
+                .is_some_and(|value| value.parse::<f64>().is_ok() || value.starts_with('<'));
+        fields.drain(1..if cpus { 3 } else { 2 });
+    }
+    let event = fields.get(3)?.trim();
+    if event.is_empty() {
+        return None;
+    }
+    // "<not counted>" and "<not supported>" have no value
+    let value = fields[1].parse().ok();
+    let derived = fields.get(6).and_then(|value| value.parse().ok()).zip(
+        fields
+            .get(7)
+            .map(|unit| unit.trim())
+            .filter(|unit| !unit.is_empty()),
+    );
+    Some(Counter {
+        secs,
+        aggregate,
+        value,
+        event,
+        derived,
+    })
+}
+
+fn started_on(contents: &str) -> Option<DateTime<Utc>> {
+    let started = contents
+        .lines()
+        .find_map(|line| line.strip_prefix("# started on "))?;
+    let naive = NaiveDateTime::parse_from_str(started.trim(), "%a %b %e %H:%M:%S %Y").ok()?;
+    Some(naive.and_local_timezone(Local).earliest()?.to_utc())
+}
+
+impl Adapter for PerfStat {
+    fn name(&self) -> &'static str {
+        "perf-stat"
+    }
+
+    fn description(&self) -> &'static str {
+        "Hardware counters from perf stat -x, -I, added to an existing run's period"
+    }
+
+    /// Each event's count per second of each interval is a "counter" series,
+    /// and the metrics perf derives from them, like insn per cycle, are
+    /// "counter-metric" series. Both are broken out by --hostname, the event
+    /// or derived metric name, and the CPU, core or socket if perf was run
+    /// with one of its --per options
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let contents = super::read(path)?;
+        let perr = |e: String| AdapterError::ParseFailed(path.display().to_string(), e);
+        let counters: Vec<Counter> = contents
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(parse_line)
+            .collect();
+        let Some(last) = counters.iter().map(|counter| counter.secs).reduce(f64::max) else {
+            return Err(
+                perr("no interval counters, was perf stat run with -x, -I?".to_string()).into(),
+            );
+        };
+        let started = match started_on(&contents) {
+            Some(started) => started,
+            None => {
+                let stopped: DateTime<Utc> = std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .map_err(|e| perr(e.to_string()))?
+                    .into();
+                stopped - Duration::milliseconds((last * 1000.0) as i64)
+            }
+        };
+
+        // The intervals in order, each beginning where the one before ended
+        let mut intervals: Vec<f64> = counters.iter().map(|counter| counter.secs).collect();
+        intervals.sort_by(f64::total_cmp);
+        intervals.dedup();
+        let mut series = Series::new();
+        for counter in &counters {
+            let i = intervals.partition_point(|secs| *secs < counter.secs);
+            let since = if i == 0 { 0.0 } else { intervals[i - 1] };
+            let length = counter.secs - since;
+            if length <= 0.0 {
+                continue;
+            }
+            let begin = started + Duration::milliseconds((since * 1000.0) as i64);
+            let end = started + Duration::milliseconds((counter.secs * 1000.0) as i64 - 1);
+            let mut push = |metric_type, name: &str, value| {
+                series
+                    .entry((metric_type, name.to_string(), counter.aggregate.to_string()))
+                    .or_default()
+                    .push(point(begin, end, value));
+            };
+            if let Some(value) = counter.value {
+                push("counter", counter.event, value / length);
+            }
+            if let Some((value, metric)) = counter.derived {
+                push("counter-metric", metric, value);
+            }
+        }
+
+        let hostname = opts.hostname.clone().unwrap_or_default();
+        let metrics = series
+            .into_iter()
+            .map(|((metric_type, name, aggregate), data)| {
+                let (class, name_key) = match metric_type {
+                    "counter" => ("throughput", "event"),
+                    _ => ("count", "metric"),
+                };
+                let mut names = vec![("hostname", hostname.as_str()), (name_key, name.as_str())];
+                if !aggregate.is_empty() {
+                    names.push(("cpu", aggregate.as_str()));
+                }
+                metric_node(class, metric_type, "perf", None, &names, data)
+            })
+            .collect();
+        Ok(attached_documents("perf-stat", opts, metrics)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{parse_sample, points};
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    /// Two intervals of `perf stat -a -x, -I 1000 -o <file>`
+    const SAMPLE: &str = "\
+# started on Tue Nov 14 22:13:20 2023
+
+     1.000000000,2000000000,,cycles,1001049632,100.00,,
+     1.000000000,1000000000,,instructions,1001049632,100.00,0.50,insn per cycle
+     1.000000000,<not counted>,,branch-misses,0,0.00,,
+     2.000000000,3000000000,,cycles,1000982211,100.00,,
+     2.000000000,1500000000,,instructions,1000982211,100.00,0.50,insn per cycle
+     2.000000000,<not counted>,,branch-misses,0,0.00,,
+";
+
+    #[test]
+    fn counters_are_rates_over_each_interval() {
+        let opts = AdapterOpts {
+            run_uuid: Some(Uuid::new_v4()),
+            period_uuid: Some(Uuid::new_v4()),
+            hostname: Some("worker-0".to_string()),
+            ..AdapterOpts::default()
+        };
+        let docs = parse_sample(&PerfStat, "perf-stat.csv", SAMPLE, &opts);
+        let started = Local
+            .with_ymd_and_hms(2023, 11, 14, 22, 13, 20)
+            .earliest()
+            .unwrap()
+            .to_utc();
+        let ms = Duration::milliseconds;
+        let host = ("hostname", "worker-0");
+        assert_eq!(
+            points(&docs, "counter", &[host, ("event", "cycles")]).unwrap(),
+            [
+                (started, started + ms(999), 2e9),
+                (started + ms(1000), started + ms(1999), 3e9),
+            ]
+        );
+        let ipc: Vec<f64> = points(
+            &docs,
+            "counter-metric",
+            &[host, ("metric", "insn per cycle")],
+        )
+        .unwrap()
+        .iter()
+        .map(|(_, _, value)| *value)
+        .collect();
+        assert_eq!(ipc, [0.5, 0.5]);
+        assert!(points(&docs, "counter", &[host, ("event", "branch-misses")]).is_none());
+    }
+
+    #[test]
+    fn lines_name_the_aggregate_of_the_per_options() {
+        let per_cpu = parse_line("1.000000000,CPU3,1000,,cycles,1001049632,100.00,,").unwrap();
+        assert_eq!((per_cpu.aggregate, per_cpu.event), ("CPU3", "cycles"));
+        assert_eq!(per_cpu.value, Some(1000.0));
+        let per_socket = parse_line("1.000000000,S0,8,4000,,cycles,1001049632,100.00,,").unwrap();
+        assert_eq!((per_socket.aggregate, per_socket.event), ("S0", "cycles"));
+        assert_eq!(per_socket.value, Some(4000.0));
+        let not_counted = parse_line("1.000000000,<not counted>,,cycles,0,0.00,,").unwrap();
+        assert_eq!(not_counted.value, None);
+    }
+}
@@ -0,0 +1,282 @@
+use super::{Adapter, AdapterError, attached_documents, metric_node, point};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local, NaiveDateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The output of `sadf -j`, the sar data of a host
+pub struct Sar;
+
+#[derive(Deserialize)]
+struct SadfOutput {
+    sysstat: Sysstat,
+}
+
+#[derive(Deserialize)]
+struct Sysstat {
+    hosts: Vec<Host>,
+}
+
+#[derive(Deserialize)]
+struct Host {
+    nodename: String,
+    #[serde(default)]
+    statistics: Vec<Map<String, Value>>,
+}
+
+#[derive(Deserialize)]
+struct Timestamp {
+    date: String,
+    time: String,
+    /// Whether the time is UTC rather than local
+    #[serde(default)]
+    utc: i64,
+    /// Seconds since the last statistics
+    interval: i64,
+}
+
+/// The statistics sar groups by device, the field naming the device, and
+/// the breakout name it becomes
+const DEVICE_GROUPS: [(&str, &str, &str); 3] = [
+    ("cpu-load", "cpu", "cpu"),
+    ("disk", "disk-device", "device"),
+    ("net-dev", "iface", "device"),
+];
+
+/// Rates sum across devices, the rest are averaged
+fn class_and_unit(group: &str, field: &str) -> (&'static str, Option<&'static str>) {
+    match (group, field) {
+        ("memory", field) if !field.ends_with("percent") => ("count", Some("KiB")),
+        ("disk" | "net-dev", field) if field.ends_with("kB") => ("throughput", Some("KiB/s")),
+        ("disk", "tps") | ("net-dev", "rxpck" | "txpck") => ("throughput", None),
+        _ => ("count", None),
+    }
+}
+
+impl Sar {
+    fn timestamp(path: &Path, statistics: &Map<String, Value>) -> Result<(DateTime<Utc>, i64)> {
+        let perr = |e: String| AdapterError::ParseFailed(path.display().to_string(), e);
+        let timestamp: Timestamp = statistics
+            .get("timestamp")
+            .cloned()
+            .ok_or_else(|| perr("statistics without a timestamp".to_string()))
+            .and_then(|t| serde_json::from_value(t).map_err(|e| perr(e.to_string())))?;
+        let naive = NaiveDateTime::parse_from_str(
+            &format!("{} {}", timestamp.date, timestamp.time),
+            "%Y-%m-%d %H:%M:%S",
+        )
+        .map_err(|e| perr(e.to_string()))?;
+        let at = if timestamp.utc == 1 {
+            naive.and_utc()
+        } else {
+            naive
+                .and_local_timezone(Local)
+                .earliest()
+                .ok_or_else(|| perr(format!("no local time {}", naive)))?
+                .to_utc()
+        };
+        Ok((at, timestamp.interval))
+    }
+}
+
+impl Adapter for Sar {
+    fn name(&self) -> &'static str {
+        "sar"
+    }
+
+    fn description(&self) -> &'static str {
+        "The sar data of a host from sadf -j, added to an existing run's period"
+    }
+
+    /// Every CPU, memory, disk and network statistic becomes a series named
+    /// by its group and field, ex: cpu-load-iowait, broken out by hostname
+    /// and the CPU or device
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let contents = super::read(path)?;
+        let output: SadfOutput = serde_json::from_str(&contents)
+            .map_err(|e| AdapterError::ParseFailed(path.display().to_string(), e.to_string()))?;
+
+        // The series by group, field, hostname and device
+        let mut series: BTreeMap<(String, String, String, String), Vec<Point>> = BTreeMap::new();
+        for host in &output.sysstat.hosts {
+            for statistics in &host.statistics {
+                let (finish, interval) = Sar::timestamp(path, statistics)?;
+                let begin = finish - Duration::seconds(interval);
+                let finish = finish - Duration::milliseconds(1);
+                let mut push = |group: &str, device: &str, stats: &Map<String, Value>| {
+                    for (field, value) in stats {
+                        if let Some(value) = value.as_f64() {
+                            series
+                                .entry((
+                                    group.to_string(),
+                                    field.clone(),
+                                    host.nodename.clone(),
+                                    device.to_string(),
+                                ))
+                                .or_default()
+                                .push(point(begin, finish, value));
+                        }
+                    }
+                };
+                if let Some(Value::Object(memory)) = statistics.get("memory") {
+                    push("memory", "", memory);
+                }
+                let network = statistics.get("network").and_then(Value::as_object);
+                for (group, device_field, _) in DEVICE_GROUPS {
+                    let devices = statistics
+                        .get(group)
+                        .or_else(|| network.and_then(|network| network.get(group)));
+                    let Some(Value::Array(devices)) = devices else {
+                        continue;
+                    };
+                    for device in devices.iter().filter_map(Value::as_object) {
+                        let name = device
+                            .get(device_field)
+                            .map(super::param_value)
+                            .unwrap_or_default();
+                        push(group, &name, device);
+                    }
+                }
+            }
+        }
+
+        let metrics = series
+            .into_iter()
+            .map(|((group, field, hostname, device), data)| {
+                let (class, unit) = class_and_unit(&group, &field);
+                let device_name = DEVICE_GROUPS
+                    .iter()
+                    .find(|(device_group, _, _)| *device_group == group)
+                    .map(|(_, _, name)| *name);
+                let mut names = vec![("hostname", hostname.as_str())];
+                if let Some(device_name) = device_name {
+                    names.push((device_name, device.as_str()));
+                }
+                metric_node(
+                    class,
+                    &format!("{}-{}", group, field),
+                    "sar",
+                    unit,
+                    &names,
+                    data,
+                )
+            })
+            .collect();
+        Ok(attached_documents("sar", opts, metrics)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, parse_sample, points, sample_path};
+    use uuid::Uuid;
+
+    /// Two ten second intervals of a host's CPU, memory, disk and network
+    const SAMPLE: &str = r#"{"sysstat": {
+  "hosts": [
+    {
+      "nodename": "worker-0",
+      "sysname": "Linux",
+      "release": "6.8.0",
+      "machine": "x86_64",
+      "number-of-cpus": 2,
+      "file-date": "2024-01-02",
+      "file-utc-time": "03:04:00",
+      "timezone": "UTC",
+      "statistics": [
+        {
+          "timestamp": {"date": "2024-01-02", "time": "03:04:10", "utc": 1, "interval": 10},
+          "cpu-load": [
+            {"cpu": "all", "user": 12.50, "nice": 0.00, "system": 3.25, "iowait": 0.50, "steal": 0.00, "idle": 83.75}
+          ],
+          "memory": {"memfree": 1024000, "avail": 2048000, "memused-percent": 60.25},
+          "disk": [
+            {"disk-device": "sda", "tps": 12.00, "rkB": 100.00, "wkB": 200.00, "util-percent": 1.50}
+          ],
+          "network": {
+            "net-dev": [
+              {"iface": "eth0", "rxpck": 10.00, "txpck": 20.00, "rxkB": 1.50, "txkB": 2.50}
+            ]
+          }
+        },
+        {
+          "timestamp": {"date": "2024-01-02", "time": "03:04:20", "utc": 1, "interval": 10},
+          "cpu-load": [
+            {"cpu": "all", "user": 14.00, "nice": 0.00, "system": 3.00, "iowait": 0.25, "steal": 0.00, "idle": 82.75}
+          ],
+          "memory": {"memfree": 1000000, "avail": 2000000, "memused-percent": 61.00},
+          "disk": [
+            {"disk-device": "sda", "tps": 8.00, "rkB": 50.00, "wkB": 150.00, "util-percent": 1.00}
+          ],
+          "network": {
+            "net-dev": [
+              {"iface": "eth0", "rxpck": 11.00, "txpck": 21.00, "rxkB": 1.75, "txkB": 2.75}
+            ]
+          }
+        }
+      ]
+    }
+  ]
+}}"#;
+
+    #[test]
+    fn statistics_are_broken_out_by_host_and_device() {
+        let opts = AdapterOpts {
+            run_uuid: Some(Uuid::new_v4()),
+            period_uuid: Some(Uuid::new_v4()),
+            ..AdapterOpts::default()
+        };
+        let docs = parse_sample(&Sar, "sar.json", SAMPLE, &opts);
+        let host = ("hostname", "worker-0");
+        assert_eq!(
+            points(&docs, "cpu-load-user", &[host, ("cpu", "all")]).unwrap(),
+            [
+                (
+                    at("2024-01-02T03:04:00Z"),
+                    at("2024-01-02T03:04:09.999Z"),
+                    12.50
+                ),
+                (
+                    at("2024-01-02T03:04:10Z"),
+                    at("2024-01-02T03:04:19.999Z"),
+                    14.00
+                ),
+            ]
+        );
+        let values = |metric_type, names: &[(&str, &str)]| -> Vec<f64> {
+            points(&docs, metric_type, names)
+                .unwrap()
+                .iter()
+                .map(|(_, _, value)| *value)
+                .collect()
+        };
+        assert_eq!(values("memory-memfree", &[host]), [1024000.0, 1000000.0]);
+        assert_eq!(
+            values("disk-wkB", &[host, ("device", "sda")]),
+            [200.0, 150.0]
+        );
+        assert_eq!(
+            values("net-dev-rxpck", &[host, ("device", "eth0")]),
+            [10.0, 11.0]
+        );
+        assert!(points(&docs, "disk-disk-device", &[host, ("device", "sda")]).is_none());
+    }
+
+    #[test]
+    fn refuses_without_a_period() {
+        let path = sample_path("sar-no-period.json");
+        std::fs::write(&path, SAMPLE).unwrap();
+        let err = Sar.parse(&path, &AdapterOpts::default()).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(AdapterError::NoPeriod("sar"))
+        ));
+    }
+}
@@ -0,0 +1,272 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, point, run_node};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The text output of a sysbench cpu or OLTP run. It has no timestamps, so
+/// the run is taken to have ended when the file was last written
+pub struct Sysbench;
+
+/// A series by metric type and stat
+type Series = BTreeMap<(&'static str, String), Vec<Point>>;
+
+/// The number after the label, ex: "tps: 1234.56" is 1234.56 for "tps:"
+fn value_after(line: &str, label: &str) -> Option<f64> {
+    let rest = &line[line.find(label)? + label.len()..];
+    let number: String = rest
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    number.parse().ok()
+}
+
+/// The value of a summary line, ex: "    avg:    27.77" or
+/// "    transactions:    17283  (287.99 per sec.)"
+fn summary_value(line: &str) -> Option<(&str, f64)> {
+    let (label, rest) = line.trim().split_once(':')?;
+    let rest = rest.trim();
+    let value = match rest.split_once('(') {
+        Some((_, per_sec)) => per_sec.split_whitespace().next()?,
+        None => rest.split_whitespace().next()?.trim_end_matches('s'),
+    };
+    Some((label, value.parse().ok()?))
+}
+
+/// The interval lines sysbench prints with --report-interval, ex:
+/// "[ 1s ] thds: 8 tps: 287.10 qps: 5742.06 (r/w/o: ...) lat (ms,95%): 40.37 err/s: 0.00"
+fn interval(line: &str, started: DateTime<Utc>, last: &mut f64, series: &mut Series) {
+    let Some(secs) = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("s ]"))
+        .and_then(|(secs, _)| secs.trim().parse::<f64>().ok())
+    else {
+        return;
+    };
+    let begin = started + Duration::milliseconds((*last * 1000.0) as i64);
+    let end = started + Duration::milliseconds((secs * 1000.0) as i64 - 1);
+    *last = secs;
+    let mut push = |metric_type, stat: &str, value: Option<f64>| {
+        if let Some(value) = value {
+            series
+                .entry((metric_type, stat.to_string()))
+                .or_default()
+                .push(point(begin, end, value));
+        }
+    };
+    push("tps", "", value_after(line, "tps:"));
+    push("qps", "", value_after(line, "qps:"));
+    push("events-sec", "", value_after(line, "eps:"));
+    push("errors-sec", "", value_after(line, "err/s:"));
+    if let Some(at) = line.find("lat (ms,") {
+        let percentile = line[at + 8..].split('%').next().unwrap_or_default();
+        push(
+            "latency",
+            &format!("p{}", percentile),
+            value_after(line, "%):"),
+        );
+    }
+}
+
+impl Adapter for Sysbench {
+    fn name(&self) -> &'static str {
+        "sysbench"
+    }
+
+    fn description(&self) -> &'static str {
+        "The text output of sysbench cpu and OLTP runs"
+    }
+
+    /// The TPS, QPS, events and latency of each --report-interval, and the
+    /// summary's latency stats and thread fairness, which is what sysbench
+    /// reports per thread. The summary rates fill in when there are no
+    /// intervals
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let contents = super::read(path)?;
+        let perr = |e: String| AdapterError::ParseFailed(path.display().to_string(), e);
+        let finish: DateTime<Utc> = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| perr(e.to_string()))?
+            .into();
+        let total_time = contents
+            .lines()
+            .find(|line| line.trim_start().starts_with("total time:"))
+            .and_then(summary_value)
+            .map(|(_, secs)| secs)
+            .ok_or_else(|| perr("no total time, is it sysbench's output?".to_string()))?;
+        let begin = finish - Duration::milliseconds((total_time * 1000.0) as i64);
+
+        let mut params = HashMap::new();
+        let mut series = Series::new();
+        let mut last = 0.0;
+        let mut section = "";
+        let mut summary: Vec<(&'static str, String, f64)> = vec![];
+        for line in contents.lines() {
+            if let Some(threads) = line.strip_prefix("Number of threads:") {
+                params.insert("threads".to_string(), threads.trim().to_string());
+            }
+            if line.starts_with('[') {
+                interval(line, begin, &mut last, &mut series);
+                continue;
+            }
+            if !line.starts_with(' ') {
+                section = line.trim_end_matches(':');
+                continue;
+            }
+            // The fairness is "avg/stddev"
+            if section == "Threads fairness"
+                && let Some((label, values)) = line.trim().split_once(':')
+                && let Some((avg, stddev)) = values.trim().split_once('/')
+            {
+                let metric_type = if label.starts_with("events") {
+                    "thread-events"
+                } else {
+                    "thread-execution-time"
+                };
+                for (stat, value) in [("avg", avg), ("stddev", stddev)] {
+                    if let Ok(value) = value.trim().parse() {
+                        summary.push((metric_type, stat.to_string(), value));
+                    }
+                }
+                continue;
+            }
+            let Some((label, value)) = summary_value(line) else {
+                continue;
+            };
+            match (section, label) {
+                ("SQL statistics", "transactions") => summary.push(("tps", String::new(), value)),
+                ("SQL statistics", "queries") => summary.push(("qps", String::new(), value)),
+                ("CPU speed", "events per second") => {
+                    summary.push(("events-sec", String::new(), value))
+                }
+                ("Latency (ms)", "min" | "avg" | "max") => {
+                    summary.push(("latency", label.to_string(), value))
+                }
+                ("Latency (ms)", percentile) if percentile.ends_with("th percentile") => {
+                    let percentile = percentile.trim_end_matches("th percentile");
+                    summary.push(("latency", format!("p{}", percentile), value))
+                }
+                _ => {}
+            }
+        }
+        for (metric_type, stat, value) in summary {
+            series
+                .entry((metric_type, stat))
+                .or_insert_with(|| vec![point(begin, finish, value)]);
+        }
+
+        let primary_metric = if series.keys().any(|(metric_type, _)| *metric_type == "tps") {
+            "tps"
+        } else {
+            "events-sec"
+        };
+        let metrics = series
+            .into_iter()
+            .map(|((metric_type, stat), data)| {
+                let (class, unit) = match metric_type {
+                    "tps" | "qps" | "events-sec" | "errors-sec" => ("throughput", None),
+                    "latency" => ("count", Some("ms")),
+                    "thread-execution-time" => ("count", Some("s")),
+                    _ => ("count", None),
+                };
+                let names: &[(&str, &str)] = if stat.is_empty() {
+                    &[]
+                } else {
+                    &[("stat", &stat)]
+                };
+                metric_node(class, metric_type, "sysbench", unit, names, data)
+            })
+            .collect();
+        let iteration = iteration_node(1, "pass", primary_metric, params, begin, finish, metrics);
+
+        let run = run_node(opts, "sysbench", begin, finish, vec![iteration])?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{parse_sample, periods, points, value};
+
+    /// An OLTP run with --report-interval=1, which ran for two seconds
+    const SAMPLE: &str = "\
+sysbench 1.0.20 (using system LuaJIT 2.1.0-beta3)
+
+Running the test with following options:
+Number of threads: 8
+Report intermediate results every 1 second(s)
+Initializing random number generator from current time
+
+
+Initializing worker threads...
+
+Threads started!
+
+[ 1s ] thds: 8 tps: 287.10 qps: 5742.06 (r/w/o: 4019.44/1148.41/574.21) lat (ms,95%): 40.37 err/s: 0.00 reconn/s: 0.00
+[ 2s ] thds: 8 tps: 290.00 qps: 5800.00 (r/w/o: 4060.00/1160.00/580.00) lat (ms,95%): 39.65 err/s: 0.00 reconn/s: 0.00
+SQL statistics:
+    queries performed:
+        read:                            8078
+        write:                           2308
+        other:                           1154
+        total:                           11540
+    transactions:                        577    (288.40 per sec.)
+    queries:                             11540  (5768.00 per sec.)
+    ignored errors:                      0      (0.00 per sec.)
+    reconnects:                          0      (0.00 per sec.)
+
+General statistics:
+    total time:                          2.0006s
+    total number of events:              577
+
+Latency (ms):
+         min:                                   10.12
+         avg:                                   27.70
+         max:                                   80.33
+         95th percentile:                       40.37
+         sum:                                15983.12
+
+Threads fairness:
+    events (avg/stddev):           72.1250/1.45
+    execution time (avg/stddev):   1.9979/0.00
+";
+
+    #[test]
+    fn intervals_come_before_the_summary() {
+        let docs = parse_sample(&Sysbench, "sysbench.txt", SAMPLE, &AdapterOpts::default());
+        let [(begin, finish)] = periods(&docs)[..] else {
+            panic!("not one period");
+        };
+        assert_eq!(finish - begin, Duration::milliseconds(2000));
+
+        let tps = points(&docs, "tps", &[]).unwrap();
+        assert_eq!(
+            tps,
+            [
+                (begin, begin + Duration::milliseconds(999), 287.10),
+                (
+                    begin + Duration::milliseconds(1000),
+                    begin + Duration::milliseconds(1999),
+                    290.00
+                ),
+            ]
+        );
+        let p95: Vec<f64> = points(&docs, "latency", &[("stat", "p95")])
+            .unwrap()
+            .iter()
+            .map(|(_, _, value)| *value)
+            .collect();
+        assert_eq!(p95, [40.37, 39.65]);
+        assert_eq!(value(&docs, "latency", &[("stat", "avg")]), 27.70);
+        assert_eq!(value(&docs, "thread-events", &[("stat", "avg")]), 72.125);
+        assert_eq!(
+            value(&docs, "thread-execution-time", &[("stat", "stddev")]),
+            0.0
+        );
+    }
+}
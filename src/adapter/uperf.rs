@@ -0,0 +1,172 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, point, run_node};
+use crate::add::Point;
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The output of `uperf -R -i <secs>`. uperf has no JSON or XML output for
+/// its results, the XML is the profile, so this reads the raw statistics
+/// lines crucible reads, like
+/// `timestamp_ms:1700000001000.1234 name:Txn2 nr_bytes:1136914432 nr_ops:17349`
+pub struct Uperf;
+
+/// The running totals of a transaction or flowop at a point in time
+struct Sample {
+    at: DateTime<Utc>,
+    bytes: f64,
+    ops: f64,
+}
+
+fn parse_line(line: &str) -> Option<(String, Sample)> {
+    let fields: HashMap<&str, &str> = line
+        .split_whitespace()
+        .filter_map(|field| field.split_once(':'))
+        .collect();
+    let millis: f64 = fields.get("timestamp_ms")?.parse().ok()?;
+    let sample = Sample {
+        at: DateTime::from_timestamp_millis(millis as i64)?,
+        bytes: fields.get("nr_bytes")?.parse().ok()?,
+        ops: fields.get("nr_ops")?.parse().ok()?,
+    };
+    Some((fields.get("name")?.to_string(), sample))
+}
+
+impl Adapter for Uperf {
+    fn name(&self) -> &'static str {
+        "uperf"
+    }
+
+    fn description(&self) -> &'static str {
+        "The raw statistics uperf prints with -R -i"
+    }
+
+    /// The throughput and op rate between each statistics line, broken out
+    /// by transaction or flowop name. The params are left to --param since
+    /// the output doesn't have them
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let contents = super::read(path)?;
+        let mut totals: BTreeMap<String, Vec<Sample>> = BTreeMap::new();
+        for (name, sample) in contents.lines().filter_map(parse_line) {
+            // The sum of the others, which breakouts would count twice
+            if name == "Total" {
+                continue;
+            }
+            totals.entry(name).or_default().push(sample);
+        }
+        let (Some(begin), Some(finish)) = (
+            totals.values().flatten().map(|sample| sample.at).min(),
+            totals.values().flatten().map(|sample| sample.at).max(),
+        ) else {
+            return Err(AdapterError::ParseFailed(
+                path.display().to_string(),
+                "no timestamp_ms statistics lines, was uperf run with -R -i?".to_string(),
+            )
+            .into());
+        };
+
+        let mut metrics = vec![];
+        for (name, samples) in &totals {
+            let mut throughput: Vec<Point> = vec![];
+            let mut ops: Vec<Point> = vec![];
+            for pair in samples.windows(2) {
+                let secs = (pair[1].at - pair[0].at).num_milliseconds() as f64 / 1000.0;
+                if secs <= 0.0 {
+                    continue;
+                }
+                let end = pair[1].at - Duration::milliseconds(1);
+                let bytes = pair[1].bytes - pair[0].bytes;
+                throughput.push(point(pair[0].at, end, bytes * 8.0 / secs));
+                ops.push(point(pair[0].at, end, (pair[1].ops - pair[0].ops) / secs));
+            }
+            if throughput.is_empty() {
+                continue;
+            }
+            let names = [("name", name.as_str())];
+            metrics.push(metric_node(
+                "throughput",
+                "throughput",
+                "uperf",
+                Some("bps"),
+                &names,
+                throughput,
+            ));
+            metrics.push(metric_node(
+                "throughput",
+                "ops-sec",
+                "uperf",
+                None,
+                &names,
+                ops,
+            ));
+        }
+        let status = if contents.contains("Errors detected") {
+            "fail"
+        } else {
+            "pass"
+        };
+        let iteration = iteration_node(
+            1,
+            status,
+            "throughput",
+            HashMap::new(),
+            begin,
+            finish,
+            metrics,
+        );
+
+        let run = run_node(opts, "uperf", begin, finish, vec![iteration])?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, parse_sample, periods, points};
+
+    const SAMPLE: &str = "\
+Starting 1 threads running profile:tcp_stream ...   0.00 seconds
+timestamp_ms:1700000000000.1234 name:Txn2 nr_bytes:0 nr_ops:0
+timestamp_ms:1700000000000.1234 name:Total nr_bytes:0 nr_ops:0
+timestamp_ms:1700000001000.1234 name:Txn2 nr_bytes:125000000 nr_ops:1000
+timestamp_ms:1700000001000.1234 name:Total nr_bytes:125000000 nr_ops:1000
+timestamp_ms:1700000002000.1234 name:Txn2 nr_bytes:375000000 nr_ops:3000
+timestamp_ms:1700000002000.1234 name:Total nr_bytes:375000000 nr_ops:3000
+Txn2                        375.00MB /   2.00(s) =     1.50Gb/s        1500op/s
+";
+
+    #[test]
+    fn rates_are_between_statistics_lines() {
+        let docs = parse_sample(&Uperf, "uperf.txt", SAMPLE, &AdapterOpts::default());
+        let names = [("name", "Txn2")];
+        assert_eq!(
+            points(&docs, "throughput", &names).unwrap(),
+            [
+                (
+                    at("2023-11-14T22:13:20Z"),
+                    at("2023-11-14T22:13:20.999Z"),
+                    1e9
+                ),
+                (
+                    at("2023-11-14T22:13:21Z"),
+                    at("2023-11-14T22:13:21.999Z"),
+                    2e9
+                ),
+            ]
+        );
+        let ops: Vec<f64> = points(&docs, "ops-sec", &names)
+            .unwrap()
+            .iter()
+            .map(|(_, _, value)| *value)
+            .collect();
+        assert_eq!(ops, [1000.0, 2000.0]);
+        assert!(points(&docs, "throughput", &[("name", "Total")]).is_none());
+        assert_eq!(
+            periods(&docs),
+            [(at("2023-11-14T22:13:20Z"), at("2023-11-14T22:13:22Z"))]
+        );
+    }
+}
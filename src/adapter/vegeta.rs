@@ -0,0 +1,292 @@
+use super::{Adapter, AdapterError, iteration_node, metric_node, point, run_node};
+use crate::add::{MetricNode, Point};
+use crate::args::AdapterOpts;
+use crate::parser::BodyJson;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// vegeta's attack results encoded as ndjson with `vegeta encode --to json`,
+/// or the summary of `vegeta report -type=json`
+pub struct Vegeta;
+
+#[derive(Deserialize)]
+struct Hit {
+    #[serde(default)]
+    attack: String,
+    code: u16,
+    timestamp: DateTime<Utc>,
+    /// Nanoseconds
+    latency: i64,
+    #[serde(default)]
+    bytes_in: i64,
+}
+
+#[derive(Deserialize)]
+struct Report {
+    latencies: HashMap<String, i64>,
+    earliest: DateTime<Utc>,
+    end: DateTime<Utc>,
+    rate: f64,
+    throughput: f64,
+    success: f64,
+    #[serde(default)]
+    status_codes: HashMap<String, f64>,
+}
+
+/// The report's latencies, by their stat names
+const REPORT_LATENCIES: [(&str, &str); 7] = [
+    ("min", "min"),
+    ("mean", "mean"),
+    ("50th", "p50"),
+    ("90th", "p90"),
+    ("95th", "p95"),
+    ("99th", "p99"),
+    ("max", "max"),
+];
+
+fn millis(nanos: f64) -> f64 {
+    nanos / 1_000_000.0
+}
+
+/// A point per second of each attack's request rate, mean latency, received
+/// throughput and the rate of each status code, where 0 is a failed request
+fn hit_metrics(hits: Vec<Hit>) -> Vec<MetricNode> {
+    // Hits by attack and second, then the series by metric type, attack and code
+    let mut seconds: BTreeMap<(String, i64), Vec<Hit>> = BTreeMap::new();
+    for hit in hits {
+        seconds
+            .entry((hit.attack.clone(), hit.timestamp.timestamp()))
+            .or_default()
+            .push(hit);
+    }
+    let mut series: BTreeMap<(&str, String, String), Vec<Point>> = BTreeMap::new();
+    for ((attack, secs), hits) in seconds {
+        let begin = DateTime::from_timestamp(secs, 0).unwrap_or_default();
+        let end = begin + Duration::milliseconds(999);
+        let mut push = |metric_type, code: String, value| {
+            series
+                .entry((metric_type, attack.clone(), code))
+                .or_default()
+                .push(point(begin, end, value));
+        };
+        let requests = hits.len() as f64;
+        let latency: i64 = hits.iter().map(|hit| hit.latency).sum();
+        let bytes: i64 = hits.iter().map(|hit| hit.bytes_in).sum();
+        push("requests-sec", String::new(), requests);
+        push("latency", String::new(), millis(latency as f64) / requests);
+        push("throughput", String::new(), bytes as f64 * 8.0);
+        let mut codes: BTreeMap<u16, f64> = BTreeMap::new();
+        for hit in &hits {
+            *codes.entry(hit.code).or_default() += 1.0;
+        }
+        for (code, count) in codes {
+            push("status-code", code.to_string(), count);
+        }
+    }
+    series
+        .into_iter()
+        .map(|((metric_type, attack, code), data)| {
+            let (class, unit) = match metric_type {
+                "latency" => ("count", Some("ms")),
+                "throughput" => ("throughput", Some("bps")),
+                _ => ("throughput", None),
+            };
+            let mut names = vec![];
+            if !attack.is_empty() {
+                names.push(("attack", attack.as_str()));
+            }
+            if !code.is_empty() {
+                names.push(("code", code.as_str()));
+            }
+            metric_node(class, metric_type, "vegeta", unit, &names, data)
+        })
+        .collect()
+}
+
+/// The report as a single point of each of its stats over the attack
+fn report_metrics(report: &Report, begin: DateTime<Utc>, finish: DateTime<Utc>) -> Vec<MetricNode> {
+    let single = |value| vec![point(begin, finish, value)];
+    let mut metrics = vec![];
+    for (field, stat) in REPORT_LATENCIES {
+        if let Some(nanos) = report.latencies.get(field) {
+            metrics.push(metric_node(
+                "count",
+                "latency",
+                "vegeta",
+                Some("ms"),
+                &[("stat", stat)],
+                single(millis(*nanos as f64)),
+            ));
+        }
+    }
+    metrics.push(metric_node(
+        "throughput",
+        "requests-sec",
+        "vegeta",
+        None,
+        &[],
+        single(report.rate),
+    ));
+    metrics.push(metric_node(
+        "throughput",
+        "successes-sec",
+        "vegeta",
+        None,
+        &[],
+        single(report.throughput),
+    ));
+    metrics.push(metric_node(
+        "count",
+        "success-ratio",
+        "vegeta",
+        None,
+        &[],
+        single(report.success),
+    ));
+    let secs = (finish - begin).num_milliseconds() as f64 / 1000.0;
+    for (code, count) in &report.status_codes {
+        metrics.push(metric_node(
+            "throughput",
+            "status-code",
+            "vegeta",
+            None,
+            &[("code", code)],
+            single(count / secs),
+        ));
+    }
+    metrics
+}
+
+impl Adapter for Vegeta {
+    fn name(&self) -> &'static str {
+        "vegeta"
+    }
+
+    fn description(&self) -> &'static str {
+        "vegeta attack results from vegeta encode --to json, or vegeta report -type=json"
+    }
+
+    /// Results become series of each second, broken out by attack and status
+    /// code, a report becomes the latency stats and rates of the attack
+    fn parse(&self, path: &Path, opts: &AdapterOpts) -> Result<Vec<BodyJson>> {
+        let contents = super::read(path)?;
+        let perr = |e: String| AdapterError::ParseFailed(path.display().to_string(), e);
+
+        let (begin, finish, metrics) = if let Ok(report) = serde_json::from_str::<Report>(&contents)
+        {
+            let finish = report.end.max(report.earliest + Duration::milliseconds(1));
+            let metrics = report_metrics(&report, report.earliest, finish);
+            (report.earliest, finish, metrics)
+        } else {
+            let hits = contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str::<Hit>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| perr(e.to_string()))?;
+            let (Some(begin), Some(last)) = (
+                hits.iter().map(|hit| hit.timestamp).min(),
+                hits.iter()
+                    .map(|hit| hit.timestamp + Duration::nanoseconds(hit.latency))
+                    .max(),
+            ) else {
+                return Err(perr("no results".to_string()).into());
+            };
+            let finish = last.max(begin + Duration::milliseconds(1));
+            (begin, finish, hit_metrics(hits))
+        };
+        let iteration = iteration_node(
+            1,
+            "pass",
+            "requests-sec",
+            HashMap::new(),
+            begin,
+            finish,
+            metrics,
+        );
+
+        let run = run_node(opts, "vegeta", begin, finish, vec![iteration])?;
+        Ok(super::documents(vec![run]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::tests::{at, parse_sample, periods, points, value};
+
+    const RESULTS: &str = r#"{"attack":"load","seq":0,"code":200,"timestamp":"2023-11-14T22:13:20.1Z","latency":2000000,"bytes_out":0,"bytes_in":512,"error":"","body":null,"method":"GET","url":"http://app/","headers":{"Content-Type":["text/html"]}}
+{"attack":"load","seq":1,"code":500,"timestamp":"2023-11-14T22:13:20.6Z","latency":4000000,"bytes_out":0,"bytes_in":100,"error":"500 Internal Server Error","body":null,"method":"GET","url":"http://app/","headers":{}}
+{"attack":"load","seq":2,"code":200,"timestamp":"2023-11-14T22:13:21.1Z","latency":3000000,"bytes_out":0,"bytes_in":512,"error":"","body":null,"method":"GET","url":"http://app/","headers":{}}
+"#;
+
+    const REPORT: &str = r#"{
+  "latencies": {"total": 9000000, "mean": 3000000, "50th": 3000000, "90th": 4000000, "95th": 4000000, "99th": 4000000, "max": 4000000, "min": 2000000},
+  "bytes_in": {"total": 1124, "mean": 374.67},
+  "bytes_out": {"total": 0, "mean": 0},
+  "earliest": "2023-11-14T22:13:20.1Z",
+  "latest": "2023-11-14T22:13:21.1Z",
+  "end": "2023-11-14T22:13:21.1Z",
+  "duration": 1000000000,
+  "wait": 3000000,
+  "requests": 3,
+  "rate": 3,
+  "throughput": 2,
+  "success": 0.6666666666666666,
+  "status_codes": {"200": 2, "500": 1},
+  "errors": ["500 Internal Server Error"]
+}"#;
+
+    #[test]
+    fn results_are_summed_per_second() {
+        let docs = parse_sample(&Vegeta, "vegeta.json", RESULTS, &AdapterOpts::default());
+        let attack = ("attack", "load");
+        assert_eq!(
+            points(&docs, "requests-sec", &[attack]).unwrap(),
+            [
+                (
+                    at("2023-11-14T22:13:20Z"),
+                    at("2023-11-14T22:13:20.999Z"),
+                    2.0
+                ),
+                (
+                    at("2023-11-14T22:13:21Z"),
+                    at("2023-11-14T22:13:21.999Z"),
+                    1.0
+                ),
+            ]
+        );
+        let first =
+            |metric_type, names: &[(&str, &str)]| points(&docs, metric_type, names).unwrap()[0].2;
+        assert_eq!(first("latency", &[attack]), 3.0);
+        assert_eq!(first("throughput", &[attack]), 4896.0);
+        assert_eq!(first("status-code", &[attack, ("code", "200")]), 1.0);
+        assert_eq!(value(&docs, "status-code", &[attack, ("code", "500")]), 1.0);
+        assert_eq!(
+            periods(&docs),
+            [(at("2023-11-14T22:13:20.1Z"), at("2023-11-14T22:13:21.103Z"))]
+        );
+    }
+
+    #[test]
+    fn a_report_is_one_point_of_each_stat() {
+        let docs = parse_sample(
+            &Vegeta,
+            "vegeta-report.json",
+            REPORT,
+            &AdapterOpts::default(),
+        );
+        assert_eq!(value(&docs, "latency", &[("stat", "p50")]), 3.0);
+        assert_eq!(value(&docs, "latency", &[("stat", "max")]), 4.0);
+        assert_eq!(value(&docs, "requests-sec", &[]), 3.0);
+        assert_eq!(value(&docs, "successes-sec", &[]), 2.0);
+        assert_eq!(value(&docs, "status-code", &[("code", "200")]), 2.0);
+        assert_eq!(
+            periods(&docs),
+            [(at("2023-11-14T22:13:20.1Z"), at("2023-11-14T22:13:21.1Z"))]
+        );
+    }
+}
@@ -1,21 +1,26 @@
+use crate::cdm;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Deserializer, Serialize, de};
-use serde_json::Value;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use serde_json::{Value, json};
 use sqlx::PgPool;
 use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
+use tracing::instrument;
 use uuid::Uuid;
 
+use crate::args::StatusMode;
 use crate::parser::{
-    BodyJson, CDMSpecJson, IterationFKJson, IterationJson, IterationSpecJson, MetricDataJson,
-    MetricDataSpecJson, MetricDescFKJson, MetricDescJson, MetricDescSpecJson, PeriodFKJson,
-    PeriodJson, PeriodSpecJson, RunFKJson, RunJson, RunSpecJson, SampleFKJson, SampleJson,
-    SampleSpecJson, TagJson, TagSpecJson, date_time_utc_from_str, insert_records,
+    BodyJson, CDMSpecJson, IngestOpts, IterationFKJson, IterationJson, IterationSpecJson,
+    MetricDataJson, MetricDataSpecJson, MetricDescFKJson, MetricDescJson, MetricDescSpecJson,
+    ParamJson, ParamSpecJson, PeriodFKJson, PeriodJson, PeriodSpecJson, RunFKJson, RunJson,
+    RunSpecJson, SampleFKJson, SampleJson, SampleSpecJson, TagJson, TagSpecJson,
+    date_time_utc_from_str, document_statuses, ingest, normalize_statuses,
 };
+use crate::report;
 
 #[derive(Error, Debug)]
 pub enum AddError {
@@ -62,7 +67,7 @@ where
     for p in s {
         let new_point = Point {
             begin: DateTime::from_timestamp_millis(
-                *p.get(0)
+                *p.first()
                     .ok_or(AddError::PointParseFailed(format!("{:?}", p)))
                     .map_err(de::Error::custom)? as i64,
             )
@@ -85,9 +90,23 @@ where
     Ok(points)
 }
 
+/// Writes the points the way `point_from_array` reads them
+pub fn point_to_array<S>(points: &[Point], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(points.iter().map(|p| {
+        (
+            p.begin.timestamp_millis(),
+            p.finish.timestamp_millis(),
+            p.value,
+        )
+    }))
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RunNode {
-    #[serde(default = "Uuid::new_v4", rename = "run-uuid")]
+    #[serde(default, rename = "run-uuid")]
     pub run_uuid: Uuid,
     #[serde(deserialize_with = "date_time_utc_from_str")]
     pub begin: DateTime<Utc>,
@@ -100,6 +119,9 @@ pub struct RunNode {
     pub source: String,
     pub tags: HashMap<String, String>,
     pub iterations: Vec<IterationNode>,
+    /// Metrics of the whole run, rather than a period of it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metrics: Vec<MetricNode>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -118,7 +140,7 @@ fn default_period() -> String {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IterationNode {
-    #[serde(default = "Uuid::new_v4", rename = "iteration-uuid")]
+    #[serde(default, rename = "iteration-uuid")]
     pub iteration_uuid: Uuid,
     pub num: i64,
     pub status: String,
@@ -133,7 +155,7 @@ pub struct IterationNode {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SampleNode {
-    #[serde(default = "Uuid::new_v4", rename = "sample-uuid")]
+    #[serde(default, rename = "sample-uuid")]
     pub sample_uuid: Uuid,
     pub num: i64,
     pub status: String,
@@ -143,7 +165,7 @@ pub struct SampleNode {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeriodNode {
-    #[serde(default = "Uuid::new_v4", rename = "period-uuid")]
+    #[serde(default, rename = "period-uuid")]
     pub period_uuid: Uuid,
     #[serde(deserialize_with = "date_time_utc_from_str")]
     pub begin: DateTime<Utc>,
@@ -155,14 +177,19 @@ pub struct PeriodNode {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MetricNode {
-    #[serde(default = "Uuid::new_v4", rename = "metric-desc-uuid")]
+    #[serde(default, rename = "metric-desc-uuid")]
     pub metric_desc_uuid: Uuid,
     pub class: String,
     #[serde(rename = "metric-type")]
     pub metric_type: String,
     pub source: String,
+    #[serde(default)]
+    pub unit: Option<String>,
     pub names: HashMap<String, String>,
-    #[serde(deserialize_with = "point_from_array")]
+    #[serde(
+        deserialize_with = "point_from_array",
+        serialize_with = "point_to_array"
+    )]
     pub data: Vec<Point>,
 }
 
@@ -173,7 +200,81 @@ pub struct Point {
     pub value: f64,
 }
 
-fn run_to_body_jsons(run_node: RunNode) -> Vec<BodyJson> {
+/// The documents of a metric and its data, in the period of a run or, without
+/// one, the run as a whole
+pub fn metric_to_body_jsons(
+    run_uuid: Uuid,
+    iteration_uuid: Option<Uuid>,
+    sample_uuid: Option<Uuid>,
+    period_uuid: Option<Uuid>,
+    metric: MetricNode,
+) -> Vec<BodyJson> {
+    let cdm_spec = CDMSpecJson {
+        ver: "v8dev".to_string(),
+    };
+    let mut bodies = vec![BodyJson::MetricDesc(MetricDescJson {
+        cdm: cdm_spec.clone(),
+        iteration: iteration_uuid.map(|iteration_uuid| IterationFKJson { iteration_uuid }),
+        run: RunFKJson { run_uuid },
+        metric_desc: MetricDescSpecJson {
+            metric_desc_uuid: metric.metric_desc_uuid,
+            class: metric.class,
+            metric_type: metric.metric_type,
+            source: metric.source,
+            unit: metric.unit,
+            names_list: metric.names.keys().cloned().collect(),
+            names: metric
+                .names
+                .iter()
+                .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                .collect(),
+        },
+        period: period_uuid.map(|period_uuid| PeriodFKJson { period_uuid }),
+        sample: sample_uuid.map(|sample_uuid| SampleFKJson { sample_uuid }),
+    })];
+    for point in metric.data {
+        bodies.push(BodyJson::MetricData(MetricDataJson {
+            cdm: cdm_spec.clone(),
+            metric_data: MetricDataSpecJson {
+                begin: point.begin,
+                end: point.finish,
+                duration: (point.finish - point.begin).num_milliseconds(),
+                value: point.value,
+            },
+            metric_desc: MetricDescFKJson {
+                metric_desc_uuid: metric.metric_desc_uuid,
+            },
+            run: RunFKJson { run_uuid },
+        }));
+    }
+    bodies
+}
+
+impl RunNode {
+    /// Makes up the UUIDs of the run and its resources that the results
+    /// left out
+    pub fn make_up_uuids(&mut self, uuid_v7: bool) {
+        cdm::make_up_uuid(&mut self.run_uuid, uuid_v7);
+        for iteration in &mut self.iterations {
+            cdm::make_up_uuid(&mut iteration.iteration_uuid, uuid_v7);
+            for sample in &mut iteration.samples {
+                cdm::make_up_uuid(&mut sample.sample_uuid, uuid_v7);
+                for period in &mut sample.periods {
+                    cdm::make_up_uuid(&mut period.period_uuid, uuid_v7);
+                    for metric in &mut period.metrics {
+                        cdm::make_up_uuid(&mut metric.metric_desc_uuid, uuid_v7);
+                    }
+                }
+            }
+        }
+        for metric in &mut self.metrics {
+            cdm::make_up_uuid(&mut metric.metric_desc_uuid, uuid_v7);
+        }
+    }
+}
+
+/// The documents of the run, whose UUIDs have to be made up already
+pub fn run_to_body_jsons(run_node: RunNode) -> Vec<BodyJson> {
     let mut bodies: Vec<BodyJson> = Vec::new();
     let cdm_spec = CDMSpecJson {
         ver: "v8dev".to_string(),
@@ -190,6 +291,7 @@ fn run_to_body_jsons(run_node: RunNode) -> Vec<BodyJson> {
             name: run_node.name,
             description: run_node.description,
             source: run_node.source,
+            owner: None,
         },
     });
     bodies.push(run);
@@ -222,6 +324,19 @@ fn run_to_body_jsons(run_node: RunNode) -> Vec<BodyJson> {
         });
         bodies.push(iteration_json);
 
+        for (arg, val) in iteration.params {
+            bodies.push(BodyJson::Param(ParamJson {
+                cdm: cdm_spec.clone(),
+                param: ParamSpecJson { arg, val },
+                iteration: IterationFKJson {
+                    iteration_uuid: iteration.iteration_uuid,
+                },
+                run: RunFKJson {
+                    run_uuid: run_node.run_uuid,
+                },
+            }));
+        }
+
         for sample in iteration.samples {
             let sample_json = BodyJson::Sample(SampleJson {
                 cdm: cdm_spec.clone(),
@@ -262,62 +377,34 @@ fn run_to_body_jsons(run_node: RunNode) -> Vec<BodyJson> {
                 bodies.push(period_json);
 
                 for metric in period.metrics {
-                    let metric_desc_json = BodyJson::MetricDesc(MetricDescJson {
-                        cdm: cdm_spec.clone(),
-                        iteration: Some(IterationFKJson {
-                            iteration_uuid: iteration.iteration_uuid,
-                        }),
-                        run: RunFKJson {
-                            run_uuid: run_node.run_uuid,
-                        },
-                        metric_desc: MetricDescSpecJson {
-                            metric_desc_uuid: metric.metric_desc_uuid,
-                            class: metric.class,
-                            metric_type: metric.metric_type,
-                            source: metric.source,
-                            names_list: metric.names.keys().cloned().collect(),
-                            names: metric
-                                .names
-                                .iter()
-                                .map(|(k, v)| (k.clone(), Value::String(v.clone())))
-                                .collect(),
-                        },
-                        period: Some(PeriodFKJson {
-                            period_uuid: period.period_uuid,
-                        }),
-                        sample: Some(SampleFKJson {
-                            sample_uuid: sample.sample_uuid,
-                        }),
-                    });
-                    bodies.push(metric_desc_json);
-
-                    for point in metric.data {
-                        let metric_data_json = BodyJson::MetricData(MetricDataJson {
-                            cdm: cdm_spec.clone(),
-                            metric_data: MetricDataSpecJson {
-                                begin: point.begin,
-                                end: point.finish,
-                                duration: (point.finish - point.begin).num_milliseconds(),
-                                value: point.value,
-                            },
-                            metric_desc: MetricDescFKJson {
-                                metric_desc_uuid: metric.metric_desc_uuid,
-                            },
-                            run: RunFKJson {
-                                run_uuid: run_node.run_uuid,
-                            },
-                        });
-                        bodies.push(metric_data_json);
-                    }
+                    bodies.extend(metric_to_body_jsons(
+                        run_node.run_uuid,
+                        Some(iteration.iteration_uuid),
+                        Some(sample.sample_uuid),
+                        Some(period.period_uuid),
+                        metric,
+                    ));
                 }
             }
         }
     }
 
+    for metric in run_node.metrics {
+        bodies.extend(metric_to_body_jsons(
+            run_node.run_uuid,
+            None,
+            None,
+            None,
+            metric,
+        ));
+    }
+
     bodies
 }
 
-pub async fn add(pool: &PgPool, path: &Path) -> Result<()> {
+/// Reads the documents out of a JSON results file, or every one in the directory
+#[instrument(skip_all, fields(path = %path.display()))]
+pub fn read_run_nodes(path: &Path, uuid_v7: bool) -> Result<Vec<BodyJson>> {
     let json_paths: Vec<PathBuf> = match fs::read_dir(path) {
         Ok(files) => {
             let paths = files
@@ -351,17 +438,49 @@ pub async fn add(pool: &PgPool, path: &Path) -> Result<()> {
                 e.to_string(),
             )
         })?;
-        records.extend(run_node.into_iter().map(run_to_body_jsons).flatten());
+        records.extend(run_node.into_iter().flat_map(|mut run_node| {
+            run_node.make_up_uuids(uuid_v7);
+            run_to_body_jsons(run_node)
+        }));
     }
 
-    // Ingest the documents in one transaction
-    let mut txn = pool.begin().await?;
+    Ok(records)
+}
+
+/// Reads the documents out of a message holding a run, or an array of them
+pub fn parse_run_nodes(
+    source: &str,
+    bytes: &[u8],
+    uuid_v7: bool,
+) -> Result<Vec<BodyJson>, AddError> {
+    let perr = |e: serde_json::Error| AddError::JSONParseFailed(source.to_string(), e.to_string());
+    let run_nodes: Vec<RunNode> = match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'[') => serde_json::from_slice(bytes).map_err(perr)?,
+        _ => vec![serde_json::from_slice(bytes).map_err(perr)?],
+    };
+    Ok(run_nodes
+        .into_iter()
+        .flat_map(|mut run_node| {
+            run_node.make_up_uuids(uuid_v7);
+            run_to_body_jsons(run_node)
+        })
+        .collect())
+}
 
-    let total_records = insert_records(&mut txn, &records).await?;
+pub async fn add(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    path: &Path,
+    status_mode: StatusMode,
+    opts: &IngestOpts,
+) -> Result<()> {
+    let mut records = read_run_nodes(path, opts.uuid_v7)?;
+    normalize_statuses(document_statuses(&mut records), status_mode)?;
 
-    txn.commit().await?;
+    let filters = json!({ "path": path });
+    let total_records = ingest(pool, replica, &records, "add", &filters, opts).await?;
 
-    println!("added {} rows", total_records);
+    report!("added {} rows", total_records);
 
     Ok(())
 }
@@ -0,0 +1,66 @@
+use crate::args::AgentArgs;
+use crate::parser::{document_statuses, normalize_statuses};
+use crate::{backup, report, validate};
+use anyhow::Result;
+use reqwest::header;
+use serde::Deserialize;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AgentError {
+    #[error("Couldn't push {0} to {1}, {2}")]
+    PushFailed(String, String, String),
+}
+
+/// The server's reply to a push
+#[derive(Deserialize)]
+struct Pushed {
+    added: u64,
+}
+
+/// The host the results come from, recorded in the server's audit log
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("unknown".to_string())
+}
+
+/// Parses the results here and pushes them as CDM ndjson to `scdm serve`,
+/// which writes them to the database. The UUIDs the results leave out are
+/// made up here, as UUIDv7s when `uuid_v7` is set
+pub async fn agent(args: AgentArgs, uuid_v7: bool) -> Result<()> {
+    let url = format!("{}/api/v1/ingest", args.push_to.trim_end_matches('/'));
+    let client = reqwest::Client::new();
+    let agent = hostname();
+    for path in &args.path {
+        let mut records = validate::read_results(Path::new(path), uuid_v7)?;
+        normalize_statuses(document_statuses(&mut records), args.status.mode())?;
+        let mut body = vec![];
+        for record in &records {
+            backup::write_document(&mut body, record)?;
+        }
+
+        let perr = |e: String| AgentError::PushFailed(path.clone(), url.clone(), e);
+        let mut request = client
+            .post(&url)
+            .header(header::CONTENT_TYPE, "application/x-ndjson")
+            .header("X-Scdm-Agent", &agent)
+            .body(body);
+        if let Some(token) = &args.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.map_err(|e| perr(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(perr(format!("{} {}", status.as_u16(), body.trim())).into());
+        }
+        let pushed: Pushed = response.json().await.map_err(|e| perr(e.to_string()))?;
+        report!("pushed {}, the server added {} rows", path, pushed.added);
+    }
+    Ok(())
+}
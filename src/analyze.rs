@@ -0,0 +1,196 @@
+use crate::args::{
+    Aggregator, AnalyzeArgs, AnalyzeCommand, AnomaliesArgs, DisplayOpts, MetricArgs,
+};
+use crate::metric::{self, MetricQuery};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AnalyzeError {
+    #[error("Couldn't analyze the metrics, {0}")]
+    AnalyzeFailed(String),
+}
+
+/// The begin, finish and aggregated value of a window
+type Window = (String, String, f64);
+
+/// A run of consecutive anomalous windows of one series
+struct Anomaly {
+    begin: String,
+    finish: String,
+    windows: usize,
+    baseline_mean: f64,
+    sum: f64,
+    max_zscore: f64,
+}
+
+/// Flags every window whose value is more than `threshold` standard
+/// deviations away from the mean of the `baseline` windows before it, and
+/// merges consecutive flagged windows.
+fn detect(windows: &[Window], baseline: usize, threshold: f64) -> Vec<Anomaly> {
+    let mut anomalies: Vec<Anomaly> = Vec::new();
+    let mut current: Option<Anomaly> = None;
+    for (i, (begin, finish, value)) in windows.iter().enumerate() {
+        let zscore = if i < baseline.max(2) {
+            None
+        } else {
+            let previous: Vec<f64> = windows[i - baseline.max(2)..i]
+                .iter()
+                .map(|w| w.2)
+                .collect();
+            let n = previous.len() as f64;
+            let mean = previous.iter().sum::<f64>() / n;
+            let stddev =
+                (previous.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+            let zscore = if stddev > 0.0 {
+                (value - mean) / stddev
+            } else if *value == mean {
+                0.0
+            } else {
+                f64::INFINITY.copysign(value - mean)
+            };
+            Some((mean, zscore))
+        };
+        match (zscore, current.as_mut()) {
+            (Some((_, zscore)), Some(anomaly)) if zscore.abs() >= threshold => {
+                anomaly.finish = finish.clone();
+                anomaly.windows += 1;
+                anomaly.sum += value;
+                anomaly.max_zscore = anomaly.max_zscore.max(zscore.abs());
+            }
+            (Some((mean, zscore)), None) if zscore.abs() >= threshold => {
+                current = Some(Anomaly {
+                    begin: begin.clone(),
+                    finish: finish.clone(),
+                    windows: 1,
+                    baseline_mean: mean,
+                    sum: *value,
+                    max_zscore: zscore.abs(),
+                });
+            }
+            _ => anomalies.extend(current.take()),
+        }
+    }
+    anomalies.extend(current.take());
+    anomalies
+}
+
+async fn anomalies(pool: &PgPool, args: AnomaliesArgs) -> Result<()> {
+    if matches!(args.aggregator, Aggregator::None) {
+        return Err(AnalyzeError::AnalyzeFailed(
+            "anomalies need an aggregator other than none".to_string(),
+        )
+        .into());
+    }
+
+    let raw_query: &str = r#"
+        SELECT MIN(metric_data.begin), MAX(metric_data.finish)
+        FROM metric_data
+        LEFT JOIN metric_desc
+            ON metric_desc.metric_desc_uuid = metric_data.metric_desc_uuid
+        LEFT JOIN period
+            ON period.period_uuid = metric_desc.period_uuid
+        LEFT JOIN sample
+            ON sample.sample_uuid = period.sample_uuid
+        LEFT JOIN iteration
+            ON iteration.iteration_uuid = sample.iteration_uuid
+        JOIN run
+            ON run.run_uuid = iteration.run_uuid
+        WHERE
+            iteration.run_uuid = $1 AND
+            metric_desc.metric_type = $2 AND
+            run.deleted_at IS NULL
+    "#;
+    let (begin, finish): (Option<DateTime<Utc>>, Option<DateTime<Utc>>) = sqlx::query_as(raw_query)
+        .bind(args.run_uuid)
+        .bind(&args.metric_type)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AnalyzeError::AnalyzeFailed(format!("{}", e)))?;
+    let (Some(begin), Some(finish)) = (begin, finish) else {
+        return Err(AnalyzeError::AnalyzeFailed(format!(
+            "no {} data found for run {}",
+            args.metric_type, args.run_uuid
+        ))
+        .into());
+    };
+
+    let mut metric_args = MetricArgs {
+        run_uuid: Some(args.run_uuid),
+        metric_type: Some(args.metric_type.clone()),
+        begin: Some(begin),
+        finish: Some(finish),
+        resolution: args.resolution,
+        name: args.name.clone(),
+        breakout: args.breakout.clone(),
+        aggregator: args.aggregator.clone(),
+        ..Default::default()
+    };
+    let MetricQuery { mut qb, .. } = metric::build_metric_query(pool, &mut metric_args).await?;
+    let (header, rows) = metric::fetch_rows(pool, &mut qb, &DisplayOpts::default()).await?;
+
+    // Everything before the window identifies the series, except the run
+    let window_idx =
+        header
+            .iter()
+            .position(|c| c == "window_begin")
+            .ok_or(AnalyzeError::AnalyzeFailed(
+                "the metric query has no windows".to_string(),
+            ))?;
+    // Rows come ordered by window within each series
+    let mut series: Vec<(Vec<String>, Vec<Window>)> = Vec::new();
+    for row in rows {
+        let key = row[1..window_idx].to_vec();
+        let Some(value) = row.last().and_then(|v| v.parse::<f64>().ok()) else {
+            continue;
+        };
+        let window = (row[window_idx].clone(), row[window_idx + 1].clone(), value);
+        match series.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, windows)) => windows.push(window),
+            None => series.push((key, vec![window])),
+        }
+    }
+
+    let mut out_header = header[1..window_idx].to_vec();
+    out_header.extend(
+        [
+            "begin",
+            "finish",
+            "windows",
+            "baseline_mean",
+            "mean",
+            "max_zscore",
+        ]
+        .iter()
+        .map(|c| c.to_string()),
+    );
+    let mut out_rows: Vec<Vec<String>> = Vec::new();
+    for (key, windows) in series {
+        for anomaly in detect(&windows, args.baseline, args.threshold) {
+            let mut row = key.clone();
+            row.extend([
+                anomaly.begin,
+                anomaly.finish,
+                anomaly.windows.to_string(),
+                anomaly.baseline_mean.to_string(),
+                (anomaly.sum / anomaly.windows as f64).to_string(),
+                anomaly.max_zscore.to_string(),
+            ]);
+            out_rows.push(row);
+        }
+    }
+
+    println!(
+        "{}",
+        metric::format_rows(out_header, out_rows, args.output, &DisplayOpts::default())?
+    );
+    Ok(())
+}
+
+pub async fn analyze(pool: &PgPool, analyze_args: AnalyzeArgs) -> Result<()> {
+    match analyze_args.command {
+        AnalyzeCommand::Anomalies(args) => anomalies(pool, args).await,
+    }
+}
@@ -0,0 +1,74 @@
+use crate::args::AnonymizeOpts;
+use crate::parser::BodyJson;
+use regex::Regex;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// FQDNs and IPv4 addresses
+pub const DEFAULT_HOSTNAME_PATTERN: &str =
+    r"^(([A-Za-z0-9-]+\.)+[A-Za-z]{2,}|\d{1,3}(\.\d{1,3}){3})$";
+
+#[derive(Error, Debug)]
+pub enum AnonymizeError {
+    #[error("Invalid hostname pattern {0}, {1}")]
+    InvalidPattern(String, String),
+}
+
+/// Replaces the identifying details in documents before they're shared
+pub struct Anonymizer {
+    // The same value hashes the same within one export, so runs can still be
+    // grouped by user or host, but can't be looked up against known values
+    salt: Uuid,
+    hostname: Regex,
+}
+
+impl Anonymizer {
+    /// An anonymizer, if --anonymize was given
+    pub fn from_opts(opts: &AnonymizeOpts) -> Result<Option<Self>, AnonymizeError> {
+        if !opts.anonymize {
+            return Ok(None);
+        }
+        let pattern = opts
+            .hostname_pattern
+            .as_deref()
+            .unwrap_or(DEFAULT_HOSTNAME_PATTERN);
+        let hostname = Regex::new(pattern)
+            .map_err(|e| AnonymizeError::InvalidPattern(pattern.to_string(), format!("{}", e)))?;
+        Ok(Some(Anonymizer {
+            salt: Uuid::new_v4(),
+            hostname,
+        }))
+    }
+
+    fn hash(&self, kind: &str, value: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(value.as_bytes());
+        format!("{}-{:.12x}", kind, hasher.finalize())
+    }
+
+    pub fn anonymize(&self, document: &mut BodyJson) {
+        match document {
+            BodyJson::Run(run) => {
+                run.run.email = format!("{}@example.invalid", self.hash("user", &run.run.email));
+                run.run.name = self.hash("user", &run.run.name);
+                if let Some(owner) = &mut run.run.owner {
+                    *owner = format!("{}@example.invalid", self.hash("user", owner));
+                }
+                run.run.description = None;
+            }
+            BodyJson::MetricDesc(metric_desc) => {
+                for (name, val) in metric_desc.metric_desc.names.iter_mut() {
+                    // Crucible names the hosts it ran on with keys like hostname
+                    let is_host = |v: &&str| name.contains("host") || self.hostname.is_match(v);
+                    if let Some(host) = val.as_str().filter(is_host) {
+                        *val = Value::String(self.hash("host", host));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::builder::{BoolishValueParser, PossibleValuesParser};
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use serde::Serialize;
 use uuid::Uuid;
 
 use crate::SCDMError;
+use crate::adapter;
+use crate::render::{self, Highlight, JsonFilter, RenderError, Timezone};
 
 /// SCDM: Structured Common Data Model -
 /// A tool to index and query performance metrics that come from Crucible runs.
@@ -13,36 +18,173 @@ pub struct App {
     #[clap(flatten)]
     pub global_opts: GlobalOpts,
 
+    /// Only optional with --show-config
     #[clap(subcommand)]
-    pub command: Command,
+    pub command: Option<Command>,
 }
 
-#[derive(Debug, Args)]
+/// Flags take precedence over the Env variables, which take precedence over
+/// the config file's profile
+#[derive(Debug, Clone, Args)]
 pub struct GlobalOpts {
-    /// The DB_USER Env variable takes precedence
-    #[clap(long = "db-user", short = 'u')]
+    /// A postgres:// connection URL, used in place of the other DB options
+    #[clap(long = "db-uri", env = "DATABASE_URL")]
+    pub db_uri: Option<String>,
+
+    #[clap(long = "db-user", env = "DB_USER", short = 'u')]
     pub db_user: Option<String>,
 
-    /// The DB_PASSWORD Env variable takes precedence
-    #[clap(long = "db-password", short = 'p')]
+    /// When no password is given, it's asked for
+    #[clap(
+        long = "db-password",
+        env = "DB_PASSWORD",
+        hide_env_values = true,
+        short = 'p'
+    )]
     pub db_password: Option<String>,
 
-    /// The DB_URL Env variable takes precedence
-    #[clap(long = "db-url")]
+    /// A file holding the password, like a mounted Kubernetes secret.
+    /// A password given directly is used instead
+    #[clap(long = "db-password-file", env = "DB_PASSWORD_FILE")]
+    pub db_password_file: Option<String>,
+
+    /// Take the password from the OS keyring, and keep the one that's
+    /// asked for there
+    #[clap(long = "use-keyring", action)]
+    pub use_keyring: bool,
+
+    #[clap(long = "db-url", env = "DB_URL")]
     pub db_url: Option<String>,
 
-    /// The DB_PORT Env variable takes precedence
-    #[clap(long = "db-port")]
+    /// The directory holding the Postgres socket, ex: /var/run/postgresql.
+    /// A --db-url starting with / is taken as one too
+    #[clap(long = "db-socket", env = "DB_SOCKET")]
+    pub db_socket: Option<String>,
+
+    /// [default: 5432]
+    #[clap(long = "db-port", env = "DB_PORT")]
     pub db_port: Option<String>,
 
-    /// The DB_NAME Env variable takes precedence
-    #[clap(long = "db-name", default_value = "scdm")]
+    /// The most connections kept open to the database at once [default: 10]
+    #[clap(long = "db-max-connections", env = "DB_MAX_CONNECTIONS")]
+    pub db_max_connections: Option<u32>,
+
+    /// How long to wait for a free connection, ex: 30s [default: 30s]
+    #[clap(long = "db-acquire-timeout", env = "DB_ACQUIRE_TIMEOUT")]
+    pub db_acquire_timeout: Option<String>,
+
+    /// How long an unused connection is kept open, ex: 10m [default: 10m]
+    #[clap(long = "db-idle-timeout", env = "DB_IDLE_TIMEOUT")]
+    pub db_idle_timeout: Option<String>,
+
+    /// [default: scdm]
+    #[clap(long = "db-name", env = "DB_NAME")]
     pub db_name: Option<String>,
+
+    /// The Postgres schema holding the scdm tables, instead of public
+    #[clap(long = "db-schema", env = "DB_SCHEMA")]
+    pub db_schema: Option<String>,
+
+    /// An isolated workspace, kept in its own scdm_<workspace> schema
+    #[clap(long = "workspace", short = 'w', conflicts_with = "db_schema")]
+    pub workspace: Option<String>,
+
+    /// Cancel any statement that runs longer than this, ex: 5m
+    #[clap(long = "statement-timeout", env = "DB_STATEMENT_TIMEOUT")]
+    pub statement_timeout: Option<String>,
+
+    /// A postgres:// URL of a second database that every ingest also
+    /// writes to, including the pushes serve takes, ex: a long term archive
+    #[clap(long = "replica-uri", env = "REPLICA_DATABASE_URL")]
+    pub replica_uri: Option<String>,
+
+    /// The OpenSearch instance to import from [default: http://localhost:9200]
+    #[clap(long = "opensearch-url", env = "OPENSEARCH_URL")]
+    pub opensearch_url: Option<String>,
+
+    #[clap(long = "opensearch-user", env = "OPENSEARCH_USER")]
+    pub opensearch_user: Option<String>,
+
+    #[clap(
+        long = "opensearch-password",
+        env = "OPENSEARCH_PASSWORD",
+        hide_env_values = true
+    )]
+    pub opensearch_password: Option<String>,
+
+    /// A file holding the OpenSearch password. A password given directly
+    /// is used instead
+    #[clap(long = "opensearch-password-file", env = "OPENSEARCH_PASSWORD_FILE")]
+    pub opensearch_password_file: Option<String>,
+
+    /// Only allow the commands that read the data, in read-only sessions
+    #[clap(long = "read-only", action)]
+    pub read_only: bool,
+
+    /// The profile in config.toml to take settings from, instead of the
+    /// default one
+    #[clap(long = "profile", env = "SCDM_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Log more, -v for the time each step takes and -vv for everything.
+    /// The RUST_LOG Env variable replaces the filter these set
+    #[clap(long = "verbose", short = 'v', action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only print the results asked for, and errors
+    #[clap(long = "quiet", short = 'q', action, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log every statement sent to the database, along with how long it took
+    #[clap(long = "log-sql", action)]
+    pub log_sql: bool,
+
+    #[clap(value_enum, long = "log-format", default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Generate time ordered UUIDv7s for the resources scdm makes up
+    #[clap(long = "uuid-v7", env = "UUID_V7", action = ArgAction::SetTrue, value_parser = BoolishValueParser::new())]
+    pub uuid_v7: bool,
+
+    /// How many connections an ingest that creates runs inserts over at
+    /// once. 1, the default, inserts everything in a single transaction,
+    /// more can leave part of a run behind if the ingest is interrupted
+    #[clap(long = "ingest-jobs", env = "INGEST_JOBS", default_value_t = 1)]
+    pub ingest_jobs: usize,
+
+    /// Most rows each INSERT of an ingest sends, by default as many as fit
+    /// under Postgres's limit on bind parameters
+    #[clap(long = "batch-size", env = "BATCH_SIZE", value_parser = clap::value_parser!(u32).range(1..))]
+    pub batch_size: Option<u32>,
+
+    /// Who you are, and who the ingested runs belong to instead of whoever
+    /// their email says. Once set, deleting or restoring someone else's runs
+    /// is refused. Without it runs belong to their email and anyone can
+    /// change them
+    #[clap(long = "owner", env = "SCDM_OWNER")]
+    pub owner: Option<String>,
+
+    /// Delete and restore runs whoever owns them
+    #[clap(long = "admin", action)]
+    pub admin: bool,
+
+    /// Print the settings in effect and where each came from, then exit
+    #[clap(long = "show-config", action)]
+    pub show_config: bool,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line
+    JSON,
 }
 
 #[derive(Debug, Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
-    /// Parse the results of a crucible iteration and import into DB
+    /// Parse the results of a crucible iteration, or of another tool with
+    /// --format, and import into DB
     Parse(ParseArgs),
     /// Add the results from the JSON file
     Add(AddArgs),
@@ -51,26 +193,920 @@ pub enum Command {
     /// Import run from OpenSearch CDM DB
     Import(ImportArgs),
     /// Init the SCDM tables if they don't exist
-    Init,
+    Init(InitArgs),
+    /// Materialize downsampled rollups of the metric data
+    Rollup(RollupArgs),
+    /// Refresh the materialized summary views
+    Refresh(RefreshArgs),
+    /// Analyze the metric data
+    Analyze(AnalyzeArgs),
+    /// Delete old runs and maintain the metric_data partitions
+    Prune(PruneArgs),
+    /// Report the size of the tables and how the data is spread across runs
+    Stats(StatsArgs),
+    /// Vacuum, analyze and optionally reindex the tables
+    Maintain(MaintainArgs),
+    /// Check the database and its schema for problems
+    Doctor(DoctorArgs),
+    /// Attach files to runs and get them back
+    Artifact(ArtifactArgs),
+    /// Record how one run relates to another
+    Link(LinkArgs),
+    /// Bring back soft deleted runs, or load the runs in a backup
+    Restore(RestoreArgs),
+    /// Permanently delete the soft deleted runs
+    Purge(PurgeArgs),
+    /// Review the log of commands that changed the data
+    Audit(AuditArgs),
+    /// Write runs to a compressed archive of CDM ndjson
+    Backup(BackupArgs),
+    /// Write runs out as CDM ndjson that `scdm parse` and Crucible can read,
+    /// or as the JSON `scdm add` reads
+    Export(ExportArgs),
+    /// Remove duplicated data left by ingesting the same results twice
+    Dedupe(DedupeArgs),
+    /// Set how long `scdm prune` keeps raw data and rollups
+    Retention(RetentionArgs),
+    /// Set how long `scdm prune` keeps the runs of each benchmark
+    Policy(PolicyArgs),
+    /// Check results for problems without ingesting them
+    Validate(ValidateArgs),
+    /// Convert JSON results or a backup into CDM ndjson
+    Convert(ConvertArgs),
+    /// Print the shell completion script
+    Completions(CompletionsArgs),
+    /// Write man pages for scdm and each of its commands
+    Mangen(MangenArgs),
+    /// Serve the queries over an HTTP API
+    Serve(ServeArgs),
+    /// Send the runs' aggregated metrics to an OpenTelemetry collector
+    Otlp(OtlpArgs),
+    /// Run query commands interactively over one connection
+    Repl(ReplArgs),
+    /// Write a self-contained HTML report of a run
+    Report(ReportArgs),
+    /// Compare the primary metrics of a run with its baseline
+    Compare(CompareArgs),
+    /// Summarize which iterations and samples of runs passed, failing if any
+    /// didn't
+    Status(StatusArgs),
+    /// Keep the database in sync with the OpenSearch instance
+    Sync(SyncArgs),
+    /// Ingest the results published to a NATS subject
+    Nats(NatsArgs),
+    /// Measure ingest throughput and query latency against the database
+    Bench(BenchArgs),
+    /// Push results to `scdm serve`, which writes them to the database
+    Agent(AgentArgs),
+}
+
+impl Command {
+    /// Whether the command runs without a database connection
+    pub fn is_offline(&self) -> bool {
+        matches!(
+            self,
+            Command::Validate(_)
+                | Command::Convert(_)
+                | Command::Completions(_)
+                | Command::Mangen(_)
+                | Command::Agent(_)
+        )
+    }
+
+    /// Whether the command leaves the data alone, so it can run with --read-only
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Command::Query(QueryArgs { command }) => !matches!(command, QueryCommand::Delete(_)),
+            Command::Artifact(ArtifactArgs { command }) => {
+                !matches!(command, ArtifactCommand::Add(_))
+            }
+            Command::Retention(RetentionArgs { command }) => {
+                matches!(command, RetentionCommand::Show)
+            }
+            Command::Policy(PolicyArgs { command }) => matches!(command, PolicyCommand::List(_)),
+            Command::Analyze(_)
+            | Command::Stats(_)
+            | Command::Doctor(_)
+            | Command::Audit(_)
+            | Command::Backup(_)
+            | Command::Export(_)
+            | Command::Otlp(_)
+            | Command::Report(_)
+            | Command::Compare(_)
+            | Command::Status(_)
+            // The session is read only too, so Postgres refuses its deletes
+            | Command::Repl(_) => true,
+            // Without a token the server refuses deletes
+            Command::Serve(ServeArgs { token, .. }) => token.is_none(),
+            command => command.is_offline(),
+        }
+    }
+
+    /// The display options of the commands that have them
+    pub fn display_mut(&mut self) -> Option<&mut DisplayOpts> {
+        match self {
+            Command::Query(QueryArgs {
+                command: QueryCommand::Get(get),
+            }) => Some(&mut get.get_options.display),
+            Command::Query(QueryArgs {
+                command: QueryCommand::Metric(metric),
+            }) => Some(&mut metric.display),
+            _ => None,
+        }
+    }
+
+    /// The result limit option of the commands that have one
+    pub fn limit_mut(&mut self) -> Option<&mut Option<usize>> {
+        match self {
+            Command::Query(QueryArgs {
+                command: QueryCommand::Get(get),
+            }) => Some(&mut get.get_options.limit),
+            _ => None,
+        }
+    }
+
+    /// The output format option of the commands that have one
+    pub fn output_mut(&mut self) -> Option<&mut Option<OutputFormat>> {
+        match self {
+            Command::Query(QueryArgs {
+                command: QueryCommand::Get(get),
+            }) => Some(&mut get.get_options.output),
+            Command::Query(QueryArgs {
+                command: QueryCommand::Metric(metric),
+            }) => match &mut metric.command {
+                Some(MetricCommand::Diff(diff)) => Some(&mut diff.output),
+                None => Some(&mut metric.output),
+            },
+            Command::Analyze(AnalyzeArgs {
+                command: AnalyzeCommand::Anomalies(anomalies),
+            }) => Some(&mut anomalies.output),
+            Command::Stats(stats) => Some(&mut stats.output),
+            Command::Status(status) => Some(&mut status.output),
+            Command::Artifact(ArtifactArgs {
+                command: ArtifactCommand::List(list),
+            }) => Some(&mut list.output),
+            Command::Audit(AuditArgs {
+                command: AuditCommand::List(list),
+            }) => Some(&mut list.output),
+            Command::Policy(PolicyArgs {
+                command: PolicyCommand::List(list),
+            }) => Some(&mut list.output),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct InitArgs {
+    /// Apply the schema changes the database is missing
+    #[clap(long = "migrate", action, conflicts_with_all = ["drop", "truncate"])]
+    pub migrate: bool,
+    /// Drop every scdm table and recreate them empty
+    #[clap(long = "drop", action, conflicts_with = "truncate")]
+    pub drop: bool,
+    /// Delete all of the data, but keep the schema
+    #[clap(long = "truncate", action)]
+    pub truncate: bool,
+    /// Partition metric_data by month
+    #[clap(long = "partition", action, conflicts_with = "timescale")]
+    pub partition: bool,
+    /// Make metric_data a compressed TimescaleDB hypertable, when the
+    /// extension is available
+    #[clap(long = "timescale", action)]
+    pub timescale: bool,
+    /// How old hypertable chunks get before they're compressed
+    #[clap(long = "compress-after", value_parser = parse_interval, default_value = "7d", requires = "timescale")]
+    pub compress_after: i64,
+    /// Don't ask for confirmation before dropping or truncating
+    #[clap(long = "yes", short = 'y', action)]
+    pub yes: bool,
+    /// Also create the materialized summary views (refreshed with `scdm refresh`)
+    #[clap(long = "views", action)]
+    pub views: bool,
+    /// Create the scdm_reader and scdm_writer roles and grant them access to
+    /// the tables, for handing out to dashboards and analysts
+    #[clap(long = "create-roles", action)]
+    pub create_roles: bool,
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct PruneArgs {
+    /// Delete the runs that finished longer ago than this, ex: 30d, 12h.
+    /// Runs a retention policy covers are kept as long as it says instead
+    #[clap(long = "older-than", value_parser = parse_interval)]
+    pub older_than: Option<i64>,
+    /// How many months ahead of the current one to create partitions for
+    #[clap(long = "months-ahead", default_value_t = 1)]
+    pub months_ahead: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct RefreshArgs {
+    /// Refresh without locking out readers of the views
+    #[clap(long = "concurrently", action)]
+    pub concurrently: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RetentionArgs {
+    #[clap(subcommand)]
+    pub command: RetentionCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RetentionCommand {
+    /// Replace the retention policy
+    Set(RetentionSetArgs),
+    /// Show the retention policy
+    Show,
+    /// Remove the retention policy, keeping all data until it's pruned
+    Clear,
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct RetentionSetArgs {
+    /// Keep the raw metric_data of runs that finished within this, ex: 14d
+    #[clap(long = "raw", value_parser = parse_interval)]
+    pub raw: i64,
+    /// Keep the 1 minute rollups of runs that finished within this, ex: 180d.
+    /// Older runs only keep their iteration summaries
+    #[clap(long = "rollup", value_parser = parse_interval)]
+    pub rollup: i64,
+}
+
+#[derive(Debug, Args)]
+pub struct PolicyArgs {
+    #[clap(subcommand)]
+    pub command: PolicyCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PolicyCommand {
+    /// Set how long the runs a policy covers are kept, replacing its keep
+    Set(PolicySetArgs),
+    /// List the retention policies
+    List(PolicyListArgs),
+    /// Remove a retention policy
+    Unset(PolicyScopeArgs),
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct PolicyScopeArgs {
+    /// The benchmark whose runs the policy covers, every benchmark by default
+    #[clap(long = "benchmark", short = 'b')]
+    pub benchmark: Option<String>,
+    /// Only cover the runs tagged "tag_name=tag_value", overriding the
+    /// policies without a tag
+    #[clap(long = "tag", short = 't', value_parser = parse_tag)]
+    pub tag: Option<(String, String)>,
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct PolicySetArgs {
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub scope: PolicyScopeArgs,
+    /// Prune the runs that finished longer ago than this, ex: 30d
+    #[clap(long = "keep", value_parser = parse_interval, required_unless_present = "forever")]
+    pub keep: Option<i64>,
+    /// Never prune the runs
+    #[clap(long = "forever", action, conflicts_with = "keep")]
+    pub forever: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PolicyListArgs {
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct DedupeArgs {
+    #[clap(subcommand)]
+    pub command: DedupeCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DedupeCommand {
+    /// Remove metric_data rows with the same metric_desc, begin, finish and value
+    MetricData(DedupeMetricDataArgs),
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct DedupeMetricDataArgs {
+    /// Only dedupe these runs' data
+    #[clap(long = "run-uuid", short = 'r', value_delimiter = ',')]
+    pub run_uuid: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    #[clap(subcommand)]
+    pub command: AuditCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditCommand {
+    /// List the logged commands, newest first
+    List(AuditListArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct AuditListArgs {
+    /// Only list commands starting with this, ex: "query delete"
+    #[clap(long = "command", short = 'c')]
+    pub command: Option<String>,
+    /// Only list commands run after this time.
+    /// Either a Unix epoch timestamp in millis, or a valid RFC 3339 timestamp
+    #[clap(long = "since", value_parser = parse_timestamp)]
+    pub since: Option<DateTime<Utc>>,
+    #[clap(long = "limit", short = 'l', default_value_t = 50)]
+    pub limit: i64,
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+}
+
+#[derive(Debug, Args, Serialize)]
+#[clap(group(ArgGroup::new("source").required(true).args(["file", "run_uuid"])))]
+pub struct RestoreArgs {
+    /// A backup written by `scdm backup`
+    pub file: Option<String>,
+    /// Soft deleted runs to bring back
+    #[clap(long = "run-uuid", short = 'u', value_delimiter = ',')]
+    pub run_uuid: Option<Vec<Uuid>>,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct BackupArgs {
+    /// The gzipped ndjson file to write
+    #[clap(long = "out", short = 'O')]
+    pub out: String,
+    /// Only back up these runs, every run is backed up by default
+    #[clap(long = "run-uuid", short = 'r', value_delimiter = ',')]
+    pub run_uuid: Option<Vec<Uuid>>,
+    #[clap(flatten)]
+    pub anonymize: AnonymizeOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// A directory of CDM ndjson, or JSON results like `scdm add` takes
+    pub path: String,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// JSON results like `scdm add` takes, or a backup written by `scdm backup`
+    pub path: String,
+    /// The directory to write the ndjson files to
+    #[clap(long = "out", short = 'O')]
+    pub out: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    pub shell: Shell,
+}
+
+#[derive(Debug, Args)]
+pub struct MangenArgs {
+    /// The directory to write the man pages to
+    #[clap(long = "out", short = 'O')]
+    pub out: String,
 }
 
 #[derive(Debug, Args)]
-#[group(required = true, multiple = false)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct ExportArgs {
+    #[clap(subcommand)]
+    pub command: Option<ExportCommand>,
+    #[clap(long = "run-uuid", short = 'r', value_delimiter = ',', required = true)]
+    pub run_uuid: Vec<Uuid>,
+    /// The directory to write an ndjson file per CDM index into
+    #[clap(long = "out", short = 'O', required = true)]
+    pub out: Option<String>,
+    #[clap(flatten)]
+    pub anonymize: AnonymizeOpts,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportCommand {
+    /// Write the runs as the nested JSON `scdm add` reads
+    Json(ExportJsonArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ExportJsonArgs {
+    #[clap(long = "run-uuid", short = 'r', value_delimiter = ',', required = true)]
+    pub run_uuid: Vec<Uuid>,
+    /// The JSON file to write, defaults to stdout
+    #[clap(long = "out", short = 'O')]
+    pub out: Option<String>,
+    #[clap(flatten)]
+    pub anonymize: AnonymizeOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct AnonymizeOpts {
+    /// Hash the emails, run names and hostnames, and strip the descriptions
+    #[clap(long = "anonymize", action)]
+    pub anonymize: bool,
+    /// Metric name values matching this regex are hashed as hostnames, along
+    /// with those under host keys. By default FQDNs and IPv4 addresses match
+    #[clap(long = "hostname-pattern", requires = "anonymize")]
+    pub hostname_pattern: Option<String>,
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct PurgeArgs {
+    /// Only purge the runs soft deleted longer ago than this, ex: 30d, 12h
+    #[clap(long = "older-than", value_parser = parse_interval)]
+    pub older_than: Option<i64>,
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct LinkArgs {
+    #[clap(long = "from")]
+    pub from: Uuid,
+    #[clap(long = "to")]
+    pub to: Uuid,
+    /// How the --from run relates to the --to run
+    #[clap(long = "relation", short = 'r')]
+    pub relation: RunRelation,
+    /// Remove the link instead of adding it
+    #[clap(long = "remove", action)]
+    pub remove: bool,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunRelation {
+    /// The run repeats the other one
+    RerunOf,
+    /// The run is the baseline the other one is compared against
+    BaselineFor,
+    /// The run changes some of the other one's parameters
+    TunedFrom,
+}
+
+impl RunRelation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunRelation::RerunOf => "rerun-of",
+            RunRelation::BaselineFor => "baseline-for",
+            RunRelation::TunedFrom => "tuned-from",
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ArtifactArgs {
+    #[clap(subcommand)]
+    pub command: ArtifactCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ArtifactCommand {
+    /// Attach a file or URL to a run
+    Add(ArtifactAddArgs),
+    /// Write an artifact's content out, or print where it's kept
+    Get(ArtifactGetArgs),
+    /// List the artifacts attached to runs
+    List(ArtifactListArgs),
+}
+
+#[derive(Debug, Args, Serialize)]
+pub struct ArtifactAddArgs {
+    #[clap(long = "run-uuid", short = 'u')]
+    pub run_uuid: Uuid,
+    /// Path of the file, or a URL to reference
+    pub path: String,
+    /// Defaults to the file name
+    #[clap(long = "name")]
+    pub name: Option<String>,
+    /// Defaults to a guess from the file extension
+    #[clap(long = "content-type")]
+    pub content_type: Option<String>,
+    /// Store the file's content in the database rather than a reference to it
+    #[clap(long = "embed", action)]
+    pub embed: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ArtifactGetArgs {
+    pub artifact_uuid: Uuid,
+    /// Where to write an embedded artifact, defaults to stdout
+    #[clap(long = "out", short = 'O')]
+    pub out: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ArtifactListArgs {
+    #[clap(long = "run-uuid", short = 'u')]
+    pub run_uuid: Option<Uuid>,
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// Also check that the OpenSearch `import` reads from is reachable
+    #[clap(long = "opensearch", action)]
+    pub opensearch: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MaintainArgs {
+    /// Rewrite the tables to give the space of deleted rows back to the OS,
+    /// locking them for the duration
+    #[clap(long = "full", action)]
+    pub full: bool,
+    /// Also rebuild the indexes of the tables
+    #[clap(long = "reindex", action)]
+    pub reindex: bool,
+    /// Only maintain these tables, ex: metric_data,metric_desc
+    #[clap(long = "table", short = 't', value_delimiter = ',')]
+    pub table: Option<Vec<String>>,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    /// Only report one section, required for json and csv output
+    #[clap(long = "section", short = 's')]
+    pub section: Option<StatsSection>,
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+}
+
+#[derive(Debug, Args)]
+#[clap(group(ArgGroup::new("runs").required(true).multiple(true).args(["run_uuid", "benchmark", "tag", "begin_after"])))]
+pub struct StatusArgs {
+    #[clap(long = "run-uuid", short = 'r', value_delimiter = ',')]
+    pub run_uuid: Option<Vec<Uuid>>,
+    #[clap(long = "benchmark", short = 'k')]
+    pub benchmark: Option<String>,
+    /// Only runs tagged "tag_name=tag_value", or with the tag at all
+    #[clap(long = "tag", short = 't')]
+    pub tag: Option<String>,
+    /// Only runs that begin after this time.
+    /// Either a Unix epoch timestamp in millis, or a valid RFC 3339 timestamp
+    #[clap(long = "begin-after", value_parser = parse_timestamp)]
+    pub begin_after: Option<DateTime<Utc>>,
+    /// JSON has the runs and failures, CSV and NDJSON only the failures
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum StatsSection {
+    /// Row counts and disk usage of each table
+    Tables,
+    /// Number of runs of each benchmark
+    Benchmarks,
+    /// Number of data points of each run
+    Runs,
+}
+
+#[derive(Debug, Args, Serialize)]
+#[clap(group(ArgGroup::new("runs").required(true).args(["run_uuid", "all"])))]
 pub struct ImportArgs {
     #[clap(long = "run-uuid", value_delimiter = ',')]
     pub run_uuid: Option<Vec<Uuid>>,
     #[clap(long = "all", action)]
     pub all: bool,
+    /// Leave out the runs that are already in the database
+    #[clap(long = "skip-existing", action)]
+    pub skip_existing: bool,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+    /// Keep syncing every --interval until interrupted
+    #[clap(long = "daemon", action)]
+    pub daemon: bool,
+    /// How long to wait between syncs, ex: 30s, 15m, 1h
+    #[clap(long = "interval", value_parser = parse_interval, default_value = "15m")]
+    pub interval: i64,
+    /// After each sync, prune the runs that finished longer ago than this.
+    /// The runs past their retention policy are pruned either way
+    #[clap(long = "prune-older-than", value_parser = parse_interval)]
+    pub prune_older_than: Option<i64>,
+    /// The file that keeps a second sync from starting, scdm-sync.pid in the
+    /// temporary directory by default
+    #[clap(long = "pid-file")]
+    pub pid_file: Option<String>,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct NatsArgs {
+    /// The NATS server to connect to
+    #[clap(
+        long = "url",
+        env = "NATS_URL",
+        default_value = "nats://localhost:4222"
+    )]
+    pub url: String,
+    /// The subject results are published on, each message a run like
+    /// `scdm add` reads, or an array of them
+    #[clap(long = "subject", default_value = "scdm.results")]
+    pub subject: String,
+    /// Consume through this JetStream stream, so results published while
+    /// scdm isn't running are ingested once it is
+    #[clap(long = "stream")]
+    pub stream: Option<String>,
+    /// The name of the durable JetStream consumer, a restarted scdm resumes
+    /// where the consumer left off
+    #[clap(long = "durable", default_value = "scdm", requires = "stream")]
+    pub durable: String,
+    /// A NATS credentials file to log in with
+    #[clap(long = "creds", env = "NATS_CREDS")]
+    pub creds: Option<String>,
+    /// A token to log in with
+    #[clap(long = "token", env = "NATS_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct AgentArgs {
+    /// Directories of CDM ndjson, or JSON results like `scdm add` takes
+    #[clap(required = true)]
+    pub path: Vec<String>,
+    /// The URL of the `scdm serve` to push to, ex: https://scdm.example.com
+    #[clap(long = "push-to", env = "SCDM_PUSH_TO")]
+    pub push_to: String,
+    /// The server's bearer token
+    #[clap(long = "token", env = "SCDM_SERVE_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// Iterations of the generated run
+    #[clap(long = "iterations", default_value_t = 4)]
+    pub iterations: usize,
+    /// Samples of each iteration
+    #[clap(long = "samples", default_value_t = 3)]
+    pub samples: usize,
+    /// Metrics in the period of each sample
+    #[clap(long = "metrics", default_value_t = 16)]
+    pub metrics: usize,
+    /// Data points of each metric
+    #[clap(long = "points", default_value_t = 500)]
+    pub points: usize,
+    /// How many times each query is timed
+    #[clap(long = "repeat", default_value_t = 10)]
+    pub repeat: usize,
+    /// Only report one section, required for json and csv output
+    #[clap(long = "section", short = 's')]
+    pub section: Option<BenchSection>,
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum BenchSection {
+    /// Rows inserted per second into each table
+    Ingest,
+    /// Latency of the common queries
+    Queries,
+}
+
+#[derive(Debug, Args)]
+pub struct RollupArgs {
+    /// Width of each rollup bucket, ex: 500ms, 30s, 1m, 1h
+    #[clap(long = "interval", short = 'i', value_parser = parse_interval)]
+    pub interval: i64,
+    /// Only roll up the data belonging to this run
+    #[clap(long = "run-uuid", short = 'r')]
+    pub run_uuid: Option<Uuid>,
 }
 
 #[derive(Debug, Args)]
 pub struct ParseArgs {
     pub path: String,
+    /// The format of the results
+    #[clap(
+        long = "format",
+        short = 'f',
+        default_value = "cdm",
+        value_parser = PossibleValuesParser::new(adapter::possible_values())
+    )]
+    pub format: String,
+    #[clap(flatten)]
+    pub adapter: AdapterOpts,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+    /// Hold about this many MB of the CDM ndjson in memory at most, spilling
+    /// the rest to temporary files and inserting it in one transaction
+    #[clap(long = "max-memory", value_name = "MB", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_memory: Option<u32>,
+}
+
+/// The details of the run for the formats that don't carry them
+#[derive(Debug, Default, Args)]
+pub struct AdapterOpts {
+    /// The run's UUID, a new one by default. The telemetry formats add to
+    /// this existing run instead
+    #[clap(long = "run-uuid")]
+    pub run_uuid: Option<Uuid>,
+    /// The period of the existing run the telemetry formats add to
+    #[clap(long = "period-uuid", requires = "run_uuid")]
+    pub period_uuid: Option<Uuid>,
+    /// The host the telemetry comes from, for the formats that don't name it
+    #[clap(long = "hostname")]
+    pub hostname: Option<String>,
+    /// Only add the telemetry from this time on. Either a Unix epoch
+    /// timestamp in millis, or a valid RFC 3339 timestamp
+    #[clap(long = "begin", value_parser = parse_timestamp)]
+    pub begin: Option<DateTime<Utc>>,
+    /// Only add the telemetry up to this time
+    #[clap(long = "finish", value_parser = parse_timestamp)]
+    pub finish: Option<DateTime<Utc>>,
+    /// Only add the telemetry metrics starting with these, ex: "kernel.all,disk.dev"
+    #[clap(long = "metric", value_delimiter = ',')]
+    pub metric: Vec<String>,
+    /// The timezone of pmrep's times, utc, local, a name like
+    /// America/New_York or an offset like +02:00 [default: utc]
+    #[clap(long = "timezone", value_parser = parse_timezone)]
+    pub timezone: Option<Timezone>,
+    /// The run's benchmark, the tool's name by default
+    #[clap(long = "benchmark")]
+    pub benchmark: Option<String>,
+    /// The email of whoever ran the benchmark
+    #[clap(long = "email", default_value = "")]
+    pub email: String,
+    /// The name of whoever ran the benchmark
+    #[clap(long = "user-name", env = "USER", default_value = "")]
+    pub user_name: String,
+    #[clap(long = "description")]
+    pub description: Option<String>,
+    /// Tags of the run, ex: "kernel=6.8,topo=internode"
+    #[clap(long = "tag", value_delimiter = ',')]
+    pub tag: Vec<String>,
+    /// Params of every iteration the output doesn't give, ex: "wsize=64"
+    #[clap(long = "param", value_delimiter = ',')]
+    pub param: Vec<String>,
+    /// Whether the UUIDs made up for the output are UUIDv7s, which
+    /// follows --uuid-v7
+    #[clap(skip)]
+    pub uuid_v7: bool,
 }
 
 #[derive(Debug, Args)]
 pub struct AddArgs {
     pub path: String,
+    #[clap(flatten)]
+    pub status: StatusOpts,
+}
+
+/// Iteration and sample statuses are one of pass, fail, skip or error.
+/// Common spellings like "passed" or "FAILED" are mapped onto them, and any
+/// other status is rejected unless --strict or --coerce says otherwise.
+#[derive(Debug, Args, Serialize)]
+pub struct StatusOpts {
+    /// Only take the exact statuses, without mapping other spellings
+    #[clap(long = "strict", action, conflicts_with = "coerce")]
+    pub strict: bool,
+    /// Record statuses that can't be mapped as error instead of rejecting them
+    #[clap(long = "coerce", action)]
+    pub coerce: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StatusMode {
+    Strict,
+    Map,
+    Coerce,
+}
+
+impl StatusOpts {
+    pub fn mode(&self) -> StatusMode {
+        if self.strict {
+            StatusMode::Strict
+        } else if self.coerce {
+            StatusMode::Coerce
+        } else {
+            StatusMode::Map
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// The address to listen on
+    #[clap(long = "listen", default_value = "127.0.0.1:8080")]
+    pub listen: String,
+    /// The bearer token requests need to delete runs and tags, or push
+    /// results from `scdm agent`. Both are refused when there's none and
+    /// no --owner-token. A request with it acts as --owner and --admin
+    #[clap(long = "token", env = "SCDM_SERVE_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    /// Bearer tokens of the runs' owners as "owner=token". A request with
+    /// one acts as its owner, it can only delete their runs and the results
+    /// it pushes are theirs
+    #[clap(
+        long = "owner-token",
+        env = "SCDM_SERVE_OWNER_TOKENS",
+        value_delimiter = ',',
+        value_parser = parse_owner_token,
+        hide_env_values = true
+    )]
+    pub owner_tokens: Vec<(String, String)>,
+    /// How often the data points /metrics reports are counted, the count
+    /// scans metric_data so scrapes don't do it themselves
+    #[clap(long = "count-interval", value_parser = parse_interval, default_value = "10m")]
+    pub count_interval: i64,
+}
+
+#[derive(Debug, Args)]
+pub struct OtlpArgs {
+    /// The collector's OTLP/HTTP endpoint, the metrics are posted to its
+    /// /v1/metrics
+    #[clap(
+        long = "endpoint",
+        env = "OTEL_EXPORTER_OTLP_ENDPOINT",
+        default_value = "http://localhost:4318"
+    )]
+    pub endpoint: String,
+    /// Headers to send with the metrics, ex: "authorization=Bearer abc"
+    #[clap(
+        long = "header",
+        env = "OTEL_EXPORTER_OTLP_HEADERS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub headers: Vec<String>,
+    /// Only send the periods with this name, ex: measurement
+    #[clap(long = "period-name")]
+    pub period_name: Option<String>,
+    /// Only send this metric type
+    #[clap(long = "metric-type")]
+    pub metric_type: Option<String>,
+    /// Print the OTLP JSON instead of sending it
+    #[clap(long = "dry-run", action)]
+    pub dry_run: bool,
+    #[clap(flatten)]
+    pub runs: GetRunArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplArgs {
+    /// Don't read or save the command history
+    #[clap(long = "no-history", action)]
+    pub no_history: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    #[clap(long = "run-uuid", short = 'r')]
+    pub run_uuid: Uuid,
+    /// The HTML file to write
+    #[clap(long = "out", short = 'O')]
+    pub out: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    #[clap(long = "run-uuid", short = 'r')]
+    pub run_uuid: Uuid,
+    /// The run to compare against, by default the one linked as its
+    /// baseline-for
+    #[clap(long = "baseline", short = 'b')]
+    pub baseline: Option<Uuid>,
+    /// Changes of at most this many percent are not regressions
+    #[clap(long = "threshold", default_value_t = 5.0)]
+    pub threshold: f64,
+    /// Metric types that are better when they go down, ex: latency
+    #[clap(long = "lower-is-better", value_delimiter = ',')]
+    pub lower_is_better: Vec<String>,
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<CompareOutput>,
+    /// Post the regressions to this URL as JSON with a Slack compatible
+    /// "text", when there are any
+    #[clap(long = "notify-webhook", env = "SCDM_NOTIFY_WEBHOOK")]
+    pub notify_webhook: Option<String>,
+}
+
+#[derive(Debug, ValueEnum, Clone)]
+pub enum CompareOutput {
+    JSON,
+    CSV,
+    /// One JSON object per line
+    NDJSON,
+    /// A Markdown table with a verdict per metric, for CI to comment with
+    MarkdownReport,
 }
 
 #[derive(Debug, Args)]
@@ -79,6 +1115,7 @@ pub struct QueryArgs {
     pub command: QueryCommand,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 pub enum QueryCommand {
     /// Retrieve information about a CDM resource
@@ -105,12 +1142,84 @@ pub struct GetArgs {
 pub struct GetOptions {
     #[clap(long = "output", short = 'o')]
     pub output: Option<OutputFormat>,
+    /// The file `--output xlsx` writes
+    #[clap(long = "out", short = 'O')]
+    pub out: Option<String>,
+    /// Only print this many results
+    #[clap(long = "limit", short = 'l')]
+    pub limit: Option<usize>,
+    #[clap(flatten)]
+    pub display: DisplayOpts,
+}
+
+#[derive(Debug, Clone, Args, Default)]
+pub struct DisplayOpts {
+    /// The timezone timestamps are shown in, utc, local, a name like
+    /// America/New_York or an offset like +02:00 [default: utc]
+    #[clap(long = "timezone", visible_alias = "tz", value_parser = parse_timezone)]
+    pub timezone: Option<Timezone>,
+    /// How timestamps are written, in strftime specifiers, ex: "%m-%d %H:%M:%S"
+    #[clap(long = "time-format", value_parser = render::parse_time_format)]
+    pub time_format: Option<String>,
+    /// How tables are drawn [default: modern]
+    #[clap(value_enum, long = "table-style", visible_alias = "style")]
+    pub table_style: Option<TableStyle>,
+    /// Cut cells wider than this many characters short, or wrap them with
+    /// --wrap, so long rows of UUIDs fit the terminal
+    #[clap(long = "max-col-width")]
+    pub max_col_width: Option<usize>,
+    /// Wrap cells wider than --max-col-width onto more lines
+    #[clap(long = "wrap", overrides_with = "truncate")]
+    pub wrap: bool,
+    /// Cut cells wider than --max-col-width short, the default
+    #[clap(long = "truncate", overrides_with = "wrap")]
+    pub truncate: bool,
+    /// A jq filter the json and ndjson output goes through, ex:
+    /// '.[] | select(.value > 10) | .run_uuid'. For ndjson it's run on each line
+    #[clap(long = "filter", value_parser = render::compile_filter)]
+    pub filter: Option<JsonFilter>,
+    /// Show UUIDs in full rather than cut to the start that tells them apart.
+    /// --full-ids=false turns off a profile's
+    #[clap(long = "full-ids", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub full_ids: Option<bool>,
+    /// Show values in tables with SI or IEC suffixes, ex: 1.2M or 3.4GiB, and
+    /// durations like 2m 13s. CSV and JSON keep the raw numbers.
+    /// --human=false turns off a profile's
+    #[clap(long = "human", short = 'H', num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub human: Option<bool>,
+    /// Color the table cells that compare to a value, as the cells are shown,
+    /// ex: 'value>1000=red,status==fail=yellow'. Colors are red, green,
+    /// yellow, blue, magenta, cyan, white and bold
+    #[clap(long = "highlight", value_delimiter = ',', value_parser = render::parse_highlight)]
+    pub highlight: Vec<Highlight>,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum TableStyle {
+    Modern,
+    Rounded,
+    Ascii,
+    Psql,
+    Markdown,
+    /// No borders at all
+    #[value(alias = "plain")]
+    Blank,
+}
+
+fn parse_timezone(arg: &str) -> Result<Timezone, RenderError> {
+    Timezone::parse(arg)
 }
 
 #[derive(Debug, ValueEnum, Clone)]
 pub enum OutputFormat {
     JSON,
     CSV,
+    /// One JSON object per line
+    NDJSON,
+    /// A Vega-Lite spec charting the windows of `query metric`
+    Vega,
+    /// An Excel workbook, written to --out
+    Xlsx,
 }
 
 #[derive(Debug, Subcommand)]
@@ -124,6 +1233,22 @@ pub enum GetCommand {
     MetricDesc(GetMetricDescArgs),
     MetricData(GetMetricDataArgs),
     Name(GetNameArgs),
+    RunLink(GetRunLinkArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct GetRunLinkArgs {
+    /// Links from or to this run
+    #[clap(long = "run-uuid", short = 'u')]
+    pub run_uuid: Option<Uuid>,
+    #[clap(long = "relation", short = 'r')]
+    pub relation: Option<RunRelation>,
+    /// Follow the links from the run to the runs it derives from, and on
+    #[clap(long = "lineage", action, requires = "run_uuid")]
+    pub lineage: bool,
+    /// List the links from or to soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 fn parse_timestamp(arg: &str) -> Result<DateTime<Utc>, SCDMError> {
@@ -140,6 +1265,45 @@ fn parse_timestamp(arg: &str) -> Result<DateTime<Utc>, SCDMError> {
     }
 }
 
+/// Parses a tag like "tag_name=tag_value" into its name and value
+fn parse_tag(arg: &str) -> Result<(String, String), SCDMError> {
+    arg.split_once('=')
+        .map(|(name, val)| (name.to_string(), val.to_string()))
+        .ok_or(SCDMError::FailedTagParse(arg.to_string()))
+}
+
+/// Parses an owner's token like "owner=token" into the owner and token,
+/// leaving the token out of the error
+fn parse_owner_token(arg: &str) -> Result<(String, String), SCDMError> {
+    match arg.split_once('=') {
+        Some((owner, token)) if !owner.is_empty() && !token.is_empty() => {
+            Ok((owner.to_string(), token.to_string()))
+        }
+        _ => Err(SCDMError::FailedOwnerTokenParse),
+    }
+}
+
+/// Parses an interval like "30s" or "1m" into milliseconds
+pub fn parse_interval(arg: &str) -> Result<i64, SCDMError> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (n, unit) = arg.split_at(split);
+    let n: i64 = n
+        .parse()
+        .map_err(|_| SCDMError::FailedIntervalParse(arg.to_string()))?;
+    let millis = match unit {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return Err(SCDMError::FailedIntervalParse(arg.to_string())),
+    };
+    if n <= 0 {
+        return Err(SCDMError::FailedIntervalParse(arg.to_string()));
+    }
+    Ok(n * millis)
+}
+
 #[derive(Debug, Args)]
 pub struct GetRunArgs {
     #[clap(long = "run-uuid", short = 'u')]
@@ -171,6 +1335,15 @@ pub struct GetRunArgs {
     pub name: Option<String>,
     #[clap(long = "source", short = 's')]
     pub source: Option<String>,
+    /// List the soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
+    /// Search for runs that lasted at least this long, ex: 90s, 10m
+    #[clap(long = "min-duration", value_parser = parse_interval)]
+    pub min_duration: Option<i64>,
+    /// Search for runs that lasted at most this long, ex: 90s, 10m
+    #[clap(long = "max-duration", value_parser = parse_interval)]
+    pub max_duration: Option<i64>,
 }
 
 #[derive(Debug, Args)]
@@ -180,6 +1353,9 @@ pub struct GetTagArgs {
     /// Search for runs where "tag_name=tag_value"
     #[clap(long = "tag", short = 't')]
     pub tag: Option<String>,
+    /// List the tags of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -192,6 +1368,9 @@ pub struct GetIterationArgs {
     pub num: Option<i64>,
     #[clap(long = "status", short = 's')]
     pub status: Option<String>,
+    /// List the iterations of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -202,6 +1381,9 @@ pub struct GetParamArgs {
     pub arg: Option<String>,
     #[clap(long = "value", short = 'v')]
     pub val: Option<String>,
+    /// List the params of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -214,6 +1396,9 @@ pub struct GetSampleArgs {
     pub num: Option<i64>,
     #[clap(long = "status", short = 's')]
     pub status: Option<String>,
+    /// List the samples of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -240,6 +1425,15 @@ pub struct GetPeriodArgs {
     pub finish_after: Option<DateTime<Utc>>,
     #[clap(long = "name", short = 'n')]
     pub name: Option<String>,
+    /// Search for periods that lasted at least this long, ex: 90s, 10m
+    #[clap(long = "min-duration", value_parser = parse_interval)]
+    pub min_duration: Option<i64>,
+    /// Search for periods that lasted at most this long, ex: 90s, 10m
+    #[clap(long = "max-duration", value_parser = parse_interval)]
+    pub max_duration: Option<i64>,
+    /// List the periods of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -254,6 +1448,18 @@ pub struct GetMetricDescArgs {
     pub metric_type: Option<String>,
     #[clap(long = "source", short = 's')]
     pub source: Option<String>,
+    /// Only descs whose names contain this JSON object, ex: '{"hostname":"w3"}'
+    #[clap(long = "names-contain")]
+    pub names_contain: Option<String>,
+    /// Only descs whose names match this JSON path, ex: '$.cpu ? (@ == "0")'
+    #[clap(long = "names-path")]
+    pub names_path: Option<String>,
+    /// Only descs that have this name, ex: hostname
+    #[clap(long = "has-name")]
+    pub has_name: Option<String>,
+    /// List the metric descs of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -291,6 +1497,9 @@ pub struct GetMetricDataArgs {
     /// Search for values greater than
     #[clap(long = "value-gt")]
     pub value_gt: Option<f64>,
+    /// List the data of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -301,6 +1510,9 @@ pub struct GetNameArgs {
     pub name: Option<String>,
     #[clap(long = "value", short = 'v')]
     pub val: Option<String>,
+    /// List the names of soft deleted runs instead
+    #[clap(long = "deleted", action)]
+    pub deleted: bool,
 }
 
 #[derive(Debug, Args)]
@@ -323,7 +1535,7 @@ pub enum DeleteCommand {
     Tag(DeleteTagArgs),
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Args, Serialize)]
 pub struct DeleteRunArgs {
     #[clap(long = "run-uuid", short = 'u')]
     pub run_uuid: Option<Uuid>,
@@ -354,9 +1566,13 @@ pub struct DeleteRunArgs {
     pub name: Option<String>,
     #[clap(long = "source", short = 's')]
     pub source: Option<String>,
+    /// Hide the runs from queries instead of deleting them, so they can be
+    /// brought back with `scdm restore`
+    #[clap(long = "soft", action)]
+    pub soft: bool,
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Args, Serialize)]
 pub struct DeleteTagArgs {
     #[clap(long = "run-uuid", short = 'r')]
     pub run_uuid: Option<Uuid>,
@@ -366,7 +1582,13 @@ pub struct DeleteTagArgs {
 }
 
 #[derive(Debug, Args)]
+#[command(
+    group(ArgGroup::new("breakouts").multiple(true).args(["name", "breakout"])),
+    args_conflicts_with_subcommands = true
+)]
 pub struct MetricArgs {
+    #[clap(subcommand)]
+    pub command: Option<MetricCommand>,
     #[clap(long = "run-uuid", short = 'r')]
     pub run_uuid: Option<Uuid>,
     #[clap(long = "iteration-uuid", short = 'i')]
@@ -381,18 +1603,25 @@ pub struct MetricArgs {
     pub metric_type: Option<String>,
 
     /// ref-period is a convenience option to use in place of specifying both a `begin`,
-    /// and an `end`. It inherits the period's begin and end.
-    #[clap(long = "ref-period", conflicts_with_all = ["begin", "finish"])]
-    pub ref_period: Option<Uuid>,
+    /// and an `end`. It inherits the period's begin and end. Provide a comma separated
+    /// list to get windows for each of the periods.
+    #[clap(long = "ref-period", value_delimiter = ',', conflicts_with_all = ["begin", "finish"])]
+    pub ref_period: Option<Vec<Uuid>>,
+    /// Use every period of this iteration as a ref-period
+    #[clap(long = "ref-periods-from-iteration", conflicts_with_all = ["begin", "finish"])]
+    pub ref_periods_from_iteration: Option<Uuid>,
     /// Either a Unix epoch timestamp in millis, or a valid RFC 3339 timestamp
-    #[clap(long = "begin", short = 'b', value_parser = parse_timestamp, conflicts_with = "ref_period", requires = "finish")]
+    #[clap(long = "begin", short = 'b', value_parser = parse_timestamp, conflicts_with_all = ["ref_period", "ref_periods_from_iteration"], requires = "finish")]
     pub begin: Option<DateTime<Utc>>,
     /// Either a Unix epoch timestamp in millis, or a valid RFC 3339 timestamp
-    #[clap(long = "finish", short = 'f', value_parser = parse_timestamp, conflicts_with = "ref_period", requires = "begin")]
+    #[clap(long = "finish", short = 'f', value_parser = parse_timestamp, conflicts_with_all = ["ref_period", "ref_periods_from_iteration"], requires = "begin")]
     pub finish: Option<DateTime<Utc>>,
 
     #[clap(long = "resolution", default_value_t = 1)]
     pub resolution: u64,
+    /// Always aggregate the raw metric data, even when a rollup is coarse enough
+    #[clap(long = "no-rollup", action)]
+    pub no_rollup: bool,
 
     #[clap(long = "value-eq")]
     /// Search for values equal to
@@ -403,19 +1632,168 @@ pub struct MetricArgs {
     /// Search for values greater than
     #[clap(long = "value-gt")]
     pub value_gt: Option<f64>,
+    /// Only keep aggregated values greater than
+    #[clap(long = "agg-gt")]
+    pub agg_gt: Option<f64>,
+    /// Only keep aggregated values less than
+    #[clap(long = "agg-lt")]
+    pub agg_lt: Option<f64>,
     /// Names used to breakout the data. Provide a comma separated list of
     /// names, with or without a corresponding value. Ex: "hostname,userenv=fedora40"
     #[clap(long = "name", short = 'n', value_delimiter = ',')]
     pub name: Option<Vec<String>>,
-    #[clap(value_enum, long = "aggregator", short = 'a', requires = "name", default_value_t = Aggregator::None)]
+    /// Breakout the data by metric_desc names, run tags or iteration params.
+    /// Provide a comma separated list of "name:<name>", "tag:<name>" or
+    /// "param:<arg>", with or without a corresponding value.
+    /// Ex: "tag:kernel,param:wsize,name:hostname=w1"
+    #[clap(long = "breakout", value_delimiter = ',')]
+    pub breakout: Option<Vec<String>>,
+    /// How to aggregate the data of each breakout group. By default the
+    /// metric_desc class decides, see `auto`
+    #[clap(value_enum, long = "aggregator", short = 'a', requires = "breakouts", default_value_t = Aggregator::Auto)]
     pub aggregator: Aggregator,
+    /// How metric data that only partially overlaps a window contributes to
+    /// the window's weighted average
+    #[clap(value_enum, long = "overlap", default_value_t = Overlap::Clip)]
+    pub overlap: Overlap,
 
+    /// Reuse the result of an identical query run within the cache TTL
+    #[clap(long = "cache", overrides_with = "no_cache")]
+    pub cache: bool,
+    #[clap(long = "no-cache", overrides_with = "cache")]
+    pub no_cache: bool,
+    /// How long a cached result stays valid, ex: 30s, 10m, 1h
+    #[clap(long = "cache-ttl", value_parser = parse_interval, default_value = "1h")]
+    pub cache_ttl: i64,
+
+    /// Convert values to this unit before aggregating, ex: Gbps, MiB/s, ms.
+    /// Data whose unit can't be converted is excluded
+    #[clap(long = "unit")]
+    pub unit: Option<String>,
+
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+    /// With `--output vega`, have the spec read the data from this URL, ex:
+    /// the CSV of the same query, instead of inlining it
+    #[clap(long = "data-url")]
+    pub data_url: Option<String>,
+    /// The file `--output xlsx` writes, with a sheet per breakout
+    #[clap(long = "out", short = 'O')]
+    pub out: Option<String>,
+    #[clap(flatten)]
+    pub display: DisplayOpts,
+}
+
+/// Mirrors the defaults of the command line options
+impl Default for MetricArgs {
+    fn default() -> Self {
+        MetricArgs {
+            command: None,
+            run_uuid: None,
+            iteration_uuid: None,
+            metric_desc_uuid: None,
+            period_uuid: None,
+            metric_type: None,
+            ref_period: None,
+            ref_periods_from_iteration: None,
+            begin: None,
+            finish: None,
+            resolution: 1,
+            no_rollup: false,
+            value_eq: None,
+            value_lt: None,
+            value_gt: None,
+            agg_gt: None,
+            agg_lt: None,
+            name: None,
+            breakout: None,
+            aggregator: Aggregator::Auto,
+            overlap: Overlap::Clip,
+            cache: false,
+            no_cache: false,
+            cache_ttl: 60 * 60 * 1000,
+            unit: None,
+            output: None,
+            data_url: None,
+            out: None,
+            display: DisplayOpts::default(),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MetricCommand {
+    /// Compare the aggregated metrics of two periods
+    Diff(MetricDiffArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct MetricDiffArgs {
+    /// The two periods to compare, ex: "--period <warmup> --period <measurement>"
+    #[clap(long = "period", short = 'p', required = true)]
+    pub period: Vec<Uuid>,
+    #[clap(long = "metric-type", short = 't')]
+    pub metric_type: Option<String>,
+    /// Names used to breakout the data, see `query metric --name`
+    #[clap(long = "name", short = 'n', value_delimiter = ',')]
+    pub name: Option<Vec<String>>,
+    /// Breakouts by name, tag or param, see `query metric --breakout`
+    #[clap(long = "breakout", value_delimiter = ',')]
+    pub breakout: Option<Vec<String>>,
+    #[clap(value_enum, long = "aggregator", short = 'a', default_value_t = Aggregator::Auto)]
+    pub aggregator: Aggregator,
+    /// Convert values to this unit before aggregating
+    #[clap(long = "unit")]
+    pub unit: Option<String>,
+    #[clap(long = "output", short = 'o')]
+    pub output: Option<OutputFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct AnalyzeArgs {
+    #[clap(subcommand)]
+    pub command: AnalyzeCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AnalyzeCommand {
+    /// Find the time ranges where a metric shifted away from its recent behavior
+    Anomalies(AnomaliesArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct AnomaliesArgs {
+    #[clap(long = "run-uuid", short = 'r')]
+    pub run_uuid: Uuid,
+    #[clap(long = "metric-type", short = 't')]
+    pub metric_type: String,
+    /// The number of windows the run is split into
+    #[clap(long = "resolution", default_value_t = 100)]
+    pub resolution: u64,
+    /// The number of preceding windows each window is compared against
+    #[clap(long = "baseline", default_value_t = 10)]
+    pub baseline: usize,
+    /// How many standard deviations from the baseline mean a window has to
+    /// be to count as anomalous
+    #[clap(long = "threshold", default_value_t = 3.0)]
+    pub threshold: f64,
+    /// Names used to breakout the data, see `query metric --name`
+    #[clap(long = "name", short = 'n', value_delimiter = ',')]
+    pub name: Option<Vec<String>>,
+    /// Breakouts by name, tag or param, see `query metric --breakout`
+    #[clap(long = "breakout", value_delimiter = ',')]
+    pub breakout: Option<Vec<String>>,
+    #[clap(value_enum, long = "aggregator", short = 'a', default_value_t = Aggregator::Auto)]
+    pub aggregator: Aggregator,
     #[clap(long = "output", short = 'o')]
     pub output: Option<OutputFormat>,
 }
 
 #[derive(Debug, ValueEnum, Clone)]
 pub enum Aggregator {
+    /// Sum the rates of `throughput` metrics, take the weighted average of
+    /// `count` metrics. Without a breakout the data isn't aggregated
+    Auto,
     None,
     Avg,
     WeightedAvg,
@@ -423,3 +1801,13 @@ pub enum Aggregator {
     Min,
     Max,
 }
+
+#[derive(Debug, ValueEnum, Clone)]
+pub enum Overlap {
+    /// Weight by the part of the data interval that falls inside the window
+    Clip,
+    /// Weight by the fraction of the data interval that falls inside the window
+    Proportional,
+    /// Weight by the full data interval, regardless of how much of it overlaps
+    WholePoint,
+}
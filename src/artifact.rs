@@ -0,0 +1,168 @@
+use crate::args::{
+    ArtifactAddArgs, ArtifactArgs, ArtifactCommand, ArtifactGetArgs, ArtifactListArgs, DisplayOpts,
+};
+use crate::audit;
+use crate::cdm::{self, Artifact};
+use crate::query::{self, QueryError, QueryGet};
+use crate::report;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// The largest file `artifact add --embed` stores in the database
+pub const MAX_EMBED_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ArtifactError {
+    #[error("Couldn't read the artifact {0}, {1}")]
+    ReadFailed(String, String),
+    #[error("{0} is {1} bytes, only files up to {2} bytes can be embedded")]
+    TooLarge(String, usize, usize),
+    #[error("URLs can only be referenced, not embedded")]
+    EmbedUrl,
+    #[error("No artifact {0}")]
+    NotFound(Uuid),
+    #[error("Couldn't store the artifact, {0}")]
+    StoreFailed(String),
+}
+
+fn guess_content_type(path: &str) -> Option<String> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    let content_type = match extension.as_str() {
+        "json" | "ndjson" => "application/json",
+        "yaml" | "yml" => "application/yaml",
+        "txt" | "log" | "conf" | "cfg" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "gz" => "application/gzip",
+        "xz" => "application/x-xz",
+        "tar" => "application/x-tar",
+        _ => return None,
+    };
+    Some(content_type.to_string())
+}
+
+async fn add(pool: &PgPool, args: ArtifactAddArgs, uuid_v7: bool) -> Result<()> {
+    let is_url = args.path.contains("://");
+    if is_url && args.embed {
+        return Err(ArtifactError::EmbedUrl.into());
+    }
+
+    let name = args.name.clone().unwrap_or_else(|| {
+        Path::new(args.path.trim_end_matches('/'))
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(args.path.clone())
+    });
+    let content_type = args
+        .content_type
+        .clone()
+        .or_else(|| guess_content_type(&args.path));
+
+    let (location, checksum, size, content) = if is_url {
+        (args.path.clone(), None, None, None)
+    } else {
+        let bytes = std::fs::read(&args.path)
+            .map_err(|e| ArtifactError::ReadFailed(args.path.clone(), format!("{}", e)))?;
+        if args.embed && bytes.len() > MAX_EMBED_BYTES {
+            return Err(
+                ArtifactError::TooLarge(args.path.clone(), bytes.len(), MAX_EMBED_BYTES).into(),
+            );
+        }
+        let location = std::fs::canonicalize(&args.path)
+            .map_err(|e| ArtifactError::ReadFailed(args.path.clone(), format!("{}", e)))?
+            .to_string_lossy()
+            .to_string();
+        let checksum = format!("sha256:{:x}", Sha256::digest(&bytes));
+        let size = bytes.len() as i64;
+        (
+            location,
+            Some(checksum),
+            Some(size),
+            args.embed.then_some(bytes),
+        )
+    };
+
+    let artifact_uuid = cdm::new_uuid(uuid_v7);
+    let raw_query: &str = r#"
+        INSERT INTO artifact (artifact_uuid, run_uuid, name, location, checksum, content_type, size, content)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    "#;
+    let serr = |e: sqlx::Error| ArtifactError::StoreFailed(format!("{}", e));
+    let mut txn = pool.begin().await.map_err(serr)?;
+    sqlx::query(raw_query)
+        .bind(artifact_uuid)
+        .bind(args.run_uuid)
+        .bind(&name)
+        .bind(location)
+        .bind(checksum)
+        .bind(content_type)
+        .bind(size)
+        .bind(content)
+        .execute(&mut *txn)
+        .await
+        .map_err(serr)?;
+    audit::record(&mut *txn, "artifact add", &args, 1).await?;
+    txn.commit().await.map_err(serr)?;
+    report!("added artifact {} ({})", artifact_uuid, name);
+    Ok(())
+}
+
+async fn get(pool: &PgPool, args: ArtifactGetArgs) -> Result<()> {
+    let artifact: Option<(Option<String>, Option<Vec<u8>>)> =
+        sqlx::query_as("SELECT location, content FROM artifact WHERE artifact_uuid = $1")
+            .bind(args.artifact_uuid)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| QueryError::GetError(format!("{}", e)))?;
+    match artifact {
+        None => Err(ArtifactError::NotFound(args.artifact_uuid).into()),
+        Some((_, Some(content))) => {
+            match args.out {
+                Some(out) => std::fs::write(out, content)?,
+                None => std::io::stdout().write_all(&content)?,
+            }
+            Ok(())
+        }
+        Some((location, None)) => {
+            println!("{}", location.unwrap_or_default());
+            Ok(())
+        }
+    }
+}
+
+impl QueryGet<Artifact> for ArtifactListArgs {
+    async fn query_get(&self, pool: &PgPool) -> Result<Vec<Artifact>, QueryError> {
+        let raw_query: &str = r#"
+            SELECT
+                artifact_uuid, run_uuid, name, location, checksum, content_type, size,
+                content IS NOT NULL as embedded, added
+            FROM artifact
+            WHERE $1 IS NULL OR run_uuid = $1
+            ORDER BY run_uuid, added
+        "#;
+        sqlx::query_as(raw_query)
+            .bind(self.run_uuid)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
+    }
+}
+
+/// New artifacts get UUIDv7s when `uuid_v7` is set
+pub async fn artifact(pool: &PgPool, args: ArtifactArgs, uuid_v7: bool) -> Result<()> {
+    match args.command {
+        ArtifactCommand::Add(args) => add(pool, args, uuid_v7).await,
+        ArtifactCommand::Get(args) => get(pool, args).await,
+        ArtifactCommand::List(args) => {
+            let output = args.output.clone();
+            query::query_get(pool, args, output, None, None, &DisplayOpts::default()).await
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::args::{AuditArgs, AuditCommand, AuditListArgs, DisplayOpts};
+use crate::cdm::AuditEntry;
+use crate::query::{self, QueryError, QueryGet};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Executor, PgPool, Postgres};
+use std::env;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("Couldn't record the command in the audit log, {0}")]
+    RecordFailed(String),
+}
+
+/// Logs a command that changed the data. Pass the command's transaction,
+/// when it has one, so the entry is only kept if the change is.
+pub async fn record<'c, E, T>(executor: E, command: &str, filters: &T, rows: u64) -> Result<()>
+where
+    E: Executor<'c, Database = Postgres>,
+    T: Serialize,
+{
+    insert(executor, command, filters, rows, None).await
+}
+
+/// Like `record`, for the ingests, whose durations `serve` exports
+pub async fn record_timed<'c, E, T>(
+    executor: E,
+    command: &str,
+    filters: &T,
+    rows: u64,
+    duration: Duration,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Postgres>,
+    T: Serialize,
+{
+    insert(
+        executor,
+        command,
+        filters,
+        rows,
+        Some(duration.as_millis() as i64),
+    )
+    .await
+}
+
+async fn insert<'c, E, T>(
+    executor: E,
+    command: &str,
+    filters: &T,
+    rows: u64,
+    duration_ms: Option<i64>,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Postgres>,
+    T: Serialize,
+{
+    // Only the filters that were given are worth keeping
+    let filters = match serde_json::to_value(filters)
+        .map_err(|e| AuditError::RecordFailed(format!("{}", e)))?
+    {
+        Value::Null => None,
+        Value::Object(mut fields) => {
+            fields.retain(|_, v| !v.is_null());
+            Some(Value::Object(fields))
+        }
+        other => Some(other),
+    };
+    let os_user = env::var("USER").or(env::var("USERNAME")).ok();
+    let raw_query: &str = r#"
+        INSERT INTO audit_log (os_user, command, filters, rows_affected, duration_ms)
+        VALUES ($1, $2, $3, $4, $5)
+    "#;
+    sqlx::query(raw_query)
+        .bind(os_user)
+        .bind(command)
+        .bind(filters)
+        .bind(rows as i64)
+        .bind(duration_ms)
+        .execute(executor)
+        .await
+        .map_err(|e| AuditError::RecordFailed(format!("{}", e)))?;
+    Ok(())
+}
+
+impl QueryGet<AuditEntry> for AuditListArgs {
+    async fn query_get(&self, pool: &PgPool) -> Result<Vec<AuditEntry>, QueryError> {
+        let raw_query: &str = r#"
+            SELECT audit_id, at, db_user, os_user, command, filters::text as filters, rows_affected,
+                duration_ms
+            FROM audit_log
+            WHERE
+                ($1::text IS NULL OR starts_with(command, $1)) AND
+                ($2::timestamptz IS NULL OR at >= $2)
+            ORDER BY at DESC, audit_id DESC
+            LIMIT $3
+        "#;
+        sqlx::query_as(raw_query)
+            .bind(self.command.clone())
+            .bind(self.since)
+            .bind(self.limit)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
+    }
+}
+
+pub async fn audit(pool: &PgPool, args: AuditArgs) -> Result<()> {
+    match args.command {
+        AuditCommand::List(args) => {
+            let output = args.output.clone();
+            query::query_get(pool, args, output, None, None, &DisplayOpts::default()).await
+        }
+    }
+}
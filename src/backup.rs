@@ -0,0 +1,389 @@
+use crate::anonymize::Anonymizer;
+use crate::args::BackupArgs;
+use crate::parser::{
+    BodyJson, CDMSpecJson, IterationFKJson, IterationJson, IterationSpecJson, MetricDataJson,
+    MetricDataSpecJson, MetricDescFKJson, MetricDescJson, MetricDescSpecJson, ParamJson,
+    ParamSpecJson, PeriodFKJson, PeriodJson, PeriodSpecJson, RunFKJson, RunJson, RunSpecJson,
+    SampleFKJson, SampleJson, SampleSpecJson, TagJson, TagSpecJson,
+};
+use crate::report;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use serde_json::{Value, json};
+use sqlx::types::Json;
+use sqlx::{PgPool, Row};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("Couldn't write the backup {0}, {1}")]
+    WriteFailed(String, String),
+    #[error("No run {0}")]
+    NotFound(Uuid),
+}
+
+fn cdm() -> CDMSpecJson {
+    CDMSpecJson {
+        ver: "v8dev".to_string(),
+    }
+}
+
+/// The OpenSearch index a document belongs in
+pub fn index_name(document: &BodyJson) -> Option<&'static str> {
+    match document {
+        BodyJson::Iteration(_) => Some("cdmv8dev-iteration"),
+        BodyJson::MetricData(_) => Some("cdmv8dev-metric_data"),
+        BodyJson::MetricDesc(_) => Some("cdmv8dev-metric_desc"),
+        BodyJson::Param(_) => Some("cdmv8dev-param"),
+        BodyJson::Period(_) => Some("cdmv8dev-period"),
+        BodyJson::Run(_) => Some("cdmv8dev-run"),
+        BodyJson::Sample(_) => Some("cdmv8dev-sample"),
+        BodyJson::Tag(_) => Some("cdmv8dev-tag"),
+        // Names are carried in the metric_desc documents
+        BodyJson::Name(_) => None,
+    }
+}
+
+fn write_line<W: Write, T: Serialize>(writer: &mut W, value: &T) -> Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Writes a document as its index line followed by the body line
+pub fn write_document<W: Write>(writer: &mut W, document: &BodyJson) -> Result<()> {
+    let Some(index) = index_name(document) else {
+        return Ok(());
+    };
+    write_line(writer, &json!({ "index": { "_index": index } }))?;
+    match document {
+        BodyJson::Iteration(body) => write_line(writer, body),
+        BodyJson::MetricData(body) => write_line(writer, body),
+        BodyJson::MetricDesc(body) => write_line(writer, body),
+        BodyJson::Param(body) => write_line(writer, body),
+        BodyJson::Period(body) => write_line(writer, body),
+        BodyJson::Run(body) => write_line(writer, body),
+        BodyJson::Sample(body) => write_line(writer, body),
+        BodyJson::Tag(body) => write_line(writer, body),
+        BodyJson::Name(_) => Ok(()),
+    }
+}
+
+/// Reads a run back out as CDM documents, passing each to `emit` in foreign
+/// key order. The global iteration scdm adds for run scoped metrics is left
+/// out, and those metrics are written without a period like Crucible does,
+/// so loading them again recreates it.
+pub async fn run_documents<F>(pool: &PgPool, run_uuid: Uuid, mut emit: F) -> Result<u64>
+where
+    F: FnMut(BodyJson) -> Result<()>,
+{
+    let mut num_documents = 0;
+    let run = sqlx::query(
+        "SELECT begin, finish, benchmark, email, name, description, source, owner
+        FROM run WHERE run_uuid = $1",
+    )
+    .bind(run_uuid)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(BackupError::NotFound(run_uuid))?;
+    emit(BodyJson::Run(RunJson {
+        cdm: cdm(),
+        run: RunSpecJson {
+            run_uuid,
+            begin: run.try_get("begin")?,
+            end: run.try_get("finish")?,
+            benchmark: run
+                .try_get::<Option<String>, _>("benchmark")?
+                .unwrap_or_default(),
+            email: run
+                .try_get::<Option<String>, _>("email")?
+                .unwrap_or_default(),
+            name: run
+                .try_get::<Option<String>, _>("name")?
+                .unwrap_or_default(),
+            description: run.try_get("description")?,
+            source: run
+                .try_get::<Option<String>, _>("source")?
+                .unwrap_or_default(),
+            owner: run.try_get("owner")?,
+        },
+    }))?;
+    num_documents += 1;
+
+    let tags = sqlx::query("SELECT name, val FROM tag WHERE run_uuid = $1 ORDER BY name")
+        .bind(run_uuid)
+        .fetch_all(pool)
+        .await?;
+    for tag in tags {
+        emit(BodyJson::Tag(TagJson {
+            cdm: cdm(),
+            tag: TagSpecJson {
+                name: tag
+                    .try_get::<Option<String>, _>("name")?
+                    .unwrap_or_default(),
+                val: tag.try_get::<Option<String>, _>("val")?.unwrap_or_default(),
+            },
+            run: RunFKJson { run_uuid },
+        }))?;
+        num_documents += 1;
+    }
+
+    let mut global_iterations = HashSet::new();
+    let iterations = sqlx::query(
+        "SELECT iteration_uuid, num, status, path, primary_metric, primary_period
+        FROM iteration WHERE run_uuid = $1 ORDER BY num",
+    )
+    .bind(run_uuid)
+    .fetch_all(pool)
+    .await?;
+    for iteration in iterations {
+        let iteration_uuid: Uuid = iteration.try_get("iteration_uuid")?;
+        let primary_metric: String = iteration.try_get("primary_metric")?;
+        let primary_period: String = iteration.try_get("primary_period")?;
+        if primary_metric == "global" && primary_period == "global" {
+            global_iterations.insert(iteration_uuid);
+            continue;
+        }
+        emit(BodyJson::Iteration(IterationJson {
+            cdm: cdm(),
+            iteration: IterationSpecJson {
+                iteration_uuid,
+                num: iteration.try_get("num")?,
+                primary_metric,
+                primary_period,
+                status: iteration
+                    .try_get::<Option<String>, _>("status")?
+                    .unwrap_or_default(),
+                path: iteration.try_get("path")?,
+            },
+            run: RunFKJson { run_uuid },
+        }))?;
+        num_documents += 1;
+    }
+
+    let params = sqlx::query(
+        "SELECT param.iteration_uuid, param.arg, param.val
+        FROM param JOIN iteration USING (iteration_uuid)
+        WHERE iteration.run_uuid = $1 ORDER BY iteration.num, param.arg",
+    )
+    .bind(run_uuid)
+    .fetch_all(pool)
+    .await?;
+    for param in params {
+        emit(BodyJson::Param(ParamJson {
+            cdm: cdm(),
+            param: ParamSpecJson {
+                arg: param
+                    .try_get::<Option<String>, _>("arg")?
+                    .unwrap_or_default(),
+                val: param
+                    .try_get::<Option<String>, _>("val")?
+                    .unwrap_or_default(),
+            },
+            iteration: IterationFKJson {
+                iteration_uuid: param.try_get("iteration_uuid")?,
+            },
+            run: RunFKJson { run_uuid },
+        }))?;
+        num_documents += 1;
+    }
+
+    let samples = sqlx::query(
+        "SELECT sample.sample_uuid, sample.iteration_uuid, sample.num, sample.status, sample.path
+        FROM sample JOIN iteration USING (iteration_uuid)
+        WHERE iteration.run_uuid = $1 ORDER BY iteration.num, sample.num",
+    )
+    .bind(run_uuid)
+    .fetch_all(pool)
+    .await?;
+    for sample in samples {
+        let iteration_uuid: Uuid = sample.try_get("iteration_uuid")?;
+        if global_iterations.contains(&iteration_uuid) {
+            continue;
+        }
+        emit(BodyJson::Sample(SampleJson {
+            cdm: cdm(),
+            sample: SampleSpecJson {
+                sample_uuid: sample.try_get("sample_uuid")?,
+                path: sample.try_get("path")?,
+                status: sample
+                    .try_get::<Option<String>, _>("status")?
+                    .unwrap_or_default(),
+                num: sample.try_get::<Option<i64>, _>("num")?.unwrap_or_default(),
+            },
+            iteration: IterationFKJson { iteration_uuid },
+            run: RunFKJson { run_uuid },
+        }))?;
+        num_documents += 1;
+    }
+
+    let periods = sqlx::query(
+        "SELECT period.period_uuid, period.sample_uuid, sample.iteration_uuid,
+            period.begin, period.finish, period.name
+        FROM period
+            JOIN sample USING (sample_uuid)
+            JOIN iteration USING (iteration_uuid)
+        WHERE iteration.run_uuid = $1 ORDER BY period.begin",
+    )
+    .bind(run_uuid)
+    .fetch_all(pool)
+    .await?;
+    for period in periods {
+        let iteration_uuid: Uuid = period.try_get("iteration_uuid")?;
+        if global_iterations.contains(&iteration_uuid) {
+            continue;
+        }
+        emit(BodyJson::Period(PeriodJson {
+            cdm: cdm(),
+            period: PeriodSpecJson {
+                period_uuid: period.try_get("period_uuid")?,
+                begin: period.try_get("begin")?,
+                end: period.try_get("finish")?,
+                name: period
+                    .try_get::<Option<String>, _>("name")?
+                    .unwrap_or_default(),
+            },
+            iteration: IterationFKJson { iteration_uuid },
+            run: RunFKJson { run_uuid },
+            sample: SampleFKJson {
+                sample_uuid: period.try_get("sample_uuid")?,
+            },
+        }))?;
+        num_documents += 1;
+    }
+
+    // The metric_desc scdm adds to the global period has no data worth keeping
+    let mut skipped_metric_descs = HashSet::new();
+    let metric_descs = sqlx::query(
+        "SELECT metric_desc.metric_desc_uuid, metric_desc.period_uuid, period.sample_uuid,
+            sample.iteration_uuid, metric_desc.class, metric_desc.metric_type,
+            metric_desc.source, metric_desc.names_list, metric_desc.names, metric_desc.unit
+        FROM metric_desc
+            JOIN period USING (period_uuid)
+            JOIN sample USING (sample_uuid)
+            JOIN iteration USING (iteration_uuid)
+        WHERE iteration.run_uuid = $1",
+    )
+    .bind(run_uuid)
+    .fetch_all(pool)
+    .await?;
+    for metric_desc in metric_descs {
+        let metric_desc_uuid: Uuid = metric_desc.try_get("metric_desc_uuid")?;
+        let iteration_uuid: Uuid = metric_desc.try_get("iteration_uuid")?;
+        let metric_type: String = metric_desc.try_get("metric_type")?;
+        let source: String = metric_desc.try_get("source")?;
+        let global = global_iterations.contains(&iteration_uuid);
+        if global && metric_type == "global" && source == "global" {
+            skipped_metric_descs.insert(metric_desc_uuid);
+            continue;
+        }
+        let names: Option<Json<HashMap<String, Value>>> = metric_desc.try_get("names")?;
+        emit(BodyJson::MetricDesc(MetricDescJson {
+            cdm: cdm(),
+            metric_desc: MetricDescSpecJson {
+                metric_desc_uuid,
+                class: metric_desc.try_get("class")?,
+                names: names.map(|n| n.0).unwrap_or_default(),
+                names_list: metric_desc
+                    .try_get::<Option<Vec<String>>, _>("names_list")?
+                    .unwrap_or_default(),
+                source,
+                metric_type,
+                unit: metric_desc.try_get("unit")?,
+            },
+            iteration: (!global).then_some(IterationFKJson { iteration_uuid }),
+            period: (!global).then_some(PeriodFKJson {
+                period_uuid: metric_desc.try_get("period_uuid")?,
+            }),
+            run: RunFKJson { run_uuid },
+            sample: (!global).then_some(SampleFKJson {
+                sample_uuid: metric_desc.try_get("sample_uuid")?,
+            }),
+        }))?;
+        num_documents += 1;
+    }
+
+    // The metric data can be large, so it's streamed rather than collected
+    let mut metric_datas = sqlx::query(
+        "SELECT metric_data.metric_desc_uuid, metric_data.value, metric_data.begin,
+            metric_data.finish, metric_data.duration
+        FROM metric_data
+            JOIN metric_desc USING (metric_desc_uuid)
+            JOIN period USING (period_uuid)
+            JOIN sample USING (sample_uuid)
+            JOIN iteration USING (iteration_uuid)
+        WHERE iteration.run_uuid = $1
+        ORDER BY metric_data.metric_desc_uuid, metric_data.begin",
+    )
+    .bind(run_uuid)
+    .fetch(pool);
+    while let Some(metric_data) = metric_datas.try_next().await? {
+        let metric_desc_uuid: Uuid = metric_data.try_get("metric_desc_uuid")?;
+        if skipped_metric_descs.contains(&metric_desc_uuid) {
+            continue;
+        }
+        emit(BodyJson::MetricData(MetricDataJson {
+            cdm: cdm(),
+            metric_data: MetricDataSpecJson {
+                begin: metric_data.try_get::<DateTime<Utc>, _>("begin")?,
+                end: metric_data.try_get::<DateTime<Utc>, _>("finish")?,
+                duration: metric_data.try_get("duration")?,
+                value: metric_data.try_get("value")?,
+            },
+            metric_desc: MetricDescFKJson { metric_desc_uuid },
+            run: RunFKJson { run_uuid },
+        }))?;
+        num_documents += 1;
+    }
+
+    Ok(num_documents)
+}
+
+/// Writes the runs, or every run that isn't soft deleted, to a gzipped
+/// ndjson archive that `scdm restore` can load into any scdm database
+pub async fn backup(pool: &PgPool, args: BackupArgs) -> Result<()> {
+    let run_uuids: Vec<Uuid> = match args.run_uuid {
+        Some(run_uuids) => run_uuids,
+        None => {
+            sqlx::query_scalar("SELECT run_uuid FROM run WHERE deleted_at IS NULL ORDER BY begin")
+                .fetch_all(pool)
+                .await?
+        }
+    };
+
+    let anonymizer = Anonymizer::from_opts(&args.anonymize)?;
+    let f = File::create(&args.out)
+        .map_err(|e| BackupError::WriteFailed(args.out.clone(), format!("{}", e)))?;
+    let mut writer = GzEncoder::new(BufWriter::new(f), Compression::default());
+
+    let mut num_documents = 0;
+    for run_uuid in &run_uuids {
+        num_documents += run_documents(pool, *run_uuid, |mut document| {
+            if let Some(anonymizer) = &anonymizer {
+                anonymizer.anonymize(&mut document);
+            }
+            write_document(&mut writer, &document)
+        })
+        .await?;
+    }
+    writer
+        .finish()
+        .and_then(|mut w| w.flush())
+        .map_err(|e| BackupError::WriteFailed(args.out.clone(), format!("{}", e)))?;
+
+    report!(
+        "backed up {} runs ({} documents) to {}",
+        run_uuids.len(),
+        num_documents,
+        args.out
+    );
+    Ok(())
+}
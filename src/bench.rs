@@ -0,0 +1,316 @@
+use crate::add;
+use crate::args::{BenchArgs, BenchSection, DisplayOpts};
+use crate::cdm;
+use crate::metric;
+use crate::parser::{self, BodyJson, GlobalResource, IngestOpts};
+use crate::report::SQL_PRIMARY_SUMMARIES;
+use anyhow::Result;
+use serde_json::{Value, json};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum BenchError {
+    #[error("{0}")]
+    BenchFailed(String),
+}
+
+/// Rows inserted into a table and how long it took
+struct Ingest {
+    table: &'static str,
+    rows: u64,
+    elapsed: Duration,
+}
+
+/// The queries that are timed, each binds the run
+const QUERIES: [(&str, &str); 5] = [
+    ("run", "SELECT * FROM run WHERE run_uuid = $1"),
+    (
+        "iterations",
+        r#"
+        SELECT iteration.*, param.arg, param.val
+        FROM iteration
+        LEFT JOIN param ON param.iteration_uuid = iteration.iteration_uuid
+        WHERE iteration.run_uuid = $1
+        "#,
+    ),
+    (
+        "metric_types",
+        r#"
+        SELECT DISTINCT metric_desc.metric_type
+        FROM metric_desc
+        JOIN period ON period.period_uuid = metric_desc.period_uuid
+        JOIN sample ON sample.sample_uuid = period.sample_uuid
+        JOIN iteration ON iteration.iteration_uuid = sample.iteration_uuid
+        WHERE iteration.run_uuid = $1
+        "#,
+    ),
+    (
+        "hostname_breakout",
+        r#"
+        SELECT iteration.iteration_uuid, name.val, avg(metric_data.value)
+        FROM iteration
+        JOIN sample ON sample.iteration_uuid = iteration.iteration_uuid
+        JOIN period ON period.sample_uuid = sample.sample_uuid
+        JOIN metric_desc ON metric_desc.period_uuid = period.period_uuid
+        JOIN name ON name.metric_desc_uuid = metric_desc.metric_desc_uuid
+        JOIN metric_data ON metric_data.metric_desc_uuid = metric_desc.metric_desc_uuid
+        WHERE iteration.run_uuid = $1
+            AND metric_desc.metric_type = iteration.primary_metric
+            AND name.name = 'hostname'
+        GROUP BY iteration.iteration_uuid, name.val
+        "#,
+    ),
+    ("primary_summaries", SQL_PRIMARY_SUMMARIES),
+];
+
+/// A run of the requested size, in the JSON `scdm add` reads, starting an
+/// hour ago so its data lands in an existing partition
+fn synthetic_run(args: &BenchArgs, run_uuid: Uuid) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let begin = now - 3_600_000;
+    let step = 3_600_000 / args.points.max(1) as i64;
+    let iterations: Vec<Value> = (1..=args.iterations)
+        .map(|i| {
+            let samples: Vec<Value> = (1..=args.samples)
+                .map(|s| {
+                    let metrics: Vec<Value> = (0..args.metrics)
+                        .map(|m| {
+                            let data: Vec<Value> = (0..args.points as i64)
+                                .map(|p| {
+                                    let from = begin + p * step;
+                                    json!([from, from + step - 1, (p % 100) as f64 + m as f64])
+                                })
+                                .collect();
+                            json!({
+                                "class": "throughput",
+                                "metric-type": if m == 0 { "ops-sec" } else { "bytes-sec" },
+                                "source": "bench",
+                                "names": {"hostname": format!("host-{}", m % 8), "cpu": m.to_string()},
+                                "data": data,
+                            })
+                        })
+                        .collect();
+                    json!({
+                        "num": s,
+                        "status": "pass",
+                        "periods": [{
+                            "begin": begin.to_string(),
+                            "finish": (begin + step * args.points as i64).to_string(),
+                            "name": "measurement",
+                            "metrics": metrics,
+                        }],
+                    })
+                })
+                .collect();
+            json!({
+                "num": i,
+                "status": "pass",
+                "primary_metric": "ops-sec",
+                "primary_period": "measurement",
+                "params": {},
+                "samples": samples,
+            })
+        })
+        .collect();
+    json!({
+        "run-uuid": run_uuid,
+        "begin": begin.to_string(),
+        "finish": now.to_string(),
+        "benchmark": "scdm-bench",
+        "email": "scdm@localhost",
+        "name": "scdm bench",
+        "description": "synthetic run of scdm bench",
+        "source": "scdm",
+        "tags": {"bench": "true"},
+        "iterations": iterations,
+    })
+}
+
+/// Inserts the records as parser::insert_records does, timing each table
+async fn timed_inserts(
+    txn: &mut Transaction<'_, Postgres>,
+    records: &[BodyJson],
+    opts: &IngestOpts,
+) -> Result<Vec<Ingest>> {
+    let mut runs = Vec::new();
+    let mut tags = Vec::new();
+    let mut iterations = Vec::new();
+    let mut params = Vec::new();
+    let mut samples = Vec::new();
+    let mut periods = Vec::new();
+    let mut metric_descs = Vec::new();
+    let mut metric_datas = Vec::new();
+    let mut names = Vec::new();
+    for record in records {
+        match record {
+            BodyJson::Run(run) => runs.push(run),
+            BodyJson::Tag(tag) => tags.push(tag),
+            BodyJson::Iteration(iteration) => iterations.push(iteration),
+            BodyJson::Param(param) => params.push(param),
+            BodyJson::Sample(sample) => samples.push(sample),
+            BodyJson::Period(period) => periods.push(period),
+            BodyJson::MetricDesc(metric_desc) => metric_descs.push(metric_desc),
+            BodyJson::MetricData(metric_data) => metric_datas.push(metric_data),
+            BodyJson::Name(name) => names.push(name.clone()),
+        };
+    }
+    names.extend(
+        metric_descs
+            .clone()
+            .into_iter()
+            .flat_map(parser::extract_names),
+    );
+    let names = names.iter().collect();
+    let mut globals: HashMap<Uuid, GlobalResource> = HashMap::new();
+
+    let mut ingests = vec![];
+    macro_rules! timed {
+        ($table:expr, $insert:expr) => {{
+            let start = Instant::now();
+            let rows = $insert.await?;
+            ingests.push(Ingest {
+                table: $table,
+                rows,
+                elapsed: start.elapsed(),
+            });
+        }};
+    }
+    // The run's global iteration and friends are left out, bench data has
+    // no run-scoped metrics
+    let start = Instant::now();
+    let (rows, ..) = parser::insert_runs(txn, &mut globals, &runs, opts).await?;
+    ingests.push(Ingest {
+        table: "run",
+        rows,
+        elapsed: start.elapsed(),
+    });
+    timed!("tag", parser::insert_tags(txn, &tags, opts));
+    timed!(
+        "iteration",
+        parser::insert_iterations(txn, &iterations, opts)
+    );
+    timed!("param", parser::insert_params(txn, &params, opts));
+    timed!("sample", parser::insert_samples(txn, &samples, opts));
+    timed!("period", parser::insert_periods(txn, &periods, opts));
+    timed!(
+        "metric_desc",
+        parser::insert_metric_descs(txn, &globals, &metric_descs, opts)
+    );
+    timed!("name", parser::insert_names(txn, &names, opts));
+    timed!(
+        "metric_data",
+        parser::insert_metric_datas(txn, &metric_datas, opts)
+    );
+    Ok(ingests)
+}
+
+/// The latency of each query in milliseconds, sorted
+async fn time_query(
+    txn: &mut Transaction<'_, Postgres>,
+    sql: &str,
+    run_uuid: Uuid,
+    repeat: usize,
+) -> Result<Vec<f64>> {
+    let mut latencies = vec![];
+    for _ in 0..repeat.max(1) {
+        let start = Instant::now();
+        sqlx::query(sql)
+            .bind(run_uuid)
+            .fetch_all(&mut **txn)
+            .await?;
+        latencies.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    latencies.sort_by(f64::total_cmp);
+    Ok(latencies)
+}
+
+/// The latency at the fraction of the sorted latencies
+fn percentile(latencies: &[f64], fraction: f64) -> f64 {
+    let rank = (fraction * (latencies.len() - 1) as f64).round() as usize;
+    latencies[rank]
+}
+
+/// Ingests a synthetic run and times the common queries against it, inside
+/// a transaction that's rolled back so nothing is left behind
+pub async fn bench(pool: &PgPool, args: BenchArgs, opts: &IngestOpts) -> Result<()> {
+    let sections = match args.section {
+        Some(section) => vec![section],
+        None if args.output.is_some() => {
+            return Err(BenchError::BenchFailed(
+                "pick a --section to output as json or csv".to_string(),
+            )
+            .into());
+        }
+        None => vec![BenchSection::Ingest, BenchSection::Queries],
+    };
+    let run_uuid = cdm::new_uuid(opts.uuid_v7);
+    let run = serde_json::to_vec(&synthetic_run(&args, run_uuid))?;
+    let records = add::parse_run_nodes("bench", &run, opts.uuid_v7)?;
+
+    let mut txn = pool.begin().await?;
+    let ingests = timed_inserts(&mut txn, &records, opts).await?;
+    let mut latencies = vec![];
+    if sections.contains(&BenchSection::Queries) {
+        for (name, sql) in QUERIES {
+            latencies.push((
+                name,
+                time_query(&mut txn, sql, run_uuid, args.repeat).await?,
+            ));
+        }
+    }
+    txn.rollback().await?;
+
+    for section in sections {
+        let (header, rows): (&[&str], Vec<Vec<String>>) = match section {
+            BenchSection::Ingest => (
+                &["table", "rows", "ms", "rows_per_sec"],
+                ingests
+                    .iter()
+                    .filter(|ingest| ingest.rows > 0)
+                    .map(|ingest| {
+                        let secs = ingest.elapsed.as_secs_f64();
+                        vec![
+                            ingest.table.to_string(),
+                            ingest.rows.to_string(),
+                            format!("{:.1}", secs * 1000.0),
+                            format!("{:.0}", ingest.rows as f64 / secs.max(f64::EPSILON)),
+                        ]
+                    })
+                    .collect(),
+            ),
+            BenchSection::Queries => (
+                &["query", "runs", "min_ms", "median_ms", "p95_ms", "max_ms"],
+                latencies
+                    .iter()
+                    .map(|(name, latencies)| {
+                        vec![
+                            name.to_string(),
+                            latencies.len().to_string(),
+                            format!("{:.2}", latencies[0]),
+                            format!("{:.2}", percentile(latencies, 0.5)),
+                            format!("{:.2}", percentile(latencies, 0.95)),
+                            format!("{:.2}", latencies[latencies.len() - 1]),
+                        ]
+                    })
+                    .collect(),
+            ),
+        };
+        println!(
+            "{}",
+            metric::format_rows(
+                header.iter().map(|c| c.to_string()).collect(),
+                rows,
+                args.output.clone(),
+                &DisplayOpts::default()
+            )?
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// The rows of a finished metric query. Timestamps are kept in RFC 3339 and
+/// UTC, and rendered for display after loading
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachedResult {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// The columns holding timestamps
+    pub times: Vec<usize>,
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = env::var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .ok()?;
+    Some(base.join("scdm").join("metric"))
+}
+
+/// Content address for a query, built from everything that determines its result
+pub fn key(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns the cached result for `key` if it was stored less than `ttl` ago
+pub fn load(key: &str, ttl: Duration) -> Option<CachedResult> {
+    let path = cache_dir()?.join(format!("{}.json", key));
+    let modified = fs::metadata(&path).ok()?.modified().ok()?;
+    if SystemTime::now().duration_since(modified).ok()? > ttl {
+        return None;
+    }
+    let contents = fs::read(&path).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+pub fn store(key: &str, result: &CachedResult) -> Result<()> {
+    if let Some(dir) = cache_dir() {
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join(format!("{}.json", key)),
+            serde_json::to_vec(result)?,
+        )?;
+    }
+    Ok(())
+}
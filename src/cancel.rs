@@ -0,0 +1,162 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::{PgConnectOptions, PgConnection};
+use sqlx::{ConnectOptions, Connection};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+
+#[derive(Error, Debug)]
+pub enum CancelError {
+    #[error("Cancelled, {0}")]
+    Cancelled(String),
+    #[error("Cancelled before the next statement")]
+    Stopped,
+}
+
+/// The cancellation and progress of a command. The pools record their
+/// sessions in it as they connect and the ingest counts its rows in it, so
+/// an interrupted command can be cancelled and say how far it got
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<State>);
+
+#[derive(Debug, Default)]
+struct State {
+    /// The sessions of the pools, by pid and when they started since a pid
+    /// can be reused
+    backends: Mutex<Vec<(i32, DateTime<Utc>)>>,
+    cancelled: AtomicBool,
+    inserted: AtomicU64,
+    committed: AtomicU64,
+}
+
+/// How long a cancelled command gets to roll back before it's dropped
+const UNWIND_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Cancellation {
+    /// Records the session of a new connection, so its statements can be
+    /// cancelled. Called as the pool connects
+    pub async fn track_backend(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        let backend: (i32, DateTime<Utc>) = sqlx::query_as(
+            "SELECT pid, backend_start FROM pg_stat_activity WHERE pid = pg_backend_pid()",
+        )
+        .fetch_one(conn)
+        .await?;
+        self.0.backends.lock().unwrap().push(backend);
+        Ok(())
+    }
+
+    /// Fails once the command has been interrupted, so it starts no more
+    /// statements while it unwinds
+    pub fn check(&self) -> Result<(), CancelError> {
+        match self.0.cancelled.load(Ordering::Relaxed) {
+            true => Err(CancelError::Stopped),
+            false => Ok(()),
+        }
+    }
+
+    /// Counts rows an insert added, committed or not
+    pub fn add_inserted(&self, rows: u64) {
+        self.0.inserted.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// Counts rows a transaction committed
+    pub fn add_committed(&self, rows: u64) {
+        self.0.committed.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// How far the command got, in rows
+    fn progress(&self) -> String {
+        match (
+            self.0.inserted.load(Ordering::Relaxed),
+            self.0.committed.load(Ordering::Relaxed),
+        ) {
+            (0, _) => "nothing had been added".to_string(),
+            (inserted, 0) => format!("the {} rows inserted were rolled back", inserted),
+            (inserted, committed) if committed >= inserted => {
+                format!("the {} rows inserted had already been committed", committed)
+            }
+            (inserted, committed) => format!(
+                "{} of the {} rows inserted had been committed, the rest were rolled back",
+                committed, inserted
+            ),
+        }
+    }
+
+    /// Cancels whatever statements the pools' sessions are running, over a
+    /// connection of its own since the pool's may all be busy
+    async fn cancel_statements(&self, conn_opts: &PgConnectOptions) -> Result<u64> {
+        let (pids, starts): (Vec<i32>, Vec<DateTime<Utc>>) =
+            self.0.backends.lock().unwrap().iter().copied().unzip();
+        let mut conn = conn_opts.connect().await?;
+        let cancelled: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FILTER (WHERE pg_cancel_backend(activity.pid))
+            FROM pg_stat_activity AS activity
+            JOIN unnest($1::int[], $2::timestamptz[]) AS backend(pid, backend_start)
+                ON backend.pid = activity.pid AND backend.backend_start = activity.backend_start
+            WHERE activity.state = 'active'
+            "#,
+        )
+        .bind(pids)
+        .bind(starts)
+        .fetch_one(&mut conn)
+        .await?;
+        conn.close().await?;
+        Ok(cancelled as u64)
+    }
+
+    /// Runs the command until it's done or interrupted by SIGINT or SIGTERM.
+    /// An interrupted command has its statements cancelled, then gets a
+    /// moment to roll back before it's dropped, and fails saying how far it
+    /// got. A second signal exits right away
+    pub async fn run(
+        &self,
+        conn_opts: &PgConnectOptions,
+        command: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        let mut command = std::pin::pin!(command);
+        // The handlers are installed as the signal is first polled, which has
+        // to be before the command gets to work
+        tokio::select! {
+            biased;
+            _ = interrupted() => {}
+            done = &mut command => return done,
+        }
+
+        self.0.cancelled.store(true, Ordering::Relaxed);
+        info!("interrupted, cancelling");
+        match self.cancel_statements(conn_opts).await {
+            Ok(cancelled) => info!("cancelled {} running statements", cancelled),
+            Err(e) => warn!("couldn't cancel the running statements ({})", e),
+        }
+        let unwound = tokio::select! {
+            unwound = tokio::time::timeout(UNWIND_TIMEOUT, &mut command) => unwound.ok(),
+            _ = interrupted() => std::process::exit(130),
+        };
+        // Finishing anyway means the signal came too late to stop anything
+        if let Some(Ok(())) = unwound {
+            return Ok(());
+        }
+        Err(CancelError::Cancelled(self.progress()).into())
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM
+async fn interrupted() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    tokio::signal::ctrl_c().await.ok();
+}
@@ -1,3 +1,4 @@
+use crate::render;
 use chrono::{DateTime, Utc};
 use serde::Serialize;
 use sqlx::prelude::FromRow;
@@ -5,41 +6,43 @@ use tabled::Tabled;
 use tabled::derive::display;
 use uuid::Uuid;
 
-pub const SQL_TABLE_RUN: &str = r#"
-    CREATE TABLE IF NOT EXISTS run (
-        run_uuid uuid PRIMARY KEY,
-        begin timestamptz NOT NULL,
-        finish timestamptz NOT NULL,
-        benchmark text,
-        email text,
-        name text,
-        description text,
-        source text
-    )
-"#;
+/// A UUID for a resource scdm makes up, rather than one from the results.
+/// UUIDv7s are time ordered, which keeps the B-tree indexes append only and
+/// sorts them roughly by when they were made
+pub fn new_uuid(v7: bool) -> Uuid {
+    if v7 { Uuid::now_v7() } else { Uuid::new_v4() }
+}
+
+/// Makes up the UUID of a resource the results left it out of, which is
+/// nil until then
+pub fn make_up_uuid(uuid: &mut Uuid, v7: bool) {
+    if uuid.is_nil() {
+        *uuid = new_uuid(v7);
+    }
+}
 
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct Run {
     pub run_uuid: Uuid,
+    #[tabled(display = "render::time")]
     pub begin: DateTime<Utc>,
+    #[tabled(display = "render::time")]
     pub finish: DateTime<Utc>,
+    /// finish - begin
+    #[tabled(rename = "duration", display = "render::elapsed")]
+    pub duration_ms: i64,
     pub benchmark: String,
     pub email: String,
     pub name: String,
     #[tabled(display("display::option", "null"))]
     pub description: Option<String>,
     pub source: String,
+    #[tabled(display("display::option", "null"))]
+    pub owner: Option<String>,
+    #[tabled(display("render::option_time", "null"))]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
-pub const SQL_TABLE_TAG: &str = r#"
-    CREATE TABLE IF NOT EXISTS tag (
-        run_uuid uuid REFERENCES run ON DELETE CASCADE,
-        name text,
-        val text,
-        PRIMARY KEY (run_uuid, name)
-    )
-"#;
-
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct Tag {
     pub run_uuid: Uuid,
@@ -47,18 +50,6 @@ pub struct Tag {
     pub val: String,
 }
 
-pub const SQL_TABLE_ITERATION: &str = r#"
-    CREATE TABLE IF NOT EXISTS iteration (
-        iteration_uuid uuid PRIMARY KEY,
-        run_uuid uuid REFERENCES run ON DELETE CASCADE,
-        num bigint NOT NULL,
-        status text,
-        path text,
-        primary_metric text NOT NULL,
-        primary_period text NOT NULL
-    )
-"#;
-
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct Iteration {
     pub iteration_uuid: Uuid,
@@ -72,17 +63,12 @@ pub struct Iteration {
     pub primary_metric: Option<String>,
     #[tabled(display("display::option", "null"))]
     pub primary_period: Option<String>,
+    /// From the first of its periods beginning to the last finishing, if it
+    /// has any
+    #[tabled(rename = "duration", display("render::option_elapsed", "null"))]
+    pub duration_ms: Option<i64>,
 }
 
-pub const SQL_TABLE_PARAM: &str = r#"
-    CREATE TABLE IF NOT EXISTS param (
-        iteration_uuid uuid REFERENCES iteration ON DELETE CASCADE,
-        arg text,
-        val text,
-        PRIMARY KEY (iteration_uuid, arg)
-    )
-"#;
-
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct Param {
     pub iteration_uuid: Uuid,
@@ -90,16 +76,6 @@ pub struct Param {
     pub val: String,
 }
 
-pub const SQL_TABLE_SAMPLE: &str = r#"
-    CREATE TABLE IF NOT EXISTS sample (
-        sample_uuid uuid PRIMARY KEY,
-        iteration_uuid uuid REFERENCES iteration ON DELETE CASCADE,
-        num bigint,
-        status text,
-        path text
-    )
-"#;
-
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct Sample {
     pub sample_uuid: Uuid,
@@ -110,37 +86,20 @@ pub struct Sample {
     pub path: Option<String>,
 }
 
-pub const SQL_TABLE_PERIOD: &str = r#"
-    CREATE TABLE IF NOT EXISTS period (
-        period_uuid uuid PRIMARY KEY,
-        sample_uuid uuid REFERENCES sample ON DELETE CASCADE,
-        begin timestamptz NOT NULL,
-        finish timestamptz NOT NULL,
-        name text
-    )
-"#;
-
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct Period {
     pub period_uuid: Uuid,
     pub sample_uuid: Uuid,
+    #[tabled(display = "render::time")]
     pub begin: DateTime<Utc>,
+    #[tabled(display = "render::time")]
     pub finish: DateTime<Utc>,
     pub name: String,
+    /// finish - begin
+    #[tabled(rename = "duration", display = "render::elapsed")]
+    pub duration_ms: i64,
 }
 
-pub const SQL_TABLE_METRIC_DESC: &str = r#"
-    CREATE TABLE IF NOT EXISTS metric_desc (
-        metric_desc_uuid uuid PRIMARY KEY,
-        period_uuid uuid REFERENCES period ON DELETE CASCADE,
-        class text NOT NULL,
-        metric_type text NOT NULL,
-        source text NOT NULL,
-        names_list text,
-        names text
-    )
-"#;
-
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct MetricDesc {
     pub metric_desc_uuid: Uuid,
@@ -149,16 +108,18 @@ pub struct MetricDesc {
     pub class: String,
     pub metric_type: String,
     pub source: String,
+    #[tabled(display("display_list"))]
+    pub names_list: Option<Vec<String>>,
+    #[tabled(display("display::option", "null"))]
+    pub unit: Option<String>,
 }
 
-pub const SQL_TABLE_NAME: &str = r#"
-    CREATE TABLE IF NOT EXISTS name (
-        metric_desc_uuid uuid REFERENCES metric_desc ON DELETE CASCADE,
-        name text NOT NULL,
-        val text NOT NULL,
-        PRIMARY KEY (metric_desc_uuid, name)
-    )
-"#;
+fn display_list(list: &Option<Vec<String>>) -> String {
+    match list {
+        Some(list) => list.join(","),
+        None => "null".to_string(),
+    }
+}
 
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct Name {
@@ -167,24 +128,327 @@ pub struct Name {
     pub val: String,
 }
 
-pub const SQL_TABLE_METRIC_DATA: &str = r#"
-    CREATE TABLE IF NOT EXISTS metric_data (
-        metric_data_id bigserial,
-        metric_desc_uuid uuid REFERENCES metric_desc ON DELETE CASCADE,
-        value double precision NOT NULL,
-        begin timestamptz NOT NULL,
-        finish timestamptz NOT NULL,
-        duration bigint NOT NULL,
-        PRIMARY KEY (metric_data_id, metric_desc_uuid)
-    )
-"#;
-
 #[derive(Clone, Debug, FromRow, Tabled, Serialize)]
 pub struct MetricData {
     pub metric_data_id: i64,
     pub metric_desc_uuid: Uuid,
+    #[tabled(display = "render::time")]
     pub begin: DateTime<Utc>,
+    #[tabled(display = "render::time")]
     pub finish: DateTime<Utc>,
+    #[tabled(display = "render::millis")]
     pub duration: i64,
+    #[tabled(display = "render::value")]
     pub value: f64,
 }
+
+#[derive(Clone, Debug, FromRow, Tabled, Serialize)]
+pub struct MetricDataRollup {
+    pub metric_desc_uuid: Uuid,
+    #[tabled(display = "render::millis")]
+    pub interval_ms: i64,
+    #[tabled(display = "render::time")]
+    pub begin: DateTime<Utc>,
+    #[tabled(display = "render::time")]
+    pub finish: DateTime<Utc>,
+    #[tabled(display = "render::value")]
+    pub min: f64,
+    #[tabled(display = "render::value")]
+    pub max: f64,
+    #[tabled(display = "render::value")]
+    pub avg: f64,
+    #[tabled(display = "render::value")]
+    pub sum: f64,
+    pub sum_sq: f64,
+    pub count: i64,
+}
+
+#[derive(Clone, Debug, FromRow, Tabled, Serialize)]
+pub struct RunLink {
+    pub from_run_uuid: Uuid,
+    pub relation: String,
+    pub to_run_uuid: Uuid,
+    #[tabled(display = "render::time")]
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, FromRow, Tabled, Serialize)]
+pub struct AuditEntry {
+    pub audit_id: i64,
+    #[tabled(display = "render::time")]
+    pub at: DateTime<Utc>,
+    pub db_user: String,
+    #[tabled(display("display::option", "null"))]
+    pub os_user: Option<String>,
+    pub command: String,
+    #[tabled(display("display::option", "null"))]
+    pub filters: Option<String>,
+    #[tabled(display("display::option", "null"))]
+    pub rows_affected: Option<i64>,
+    #[tabled(display("render::option_millis", "null"))]
+    pub duration_ms: Option<i64>,
+}
+
+/// How long `scdm prune` keeps the runs a policy covers
+#[derive(Clone, Debug, FromRow, Tabled, Serialize)]
+pub struct RetentionPolicy {
+    #[tabled(display("display::option", "any"))]
+    pub benchmark: Option<String>,
+    #[tabled(display("display::option", "any"))]
+    pub tag: Option<String>,
+    #[tabled(display("display::option", "forever"))]
+    pub keep: Option<String>,
+    #[tabled(display = "render::time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A file attached to a run, without its content
+#[derive(Clone, Debug, FromRow, Tabled, Serialize)]
+pub struct Artifact {
+    pub artifact_uuid: Uuid,
+    pub run_uuid: Uuid,
+    pub name: String,
+    #[tabled(display("display::option", "null"))]
+    pub location: Option<String>,
+    #[tabled(display("display::option", "null"))]
+    pub checksum: Option<String>,
+    #[tabled(display("display::option", "null"))]
+    pub content_type: Option<String>,
+    #[tabled(display("render::option_bytes", "null"))]
+    pub size: Option<i64>,
+    pub embedded: bool,
+    #[tabled(display = "render::time")]
+    pub added: DateTime<Utc>,
+}
+
+/// Per-run overview, excluding the synthetic global iteration every run gets.
+pub const SQL_VIEW_RUN_SUMMARY: &str = r#"
+    CREATE MATERIALIZED VIEW IF NOT EXISTS mv_run_summary AS
+    SELECT
+        run.run_uuid,
+        run.benchmark,
+        run.name,
+        run.email,
+        run.begin,
+        run.finish,
+        (EXTRACT(EPOCH FROM (run.finish - run.begin)) * 1000)::bigint as duration_ms,
+        COUNT(DISTINCT iteration.iteration_uuid) as iterations,
+        COUNT(DISTINCT iteration.iteration_uuid) FILTER (WHERE iteration.status = 'pass') as passed_iterations,
+        COUNT(DISTINCT sample.sample_uuid) as samples,
+        COUNT(DISTINCT period.period_uuid) as periods
+    FROM run
+    LEFT JOIN iteration
+        ON iteration.run_uuid = run.run_uuid AND iteration.primary_period <> 'global'
+    LEFT JOIN sample
+        ON sample.iteration_uuid = iteration.iteration_uuid
+    LEFT JOIN period
+        ON period.sample_uuid = sample.sample_uuid
+    GROUP BY run.run_uuid
+"#;
+
+pub const SQL_INDEX_RUN_SUMMARY: &str = r#"
+    CREATE UNIQUE INDEX IF NOT EXISTS mv_run_summary_run_uuid ON mv_run_summary (run_uuid)
+"#;
+
+/// The primary metric of each iteration, computed per sample as the sum over
+/// its descriptors of their duration-weighted average in the primary period,
+/// then summarized across samples. The primary metric may be given either as
+/// a bare metric type or as "source::type".
+pub const SQL_VIEW_ITERATION_PRIMARY_METRIC: &str = r#"
+    CREATE MATERIALIZED VIEW IF NOT EXISTS mv_iteration_primary_metric AS
+    WITH desc_values AS (
+        SELECT
+            sample.iteration_uuid,
+            sample.sample_uuid,
+            SUM(metric_data.value * metric_data.duration) / NULLIF(SUM(metric_data.duration), 0) as value
+        FROM iteration
+        JOIN sample
+            ON sample.iteration_uuid = iteration.iteration_uuid
+        JOIN period
+            ON period.sample_uuid = sample.sample_uuid AND period.name = iteration.primary_period
+        JOIN metric_desc
+            ON metric_desc.period_uuid = period.period_uuid AND (
+                metric_desc.metric_type = iteration.primary_metric OR
+                metric_desc.source || '::' || metric_desc.metric_type = iteration.primary_metric
+            )
+        JOIN metric_data
+            ON metric_data.metric_desc_uuid = metric_desc.metric_desc_uuid
+        GROUP BY sample.iteration_uuid, sample.sample_uuid, metric_desc.metric_desc_uuid
+    ), sample_values AS (
+        SELECT iteration_uuid, sample_uuid, SUM(value) as value
+        FROM desc_values
+        GROUP BY iteration_uuid, sample_uuid
+    )
+    SELECT
+        iteration.iteration_uuid,
+        iteration.run_uuid,
+        iteration.primary_metric,
+        iteration.primary_period,
+        COUNT(sample_values.sample_uuid) as samples,
+        AVG(sample_values.value) as mean,
+        STDDEV(sample_values.value) as stddev,
+        MIN(sample_values.value) as min,
+        MAX(sample_values.value) as max
+    FROM iteration
+    LEFT JOIN sample_values
+        ON sample_values.iteration_uuid = iteration.iteration_uuid
+    WHERE iteration.primary_period <> 'global'
+    GROUP BY iteration.iteration_uuid
+"#;
+
+pub const SQL_INDEX_ITERATION_PRIMARY_METRIC: &str = r#"
+    CREATE UNIQUE INDEX IF NOT EXISTS mv_iteration_primary_metric_iteration_uuid
+        ON mv_iteration_primary_metric (iteration_uuid)
+"#;
+
+/// Every materialized view `init --views` creates, in creation order
+pub const MATERIALIZED_VIEWS: &[&str] = &["mv_run_summary", "mv_iteration_primary_metric"];
+
+/// Each run that isn't soft deleted with its tags and how many iterations,
+/// samples and periods it has
+pub const SQL_VIEW_V_RUN_SUMMARY: &str = r#"
+    CREATE OR REPLACE VIEW v_run_summary AS
+    SELECT
+        run.run_uuid,
+        run.benchmark,
+        run.name,
+        run.email,
+        run.description,
+        run.source,
+        run.begin,
+        run.finish,
+        (EXTRACT(EPOCH FROM (run.finish - run.begin)) * 1000)::bigint as duration_ms,
+        (SELECT jsonb_object_agg(tag.name, tag.val) FROM tag WHERE tag.run_uuid = run.run_uuid) as tags,
+        (SELECT COUNT(*) FROM iteration WHERE iteration.run_uuid = run.run_uuid) as iterations,
+        (
+            SELECT COUNT(*) FROM sample
+            JOIN iteration ON iteration.iteration_uuid = sample.iteration_uuid
+            WHERE iteration.run_uuid = run.run_uuid
+        ) as samples,
+        (
+            SELECT COUNT(*) FROM period
+            JOIN sample ON sample.sample_uuid = period.sample_uuid
+            JOIN iteration ON iteration.iteration_uuid = sample.iteration_uuid
+            WHERE iteration.run_uuid = run.run_uuid
+        ) as periods
+    FROM run
+    WHERE run.deleted_at IS NULL
+"#;
+
+/// Every data point joined up through its descriptor, period, sample and
+/// iteration to its run, leaving out soft deleted runs
+pub const SQL_VIEW_V_METRIC_DATA_FULL: &str = r#"
+    CREATE OR REPLACE VIEW v_metric_data_full AS
+    SELECT
+        run.run_uuid,
+        run.benchmark,
+        run.name as run_name,
+        iteration.iteration_uuid,
+        iteration.num as iteration_num,
+        iteration.status as iteration_status,
+        sample.sample_uuid,
+        sample.num as sample_num,
+        period.period_uuid,
+        period.name as period_name,
+        metric_desc.metric_desc_uuid,
+        metric_desc.class,
+        metric_desc.source,
+        metric_desc.metric_type,
+        metric_desc.unit,
+        metric_desc.names,
+        metric_data.metric_data_id,
+        metric_data.value,
+        metric_data.begin,
+        metric_data.finish,
+        metric_data.duration
+    FROM metric_data
+    JOIN metric_desc
+        ON metric_desc.metric_desc_uuid = metric_data.metric_desc_uuid
+    JOIN period
+        ON period.period_uuid = metric_desc.period_uuid
+    JOIN sample
+        ON sample.sample_uuid = period.sample_uuid
+    JOIN iteration
+        ON iteration.iteration_uuid = sample.iteration_uuid
+    JOIN run
+        ON run.run_uuid = iteration.run_uuid
+    WHERE run.deleted_at IS NULL
+"#;
+
+/// The data points of each iteration's primary metric in its primary period,
+/// matched the same way as mv_iteration_primary_metric
+pub const SQL_VIEW_V_PRIMARY_METRICS: &str = r#"
+    CREATE OR REPLACE VIEW v_primary_metrics AS
+    SELECT
+        v_metric_data_full.*,
+        iteration.primary_metric,
+        iteration.primary_period
+    FROM v_metric_data_full
+    JOIN iteration
+        ON iteration.iteration_uuid = v_metric_data_full.iteration_uuid
+    WHERE
+        v_metric_data_full.period_name = iteration.primary_period AND (
+            v_metric_data_full.metric_type = iteration.primary_metric OR
+            v_metric_data_full.source || '::' || v_metric_data_full.metric_type = iteration.primary_metric
+        )
+"#;
+
+/// Every plain view `init` creates, in creation order, with its definition
+pub const CONVENIENCE_VIEWS: &[(&str, &str)] = &[
+    ("v_run_summary", SQL_VIEW_V_RUN_SUMMARY),
+    ("v_metric_data_full", SQL_VIEW_V_METRIC_DATA_FULL),
+    ("v_primary_metrics", SQL_VIEW_V_PRIMARY_METRICS),
+];
+
+/// Every table holding CDM data, parents before children
+pub const DATA_TABLES: &[&str] = &[
+    "run",
+    "tag",
+    "iteration",
+    "param",
+    "sample",
+    "period",
+    "metric_desc",
+    "name",
+    "metric_data",
+    "metric_data_rollup",
+    "metric_data_rollup_state",
+    "artifact",
+    "run_link",
+    "audit_log",
+    "iteration_summary",
+];
+
+/// Tables holding settings, which are kept when the data is truncated
+pub const SETTINGS_TABLES: &[&str] = &["retention_tiers", "retention_policy"];
+
+/// Tables that track the schema itself rather than data
+pub const SCHEMA_TABLES: &[&str] = &["schema_version", "_sqlx_migrations"];
+
+/// The indexes the migrations create, which queries rely on to stay fast
+pub const INDEXES: &[&str] = &[
+    "iteration_run_uuid_idx",
+    "sample_iteration_uuid_idx",
+    "period_sample_uuid_idx",
+    "metric_desc_period_uuid_idx",
+    "metric_data_metric_desc_uuid_begin_idx",
+    "metric_desc_metric_type_idx",
+    "name_name_val_idx",
+    "tag_name_val_idx",
+    "run_begin_idx",
+    "period_begin_finish_idx",
+    "metric_data_rollup_interval_ms_begin_idx",
+    "metric_desc_names_idx",
+    "metric_desc_names_list_idx",
+    "metric_data_begin_finish_brin_idx",
+    "artifact_run_uuid_idx",
+    "run_link_to_run_uuid_idx",
+    "run_deleted_at_idx",
+    "audit_log_at_idx",
+];
+
+/// The role `init --create-roles` grants read access to
+pub const READER_ROLE: &str = "scdm_reader";
+
+/// The role `init --create-roles` grants read-write access to
+pub const WRITER_ROLE: &str = "scdm_writer";
@@ -0,0 +1,410 @@
+use crate::args::{CompareArgs, CompareOutput, DisplayOpts, OutputFormat};
+use crate::report::{SQL_PRIMARY_SUMMARIES, Summary};
+use crate::{metric, render};
+use anyhow::Result;
+use serde_json::json;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum CompareError {
+    #[error("No baseline for run {0}, pass --baseline or link one with `scdm link`")]
+    NoBaseline(Uuid),
+    #[error("No run {0}")]
+    NotFound(Uuid),
+    #[error("Couldn't notify the webhook {0}, {1}")]
+    NotifyFailed(String, String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+    Missing,
+    /// The baseline is zero, so there's no percentage to judge a change by
+    ZeroBaseline,
+}
+
+/// The colors of the verdicts in the table
+const VERDICT_HIGHLIGHTS: &str = "verdict==regression=red,verdict==improvement=green,\
+     verdict==missing=yellow,verdict==zero-baseline=yellow";
+
+impl Verdict {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Regression => "regression",
+            Verdict::Improvement => "improvement",
+            Verdict::Unchanged => "unchanged",
+            Verdict::Missing => "missing",
+            Verdict::ZeroBaseline => "zero-baseline",
+        }
+    }
+
+    fn emoji(&self) -> &'static str {
+        match self {
+            Verdict::Regression => "❌",
+            Verdict::Improvement => "🚀",
+            Verdict::Unchanged => "✅",
+            Verdict::Missing => "❔",
+            Verdict::ZeroBaseline => "➖",
+        }
+    }
+}
+
+/// An iteration's primary metric in both runs
+struct Comparison {
+    iteration: String,
+    metric: String,
+    baseline: Option<f64>,
+    new: Option<f64>,
+    delta_pct: Option<f64>,
+    verdict: Verdict,
+}
+
+/// The mean primary metric of each iteration, keyed by its params, or by its
+/// number when it has none, so the iterations of both runs line up. The
+/// iterations that share params are averaged together
+async fn primary_means(pool: &PgPool, run_uuid: Uuid) -> Result<BTreeMap<String, (String, f64)>> {
+    let raw_query: &str = r#"
+        SELECT
+            iteration.iteration_uuid,
+            iteration.num,
+            iteration.primary_metric,
+            string_agg(param.arg || '=' || param.val, ' ' ORDER BY param.arg, param.val)
+        FROM iteration
+        JOIN run ON run.run_uuid = iteration.run_uuid
+        LEFT JOIN param ON param.iteration_uuid = iteration.iteration_uuid
+        WHERE
+            iteration.run_uuid = $1 AND
+            iteration.primary_period <> 'global' AND
+            run.deleted_at IS NULL
+        GROUP BY iteration.iteration_uuid
+    "#;
+    let iterations: Vec<(Uuid, i64, Option<String>, Option<String>)> = sqlx::query_as(raw_query)
+        .bind(run_uuid)
+        .fetch_all(pool)
+        .await?;
+    let summaries: BTreeMap<Uuid, Summary> = sqlx::query_as(SQL_PRIMARY_SUMMARIES)
+        .bind(run_uuid)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|summary: Summary| (summary.0, summary))
+        .collect();
+    let mut sums: BTreeMap<String, (String, f64, usize)> = BTreeMap::new();
+    for (iteration_uuid, num, primary_metric, params) in iterations {
+        let Some((_, _, Some(mean), ..)) = summaries.get(&iteration_uuid) else {
+            continue;
+        };
+        let key = params.unwrap_or_else(|| format!("#{}", num));
+        let sum = sums
+            .entry(key)
+            .or_insert((primary_metric.unwrap_or_default(), 0.0, 0));
+        sum.1 += mean;
+        sum.2 += 1;
+    }
+    Ok(sums
+        .into_iter()
+        .map(|(key, (metric, sum, count))| (key, (metric, sum / count as f64)))
+        .collect())
+}
+
+fn compare_means(
+    args: &CompareArgs,
+    baseline: BTreeMap<String, (String, f64)>,
+    mut new: BTreeMap<String, (String, f64)>,
+) -> Vec<Comparison> {
+    let mut comparisons = vec![];
+    for (iteration, (metric, baseline)) in baseline {
+        let new = new.remove(&iteration).map(|(_, new)| new);
+        let delta_pct = new
+            .filter(|_| baseline != 0.0)
+            .map(|new| (new - baseline) / baseline.abs() * 100.0);
+        let verdict = match delta_pct {
+            None if new.is_some() => Verdict::ZeroBaseline,
+            None => Verdict::Missing,
+            Some(delta) if delta.abs() <= args.threshold => Verdict::Unchanged,
+            Some(delta) => {
+                if (delta > 0.0) != args.lower_is_better.contains(&metric) {
+                    Verdict::Improvement
+                } else {
+                    Verdict::Regression
+                }
+            }
+        };
+        comparisons.push(Comparison {
+            iteration,
+            metric,
+            baseline: Some(baseline),
+            new,
+            delta_pct,
+            verdict,
+        });
+    }
+    // Iterations only the new run has
+    for (iteration, (metric, new)) in new {
+        comparisons.push(Comparison {
+            iteration,
+            metric,
+            baseline: None,
+            new: Some(new),
+            delta_pct: None,
+            verdict: Verdict::Missing,
+        });
+    }
+    comparisons
+}
+
+fn number(value: Option<f64>) -> String {
+    value
+        .map(|v| format!("{:.3}", v))
+        .unwrap_or("null".to_string())
+}
+
+/// A table for a pull request comment, with the regressions first
+fn markdown_report(
+    benchmark: &str,
+    baseline: Uuid,
+    run_uuid: Uuid,
+    threshold: f64,
+    comparisons: &[Comparison],
+) -> String {
+    let mut comparisons: Vec<&Comparison> = comparisons.iter().collect();
+    comparisons.sort_by_key(|c| match c.verdict {
+        Verdict::Regression => 0,
+        Verdict::Improvement => 1,
+        Verdict::Missing => 2,
+        Verdict::ZeroBaseline => 3,
+        Verdict::Unchanged => 4,
+    });
+    let count = |verdict| comparisons.iter().filter(|c| c.verdict == verdict).count();
+    let regressions = count(Verdict::Regression);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "### {} {}: {}",
+        if regressions > 0 { "❌" } else { "✅" },
+        benchmark,
+        match regressions {
+            0 => "no regressions".to_string(),
+            1 => "1 regression".to_string(),
+            n => format!("{} regressions", n),
+        }
+    );
+    let _ = writeln!(
+        out,
+        "\nBaseline `{}`, new `{}`, changes within ±{}% are unchanged\n",
+        baseline, run_uuid, threshold
+    );
+    let _ = writeln!(
+        out,
+        "| metric | iteration | baseline | new | delta % | verdict |\n\
+         |---|---|---:|---:|---:|:-:|"
+    );
+    let escape = |s: &str| s.replace('|', "\\|");
+    for c in &comparisons {
+        let _ = writeln!(
+            out,
+            "| {} | {} | {} | {} | {} | {} |",
+            escape(&c.metric),
+            escape(&c.iteration),
+            number(c.baseline),
+            number(c.new),
+            c.delta_pct
+                .map(|d| format!("{:+.2}%", d))
+                .unwrap_or("n/a".to_string()),
+            c.verdict.emoji()
+        );
+    }
+    let _ = write!(
+        out,
+        "\n{} regressed, {} improved, {} unchanged, {} missing from one run, \
+         {} with a zero baseline",
+        regressions,
+        count(Verdict::Improvement),
+        count(Verdict::Unchanged),
+        count(Verdict::Missing),
+        count(Verdict::ZeroBaseline)
+    );
+    out
+}
+
+/// Posts the regressions to the webhook. Slack shows the text, other
+/// receivers can use the rest of the fields
+async fn notify(
+    url: &str,
+    benchmark: &str,
+    baseline: Uuid,
+    args: &CompareArgs,
+    regressions: &[&Comparison],
+) -> Result<()> {
+    let mut text = format!(
+        ":x: {} run {} regressed against baseline {} in {} metric(s)",
+        benchmark,
+        args.run_uuid,
+        baseline,
+        regressions.len()
+    );
+    for c in regressions {
+        let _ = write!(
+            text,
+            "\n• {} {}: {} → {} ({})",
+            c.metric,
+            c.iteration,
+            number(c.baseline),
+            number(c.new),
+            c.delta_pct
+                .map(|d| format!("{:+.2}%", d))
+                .unwrap_or("n/a".to_string())
+        );
+    }
+    let payload = json!({
+        "text": text,
+        "benchmark": benchmark,
+        "run_uuid": args.run_uuid,
+        "baseline": baseline,
+        "threshold_pct": args.threshold,
+        "regressions": regressions
+            .iter()
+            .map(|c| json!({
+                "metric": c.metric,
+                "iteration": c.iteration,
+                "baseline": c.baseline,
+                "new": c.new,
+                "delta_pct": c.delta_pct,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    let response = reqwest::Client::new()
+        .post(url)
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| CompareError::NotifyFailed(url.to_string(), e.to_string()))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(CompareError::NotifyFailed(
+            url.to_string(),
+            format!("{} {}", status.as_u16(), body),
+        )
+        .into());
+    }
+    info!("notified {} of {} regressions", url, regressions.len());
+    Ok(())
+}
+
+/// Compares the primary metric of each iteration of a run with its baseline
+pub async fn compare(pool: &PgPool, args: CompareArgs) -> Result<()> {
+    let benchmark: String =
+        sqlx::query_scalar("SELECT benchmark FROM run WHERE run_uuid = $1 AND deleted_at IS NULL")
+            .bind(args.run_uuid)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(CompareError::NotFound(args.run_uuid))?;
+    let baseline = match args.baseline {
+        Some(baseline) => {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM run WHERE run_uuid = $1 AND deleted_at IS NULL)",
+            )
+            .bind(baseline)
+            .fetch_one(pool)
+            .await?;
+            if !exists {
+                return Err(CompareError::NotFound(baseline).into());
+            }
+            baseline
+        }
+        None => sqlx::query_scalar(
+            r#"
+            SELECT run_link.from_run_uuid FROM run_link
+            JOIN run ON run.run_uuid = run_link.from_run_uuid
+            WHERE
+                run_link.to_run_uuid = $1 AND
+                run_link.relation = 'baseline-for' AND
+                run.deleted_at IS NULL
+            ORDER BY run_link.created DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(args.run_uuid)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(CompareError::NoBaseline(args.run_uuid))?,
+    };
+    let baseline_means = primary_means(pool, baseline).await?;
+    let new_means = primary_means(pool, args.run_uuid).await?;
+    let comparisons = compare_means(&args, baseline_means, new_means);
+
+    let output = match args.output {
+        Some(CompareOutput::MarkdownReport) => {
+            println!(
+                "{}",
+                markdown_report(
+                    &benchmark,
+                    baseline,
+                    args.run_uuid,
+                    args.threshold,
+                    &comparisons
+                )
+            );
+            None
+        }
+        Some(CompareOutput::JSON) => Some(Some(OutputFormat::JSON)),
+        Some(CompareOutput::CSV) => Some(Some(OutputFormat::CSV)),
+        Some(CompareOutput::NDJSON) => Some(Some(OutputFormat::NDJSON)),
+        None => Some(None),
+    };
+    if let Some(output) = output {
+        let header = [
+            "metric",
+            "iteration",
+            "baseline",
+            "new",
+            "delta_pct",
+            "verdict",
+        ];
+        let rows = comparisons
+            .iter()
+            .map(|c| {
+                vec![
+                    c.metric.clone(),
+                    c.iteration.clone(),
+                    number(c.baseline),
+                    number(c.new),
+                    number(c.delta_pct),
+                    c.verdict.as_str().to_string(),
+                ]
+            })
+            .collect();
+        let display = DisplayOpts {
+            highlight: render::parse_highlights(VERDICT_HIGHLIGHTS)?,
+            ..Default::default()
+        };
+        println!(
+            "{}",
+            metric::format_rows(
+                header.iter().map(|c| c.to_string()).collect(),
+                rows,
+                output,
+                &display
+            )?
+        );
+    }
+
+    let regressions: Vec<&Comparison> = comparisons
+        .iter()
+        .filter(|c| c.verdict == Verdict::Regression)
+        .collect();
+    if let Some(url) = &args.notify_webhook
+        && !regressions.is_empty()
+    {
+        notify(url, &benchmark, baseline, &args, &regressions).await?;
+    }
+    Ok(())
+}
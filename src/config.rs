@@ -0,0 +1,433 @@
+use crate::args::{Command, DisplayOpts, GlobalOpts, OutputFormat, TableStyle};
+use crate::metric;
+use crate::render::{self, RenderError, Timezone};
+use anyhow::Result;
+use clap::ArgMatches;
+use clap::ValueEnum;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Couldn't read the config file {0}, {1}")]
+    ReadFailed(String, String),
+    #[error("No profile {0} in {1}")]
+    UnknownProfile(String, String),
+    #[error("Unknown output format {0} in profile {1}")]
+    UnknownOutput(String, String),
+    #[error("Unknown table style {0} in profile {1}")]
+    UnknownTableStyle(String, String),
+    #[error("{0} in profile {1}")]
+    InvalidTimezone(RenderError, String),
+    #[error("{0} in profile {1}")]
+    InvalidTimeFormat(RenderError, String),
+}
+
+/// A named set of settings, used for whatever isn't given on the command line
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub db_uri: Option<String>,
+    pub db_user: Option<String>,
+    pub db_password: Option<String>,
+    pub db_password_file: Option<String>,
+    pub db_url: Option<String>,
+    pub db_socket: Option<String>,
+    pub db_port: Option<u16>,
+    pub db_name: Option<String>,
+    pub db_schema: Option<String>,
+    pub db_max_connections: Option<u32>,
+    pub replica_uri: Option<String>,
+    /// An interval, ex: 30s
+    pub db_acquire_timeout: Option<String>,
+    /// An interval, ex: 10m
+    pub db_idle_timeout: Option<String>,
+    /// An interval, ex: 5m
+    pub statement_timeout: Option<String>,
+    pub workspace: Option<String>,
+    pub opensearch_url: Option<String>,
+    pub opensearch_user: Option<String>,
+    pub opensearch_password: Option<String>,
+    pub opensearch_password_file: Option<String>,
+    /// Who you are, see --owner
+    pub owner: Option<String>,
+    /// Only allow the commands that read the data, regardless of --read-only
+    pub read_only: Option<bool>,
+    /// The output format of the commands that print tables by default
+    pub output: Option<String>,
+    /// The timezone of the timestamps in `query` results
+    pub timezone: Option<String>,
+    /// The strftime format of the timestamps in `query` results
+    pub time_format: Option<String>,
+    /// How `query` results are drawn as tables
+    pub table_style: Option<String>,
+    /// The widest a `query` table's cells are drawn before they're cut short
+    pub max_col_width: Option<usize>,
+    /// Whether `query` tables show UUIDs in full
+    pub full_ids: Option<bool>,
+    /// Whether `query` tables show values with SI or IEC suffixes
+    pub human: Option<bool>,
+    /// The most results `query get` prints
+    pub limit: Option<usize>,
+}
+
+/// The contents of config.toml
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The profile used when --profile isn't given, "default" otherwise
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+/// SCDM_CONFIG, or config.toml in the scdm directory of the user's config
+/// directory, ex: ~/.config/scdm/config.toml
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = env::var("SCDM_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok()?;
+    Some(config_dir.join("scdm").join("config.toml"))
+}
+
+/// Finds the profile to use, and its name. Naming a profile that doesn't
+/// exist is an error, but having no config file or default profile isn't.
+pub fn load_profile(name: Option<&str>) -> Result<Option<(String, Profile)>, ConfigError> {
+    let Some(path) = config_path() else {
+        return Ok(None);
+    };
+    let path_str = path.display().to_string();
+    let config: Config = match std::fs::read_to_string(&path) {
+        Ok(contents) => toml::from_str(&contents)
+            .map_err(|e| ConfigError::ReadFailed(path_str.clone(), format!("{}", e)))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(ConfigError::ReadFailed(path_str, format!("{}", e))),
+    };
+    let mut profiles = config.profiles;
+    match name.map(str::to_string).or(config.default_profile) {
+        Some(name) => match profiles.remove(&name) {
+            Some(profile) => Ok(Some((name, profile))),
+            None => Err(ConfigError::UnknownProfile(name, path_str)),
+        },
+        None => Ok(profiles
+            .remove("default")
+            .map(|profile| ("default".to_string(), profile))),
+    }
+}
+
+/// What the profile supplied, for --show-config to tell apart from the
+/// defaults
+#[derive(Debug, Default)]
+pub struct Applied {
+    /// The profile's name, when config.toml's default_profile picked it
+    pub profile: Option<String>,
+    /// The settings the profile filled in
+    pub supplied: HashSet<&'static str>,
+}
+
+/// Fills in an option that wasn't given on the command line, noting it
+fn fill<T>(
+    opt: &mut Option<T>,
+    value: Option<T>,
+    id: &'static str,
+    supplied: &mut HashSet<&'static str>,
+) {
+    if opt.is_none() && value.is_some() {
+        *opt = value;
+        supplied.insert(id);
+    }
+}
+
+impl Profile {
+    /// Fills in the options that weren't given on the command line, and
+    /// returns which ones it filled
+    pub fn apply(self, opts: &mut GlobalOpts) -> HashSet<&'static str> {
+        let mut supplied = HashSet::new();
+        let s = &mut supplied;
+        fill(&mut opts.db_uri, self.db_uri, "db_uri", s);
+        fill(&mut opts.db_user, self.db_user, "db_user", s);
+        // A password from the command line replaces the profile's either way
+        if opts.db_password.is_none() && opts.db_password_file.is_none() {
+            fill(&mut opts.db_password, self.db_password, "db_password", s);
+            fill(
+                &mut opts.db_password_file,
+                self.db_password_file,
+                "db_password_file",
+                s,
+            );
+        }
+        fill(&mut opts.db_url, self.db_url, "db_url", s);
+        fill(&mut opts.db_socket, self.db_socket, "db_socket", s);
+        fill(
+            &mut opts.db_port,
+            self.db_port.map(|p| p.to_string()),
+            "db_port",
+            s,
+        );
+        fill(&mut opts.db_name, self.db_name, "db_name", s);
+        fill(
+            &mut opts.db_max_connections,
+            self.db_max_connections,
+            "db_max_connections",
+            s,
+        );
+        fill(&mut opts.replica_uri, self.replica_uri, "replica_uri", s);
+        fill(
+            &mut opts.db_acquire_timeout,
+            self.db_acquire_timeout,
+            "db_acquire_timeout",
+            s,
+        );
+        fill(
+            &mut opts.db_idle_timeout,
+            self.db_idle_timeout,
+            "db_idle_timeout",
+            s,
+        );
+        fill(
+            &mut opts.statement_timeout,
+            self.statement_timeout,
+            "statement_timeout",
+            s,
+        );
+        // A schema or workspace from the command line replaces the profile's
+        if opts.db_schema.is_none() && opts.workspace.is_none() {
+            fill(&mut opts.db_schema, self.db_schema, "db_schema", s);
+            if opts.db_schema.is_none() {
+                fill(&mut opts.workspace, self.workspace, "workspace", s);
+            }
+        }
+        fill(
+            &mut opts.opensearch_url,
+            self.opensearch_url,
+            "opensearch_url",
+            s,
+        );
+        fill(
+            &mut opts.opensearch_user,
+            self.opensearch_user,
+            "opensearch_user",
+            s,
+        );
+        if opts.opensearch_password.is_none() && opts.opensearch_password_file.is_none() {
+            fill(
+                &mut opts.opensearch_password,
+                self.opensearch_password,
+                "opensearch_password",
+                s,
+            );
+            fill(
+                &mut opts.opensearch_password_file,
+                self.opensearch_password_file,
+                "opensearch_password_file",
+                s,
+            );
+        }
+        fill(&mut opts.owner, self.owner, "owner", s);
+        if !opts.read_only && self.read_only == Some(true) {
+            opts.read_only = true;
+            s.insert("read_only");
+        }
+        supplied
+    }
+
+    /// The display options, checked as the command line ones are
+    pub fn display(&self, name: &str) -> Result<DisplayOpts, ConfigError> {
+        let timezone = self
+            .timezone
+            .as_deref()
+            .map(|t| {
+                Timezone::parse(t).map_err(|e| ConfigError::InvalidTimezone(e, name.to_string()))
+            })
+            .transpose()?;
+        let time_format = self
+            .time_format
+            .as_deref()
+            .map(|f| {
+                render::parse_time_format(f)
+                    .map_err(|e| ConfigError::InvalidTimeFormat(e, name.to_string()))
+            })
+            .transpose()?;
+        let table_style = self
+            .table_style
+            .as_deref()
+            .map(|s| {
+                TableStyle::from_str(s, true)
+                    .map_err(|_| ConfigError::UnknownTableStyle(s.to_string(), name.to_string()))
+            })
+            .transpose()?;
+        Ok(DisplayOpts {
+            timezone,
+            time_format,
+            table_style,
+            max_col_width: self.max_col_width,
+            full_ids: self.full_ids,
+            human: self.human,
+            ..Default::default()
+        })
+    }
+
+    pub fn output(&self, name: &str) -> Result<Option<OutputFormat>, ConfigError> {
+        self.output
+            .as_deref()
+            .map(|o| {
+                OutputFormat::from_str(o, true)
+                    .map_err(|_| ConfigError::UnknownOutput(o.to_string(), name.to_string()))
+            })
+            .transpose()
+    }
+}
+
+/// Loads the profile and applies it to the command line options
+pub fn apply_profile(
+    opts: &mut GlobalOpts,
+    command: Option<&mut Command>,
+) -> Result<Applied, ConfigError> {
+    let Some((name, profile)) = load_profile(opts.profile.as_deref())? else {
+        return Ok(Applied::default());
+    };
+    let name = name.as_str();
+    if let Some(command) = command {
+        if let Some(output) = profile.output(name)?
+            && let Some(command_output) = command.output_mut()
+        {
+            command_output.get_or_insert(output);
+        }
+        let display = profile.display(name)?;
+        if let Some(command_display) = command.display_mut() {
+            command_display.timezone = command_display.timezone.or(display.timezone);
+            command_display.time_format =
+                command_display.time_format.take().or(display.time_format);
+            command_display.table_style = command_display.table_style.or(display.table_style);
+            command_display.max_col_width = command_display.max_col_width.or(display.max_col_width);
+            command_display.full_ids = command_display.full_ids.or(display.full_ids);
+            command_display.human = command_display.human.or(display.human);
+        }
+        if let Some(limit) = profile.limit
+            && let Some(command_limit) = command.limit_mut()
+        {
+            command_limit.get_or_insert(limit);
+        }
+    }
+    // config.toml's default_profile picked it, rather than --profile
+    let picked = (opts.profile.is_none() && name != "default").then(|| name.to_string());
+    let mut supplied = profile.apply(opts);
+    if picked.is_some() {
+        supplied.insert("profile");
+    }
+    Ok(Applied {
+        profile: picked,
+        supplied,
+    })
+}
+
+/// Hides the password in a connection URL
+fn mask_uri(uri: &str) -> String {
+    let Some((scheme, rest)) = uri.split_once("://") else {
+        return uri.to_string();
+    };
+    match rest.split_once('@') {
+        Some((user_info, host)) if user_info.contains(':') => {
+            let user = user_info.split(':').next().unwrap_or_default();
+            format!("{}://{}:********@{}", scheme, user, host)
+        }
+        _ => uri.to_string(),
+    }
+}
+
+/// Prints each connection setting, its value and where the value came from:
+/// a flag, an Env variable, the config file or the default
+pub fn show_config(matches: &ArgMatches, opts: &GlobalOpts, applied: &Applied) -> Result<()> {
+    let secret = |s: &Option<String>| s.as_ref().map(|_| "********".to_string());
+    let flag = |b: bool| b.then(|| "true".to_string());
+    let settings: Vec<(&str, Option<String>, Option<&str>)> = vec![
+        (
+            "profile",
+            opts.profile.clone().or(applied.profile.clone()),
+            Some("default"),
+        ),
+        ("db_uri", opts.db_uri.as_deref().map(mask_uri), None),
+        ("db_user", opts.db_user.clone(), None),
+        ("db_password", secret(&opts.db_password), None),
+        ("db_password_file", opts.db_password_file.clone(), None),
+        ("use_keyring", flag(opts.use_keyring), Some("false")),
+        ("db_url", opts.db_url.clone(), None),
+        ("db_socket", opts.db_socket.clone(), None),
+        ("db_port", opts.db_port.clone(), Some("5432")),
+        ("db_name", opts.db_name.clone(), Some("scdm")),
+        ("db_schema", opts.db_schema.clone(), None),
+        ("workspace", opts.workspace.clone(), None),
+        (
+            "db_max_connections",
+            opts.db_max_connections.map(|n| n.to_string()),
+            Some("10"),
+        ),
+        (
+            "db_acquire_timeout",
+            opts.db_acquire_timeout.clone(),
+            Some("30s"),
+        ),
+        ("db_idle_timeout", opts.db_idle_timeout.clone(), Some("10m")),
+        ("statement_timeout", opts.statement_timeout.clone(), None),
+        (
+            "replica_uri",
+            opts.replica_uri.as_deref().map(mask_uri),
+            None,
+        ),
+        (
+            "opensearch_url",
+            opts.opensearch_url.clone(),
+            Some("http://localhost:9200"),
+        ),
+        ("opensearch_user", opts.opensearch_user.clone(), None),
+        (
+            "opensearch_password",
+            secret(&opts.opensearch_password),
+            None,
+        ),
+        (
+            "opensearch_password_file",
+            opts.opensearch_password_file.clone(),
+            None,
+        ),
+        ("owner", opts.owner.clone(), None),
+        ("read_only", flag(opts.read_only), Some("false")),
+        ("uuid_v7", flag(opts.uuid_v7), Some("false")),
+    ];
+
+    let rows = settings
+        .into_iter()
+        .map(|(id, value, default)| {
+            let source = match matches.value_source(id) {
+                Some(ValueSource::CommandLine) => "flag",
+                Some(ValueSource::EnvVariable) => "env",
+                _ if applied.supplied.contains(id) => "config file",
+                _ if default.is_some() => "default",
+                _ => "unset",
+            };
+            let value = value.or(default.map(str::to_string)).unwrap_or_default();
+            vec![id.to_string(), value, source.to_string()]
+        })
+        .collect();
+    let header = vec![
+        "setting".to_string(),
+        "value".to_string(),
+        "source".to_string(),
+    ];
+    if let Some(path) = config_path() {
+        println!("config file: {}", path.display());
+    }
+    println!(
+        "{}",
+        metric::format_rows(header, rows, None, &DisplayOpts::default())?
+    );
+    Ok(())
+}
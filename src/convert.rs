@@ -0,0 +1,42 @@
+use crate::add;
+use crate::args::ConvertArgs;
+use crate::export::NdjsonDir;
+use crate::parser;
+use crate::report;
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConvertError {
+    #[error("Couldn't read {0}, {1}")]
+    ReadFailed(String, String),
+}
+
+/// Writes JSON results, or the runs in a backup, out as CDM ndjson. The
+/// UUIDs the results leave out are made up, as UUIDv7s when `uuid_v7` is set
+pub fn convert(args: ConvertArgs, uuid_v7: bool) -> Result<()> {
+    let mut out = NdjsonDir::create(&args.out)?;
+    let mut num_documents = 0;
+    // Backups are gzipped ndjson, and get streamed through
+    if args.path.ends_with(".gz") {
+        let f = File::open(&args.path)
+            .map_err(|e| ConvertError::ReadFailed(args.path.clone(), format!("{}", e)))?;
+        for document in parser::ndjson_documents(BufReader::new(GzDecoder::new(f))) {
+            out.write(&document?)?;
+            num_documents += 1;
+        }
+    } else {
+        for document in add::read_run_nodes(Path::new(&args.path), uuid_v7)? {
+            out.write(&document)?;
+            num_documents += 1;
+        }
+    }
+    out.flush()?;
+
+    report!("converted {} documents to {}", num_documents, args.out);
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use keyring::Entry;
+use std::io::{self, IsTerminal};
+use thiserror::Error;
+
+/// The service scdm's passwords are kept under in the OS keyring
+pub const KEYRING_SERVICE: &str = "scdm";
+
+#[derive(Error, Debug)]
+pub enum CredentialsError {
+    #[error("No password was given, and there's no terminal to ask for one on")]
+    NoTerminal,
+    #[error("Couldn't read the password, {0}")]
+    PromptFailed(String),
+    #[error("Couldn't use the keyring, {0}")]
+    KeyringFailed(String),
+    #[error("Couldn't read the secret file {0}, {1}")]
+    SecretFileFailed(String, String),
+}
+
+/// Reads a secret mounted as a file, like Kubernetes secrets and systemd
+/// credentials are, without the trailing newline
+pub fn read_secret_file(path: &str) -> Result<String, CredentialsError> {
+    let secret = std::fs::read_to_string(path)
+        .map_err(|e| CredentialsError::SecretFileFailed(path.to_string(), e.to_string()))?;
+    Ok(secret.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// A secret given directly, or else read from the file it's given in
+pub fn secret(
+    secret: Option<String>,
+    file: Option<String>,
+) -> Result<Option<String>, CredentialsError> {
+    match secret {
+        Some(secret) => Ok(Some(secret)),
+        None => file.map(|path| read_secret_file(&path)).transpose(),
+    }
+}
+
+fn keyring_entry(user: &str, host: &str, port: u16) -> Result<Entry, CredentialsError> {
+    Entry::new(KEYRING_SERVICE, &format!("{}@{}:{}", user, host, port))
+        .map_err(|e| CredentialsError::KeyringFailed(e.to_string()))
+}
+
+/// The password stored for the user on this server, if there is one
+pub fn keyring_password(
+    user: &str,
+    host: &str,
+    port: u16,
+) -> Result<Option<String>, CredentialsError> {
+    match keyring_entry(user, host, port)?.get_password() {
+        Ok(password) => Ok(Some(password)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(CredentialsError::KeyringFailed(e.to_string())),
+    }
+}
+
+pub fn save_keyring_password(
+    user: &str,
+    host: &str,
+    port: u16,
+    password: &str,
+) -> Result<(), CredentialsError> {
+    keyring_entry(user, host, port)?
+        .set_password(password)
+        .map_err(|e| CredentialsError::KeyringFailed(e.to_string()))
+}
+
+/// Asks for the password without echoing it
+pub fn prompt_password(user: &str, host: &str) -> Result<String, CredentialsError> {
+    if !io::stdin().is_terminal() {
+        return Err(CredentialsError::NoTerminal);
+    }
+    rpassword::prompt_password(format!("Password for {}@{}: ", user, host))
+        .map_err(|e| CredentialsError::PromptFailed(e.to_string()))
+}
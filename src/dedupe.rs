@@ -0,0 +1,88 @@
+use crate::args::{DedupeArgs, DedupeCommand, DedupeMetricDataArgs};
+use crate::owner::{self, Actor};
+use crate::report;
+use crate::{audit, rollup};
+use anyhow::Result;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum DedupeError {
+    #[error("Couldn't remove the duplicates, {0}")]
+    DedupeFailed(String),
+}
+
+/// Deletes all but the first of each set of identical metric_data rows, and
+/// rolls up the runs they belonged to again
+async fn dedupe_metric_data(
+    pool: &PgPool,
+    args: DedupeMetricDataArgs,
+    actor: &Actor,
+) -> Result<()> {
+    let raw_query: &str = r#"
+        WITH deduped AS (
+            DELETE FROM metric_data
+            WHERE metric_data_id IN (
+                SELECT metric_data_id
+                FROM (
+                    SELECT metric_data_id,
+                        row_number() OVER (
+                            PARTITION BY metric_desc_uuid, begin, finish, value
+                            ORDER BY metric_data_id
+                        ) AS copy
+                    FROM metric_data
+                    WHERE $1::uuid[] IS NULL OR metric_desc_uuid IN (
+                        SELECT metric_desc.metric_desc_uuid
+                        FROM metric_desc
+                            JOIN period USING (period_uuid)
+                            JOIN sample USING (sample_uuid)
+                            JOIN iteration USING (iteration_uuid)
+                        WHERE iteration.run_uuid = ANY($1)
+                    )
+                ) AS numbered
+                WHERE copy > 1
+            )
+            RETURNING metric_desc_uuid
+        )
+        SELECT deduped.metric_desc_uuid, run.run_uuid, run.owner
+        FROM deduped
+            LEFT JOIN metric_desc USING (metric_desc_uuid)
+            LEFT JOIN period USING (period_uuid)
+            LEFT JOIN sample USING (sample_uuid)
+            LEFT JOIN iteration USING (iteration_uuid)
+            LEFT JOIN run ON run.run_uuid = iteration.run_uuid
+    "#;
+    let mut txn = pool.begin().await?;
+    let rows: Vec<(Uuid, Option<Uuid>, Option<String>)> = sqlx::query_as(raw_query)
+        .bind(&args.run_uuid)
+        .fetch_all(&mut *txn)
+        .await
+        .map_err(|e| DedupeError::DedupeFailed(format!("{}", e)))?;
+    let removed = rows.len() as u64;
+    let runs: Vec<(Uuid, Option<String>)> = rows
+        .iter()
+        .filter_map(|(_, run_uuid, owner)| run_uuid.map(|run_uuid| (run_uuid, owner.clone())))
+        .collect();
+    owner::check(actor, "dedupe", &runs)?;
+    let mut deduped: Vec<Uuid> = rows.into_iter().map(|(desc, _, _)| desc).collect();
+    deduped.sort();
+    deduped.dedup();
+    let rolled_up = rollup::refresh(&mut txn, &deduped).await?;
+    if rolled_up > 0 {
+        info!("rolled up {} buckets again", rolled_up);
+    }
+    audit::record(&mut *txn, "dedupe metric-data", &args, removed).await?;
+    txn.commit().await?;
+    report!("removed {} duplicate metric_data rows", removed);
+    Ok(())
+}
+
+pub async fn dedupe(pool: &PgPool, args: DedupeArgs, actor: &Actor) -> Result<()> {
+    match args.command {
+        DedupeCommand::MetricData(metric_data_args) => {
+            dedupe_metric_data(pool, metric_data_args, actor).await
+        }
+    }
+}
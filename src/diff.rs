@@ -0,0 +1,124 @@
+use crate::args::{Aggregator, DisplayOpts, MetricArgs, MetricDiffArgs};
+use crate::metric::{self, MetricQuery};
+use crate::query::QueryError;
+use anyhow::Result;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The aggregated metrics of a period, keyed by metric type and breakout values
+async fn period_metrics(
+    pool: &PgPool,
+    diff_args: &MetricDiffArgs,
+    period_uuid: Uuid,
+) -> Result<(Vec<String>, Vec<(Vec<String>, String)>)> {
+    // Only consider the data of the period's own iteration, so periods of
+    // different runs can be compared
+    let iteration_uuid: Option<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT sample.iteration_uuid
+        FROM period
+        LEFT JOIN sample ON sample.sample_uuid = period.sample_uuid
+        WHERE period.period_uuid = $1
+        "#,
+    )
+    .bind(period_uuid)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| QueryError::MetricError(format!("{}", e)))?
+    .flatten();
+    let iteration_uuid = iteration_uuid.ok_or(QueryError::MetricError(format!(
+        "no iteration found for period {}",
+        period_uuid
+    )))?;
+
+    let mut metric_args = MetricArgs {
+        iteration_uuid: Some(iteration_uuid),
+        metric_type: diff_args.metric_type.clone(),
+        ref_period: Some(vec![period_uuid]),
+        name: diff_args.name.clone(),
+        breakout: diff_args.breakout.clone(),
+        aggregator: diff_args.aggregator.clone(),
+        unit: diff_args.unit.clone(),
+        ..Default::default()
+    };
+    let MetricQuery { mut qb, .. } = metric::build_metric_query(pool, &mut metric_args).await?;
+    let (header, rows) = metric::fetch_rows(pool, &mut qb, &DisplayOpts::default()).await?;
+
+    // The columns between the iteration_uuid and ref_period_uuid identify
+    // the metric, the last one is the aggregated value
+    let key_end = header
+        .iter()
+        .position(|c| c == "ref_period_uuid")
+        .unwrap_or(header.len());
+    let key_header = header.get(2..key_end).unwrap_or_default().to_vec();
+    let metrics = rows
+        .into_iter()
+        .map(|row| {
+            let key = row.get(2..key_end).unwrap_or_default().to_vec();
+            let value = row.last().cloned().unwrap_or("null".to_string());
+            (key, value)
+        })
+        .collect();
+    Ok((key_header, metrics))
+}
+
+pub async fn metric_diff(pool: &PgPool, diff_args: MetricDiffArgs) -> Result<()> {
+    let [period_a, period_b] = diff_args.period[..] else {
+        return Err(QueryError::MetricError(format!(
+            "diff needs exactly two periods, got {}",
+            diff_args.period.len()
+        ))
+        .into());
+    };
+    if matches!(diff_args.aggregator, Aggregator::None) {
+        return Err(QueryError::MetricError(
+            "diff needs an aggregator other than none".to_string(),
+        )
+        .into());
+    }
+
+    let (key_header, metrics_a) = period_metrics(pool, &diff_args, period_a).await?;
+    let (_, metrics_b) = period_metrics(pool, &diff_args, period_b).await?;
+
+    let mut keys: Vec<Vec<String>> = Vec::new();
+    for (key, _) in metrics_a.iter().chain(metrics_b.iter()) {
+        if !keys.contains(key) {
+            keys.push(key.clone());
+        }
+    }
+    let lookup = |metrics: &[(Vec<String>, String)], key: &Vec<String>| -> Option<f64> {
+        metrics
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.parse::<f64>().ok())
+    };
+    let show = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or("null".to_string());
+
+    let mut header = key_header;
+    header.extend(
+        ["period_a", "period_b", "change", "change_pct"]
+            .iter()
+            .map(|c| c.to_string()),
+    );
+    let rows: Vec<Vec<String>> = keys
+        .into_iter()
+        .map(|key| {
+            let a = lookup(&metrics_a, &key);
+            let b = lookup(&metrics_b, &key);
+            let change = a.zip(b).map(|(a, b)| b - a);
+            let change_pct = a
+                .zip(change)
+                .filter(|(a, _)| *a != 0.0)
+                .map(|(a, change)| change / a.abs() * 100.0);
+            let mut row = key;
+            row.extend([show(a), show(b), show(change), show(change_pct)]);
+            row
+        })
+        .collect();
+
+    println!(
+        "{}",
+        metric::format_rows(header, rows, diff_args.output, &DisplayOpts::default())?
+    );
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use crate::args::DoctorArgs;
+use crate::import::{self, OpenSearchOpts};
+use crate::{cdm, init};
+use anyhow::Result;
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DoctorError {
+    #[error("{0} of the checks failed")]
+    ChecksFailed(usize),
+}
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn print(&mut self, status: Status, check: &str, detail: &str) {
+        let label = match status {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        };
+        if status == Status::Fail {
+            self.failed += 1;
+        }
+        println!("{:<4}  {:<12}  {}", label, check, detail);
+    }
+}
+
+/// Rows whose parent is missing, as (child table, parent table, condition)
+const ORPHANS: &[(&str, &str, &str)] = &[
+    (
+        "tag",
+        "run",
+        "NOT EXISTS (SELECT 1 FROM run WHERE run.run_uuid = tag.run_uuid)",
+    ),
+    (
+        "iteration",
+        "run",
+        "NOT EXISTS (SELECT 1 FROM run WHERE run.run_uuid = iteration.run_uuid)",
+    ),
+    (
+        "param",
+        "iteration",
+        "NOT EXISTS (SELECT 1 FROM iteration WHERE iteration.iteration_uuid = param.iteration_uuid)",
+    ),
+    (
+        "sample",
+        "iteration",
+        "NOT EXISTS (SELECT 1 FROM iteration WHERE iteration.iteration_uuid = sample.iteration_uuid)",
+    ),
+    (
+        "period",
+        "sample",
+        "NOT EXISTS (SELECT 1 FROM sample WHERE sample.sample_uuid = period.sample_uuid)",
+    ),
+    (
+        "metric_desc",
+        "period",
+        "NOT EXISTS (SELECT 1 FROM period WHERE period.period_uuid = metric_desc.period_uuid)",
+    ),
+    (
+        "name",
+        "metric_desc",
+        "NOT EXISTS (SELECT 1 FROM metric_desc WHERE metric_desc.metric_desc_uuid = name.metric_desc_uuid)",
+    ),
+    (
+        "metric_data",
+        "metric_desc",
+        "NOT EXISTS (SELECT 1 FROM metric_desc WHERE metric_desc.metric_desc_uuid = metric_data.metric_desc_uuid)",
+    ),
+];
+
+/// Runs every check, printing a pass/warn/fail line for each. The checks
+/// that need the tables are skipped when the schema isn't usable.
+pub async fn doctor(pool: &PgPool, args: DoctorArgs, opensearch: &OpenSearchOpts) -> Result<()> {
+    let mut report = Report { failed: 0 };
+
+    match sqlx::query_scalar::<_, String>("SELECT current_setting('server_version')")
+        .fetch_one(pool)
+        .await
+    {
+        Ok(version) => report.print(
+            Status::Pass,
+            "connectivity",
+            &format!("postgres {}", version),
+        ),
+        Err(e) => {
+            report.print(Status::Fail, "connectivity", &format!("{}", e));
+            return Err(DoctorError::ChecksFailed(report.failed).into());
+        }
+    }
+
+    let schema_ok = match init::check_schema(pool).await {
+        Ok(()) => {
+            report.print(Status::Pass, "schema", "up to date");
+            true
+        }
+        Err(e) => {
+            report.print(Status::Fail, "schema", &format!("{}", e));
+            false
+        }
+    };
+
+    if schema_ok {
+        permissions(pool, &mut report).await?;
+        indexes(pool, &mut report).await?;
+        orphans(pool, &mut report).await?;
+    }
+
+    if args.opensearch {
+        match import::opensearch_client(opensearch)?.ping().send().await {
+            Ok(response) if response.status_code().is_success() => {
+                report.print(Status::Pass, "opensearch", "reachable")
+            }
+            Ok(response) => report.print(
+                Status::Fail,
+                "opensearch",
+                &format!("responded with {}", response.status_code()),
+            ),
+            Err(e) => report.print(Status::Fail, "opensearch", &format!("{}", e)),
+        }
+    }
+
+    if report.failed > 0 {
+        return Err(DoctorError::ChecksFailed(report.failed).into());
+    }
+    Ok(())
+}
+
+async fn permissions(pool: &PgPool, report: &mut Report) -> Result<()> {
+    let mut unreadable = Vec::new();
+    let mut unwritable = Vec::new();
+    for table in cdm::DATA_TABLES {
+        let (read, write): (bool, bool) = sqlx::query_as(
+            "SELECT has_table_privilege($1, 'SELECT'), has_table_privilege($1, 'INSERT, UPDATE, DELETE')",
+        )
+        .bind(table)
+        .fetch_one(pool)
+        .await?;
+        if !read {
+            unreadable.push(*table);
+        } else if !write {
+            unwritable.push(*table);
+        }
+    }
+    if !unreadable.is_empty() {
+        report.print(
+            Status::Fail,
+            "permissions",
+            &format!("can't read {}", unreadable.join(", ")),
+        );
+    } else if !unwritable.is_empty() {
+        report.print(
+            Status::Warn,
+            "permissions",
+            &format!("can't write {}", unwritable.join(", ")),
+        );
+    } else {
+        report.print(
+            Status::Pass,
+            "permissions",
+            "can read and write every table",
+        );
+    }
+    Ok(())
+}
+
+async fn indexes(pool: &PgPool, report: &mut Report) -> Result<()> {
+    let mut missing = Vec::new();
+    for index in cdm::INDEXES {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(index)
+            .fetch_one(pool)
+            .await?;
+        if !exists {
+            missing.push(*index);
+        }
+    }
+    if missing.is_empty() {
+        report.print(Status::Pass, "indexes", "all present");
+    } else {
+        report.print(
+            Status::Warn,
+            "indexes",
+            &format!("missing {}", missing.join(", ")),
+        );
+    }
+    Ok(())
+}
+
+async fn orphans(pool: &PgPool, report: &mut Report) -> Result<()> {
+    let mut found = Vec::new();
+    for (table, parent, condition) in ORPHANS {
+        let count: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} WHERE {}",
+            table, condition
+        ))
+        .fetch_one(pool)
+        .await?;
+        if count > 0 {
+            found.push(format!("{} {} rows without a {}", count, table, parent));
+        }
+    }
+    if found.is_empty() {
+        report.print(Status::Pass, "orphans", "none");
+    } else {
+        report.print(Status::Warn, "orphans", &found.join(", "));
+    }
+    Ok(())
+}
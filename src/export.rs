@@ -0,0 +1,297 @@
+use crate::add::{IterationNode, MetricNode, PeriodNode, Point, RunNode, SampleNode};
+use crate::anonymize::Anonymizer;
+use crate::args::{ExportArgs, ExportCommand, ExportJsonArgs};
+use crate::backup;
+use crate::parser::BodyJson;
+use crate::report;
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Couldn't write the export to {0}, {1}")]
+    WriteFailed(String, String),
+}
+
+/// A directory of CDM ndjson, one file per index like Crucible produces
+pub struct NdjsonDir {
+    path: PathBuf,
+    writers: HashMap<&'static str, BufWriter<File>>,
+}
+
+impl NdjsonDir {
+    pub fn create(path: &str) -> Result<Self, ExportError> {
+        fs::create_dir_all(path)
+            .map_err(|e| ExportError::WriteFailed(path.to_string(), format!("{}", e)))?;
+        Ok(NdjsonDir {
+            path: PathBuf::from(path),
+            writers: HashMap::new(),
+        })
+    }
+
+    /// Appends the document to its index's file, creating it on first use
+    pub fn write(&mut self, document: &BodyJson) -> Result<()> {
+        let Some(index) = backup::index_name(document) else {
+            return Ok(());
+        };
+        let writer = match self.writers.get_mut(index) {
+            Some(writer) => writer,
+            None => {
+                let path = self.path.join(format!("{}.ndjson", index));
+                let f = File::create(&path).map_err(|e| {
+                    ExportError::WriteFailed(path.display().to_string(), format!("{}", e))
+                })?;
+                self.writers.entry(index).or_insert(BufWriter::new(f))
+            }
+        };
+        backup::write_document(writer, document)
+    }
+
+    pub fn flush(&mut self) -> Result<(), ExportError> {
+        for writer in self.writers.values_mut() {
+            writer.flush().map_err(|e| {
+                ExportError::WriteFailed(self.path.display().to_string(), format!("{}", e))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The position of a period in its run, by iteration, sample and period
+type PeriodPos = (usize, usize, usize);
+
+/// A run nested from its documents, which come parents first
+struct RunTree {
+    run: RunNode,
+    iterations: HashMap<Uuid, usize>,
+    samples: HashMap<Uuid, (usize, usize)>,
+    periods: HashMap<Uuid, PeriodPos>,
+    /// The metrics by the period they're in, or none for the run's
+    metrics: HashMap<Uuid, (Option<PeriodPos>, usize)>,
+}
+
+impl RunTree {
+    fn new(run: RunNode) -> Self {
+        RunTree {
+            run,
+            iterations: HashMap::new(),
+            samples: HashMap::new(),
+            periods: HashMap::new(),
+            metrics: HashMap::new(),
+        }
+    }
+
+    fn metric_mut(&mut self, metric_desc_uuid: &Uuid) -> Option<&mut MetricNode> {
+        let (period, i) = *self.metrics.get(metric_desc_uuid)?;
+        let metrics = match period {
+            Some((i, s, p)) => &mut self.run.iterations[i].samples[s].periods[p].metrics,
+            None => &mut self.run.metrics,
+        };
+        metrics.get_mut(i)
+    }
+
+    /// Places the document under its parent. Ones whose parent was left
+    /// out of the export are left out with it
+    fn add(&mut self, document: BodyJson) {
+        match document {
+            BodyJson::Run(_) | BodyJson::Name(_) => {}
+            BodyJson::Tag(tag) => {
+                self.run.tags.insert(tag.tag.name, tag.tag.val);
+            }
+            BodyJson::Iteration(iteration) => {
+                let iteration = iteration.iteration;
+                self.iterations
+                    .insert(iteration.iteration_uuid, self.run.iterations.len());
+                self.run.iterations.push(IterationNode {
+                    iteration_uuid: iteration.iteration_uuid,
+                    num: iteration.num,
+                    status: iteration.status,
+                    path: iteration.path,
+                    primary_metric: iteration.primary_metric,
+                    primary_period: iteration.primary_period,
+                    params: HashMap::new(),
+                    samples: vec![],
+                });
+            }
+            BodyJson::Param(param) => {
+                if let Some(i) = self.iterations.get(&param.iteration.iteration_uuid) {
+                    self.run.iterations[*i]
+                        .params
+                        .insert(param.param.arg, param.param.val);
+                }
+            }
+            BodyJson::Sample(sample) => {
+                let Some(&i) = self.iterations.get(&sample.iteration.iteration_uuid) else {
+                    return;
+                };
+                let samples = &mut self.run.iterations[i].samples;
+                self.samples
+                    .insert(sample.sample.sample_uuid, (i, samples.len()));
+                samples.push(SampleNode {
+                    sample_uuid: sample.sample.sample_uuid,
+                    num: sample.sample.num,
+                    status: sample.sample.status,
+                    path: sample.sample.path,
+                    periods: vec![],
+                });
+            }
+            BodyJson::Period(period) => {
+                let Some(&(i, s)) = self.samples.get(&period.sample.sample_uuid) else {
+                    return;
+                };
+                let periods = &mut self.run.iterations[i].samples[s].periods;
+                self.periods
+                    .insert(period.period.period_uuid, (i, s, periods.len()));
+                periods.push(PeriodNode {
+                    period_uuid: period.period.period_uuid,
+                    begin: period.period.begin,
+                    finish: period.period.end,
+                    name: period.period.name,
+                    metrics: vec![],
+                });
+            }
+            BodyJson::MetricDesc(metric_desc) => {
+                let period = match &metric_desc.period {
+                    Some(period) => match self.periods.get(&period.period_uuid) {
+                        Some(pos) => Some(*pos),
+                        None => return,
+                    },
+                    None => None,
+                };
+                let metrics = match period {
+                    Some((i, s, p)) => &mut self.run.iterations[i].samples[s].periods[p].metrics,
+                    None => &mut self.run.metrics,
+                };
+                let spec = metric_desc.metric_desc;
+                self.metrics
+                    .insert(spec.metric_desc_uuid, (period, metrics.len()));
+                metrics.push(MetricNode {
+                    metric_desc_uuid: spec.metric_desc_uuid,
+                    class: spec.class,
+                    metric_type: spec.metric_type,
+                    source: spec.source,
+                    unit: spec.unit,
+                    names: spec
+                        .names
+                        .into_iter()
+                        .map(|(name, val)| match val {
+                            Value::String(val) => (name, val),
+                            val => (name, val.to_string()),
+                        })
+                        .collect(),
+                    data: vec![],
+                });
+            }
+            BodyJson::MetricData(metric_data) => {
+                if let Some(metric) = self.metric_mut(&metric_data.metric_desc.metric_desc_uuid) {
+                    metric.data.push(Point {
+                        begin: metric_data.metric_data.begin,
+                        finish: metric_data.metric_data.end,
+                        value: metric_data.metric_data.value,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Reads a run back out in the nested form `scdm add` reads
+async fn run_node(
+    pool: &PgPool,
+    run_uuid: Uuid,
+    anonymizer: Option<&Anonymizer>,
+) -> Result<RunNode> {
+    let mut tree: Option<RunTree> = None;
+    backup::run_documents(pool, run_uuid, |mut document| {
+        if let Some(anonymizer) = anonymizer {
+            anonymizer.anonymize(&mut document);
+        }
+        match (&mut tree, document) {
+            (None, BodyJson::Run(run)) => {
+                tree = Some(RunTree::new(RunNode {
+                    run_uuid: run.run.run_uuid,
+                    begin: run.run.begin,
+                    finish: run.run.end,
+                    benchmark: run.run.benchmark,
+                    email: run.run.email,
+                    name: run.run.name,
+                    description: run.run.description,
+                    source: run.run.source,
+                    tags: HashMap::new(),
+                    iterations: vec![],
+                    metrics: vec![],
+                }))
+            }
+            (Some(tree), document) => tree.add(document),
+            // The run always comes first
+            (None, _) => {}
+        }
+        Ok(())
+    })
+    .await?;
+    // run_documents fails on a missing run, so it was read
+    Ok(tree.unwrap().run)
+}
+
+/// Writes the runs as a JSON array of nested runs, which can be edited and
+/// added again with `scdm add`
+async fn export_json(pool: &PgPool, args: ExportJsonArgs) -> Result<()> {
+    let anonymizer = Anonymizer::from_opts(&args.anonymize)?;
+    let mut runs = vec![];
+    for run_uuid in &args.run_uuid {
+        runs.push(run_node(pool, *run_uuid, anonymizer.as_ref()).await?);
+    }
+    let json = serde_json::to_string_pretty(&runs)?;
+
+    match &args.out {
+        Some(out) => {
+            fs::write(out, json + "\n")
+                .map_err(|e| ExportError::WriteFailed(out.clone(), format!("{}", e)))?;
+            report!("exported {} runs to {}", runs.len(), out);
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// Writes the runs and everything under them as CDM ndjson
+pub async fn export(pool: &PgPool, args: ExportArgs) -> Result<()> {
+    if let Some(ExportCommand::Json(json_args)) = args.command {
+        return export_json(pool, json_args).await;
+    }
+    // Required unless there's a subcommand
+    let out_path = args.out.unwrap_or_default();
+    let anonymizer = Anonymizer::from_opts(&args.anonymize)?;
+    let mut out = NdjsonDir::create(&out_path)?;
+
+    let mut num_documents = 0;
+    for run_uuid in &args.run_uuid {
+        num_documents += backup::run_documents(pool, *run_uuid, |mut document| {
+            if let Some(anonymizer) = &anonymizer {
+                anonymizer.anonymize(&mut document);
+            }
+            // Crucible has no owner, only backups keep it
+            if let BodyJson::Run(run) = &mut document {
+                run.run.owner = None;
+            }
+            out.write(&document)
+        })
+        .await?;
+    }
+    out.flush()?;
+
+    report!(
+        "exported {} runs ({} documents) to {}",
+        args.run_uuid.len(),
+        num_documents,
+        out_path
+    );
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use crate::args::{DisplayOpts, GetRunArgs};
+use crate::cdm::Run;
+use crate::metric;
+use crate::query::QueryGet;
+use crate::serve::{self, ApiError, MetricRequest, ServeState};
+use axum::extract::State;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::Parser;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+
+/// The dashboard's time range
+#[derive(Debug, Deserialize)]
+struct Range {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Target {
+    /// The metric type
+    target: Option<String>,
+    /// More options of `scdm query metric`, ex: {"breakout": "tag:kernel"}
+    #[serde(default, alias = "data")]
+    payload: Option<Value>,
+    #[serde(default)]
+    hide: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryRequest {
+    range: Range,
+    interval_ms: Option<i64>,
+    max_data_points: Option<i64>,
+    targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnnotationRequest {
+    range: Range,
+    annotation: Value,
+}
+
+/// Lets Grafana's "Save & test" succeed
+async fn test_connection() -> &'static str {
+    "OK"
+}
+
+/// The metric types that contain the search text
+async fn search(
+    State(state): State<ServeState>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let raw_query: &str = r#"
+        SELECT DISTINCT metric_type FROM metric_desc
+        WHERE metric_type ILIKE '%' || $1 || '%'
+        ORDER BY metric_type
+    "#;
+    let metric_types: Vec<String> = sqlx::query_scalar(raw_query)
+        .bind(request.target)
+        .fetch_all(&state.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+    Ok(Json(metric_types))
+}
+
+/// Window timestamps as `metric::unpack_row` renders them by default, in UTC
+fn parse_window(window: &str) -> Option<i64> {
+    NaiveDateTime::parse_from_str(window.trim_end_matches(" UTC"), "%Y-%m-%d %H:%M:%S%.f")
+        .ok()
+        .map(|t| t.and_utc().timestamp_millis())
+}
+
+/// One series per group of the query's result, named by the group's columns
+async fn query_target(
+    state: &ServeState,
+    request: &QueryRequest,
+    metric_type: &str,
+    payload: &Map<String, Value>,
+) -> Result<Vec<Value>, ApiError> {
+    let range_ms = (request.range.to - request.range.from).num_milliseconds();
+    let mut resolution = range_ms / request.interval_ms.unwrap_or(range_ms).max(1);
+    if let Some(max_data_points) = request.max_data_points {
+        resolution = resolution.min(max_data_points);
+    }
+    let mut params = vec![
+        ("metric-type".to_string(), metric_type.to_string()),
+        ("begin".to_string(), request.range.from.to_rfc3339()),
+        ("finish".to_string(), request.range.to.to_rfc3339()),
+        ("resolution".to_string(), resolution.max(1).to_string()),
+    ];
+    for (name, value) in payload {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            Value::Bool(true) | Value::Null => String::new(),
+            other => other.to_string(),
+        };
+        params.push((name.clone(), value));
+    }
+    let mut args = vec![];
+    serve::push_params(&mut args, params);
+    let mut request = MetricRequest::try_parse_from(args)?;
+    // The windows are read back as UTC, whatever the payload asks for
+    request.metric.display = DisplayOpts::default();
+    let (header, rows) = metric::metric_rows(&state.pool, request.metric).await?;
+
+    let begin_column = header.iter().position(|c| c == "window_begin");
+    let group_columns: Vec<usize> = (0..header.len().saturating_sub(1))
+        .filter(|&i| !matches!(header[i].as_str(), "window_begin" | "window_finish"))
+        .collect();
+    let mut series: BTreeMap<String, Vec<(i64, f64)>> = BTreeMap::new();
+    for row in rows {
+        let (Some(time), Some(Ok(value))) = (
+            begin_column.and_then(|i| parse_window(&row[i])),
+            row.last().map(|v| v.parse::<f64>()),
+        ) else {
+            continue;
+        };
+        let name = group_columns
+            .iter()
+            .map(|&i| row[i].as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        series.entry(name).or_default().push((time, value));
+    }
+    Ok(series
+        .into_iter()
+        .map(|(name, mut points)| {
+            points.sort_by_key(|(time, _)| *time);
+            let datapoints: Vec<Value> = points
+                .into_iter()
+                .map(|(time, value)| json!([value, time]))
+                .collect();
+            json!({ "target": name, "datapoints": datapoints })
+        })
+        .collect())
+}
+
+/// Time series of each target's metric type over the dashboard's range
+async fn query(
+    State(state): State<ServeState>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<Vec<Value>>, ApiError> {
+    let mut results = vec![];
+    for target in &request.targets {
+        let Some(metric_type) = target.target.as_deref().filter(|_| !target.hide) else {
+            continue;
+        };
+        let payload = match &target.payload {
+            Some(Value::Object(payload)) => payload.clone(),
+            None | Some(Value::Null) => Map::new(),
+            Some(_) => {
+                return Err(ApiError::bad_request(
+                    "The payload has to be an object of metric query options",
+                ));
+            }
+        };
+        results.extend(query_target(&state, &request, metric_type, &payload).await?);
+    }
+    Ok(Json(results))
+}
+
+/// The runs during the range, the annotation's query text names a benchmark
+async fn annotations(
+    State(state): State<ServeState>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<Json<Vec<Value>>, ApiError> {
+    let benchmark = request
+        .annotation
+        .get("query")
+        .and_then(Value::as_str)
+        .filter(|query| !query.is_empty())
+        .map(str::to_string);
+    let runs: Vec<Run> = GetRunArgs {
+        run_uuid: None,
+        tag: None,
+        begin_before: Some(request.range.to),
+        begin_after: None,
+        finish_before: None,
+        finish_after: Some(request.range.from),
+        benchmark,
+        email: None,
+        name: None,
+        source: None,
+        deleted: false,
+        min_duration: None,
+        max_duration: None,
+    }
+    .query_get(&state.pool)
+    .await?;
+    Ok(Json(
+        runs.into_iter()
+            .map(|run| {
+                json!({
+                    "annotation": request.annotation,
+                    "time": run.begin.timestamp_millis(),
+                    "timeEnd": run.finish.timestamp_millis(),
+                    "title": run.benchmark,
+                    "text": run.run_uuid.to_string(),
+                    "tags": [run.benchmark],
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// The endpoints of Grafana's "simple JSON" datasource under /grafana, which
+/// the Infinity datasource can use too
+pub(crate) fn routes() -> Router<ServeState> {
+    Router::new()
+        .route("/grafana", get(test_connection))
+        .route("/grafana/", get(test_connection))
+        .route("/grafana/search", post(search))
+        .route("/grafana/query", post(query))
+        .route("/grafana/annotations", post(annotations))
+}
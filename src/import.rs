@@ -1,17 +1,25 @@
 use std::collections::HashMap;
+use std::time::Instant;
 
+use crate::audit;
 use crate::parser::{
-    GlobalResource, IterationJson, MetricDataJson, MetricDescJson, ParamJson, PeriodJson, RunJson,
-    SampleJson, insert_iterations, insert_metric_datas, insert_metric_descs, insert_params,
-    insert_periods, insert_runs, insert_samples, insert_tags,
+    GlobalResource, IngestOpts, IterationJson, MetricDataJson, MetricDescJson, ParamJson,
+    ParseError, PeriodJson, RunJson, SampleJson, insert_iterations, insert_metric_datas,
+    insert_metric_descs, insert_params, insert_periods, insert_runs, insert_samples, insert_tags,
+    lock_runs, normalize_statuses, validate_timestamps,
 };
+use crate::report;
 use crate::{args::ImportArgs, parser::TagJson};
 use anyhow::Result;
+use opensearch::auth::Credentials;
+use opensearch::http::Url;
+use opensearch::http::transport::{SingleNodeConnectionPool, TransportBuilder};
 use opensearch::{OpenSearch, SearchParts};
 use serde::de::DeserializeOwned;
 use serde_json::{Value, json};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use thiserror::Error;
+use tracing::{info, instrument};
 use uuid::Uuid;
 
 #[derive(Error, Debug, Clone)]
@@ -22,6 +30,8 @@ pub enum ImportError {
     ParseError(String),
     #[error("Bad arguments provided, {0}")]
     ArgError(String),
+    #[error("Invalid OpenSearch URL {0}, {1}")]
+    InvalidUrl(String, String),
 }
 
 fn build_queries(run_uuid: Option<Vec<Uuid>>) -> Vec<Value> {
@@ -68,6 +78,7 @@ async fn parse_response_body<T: DeserializeOwned>(value: Value) -> Result<Vec<T>
     Ok(resps)
 }
 
+#[instrument(level = "debug", skip(client, query))]
 async fn request<T: DeserializeOwned>(
     client: &OpenSearch,
     index: &str,
@@ -86,11 +97,146 @@ async fn request<T: DeserializeOwned>(
     Ok(objs)
 }
 
-pub async fn import(pool: &PgPool, args: ImportArgs) -> Result<()> {
-    let client = OpenSearch::default();
+/// Where the OpenSearch instance is and how to log in to it
+#[derive(Debug, Default)]
+pub struct OpenSearchOpts {
+    pub url: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+/// A client of the configured OpenSearch instance, or of the local one
+pub fn opensearch_client(opts: &OpenSearchOpts) -> Result<OpenSearch> {
+    if opts.url.is_none() && opts.user.is_none() {
+        return Ok(OpenSearch::default());
+    }
+    let url = opts.url.as_deref().unwrap_or("http://localhost:9200");
+    let url =
+        Url::parse(url).map_err(|e| ImportError::InvalidUrl(url.to_string(), e.to_string()))?;
+    let mut transport = TransportBuilder::new(SingleNodeConnectionPool::new(url));
+    if let Some(user) = &opts.user {
+        transport = transport.auth(Credentials::Basic(
+            user.clone(),
+            opts.password.clone().unwrap_or_default(),
+        ));
+    }
+    Ok(OpenSearch::new(transport.build()?))
+}
+
+/// The documents fetched by one query
+#[derive(Clone)]
+struct Documents {
+    runs: Vec<RunJson>,
+    tags: Vec<TagJson>,
+    iterations: Vec<IterationJson>,
+    params: Vec<ParamJson>,
+    samples: Vec<SampleJson>,
+    periods: Vec<PeriodJson>,
+    metric_descs: Vec<MetricDescJson>,
+    metric_datas: Vec<MetricDataJson>,
+}
 
-    let queries = build_queries(args.run_uuid);
+async fn insert_documents(
+    txn: &mut Transaction<'_, Postgres>,
+    documents: Documents,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let Documents {
+        runs,
+        tags,
+        mut iterations,
+        params,
+        mut samples,
+        mut periods,
+        mut metric_descs,
+        mut metric_datas,
+    } = documents;
+    let mut num_new = 0;
+    // Default resources for data that is scoped to the run
+    let mut globals: HashMap<Uuid, GlobalResource> = HashMap::new();
 
+    let (
+        new_run_rows,
+        mut global_iterations,
+        mut global_samples,
+        mut global_periods,
+        mut global_metric_descs,
+        mut global_metric_datas,
+    ) = insert_runs(txn, &mut globals, &runs.iter().collect(), opts).await?;
+    iterations.append(&mut global_iterations);
+    samples.append(&mut global_samples);
+    periods.append(&mut global_periods);
+    metric_descs.append(&mut global_metric_descs);
+    metric_datas.append(&mut global_metric_datas);
+    num_new += new_run_rows;
+
+    num_new += insert_tags(txn, &tags.iter().collect(), opts).await?;
+    num_new += insert_iterations(txn, &iterations.iter().collect(), opts).await?;
+    num_new += insert_params(txn, &params.iter().collect(), opts).await?;
+    num_new += insert_samples(txn, &samples.iter().collect(), opts).await?;
+    num_new += insert_periods(txn, &periods.iter().collect(), opts).await?;
+    num_new += insert_metric_descs(txn, &globals, &metric_descs.iter().collect(), opts).await?;
+    num_new += insert_metric_datas(txn, &metric_datas.iter().collect(), opts).await?;
+    Ok(num_new)
+}
+
+/// Narrows the queries down to the runs that aren't in Postgres yet
+async fn new_run_queries(
+    pool: &PgPool,
+    client: &OpenSearch,
+    run_uuid: Option<Vec<Uuid>>,
+) -> Result<Vec<Value>> {
+    let mut run_uuids: Vec<Uuid> = vec![];
+    for query in build_queries(run_uuid) {
+        let runs = request::<RunJson>(client, "cdmv8dev-run", query).await?;
+        run_uuids.extend(runs.into_iter().map(|run| run.run.run_uuid));
+    }
+    // Soft deleted runs count as existing, so they aren't brought back
+    let existing: Vec<Uuid> =
+        sqlx::query_scalar("SELECT run_uuid FROM run WHERE run_uuid = ANY($1)")
+            .bind(&run_uuids)
+            .fetch_all(pool)
+            .await?;
+    run_uuids.retain(|run_uuid| !existing.contains(run_uuid));
+    info!(
+        "{} runs to import, skipping {} existing",
+        run_uuids.len(),
+        existing.len()
+    );
+    if run_uuids.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(build_queries(Some(run_uuids)))
+}
+
+pub async fn import(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: ImportArgs,
+    opensearch: &OpenSearchOpts,
+    opts: &IngestOpts,
+) -> Result<()> {
+    import_runs(pool, replica, &args, opensearch, opts).await?;
+    Ok(())
+}
+
+/// Imports the runs the args select, returning how many rows were added
+pub async fn import_runs(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: &ImportArgs,
+    opensearch: &OpenSearchOpts,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let client = opensearch_client(opensearch)?;
+
+    let queries = if args.skip_existing {
+        new_run_queries(pool, &client, args.run_uuid.clone()).await?
+    } else {
+        build_queries(args.run_uuid.clone())
+    };
+
+    let mut total_new = 0;
     for query in queries {
         let runs = request::<RunJson>(&client, "cdmv8dev-run", query.clone()).await?;
         let tags = request::<TagJson>(&client, "cdmv8dev-tag", query.clone()).await?;
@@ -98,41 +244,88 @@ pub async fn import(pool: &PgPool, args: ImportArgs) -> Result<()> {
             request::<IterationJson>(&client, "cdmv8dev-iteration", query.clone()).await?;
         let params = request::<ParamJson>(&client, "cdmv8dev-param", query.clone()).await?;
         let mut samples = request::<SampleJson>(&client, "cdmv8dev-sample", query.clone()).await?;
-        let mut periods = request::<PeriodJson>(&client, "cdmv8dev-period", query.clone()).await?;
-        let mut metric_descs =
+        let periods = request::<PeriodJson>(&client, "cdmv8dev-period", query.clone()).await?;
+        let metric_descs =
             request::<MetricDescJson>(&client, "cdmv8dev-metric_desc", query.clone()).await?;
-        let mut metric_datas =
+        let metric_datas =
             request::<MetricDataJson>(&client, "cdmv8dev-metric_data", query.clone()).await?;
 
-        let mut num_new = 0;
-        let mut txn = pool.begin().await?;
-        // Default resources for data that is scoped to the run
-        let mut globals: HashMap<Uuid, GlobalResource> = HashMap::new();
-
-        let (
-            new_run_rows,
-            mut global_iterations,
-            mut global_samples,
-            mut global_periods,
-            mut global_metric_descs,
-            mut global_metric_datas,
-        ) = insert_runs(&mut txn, &mut globals, &runs.iter().collect()).await?;
-        iterations.append(&mut global_iterations);
-        samples.append(&mut global_samples);
-        periods.append(&mut global_periods);
-        metric_descs.append(&mut global_metric_descs);
-        metric_datas.append(&mut global_metric_datas);
-        num_new += new_run_rows;
-
-        num_new += insert_tags(&mut txn, &tags.iter().collect()).await?;
-        num_new += insert_iterations(&mut txn, &iterations.iter().collect()).await?;
-        num_new += insert_params(&mut txn, &params.iter().collect()).await?;
-        num_new += insert_samples(&mut txn, &samples.iter().collect()).await?;
-        num_new += insert_periods(&mut txn, &periods.iter().collect()).await?;
-        num_new += insert_metric_descs(&mut txn, &globals, &metric_descs.iter().collect()).await?;
-        num_new += insert_metric_datas(&mut txn, &metric_datas.iter().collect()).await?;
-        txn.commit().await?;
-        println!("added {} rows", num_new);
+        let iteration_statuses = iterations.iter_mut().map(|iteration| {
+            (
+                format!(
+                    "iteration {} (run {})",
+                    iteration.iteration.iteration_uuid, iteration.run.run_uuid
+                ),
+                &mut iteration.iteration.status,
+            )
+        });
+        let sample_statuses = samples.iter_mut().map(|sample| {
+            (
+                format!(
+                    "sample {} (run {})",
+                    sample.sample.sample_uuid, sample.run.run_uuid
+                ),
+                &mut sample.sample.status,
+            )
+        });
+        normalize_statuses(
+            iteration_statuses.chain(sample_statuses),
+            args.status.mode(),
+        )?;
+        validate_timestamps(
+            &runs.iter().collect::<Vec<_>>(),
+            &periods.iter().collect::<Vec<_>>(),
+            &metric_datas.iter().collect::<Vec<_>>(),
+        )?;
+
+        let documents = Documents {
+            runs,
+            tags,
+            iterations,
+            params,
+            samples,
+            periods,
+            metric_descs,
+            metric_datas,
+        };
+        let run_uuids: Vec<Uuid> = documents.runs.iter().map(|run| run.run.run_uuid).collect();
+        let start = Instant::now();
+        let locks = lock_runs(pool, &run_uuids).await?;
+        let inserted = async {
+            let mut txn = pool.begin().await?;
+            let num_new = insert_documents(&mut txn, documents.clone(), opts).await?;
+            audit::record_timed(&mut *txn, "import", args, num_new, start.elapsed()).await?;
+            txn.commit().await?;
+            opts.cancellation.add_committed(num_new);
+            anyhow::Ok(num_new)
+        }
+        .await;
+        locks.release().await;
+        let num_new = inserted?;
+
+        if let Some(replica) = replica {
+            let replicate = async {
+                let start = Instant::now();
+                let locks = lock_runs(replica, &run_uuids).await?;
+                let inserted = async {
+                    let mut txn = replica.begin().await?;
+                    let replica_new = insert_documents(&mut txn, documents, opts).await?;
+                    audit::record_timed(&mut *txn, "import", args, replica_new, start.elapsed())
+                        .await?;
+                    txn.commit().await?;
+                    opts.cancellation.add_committed(replica_new);
+                    anyhow::Ok(())
+                }
+                .await;
+                locks.release().await;
+                inserted
+            };
+            replicate
+                .await
+                .map_err(|e| ParseError::ReplicaFailed(num_new, format!("{}", e)))?;
+        }
+        report!("added {} rows", num_new);
+        total_new += num_new;
     }
-    Ok(())
+    Ok(total_new)
 }
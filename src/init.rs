@@ -1,55 +1,374 @@
 use crate::SCDMError;
-use crate::cdm;
+use crate::args::InitArgs;
+use crate::owner::{self, Actor};
+use crate::report;
+use crate::{audit, cdm, metric, partition, timescale};
 use anyhow::Result;
-use sqlx::postgres::PgPool;
+use serde_json::Value;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgConnection, PgPool};
 use std::error::Error;
+use std::io::{self, Write};
+use tracing::info;
+use uuid::Uuid;
+
+/// The schema, versioned by the migrations in `migrations/`
+pub static MIGRATOR: Migrator = sqlx::migrate!();
 
 pub fn merr<T: Error>(err: T) -> SCDMError {
     SCDMError::FailedTableInit(err.to_string())
 }
 
-pub async fn init_tables(pool: &PgPool) -> Result<()> {
+pub async fn init(
+    pool: &PgPool,
+    args: InitArgs,
+    schema: Option<&str>,
+    actor: &Actor,
+) -> Result<()> {
+    if args.drop || args.truncate {
+        let action = if args.drop { "drop" } else { "truncate" };
+        let database = pool
+            .connect_options()
+            .get_database()
+            .unwrap_or_default()
+            .to_string();
+        if !args.yes && !confirm(&format!("{} every scdm table in {}?", action, database))? {
+            return Err(SCDMError::FailedTableInit(format!("{} cancelled", action)).into());
+        }
+    }
+
+    if let Some(schema) = schema {
+        sqlx::query(&format!(
+            "CREATE SCHEMA IF NOT EXISTS {}",
+            metric::quote_ident(schema)
+        ))
+        .execute(pool)
+        .await
+        .map_err(merr)?;
+    }
+
+    // Clap keeps --migrate apart from --drop and --truncate, but whatever
+    // the args, the tables are emptied before they're migrated
+    if args.drop {
+        drop_tables(pool, actor).await?;
+    } else if args.truncate {
+        truncate_tables(pool, actor).await?;
+    }
+    if args.migrate {
+        migrate(pool).await?;
+    } else if !args.drop {
+        init_tables(pool).await?;
+    }
+    init_convenience_views(pool).await?;
+    if args.partition {
+        partition::partition_metric_data(pool).await?;
+    }
+    if args.timescale {
+        timescale::hypertable_metric_data(pool, args.compress_after).await?;
+    }
+    if args.views {
+        init_views(pool).await?;
+    }
+    if args.create_roles {
+        create_roles(pool).await?;
+    }
+    Ok(())
+}
+
+pub async fn init_views(pool: &PgPool) -> Result<()> {
     let mut txn = pool.begin().await.map_err(merr)?;
-    // Creation order is important here because of foreign keys.
-    // The other option is to defer the integrity check until the
-    // transaction commits.
-    sqlx::query(cdm::SQL_TABLE_RUN)
+    sqlx::query(cdm::SQL_VIEW_RUN_SUMMARY)
         .execute(&mut *txn)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_TAG)
+    sqlx::query(cdm::SQL_INDEX_RUN_SUMMARY)
         .execute(&mut *txn)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_ITERATION)
+    sqlx::query(cdm::SQL_VIEW_ITERATION_PRIMARY_METRIC)
         .execute(&mut *txn)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_PARAM)
+    sqlx::query(cdm::SQL_INDEX_ITERATION_PRIMARY_METRIC)
         .execute(&mut *txn)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_SAMPLE)
-        .execute(&mut *txn)
+    txn.commit().await.map_err(merr)?;
+
+    Ok(())
+}
+
+/// Creates or replaces the plain views over the tables, for anyone querying
+/// the database directly
+pub async fn init_convenience_views(pool: &PgPool) -> Result<()> {
+    let mut txn = pool.begin().await.map_err(merr)?;
+    for (_, view) in cdm::CONVENIENCE_VIEWS {
+        sqlx::query(view).execute(&mut *txn).await.map_err(merr)?;
+    }
+    txn.commit().await.map_err(merr)?;
+    Ok(())
+}
+
+/// Creates the NOLOGIN scdm_reader and scdm_writer roles if they don't exist,
+/// and grants them read or read-write access to everything in the current
+/// schema, including the tables created later on
+pub async fn create_roles(pool: &PgPool) -> Result<()> {
+    let schema: String = sqlx::query_scalar("SELECT current_schema()")
+        .fetch_one(pool)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_PERIOD)
-        .execute(&mut *txn)
+    let schema = metric::quote_ident(&schema);
+    let mut txn = pool.begin().await.map_err(merr)?;
+    for role in [cdm::READER_ROLE, cdm::WRITER_ROLE] {
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = $1)")
+                .bind(role)
+                .fetch_one(&mut *txn)
+                .await
+                .map_err(merr)?;
+        if !exists {
+            sqlx::query(&format!("CREATE ROLE {} NOLOGIN", role))
+                .execute(&mut *txn)
+                .await
+                .map_err(merr)?;
+            info!("created role {}", role);
+        }
+    }
+    for statement in [
+        format!(
+            "GRANT USAGE ON SCHEMA {} TO {}, {}",
+            schema,
+            cdm::READER_ROLE,
+            cdm::WRITER_ROLE
+        ),
+        format!(
+            "GRANT SELECT ON ALL TABLES IN SCHEMA {} TO {}",
+            schema,
+            cdm::READER_ROLE
+        ),
+        format!(
+            "GRANT SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA {} TO {}",
+            schema,
+            cdm::WRITER_ROLE
+        ),
+        format!(
+            "GRANT USAGE ON ALL SEQUENCES IN SCHEMA {} TO {}",
+            schema,
+            cdm::WRITER_ROLE
+        ),
+        format!(
+            "ALTER DEFAULT PRIVILEGES IN SCHEMA {} GRANT SELECT ON TABLES TO {}",
+            schema,
+            cdm::READER_ROLE
+        ),
+        format!(
+            "ALTER DEFAULT PRIVILEGES IN SCHEMA {} GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO {}",
+            schema,
+            cdm::WRITER_ROLE
+        ),
+        format!(
+            "ALTER DEFAULT PRIVILEGES IN SCHEMA {} GRANT USAGE ON SEQUENCES TO {}",
+            schema,
+            cdm::WRITER_ROLE
+        ),
+    ] {
+        sqlx::query(&statement)
+            .execute(&mut *txn)
+            .await
+            .map_err(merr)?;
+    }
+    txn.commit().await.map_err(merr)?;
+    report!(
+        "granted {} read and {} read-write access to {}",
+        cdm::READER_ROLE,
+        cdm::WRITER_ROLE,
+        schema
+    );
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Fails unless every run is the actor's, as dropping or truncating
+/// the tables deletes them all. A schema from before runs had owners has
+/// nothing to check
+async fn check_owners(conn: &mut PgConnection, actor: &Actor, action: &'static str) -> Result<()> {
+    let owned: bool = sqlx::query_scalar(
+        "SELECT EXISTS (
+            SELECT 1 FROM pg_attribute
+            WHERE attrelid = to_regclass('run') AND attname = 'owner' AND NOT attisdropped
+        )",
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(merr)?;
+    if !owned {
+        return Ok(());
+    }
+    let runs: Vec<(Uuid, Option<String>)> = sqlx::query_as("SELECT run_uuid, owner FROM run")
+        .fetch_all(&mut *conn)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_METRIC_DESC)
-        .execute(&mut *txn)
+    owner::check(actor, action, &runs)?;
+    Ok(())
+}
+
+/// Drops the tables and views, along with the record of applied migrations,
+/// and creates them again. The audit log is recreated along with them, so
+/// the drop is recorded in the same transaction
+pub async fn drop_tables(pool: &PgPool, actor: &Actor) -> Result<()> {
+    let tables: Vec<&str> = cdm::DATA_TABLES
+        .iter()
+        .chain(cdm::SETTINGS_TABLES)
+        .chain(cdm::SCHEMA_TABLES)
+        .copied()
+        .collect();
+    let mut txn = pool.begin().await.map_err(merr)?;
+    check_owners(&mut txn, actor, "drop").await?;
+    for view in cdm::MATERIALIZED_VIEWS {
+        sqlx::query(&format!("DROP MATERIALIZED VIEW IF EXISTS {}", view))
+            .execute(&mut *txn)
+            .await
+            .map_err(merr)?;
+    }
+    sqlx::query(&format!(
+        "DROP TABLE IF EXISTS {} CASCADE",
+        tables.join(", ")
+    ))
+    .execute(&mut *txn)
+    .await
+    .map_err(merr)?;
+    MIGRATOR.run(&mut *txn).await.map_err(merr)?;
+    audit::record(&mut *txn, "init --drop", &Value::Null, 0).await?;
+    txn.commit().await.map_err(merr)?;
+    report!("dropped {}", tables.join(", "));
+    Ok(())
+}
+
+/// Deletes every row of data and refreshes the views that exist, so they
+/// don't keep summarizing the deleted runs. The truncate is the first entry
+/// of the emptied audit log
+pub async fn truncate_tables(pool: &PgPool, actor: &Actor) -> Result<()> {
+    let mut txn = pool.begin().await.map_err(merr)?;
+    check_owners(&mut txn, actor, "truncate").await?;
+    sqlx::query(&format!(
+        "TRUNCATE {} RESTART IDENTITY",
+        cdm::DATA_TABLES.join(", ")
+    ))
+    .execute(&mut *txn)
+    .await
+    .map_err(merr)?;
+    for view in cdm::MATERIALIZED_VIEWS {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(view)
+            .fetch_one(&mut *txn)
+            .await
+            .map_err(merr)?;
+        if exists {
+            sqlx::query(&format!("REFRESH MATERIALIZED VIEW {}", view))
+                .execute(&mut *txn)
+                .await
+                .map_err(merr)?;
+        }
+    }
+    audit::record(&mut *txn, "init --truncate", &Value::Null, 0).await?;
+    txn.commit().await.map_err(merr)?;
+    report!("truncated {}", cdm::DATA_TABLES.join(", "));
+    Ok(())
+}
+
+/// Applies any pending migrations and reports them
+pub async fn migrate(pool: &PgPool) -> Result<()> {
+    let pending = pending_migrations(pool).await?;
+    MIGRATOR.run(pool).await.map_err(merr)?;
+    for (version, description) in pending {
+        info!("applied migration {} ({})", version, description);
+    }
+    Ok(())
+}
+
+/// The migrations that haven't been applied to the database yet
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<(i64, String)>> {
+    let tracked: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_NAME)
-        .execute(&mut *txn)
+    let applied: Vec<i64> = if tracked {
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await
+            .map_err(merr)?
+    } else {
+        vec![]
+    };
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+        .map(|m| (m.version, m.description.to_string()))
+        .collect())
+}
+
+/// Verifies the database's schema is the one this binary was built against,
+/// so drift shows up as a clear error rather than a missing column
+pub async fn check_schema(pool: &PgPool) -> Result<()> {
+    let expected = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+    let tracked: bool = sqlx::query_scalar("SELECT to_regclass('schema_version') IS NOT NULL")
+        .fetch_one(pool)
         .await
         .map_err(merr)?;
-    sqlx::query(cdm::SQL_TABLE_METRIC_DATA)
-        .execute(&mut *txn)
+    if !tracked {
+        return Err(SCDMError::SchemaMismatch(
+            "the database has no scdm schema, run `scdm init`".to_string(),
+        )
+        .into());
+    }
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_version")
+        .fetch_one(pool)
         .await
         .map_err(merr)?;
-    txn.commit().await.map_err(merr)?;
+    let version = version.unwrap_or(0);
+    if version > expected {
+        return Err(SCDMError::SchemaMismatch(format!(
+            "the database schema is at version {} but this scdm only knows version {}, upgrade scdm",
+            version, expected
+        ))
+        .into());
+    }
+    if version < expected || !pending_migrations(pool).await?.is_empty() {
+        return Err(SCDMError::SchemaMismatch(format!(
+            "the database schema is at version {} but this scdm needs version {}, run `scdm init --migrate`",
+            version, expected
+        ))
+        .into());
+    }
+    Ok(())
+}
 
+/// Creates the tables on a fresh database. A database that already has
+/// them is left alone, but pending migrations have to be applied with
+/// `init --migrate`.
+pub async fn init_tables(pool: &PgPool) -> Result<()> {
+    let initialized: bool = sqlx::query_scalar("SELECT to_regclass('run') IS NOT NULL")
+        .fetch_one(pool)
+        .await
+        .map_err(merr)?;
+    if !initialized {
+        MIGRATOR.run(pool).await.map_err(merr)?;
+        return Ok(());
+    }
+    let pending = pending_migrations(pool).await?;
+    if !pending.is_empty() {
+        return Err(SCDMError::FailedTableInit(format!(
+            "the database has {} pending migrations, run `scdm init --migrate`",
+            pending.len()
+        ))
+        .into());
+    }
     Ok(())
 }
@@ -0,0 +1,356 @@
+//! Ingestion and querying of Crucible Common Data Model results in Postgres.
+//!
+//! The `scdm` binary is a thin command line over this library. `parser` and
+//! `add` read results into CDM documents and insert them, `cdm` holds the
+//! types of the tables, and `query` and `metric` build the queries the
+//! `query` subcommands run. Each command's module takes a pool and its
+//! arguments from `args`, so other tools can run them without the binary.
+
+pub mod adapter;
+pub mod add;
+pub mod agent;
+pub mod analyze;
+pub mod anonymize;
+pub mod args;
+pub mod artifact;
+pub mod audit;
+pub mod backup;
+pub mod bench;
+pub mod cache;
+pub mod cancel;
+pub mod cdm;
+pub mod compare;
+pub mod config;
+pub mod convert;
+pub mod credentials;
+pub mod dedupe;
+pub mod diff;
+pub mod doctor;
+pub mod export;
+pub mod grafana;
+pub mod import;
+pub mod init;
+pub mod link;
+pub mod logging;
+pub mod maintain;
+pub mod mangen;
+pub mod metric;
+pub mod nats;
+pub mod otlp;
+pub mod owner;
+pub mod parser;
+pub mod partition;
+pub mod policy;
+pub mod prometheus;
+pub mod prune;
+pub mod purge;
+pub mod query;
+pub mod refresh;
+pub mod render;
+pub mod repl;
+pub mod report;
+pub mod restore;
+pub mod retention;
+pub mod rollup;
+pub mod serve;
+pub mod spill;
+pub mod stats;
+pub mod status;
+pub mod sync;
+pub mod timescale;
+pub mod unit;
+pub mod uuid_prefix;
+pub mod validate;
+pub mod vega;
+pub mod xlsx;
+
+use crate::args::GlobalOpts;
+use crate::cancel::Cancellation;
+use anyhow::Result;
+use log::LevelFilter;
+use sqlx::ConnectOptions;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SCDMError {
+    #[error("Couldn't find DB login info as an ENV variable or cli arg: {0}")]
+    MissingDBInfo(String),
+    #[error("Invalid DB login info provided: {0}")]
+    InvalidDBInfo(String),
+    #[error("Failed to create the necessary tables: {0}")]
+    FailedTableInit(String),
+    #[error("Failed to parse timestamp: {0}")]
+    FailedTimestampParse(String),
+    #[error("Failed to parse interval: {0}")]
+    FailedIntervalParse(String),
+    #[error("Failed to parse tag, expected \"tag_name=tag_value\": {0}")]
+    FailedTagParse(String),
+    #[error("Failed to parse owner token, expected \"owner=token\"")]
+    FailedOwnerTokenParse,
+    #[error("Incompatible database schema: {0}")]
+    SchemaMismatch(String),
+    #[error("The command changes the data, which isn't allowed with --read-only")]
+    ReadOnly,
+    #[error("Couldn't load the .env file: {0}")]
+    InvalidEnvFile(String),
+}
+
+/// The schema holding a workspace's tables
+pub fn workspace_schema(workspace: &str) -> Result<String, SCDMError> {
+    let valid = !workspace.is_empty()
+        && workspace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(SCDMError::InvalidDBInfo(format!(
+            "Workspace names may only contain letters, digits, '_' and '-' ({})",
+            workspace
+        )));
+    }
+    Ok(format!("scdm_{}", workspace))
+}
+
+/// The connection options in a postgres:// URL
+pub fn uri_connect_options(uri: &str) -> Result<PgConnectOptions, SCDMError> {
+    if !uri.starts_with("postgres://") && !uri.starts_with("postgresql://") {
+        return Err(SCDMError::InvalidDBInfo(String::from(
+            "The database URL has to start with postgres:// or postgresql://",
+        )));
+    }
+    PgConnectOptions::from_str(uri)
+        .map_err(|e| SCDMError::InvalidDBInfo(format!("Couldn't parse the database URL ({})", e)))
+}
+
+/// The schema holding the tables, DB_SCHEMA or the workspace's
+pub fn db_schema(global_opts: &GlobalOpts) -> Result<Option<String>, SCDMError> {
+    match &global_opts.workspace {
+        Some(_) if global_opts.db_schema.is_some() => Err(SCDMError::InvalidDBInfo(String::from(
+            "A workspace can't be combined with DB_SCHEMA",
+        ))),
+        Some(workspace) => workspace_schema(workspace).map(Some),
+        None => Ok(global_opts.db_schema.clone()),
+    }
+}
+
+/// A password asked for, to keep in the keyring once it's been used to
+/// connect, along with the user, host and port it's for
+type NewKeyringPassword = (String, String, u16, String);
+
+/// The connection options of the global options. A connection URL stands in
+/// for all of the separate DB_* settings, and without a password in either
+/// it's taken from the keyring or asked for
+fn connect_options(
+    global_opts: &GlobalOpts,
+) -> Result<(PgConnectOptions, Option<NewKeyringPassword>)> {
+    let mut new_keyring_password = None;
+    let conn_opts = match &global_opts.db_uri {
+        Some(db_uri) => uri_connect_options(db_uri)?,
+        None => {
+            let db_user = global_opts.db_user.clone();
+            let db_password = credentials::secret(
+                global_opts.db_password.clone(),
+                global_opts.db_password_file.clone(),
+            )?;
+            let db_url = global_opts.db_url.clone();
+            let db_port: u16 = global_opts
+                .db_port
+                .as_deref()
+                .unwrap_or("5432")
+                .parse::<u16>()
+                .map_err(|e| {
+                    SCDMError::InvalidDBInfo(format!(
+                        "Couldn't convert provided port to a u16 ({})",
+                        e
+                    ))
+                })?;
+
+            let db_name = global_opts.db_name.as_deref().unwrap_or("scdm");
+
+            // A path given as the host is the directory holding the socket
+            let db_socket = global_opts
+                .db_socket
+                .clone()
+                .or(db_url.clone().filter(|url| url.starts_with('/')));
+
+            let conn_opts = PgConnectOptions::new().port(db_port).database(db_name);
+            match db_socket {
+                // Peer authentication needs neither, and the user defaults to the OS user
+                Some(db_socket) => {
+                    let mut conn_opts = conn_opts.socket(db_socket);
+                    if let Some(db_user) = db_user {
+                        conn_opts = conn_opts.username(&db_user);
+                    }
+                    if let Some(db_password) = db_password {
+                        conn_opts = conn_opts.password(&db_password);
+                    }
+                    conn_opts
+                }
+                None => {
+                    let db_url = db_url.ok_or(SCDMError::MissingDBInfo(String::from("DB_URL")))?;
+                    let db_user =
+                        db_user.ok_or(SCDMError::MissingDBInfo(String::from("DB_USER")))?;
+                    let db_password = match db_password {
+                        Some(db_password) => db_password,
+                        None => {
+                            let stored = if global_opts.use_keyring {
+                                credentials::keyring_password(&db_user, &db_url, db_port)?
+                            } else {
+                                None
+                            };
+                            match stored {
+                                Some(db_password) => db_password,
+                                None => {
+                                    let db_password =
+                                        credentials::prompt_password(&db_user, &db_url)?;
+                                    if global_opts.use_keyring {
+                                        new_keyring_password = Some((
+                                            db_user.clone(),
+                                            db_url.clone(),
+                                            db_port,
+                                            db_password.clone(),
+                                        ));
+                                    }
+                                    db_password
+                                }
+                            }
+                        }
+                    };
+                    conn_opts
+                        .host(&db_url)
+                        .username(&db_user)
+                        .password(&db_password)
+                }
+            }
+        }
+    };
+
+    // Identifies the sessions in pg_stat_activity, unless the URL names them
+    let mut conn_opts = match conn_opts.get_application_name() {
+        Some(_) => conn_opts,
+        None => conn_opts.application_name("scdm"),
+    };
+    if let Some(statement_timeout) = &global_opts.statement_timeout {
+        let millis = args::parse_interval(statement_timeout)?;
+        conn_opts = conn_opts.options([("statement_timeout", format!("{}ms", millis))]);
+    }
+    if global_opts.log_sql {
+        conn_opts = conn_opts.log_statements(LevelFilter::Info);
+    }
+    Ok((conn_opts, new_keyring_password))
+}
+
+/// A timeout of the connection pool, given as an interval like "30s"
+fn pool_timeout(interval: &str) -> Result<Duration, SCDMError> {
+    let millis = args::parse_interval(interval)?;
+    Ok(Duration::from_millis(millis as u64))
+}
+
+/// The pool options of the global options. Each connection is tracked by the
+/// cancellation, is read only with --read-only and resolves unqualified
+/// names, including those in the migrations, to the schema
+fn pool_options(
+    global_opts: &GlobalOpts,
+    schema: Option<&str>,
+    cancellation: &Cancellation,
+) -> Result<PgPoolOptions> {
+    let mut pool_opts = PgPoolOptions::new();
+    if let Some(max_connections) = global_opts.db_max_connections {
+        pool_opts = pool_opts.max_connections(max_connections);
+    }
+    if let Some(acquire_timeout) = &global_opts.db_acquire_timeout {
+        pool_opts = pool_opts.acquire_timeout(pool_timeout(acquire_timeout)?);
+    }
+    if let Some(idle_timeout) = &global_opts.db_idle_timeout {
+        pool_opts = pool_opts.idle_timeout(pool_timeout(idle_timeout)?);
+    }
+
+    let search_path = schema.map(metric::quote_ident);
+    let read_only = global_opts.read_only;
+    let cancellation = cancellation.clone();
+    Ok(pool_opts.after_connect(move |conn, _meta| {
+        let search_path = search_path.clone();
+        let cancellation = cancellation.clone();
+        Box::pin(async move {
+            cancellation.track_backend(conn).await?;
+            // Postgres refuses any write, even from the commands allowed to run
+            if read_only {
+                sqlx::query("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY")
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            if let Some(search_path) = search_path {
+                sqlx::query("SELECT set_config('search_path', $1, false)")
+                    .bind(search_path)
+                    .execute(conn)
+                    .await?;
+            }
+            Ok(())
+        })
+    }))
+}
+
+/// The connection pool of the global options and how it connects, so a
+/// replica can connect the same way and the cancellation can reach its backends
+pub struct Database {
+    pub pool: PgPool,
+    pub connect_options: PgConnectOptions,
+    pool_options: PgPoolOptions,
+    /// The schema holding the tables, DB_SCHEMA or the workspace's
+    pub schema: Option<String>,
+}
+
+impl Database {
+    /// Connects to the replica at the URL with the pool's settings, and
+    /// checks it has the schema
+    pub async fn replica(&self, uri: &str) -> Result<PgPool> {
+        let replica_opts = match uri_connect_options(uri)? {
+            opts if opts.get_application_name().is_some() => opts,
+            opts => opts.application_name("scdm"),
+        };
+        let replica = self.pool_options.clone().connect_with(replica_opts).await?;
+        init::check_schema(&replica).await?;
+        Ok(replica)
+    }
+}
+
+/// Connects to the database the global options describe, with the pool's
+/// sessions tracked by the cancellation. A password that was asked for is
+/// kept in the keyring once it has connected
+pub async fn connect(global_opts: &GlobalOpts, cancellation: &Cancellation) -> Result<Database> {
+    let schema = db_schema(global_opts)?;
+    let (connect_options, new_keyring_password) = connect_options(global_opts)?;
+    let pool_options = pool_options(global_opts, schema.as_deref(), cancellation)?;
+    let pool = pool_options
+        .clone()
+        .connect_with(connect_options.clone())
+        .await?;
+    if let Some((db_user, db_url, db_port, db_password)) = new_keyring_password {
+        credentials::save_keyring_password(&db_user, &db_url, db_port, &db_password)?;
+    }
+    Ok(Database {
+        pool,
+        connect_options,
+        pool_options,
+        schema,
+    })
+}
+
+/// Like `connect`, but the pool only connects once it's used, so a failure
+/// to connect is the first query's
+pub fn connect_lazy(global_opts: &GlobalOpts, cancellation: &Cancellation) -> Result<Database> {
+    let schema = db_schema(global_opts)?;
+    let (connect_options, _) = connect_options(global_opts)?;
+    let pool_options = pool_options(global_opts, schema.as_deref(), cancellation)?;
+    let pool = pool_options
+        .clone()
+        .connect_lazy_with(connect_options.clone());
+    Ok(Database {
+        pool,
+        connect_options,
+        pool_options,
+        schema,
+    })
+}
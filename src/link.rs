@@ -0,0 +1,70 @@
+use crate::args::LinkArgs;
+use crate::audit;
+use crate::owner::{self, Actor};
+use crate::report;
+use anyhow::Result;
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum LinkError {
+    #[error("Couldn't link the runs, {0}")]
+    LinkFailed(String),
+    #[error("{0} isn't {1} {2}")]
+    NotLinked(String, String, String),
+}
+
+pub async fn link(pool: &PgPool, args: LinkArgs, actor: &Actor) -> Result<()> {
+    let relation = args.relation.as_str();
+    let lerr = |e: sqlx::Error| LinkError::LinkFailed(format!("{}", e));
+    let mut txn = pool.begin().await.map_err(lerr)?;
+    let linked: Vec<(Uuid, Option<String>)> =
+        sqlx::query_as("SELECT run_uuid, owner FROM run WHERE run_uuid IN ($1, $2)")
+            .bind(args.from)
+            .bind(args.to)
+            .fetch_all(&mut *txn)
+            .await
+            .map_err(lerr)?;
+    owner::check(actor, if args.remove { "unlink" } else { "link" }, &linked)?;
+    if args.remove {
+        let res = sqlx::query(
+            "DELETE FROM run_link WHERE from_run_uuid = $1 AND to_run_uuid = $2 AND relation = $3",
+        )
+        .bind(args.from)
+        .bind(args.to)
+        .bind(relation)
+        .execute(&mut *txn)
+        .await
+        .map_err(lerr)?;
+        if res.rows_affected() == 0 {
+            return Err(LinkError::NotLinked(
+                args.from.to_string(),
+                relation.to_string(),
+                args.to.to_string(),
+            )
+            .into());
+        }
+        audit::record(&mut *txn, "link --remove", &args, res.rows_affected()).await?;
+        txn.commit().await.map_err(lerr)?;
+        report!("unlinked {} {} {}", args.from, relation, args.to);
+        return Ok(());
+    }
+
+    let raw_query: &str = r#"
+        INSERT INTO run_link (from_run_uuid, to_run_uuid, relation)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+    "#;
+    let res = sqlx::query(raw_query)
+        .bind(args.from)
+        .bind(args.to)
+        .bind(relation)
+        .execute(&mut *txn)
+        .await
+        .map_err(lerr)?;
+    audit::record(&mut *txn, "link", &args, res.rows_affected()).await?;
+    txn.commit().await.map_err(lerr)?;
+    report!("linked {} {} {}", args.from, relation, args.to);
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use crate::args::LogFormat;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// Logs to stderr, so it never mixes with the output of the commands
+pub fn init(verbose: u8, quiet: bool, format: LogFormat, log_sql: bool) {
+    set_quiet(quiet);
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        let filter = match verbose {
+            _ if quiet => "error",
+            0 => "warn,scdm=info",
+            1 => "warn,scdm=debug",
+            _ => "info,scdm=trace",
+        };
+        // sqlx logs the statements under sqlx::query, at the level --log-sql sets
+        if log_sql {
+            EnvFilter::new(format!("{},sqlx::query=info", filter))
+        } else {
+            EnvFilter::new(filter)
+        }
+    });
+    // The closed spans carry how long each step took
+    let span_events = if verbose > 0 {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(span_events)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr);
+    match format {
+        LogFormat::Text if verbose > 0 => builder.init(),
+        LogFormat::Text => builder.without_time().with_target(false).init(),
+        LogFormat::JSON => builder.json().init(),
+    }
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints what a command did, unless --quiet is given. The results asked
+/// for, like query output, are printed regardless.
+#[macro_export]
+macro_rules! report {
+    ($($arg:tt)*) => {
+        if !$crate::logging::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
@@ -1,81 +1,210 @@
 use anyhow::Result;
-use args::Command;
-use clap::Parser;
-use sqlx::postgres::{PgConnectOptions, PgPool};
-use std::env;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use scdm::args::{self, Command};
+use scdm::{
+    SCDMError, add, agent, analyze, artifact, audit, backup, bench, compare, config, connect,
+    connect_lazy, convert, credentials, dedupe, doctor, export, import, init, link, logging,
+    maintain, mangen, nats, otlp, owner, parser, policy, prune, purge, query, refresh, repl,
+    report, restore, retention, rollup, serve, stats, status, sync, uuid_prefix, validate,
+};
+use std::io;
 use std::path::Path;
-use thiserror::Error;
 
-pub mod add;
-pub mod args;
-pub mod cdm;
-pub mod import;
-pub mod init;
-pub mod metric;
-pub mod parser;
-pub mod query;
-
-#[derive(Error, Debug)]
-pub enum SCDMError {
-    #[error("Couldn't find DB login info as an ENV variable or cli arg: {0}")]
-    MissingDBInfo(String),
-    #[error("Invalid DB login info provided: {0}")]
-    InvalidDBInfo(String),
-    #[error("Failed to create the necessary tables: {0}")]
-    FailedTableInit(String),
-    #[error("Failed to parse timestamp: {0}")]
-    FailedTimestampParse(String),
+/// Runs the commands that don't need the database, before any connection
+/// or credentials are looked for
+async fn offline(command: Command, uuid_v7: bool) -> Result<()> {
+    match command {
+        Command::Validate(validate_args) => validate::validate(validate_args),
+        Command::Convert(convert_args) => convert::convert(convert_args, uuid_v7),
+        Command::Completions(completions_args) => {
+            clap_complete::generate(
+                completions_args.shell,
+                &mut args::App::command(),
+                "scdm",
+                &mut io::stdout(),
+            );
+            Ok(())
+        }
+        Command::Mangen(mangen_args) => mangen::mangen(mangen_args),
+        Command::Agent(agent_args) => agent::agent(agent_args, uuid_v7).await,
+        _ => unreachable!("only offline commands are run without the database"),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = args::App::parse();
-
-    let db_user = env::var("DB_USER").or(args
-        .global_opts
-        .db_user
-        .ok_or(SCDMError::MissingDBInfo(String::from("DB_USER"))))?;
-    let db_password = env::var("DB_PASSWORD").or(args
-        .global_opts
-        .db_password
-        .ok_or(SCDMError::MissingDBInfo(String::from("DB_PASSWORD"))))?;
-    let db_url = env::var("DB_URL").or(args
-        .global_opts
-        .db_url
-        .ok_or(SCDMError::MissingDBInfo(String::from("DB_URL"))))?;
-    let db_port: u16 = env::var("DB_PORT")
-        .unwrap_or(args.global_opts.db_port.unwrap_or(String::from("5432")))
-        .parse::<u16>()
-        .map_err(|e| {
-            SCDMError::InvalidDBInfo(format!("Couldn't convert provided port to a u16 ({})", e))
-        })?;
+    // A .env in the working directory fills in the Env variables that aren't set
+    if let Err(e) = dotenvy::dotenv()
+        && !e.not_found()
+    {
+        return Err(SCDMError::InvalidEnvFile(e.to_string()).into());
+    }
+    // UUIDs given by their start are looked up once there's a database
+    let words: Vec<String> = std::env::args().collect();
+    let uuid_args = uuid_prefix::uuid_args(&args::App::command(), &words, 1);
+    let matches =
+        args::App::command().get_matches_from(uuid_prefix::with_placeholders(&words, &uuid_args));
+    let mut args = args::App::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logging::init(
+        args.global_opts.verbose,
+        args.global_opts.quiet,
+        args.global_opts.log_format,
+        args.global_opts.log_sql,
+    );
+    if let Some(command) = args.command.take_if(|command| command.is_offline()) {
+        return offline(command, args.global_opts.uuid_v7).await;
+    }
+    let applied = config::apply_profile(&mut args.global_opts, args.command.as_mut())?;
+    if args.global_opts.show_config {
+        return config::show_config(&matches, &args.global_opts, &applied);
+    }
+    let Some(mut command) = args.command else {
+        args::App::command()
+            .error(ErrorKind::MissingSubcommand, "A command is required")
+            .exit();
+    };
+    if args.global_opts.read_only && !command.is_read_only() {
+        return Err(SCDMError::ReadOnly.into());
+    }
 
-    let db_name = env::var("DB_NAME").or(args.global_opts.db_name.ok_or(
-        SCDMError::InvalidDBInfo(String::from("No database name provided")),
-    ))?;
+    let ingest_opts = parser::IngestOpts::new(&args.global_opts);
+    let actor = owner::Actor::from_global_opts(&args.global_opts);
 
-    let conn_opts = PgConnectOptions::new()
-        .host(&db_url)
-        .port(db_port)
-        .database(&db_name)
-        .username(&db_user)
-        .password(&db_password);
+    // The doctor reports a failure to connect as one of its checks
+    let database = match command {
+        Command::Doctor(_) => connect_lazy(&args.global_opts, &ingest_opts.cancellation)?,
+        _ => connect(&args.global_opts, &ingest_opts.cancellation).await?,
+    };
+    let pool = database.pool.clone();
 
-    let pool = PgPool::connect_with(conn_opts).await?;
+    if !matches!(command, Command::Init(_) | Command::Doctor(_)) {
+        init::check_schema(&pool).await?;
+    }
 
-    let result = match args.command {
-        Command::Parse(parse_args) => {
-            let dir_path = Path::new(&parse_args.path);
-            parser::parse(&pool, dir_path).await
+    if !uuid_args.is_empty() {
+        let words = uuid_prefix::resolve(&pool, &words, &uuid_args).await?;
+        let mut resolved = args::App::try_parse_from(words).unwrap_or_else(|e| e.exit());
+        config::apply_profile(&mut resolved.global_opts, resolved.command.as_mut())?;
+        if let Some(resolved) = resolved.command {
+            command = resolved;
         }
-        Command::Add(add_args) => {
-            let path = Path::new(&add_args.path);
-            add::add(&pool, path).await
+    }
+
+    // Ingested results are also written to the replica, to keep an archive in sync
+    let replica = match &args.global_opts.replica_uri {
+        Some(replica_uri)
+            if matches!(
+                command,
+                Command::Parse(_)
+                    | Command::Add(_)
+                    | Command::Import(_)
+                    | Command::Sync(_)
+                    | Command::Nats(_)
+                    | Command::Restore(_)
+                    | Command::Serve(_)
+            ) =>
+        {
+            Some(database.replica(replica_uri).await?)
         }
-        Command::Query(query_args) => query::query(&pool, query_args).await,
-        Command::Import(import_args) => import::import(&pool, import_args).await,
-        Command::Init => init::init_tables(&pool).await,
+        _ => None,
+    };
+
+    let opensearch = import::OpenSearchOpts {
+        url: args.global_opts.opensearch_url,
+        user: args.global_opts.opensearch_user,
+        password: credentials::secret(
+            args.global_opts.opensearch_password,
+            args.global_opts.opensearch_password_file,
+        )?,
     };
+    let cancel_opts = database.connect_options.clone();
+    let cancellation = ingest_opts.cancellation.clone();
+    let db_schema = database.schema;
 
-    result
+    // The daemons shut themselves down on a signal, and the REPL carries on
+    let cancellable = !matches!(
+        command,
+        Command::Serve(_) | Command::Nats(_) | Command::Sync(_) | Command::Repl(_)
+    );
+    let run = async move {
+        match command {
+            Command::Parse(parse_args) => {
+                parser::parse(&pool, replica.as_ref(), parse_args, &ingest_opts).await
+            }
+            Command::Add(add_args) => {
+                let path = Path::new(&add_args.path);
+                let status_mode = add_args.status.mode();
+                add::add(&pool, replica.as_ref(), path, status_mode, &ingest_opts).await
+            }
+            Command::Query(query_args) => query::query(&pool, query_args, &actor).await,
+            Command::Import(import_args) => {
+                import::import(
+                    &pool,
+                    replica.as_ref(),
+                    import_args,
+                    &opensearch,
+                    &ingest_opts,
+                )
+                .await
+            }
+            Command::Init(init_args) => {
+                init::init(&pool, init_args, db_schema.as_deref(), &actor).await
+            }
+            Command::Refresh(refresh_args) => refresh::refresh(&pool, refresh_args).await,
+            Command::Rollup(rollup_args) => rollup::rollup(&pool, rollup_args).await,
+            Command::Analyze(analyze_args) => analyze::analyze(&pool, analyze_args).await,
+            Command::Prune(prune_args) => prune::prune(&pool, prune_args).await,
+            Command::Stats(stats_args) => stats::stats(&pool, stats_args).await,
+            Command::Maintain(maintain_args) => maintain::maintain(&pool, maintain_args).await,
+            Command::Doctor(doctor_args) => doctor::doctor(&pool, doctor_args, &opensearch).await,
+            Command::Artifact(artifact_args) => {
+                artifact::artifact(&pool, artifact_args, ingest_opts.uuid_v7).await
+            }
+            Command::Link(link_args) => link::link(&pool, link_args, &actor).await,
+            Command::Restore(restore_args) => {
+                restore::restore(&pool, replica.as_ref(), restore_args, &ingest_opts, &actor).await
+            }
+            Command::Purge(purge_args) => purge::purge(&pool, purge_args, &actor).await,
+            Command::Audit(audit_args) => audit::audit(&pool, audit_args).await,
+            Command::Backup(backup_args) => backup::backup(&pool, backup_args).await,
+            Command::Export(export_args) => export::export(&pool, export_args).await,
+            Command::Dedupe(dedupe_args) => dedupe::dedupe(&pool, dedupe_args, &actor).await,
+            Command::Retention(retention_args) => retention::retention(&pool, retention_args).await,
+            Command::Policy(policy_args) => policy::policy(&pool, policy_args).await,
+            Command::Serve(serve_args) => {
+                serve::serve(&pool, replica.as_ref(), serve_args, &ingest_opts, &actor).await
+            }
+            Command::Otlp(otlp_args) => otlp::otlp(&pool, otlp_args).await,
+            Command::Repl(repl_args) => repl::repl(&pool, repl_args, &actor).await,
+            Command::Report(report_args) => report::report(&pool, report_args).await,
+            Command::Compare(compare_args) => compare::compare(&pool, compare_args).await,
+            Command::Status(status_args) => status::status(&pool, status_args).await,
+            Command::Sync(sync_args) => {
+                sync::sync(
+                    &pool,
+                    replica.as_ref(),
+                    sync_args,
+                    &opensearch,
+                    &ingest_opts,
+                )
+                .await
+            }
+            Command::Nats(nats_args) => {
+                nats::nats(&pool, replica.as_ref(), nats_args, &ingest_opts).await
+            }
+            Command::Bench(bench_args) => bench::bench(&pool, bench_args, &ingest_opts).await,
+            Command::Validate(_)
+            | Command::Convert(_)
+            | Command::Completions(_)
+            | Command::Mangen(_)
+            | Command::Agent(_) => {
+                unreachable!("offline commands are run before connecting")
+            }
+        }
+    };
+    match cancellable {
+        true => cancellation.run(&cancel_opts, run).await,
+        false => run.await,
+    }
 }
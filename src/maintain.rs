@@ -0,0 +1,49 @@
+use crate::args::MaintainArgs;
+use crate::cdm;
+use anyhow::Result;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum MaintainError {
+    #[error("{0} isn't an scdm table")]
+    UnknownTable(String),
+    #[error("Couldn't maintain {0}, {1}")]
+    MaintainFailed(String, String),
+}
+
+/// Vacuums and analyzes the tables, and reindexes them if asked to. VACUUM
+/// can't run inside of a transaction, so each statement runs on its own.
+pub async fn maintain(pool: &PgPool, args: MaintainArgs) -> Result<()> {
+    let tables: Vec<String> = match args.table {
+        Some(tables) => tables,
+        None => cdm::DATA_TABLES.iter().map(|t| t.to_string()).collect(),
+    };
+    if let Some(unknown) = tables
+        .iter()
+        .find(|t| !cdm::DATA_TABLES.contains(&t.as_str()))
+    {
+        return Err(MaintainError::UnknownTable(unknown.clone()).into());
+    }
+
+    let vacuum = if args.full {
+        "VACUUM (FULL, ANALYZE)"
+    } else {
+        "VACUUM (ANALYZE)"
+    };
+    for table in tables {
+        sqlx::query(&format!("{} {}", vacuum, table))
+            .execute(pool)
+            .await
+            .map_err(|e| MaintainError::MaintainFailed(table.clone(), format!("{}", e)))?;
+        if args.reindex {
+            sqlx::query(&format!("REINDEX TABLE {}", table))
+                .execute(pool)
+                .await
+                .map_err(|e| MaintainError::MaintainFailed(table.clone(), format!("{}", e)))?;
+        }
+        info!("maintained {}", table);
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use crate::args::{App, MangenArgs};
+use crate::report;
+use anyhow::Result;
+use clap::CommandFactory;
+use clap_mangen::Man;
+use std::fs::{self, File};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MangenError {
+    #[error("Couldn't write the man page {0}, {1}")]
+    WriteFailed(String, String),
+}
+
+/// Writes the man page of the command, then those of its subcommands, named
+/// like scdm-query-get.1
+fn write_pages(dir: &Path, command: clap::Command, name: &str) -> Result<u64> {
+    // The synopsis shows the full invocation, ex: scdm query get
+    let command = command
+        .bin_name(name.replace('-', " "))
+        .version(env!("CARGO_PKG_VERSION"));
+    let path = dir.join(format!("{}.1", name));
+    let mut f = File::create(&path)
+        .map_err(|e| MangenError::WriteFailed(path.display().to_string(), format!("{}", e)))?;
+    Man::new(command.clone())
+        .title(name)
+        .render(&mut f)
+        .map_err(|e| MangenError::WriteFailed(path.display().to_string(), format!("{}", e)))?;
+
+    let mut num_pages = 1;
+    for subcommand in command
+        .get_subcommands()
+        .filter(|c| !c.is_hide_set() && c.get_name() != "help")
+    {
+        let subname = format!("{}-{}", name, subcommand.get_name());
+        num_pages += write_pages(dir, subcommand.clone(), &subname)?;
+    }
+    Ok(num_pages)
+}
+
+/// Generates the man pages from the command line definitions
+pub fn mangen(args: MangenArgs) -> Result<()> {
+    let dir = Path::new(&args.out);
+    fs::create_dir_all(dir)
+        .map_err(|e| MangenError::WriteFailed(args.out.clone(), format!("{}", e)))?;
+    let mut command = App::command();
+    command.build();
+    let num_pages = write_pages(dir, command, "scdm")?;
+    report!("wrote {} man pages to {}", num_pages, args.out);
+    Ok(())
+}
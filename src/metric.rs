@@ -1,15 +1,21 @@
 use std::fmt;
 
-use crate::args::{Aggregator, MetricArgs, OutputFormat};
+use crate::args::{Aggregator, DisplayOpts, MetricArgs, MetricCommand, OutputFormat, Overlap};
+use crate::cache::{self, CachedResult};
 use crate::query::QueryError;
+use crate::xlsx::{self, Sheet, XlsxError};
+use crate::{diff, render, report, retention, rollup, unit, vega};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
 use serde::Serialize;
+use serde_json::Value;
 use sqlx::postgres::PgRow;
-use sqlx::{Column, PgPool, Postgres, QueryBuilder, Row};
+use sqlx::{Column, PgPool, Postgres, QueryBuilder, Row, TypeInfo};
 use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::time::Duration;
 use tabled::Table;
-use tabled::settings::Style;
 use uuid::Uuid;
 
 #[derive(Clone, Debug, Serialize)]
@@ -44,135 +50,451 @@ impl fmt::Display for CellValue {
     }
 }
 
-pub fn unpack_rows(
-    pg_rows: Vec<PgRow>,
-    names: &Vec<(String, Option<String>)>,
-) -> (Vec<String>, Vec<Vec<String>>) {
-    let mut results: Vec<Vec<String>> = Vec::new();
-    for pg_row in &pg_rows {
-        let run_uuid: Uuid = pg_row.try_get("run_uuid").unwrap_or(Uuid::nil());
-        let iteration_uuid: Uuid = pg_row.try_get("iteration_uuid").unwrap_or(Uuid::nil());
-        let metric_type: String = pg_row.try_get("metric_type").unwrap_or("null".to_string());
-        let mut row: Vec<String> = vec![
-            run_uuid.to_string(),
-            iteration_uuid.to_string(),
-            metric_type,
-        ];
-        let mut next_idx = 3;
-        for _ in names {
-            row.push(pg_row.try_get(next_idx).unwrap_or("null".to_string()));
-            next_idx += 1;
-        }
-        let begin: DateTime<Utc> = pg_row.try_get(next_idx).unwrap_or(DateTime::UNIX_EPOCH);
-        row.push(begin.to_string());
-        next_idx += 1;
-        let finish: DateTime<Utc> = pg_row.try_get(next_idx).unwrap_or(DateTime::UNIX_EPOCH);
-        row.push(finish.to_string());
-        next_idx += 1;
-        let value: f64 = pg_row.try_get(next_idx).unwrap_or(0.0);
-        row.push(value.to_string()); // aggregated value
-
-        results.push(row);
-    }
-    let header: Vec<String> = pg_rows
+/// Renders each column of a result row according to its Postgres type.
+pub fn unpack_row(pg_row: &PgRow, opts: &DisplayOpts) -> Vec<String> {
+    unpack_row_with(pg_row, |t| render::format_time(t, opts))
+}
+
+/// Renders each column of a result row according to its Postgres type, the
+/// timestamps with `time`
+fn unpack_row_with(pg_row: &PgRow, time: impl Fn(&DateTime<Utc>) -> String) -> Vec<String> {
+    pg_row
+        .columns()
         .iter()
-        .take(1)
-        .map(|r| {
-            r.columns()
-                .iter()
-                .map(|c| c.name().to_string())
-                .collect::<Vec<String>>()
+        .map(|column| {
+            let idx = column.ordinal();
+            let cell = match column.type_info().name() {
+                "UUID" => pg_row
+                    .try_get::<Option<Uuid>, _>(idx)
+                    .map(|v| v.map(|v| v.to_string())),
+                "TIMESTAMPTZ" => pg_row
+                    .try_get::<Option<DateTime<Utc>>, _>(idx)
+                    .map(|v| v.map(|v| time(&v))),
+                "FLOAT8" => pg_row
+                    .try_get::<Option<f64>, _>(idx)
+                    .map(|v| v.map(|v| v.to_string())),
+                "INT8" => pg_row
+                    .try_get::<Option<i64>, _>(idx)
+                    .map(|v| v.map(|v| v.to_string())),
+                _ => pg_row.try_get::<Option<String>, _>(idx),
+            };
+            cell.ok().flatten().unwrap_or("null".to_string())
         })
-        .flatten()
+        .collect()
+}
+
+pub fn row_header(pg_row: &PgRow) -> Vec<String> {
+    pg_row
+        .columns()
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect()
+}
+
+pub fn unpack_rows(pg_rows: Vec<PgRow>, opts: &DisplayOpts) -> (Vec<String>, Vec<Vec<String>>) {
+    let results: Vec<Vec<String>> = pg_rows
+        .iter()
+        .map(|pg_row| unpack_row(pg_row, opts))
         .collect();
+    let header: Vec<String> = pg_rows.first().map(row_header).unwrap_or_default();
     (header, results)
 }
 
-fn push_choose_aggregator(qb: &mut QueryBuilder<Postgres>, agg: Aggregator) {
-    match agg {
-        Aggregator::None => {
-            qb.push("metric_data.value as value");
+/// Writes CSV or NDJSON rows to stdout as they arrive from the database,
+/// so memory use doesn't grow with the size of the result.
+async fn stream_rows(
+    pool: &PgPool,
+    qb: &mut QueryBuilder<'_, Postgres>,
+    format: &OutputFormat,
+    opts: &DisplayOpts,
+) -> Result<()> {
+    let mut pg_rows = qb.build().fetch(pool);
+    match format {
+        OutputFormat::CSV => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            let mut wrote_header = false;
+            while let Some(pg_row) = pg_rows
+                .try_next()
+                .await
+                .map_err(|e| QueryError::MetricError(format!("{}", e)))?
+            {
+                if !wrote_header {
+                    writer.write_record(row_header(&pg_row))?;
+                    wrote_header = true;
+                }
+                writer.write_record(unpack_row(&pg_row, opts))?;
+            }
+            writer.flush()?;
         }
-        Aggregator::Avg => {
-            qb.push("AVG(metric_data.value) as avg");
+        _ => {
+            let mut out = BufWriter::new(io::stdout());
+            let mut header: Option<Vec<String>> = None;
+            while let Some(pg_row) = pg_rows
+                .try_next()
+                .await
+                .map_err(|e| QueryError::MetricError(format!("{}", e)))?
+            {
+                let header = header.get_or_insert_with(|| row_header(&pg_row));
+                let result: HashMap<&String, String> =
+                    HashMap::from_iter(header.iter().zip(unpack_row(&pg_row, opts)));
+                let line = render::json(&result, false, opts)
+                    .map_err(|e| QueryError::SerializeError(format!("NDJSON ({})", e)))?;
+                if !line.is_empty() {
+                    writeln!(out, "{}", line)?;
+                }
+            }
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Arithmetic on the times of a row and its window, done either on numbers
+/// or by writing out the SQL that does it, so the overlap weights the
+/// queries use are the ones `overlap_weight` computes
+pub trait Millis: Clone {
+    /// A constant number of milliseconds
+    fn ms(ms: i64) -> Self;
+    /// The milliseconds from `earlier` to `self`
+    fn since(self, earlier: Self) -> Self;
+    fn least(self, other: Self) -> Self;
+    fn greatest(self, other: Self) -> Self;
+    fn divided_by(self, other: Self) -> Self;
+}
+
+/// Times and durations as Unix epoch milliseconds
+impl Millis for f64 {
+    fn ms(ms: i64) -> Self {
+        ms as f64
+    }
+
+    fn since(self, earlier: Self) -> Self {
+        self - earlier
+    }
+
+    fn least(self, other: Self) -> Self {
+        self.min(other)
+    }
+
+    fn greatest(self, other: Self) -> Self {
+        self.max(other)
+    }
+
+    fn divided_by(self, other: Self) -> Self {
+        self / other
+    }
+}
+
+/// A SQL expression for a timestamp, or for a duration in milliseconds
+#[derive(Clone, Debug)]
+pub struct Sql(pub String);
+
+impl Millis for Sql {
+    fn ms(ms: i64) -> Self {
+        Sql(ms.to_string())
+    }
+
+    fn since(self, earlier: Self) -> Self {
+        Sql(format!(
+            "EXTRACT(EPOCH FROM ({} - {})) * 1000",
+            self.0, earlier.0
+        ))
+    }
+
+    fn least(self, other: Self) -> Self {
+        Sql(format!("LEAST({}, {})", self.0, other.0))
+    }
+
+    fn greatest(self, other: Self) -> Self {
+        Sql(format!("GREATEST({}, {})", self.0, other.0))
+    }
+
+    fn divided_by(self, other: Self) -> Self {
+        Sql(format!("{} / {}", self.0, other.0))
+    }
+}
+
+/// How much weight a row from `begin` to `finish`, lasting `duration`
+/// milliseconds, carries within the window from `window_begin` to
+/// `window_finish`
+pub fn overlap_weight<T: Millis>(
+    overlap: &Overlap,
+    begin: T,
+    finish: T,
+    duration: T,
+    window_begin: T,
+    window_finish: T,
+) -> T {
+    let clipped = finish
+        .least(window_finish)
+        .since(begin.greatest(window_begin))
+        .greatest(T::ms(0));
+    match overlap {
+        Overlap::Clip => clipped,
+        Overlap::Proportional => clipped.divided_by(duration.greatest(T::ms(1))),
+        Overlap::WholePoint => duration,
+    }
+}
+
+/// The SQL expression for how much weight a metric_data row carries within
+/// the window `woi` it overlaps
+fn overlap_weight_sql(overlap: &Overlap) -> String {
+    let column = |name: &str| Sql(name.to_string());
+    overlap_weight(
+        overlap,
+        column("metric_data.begin"),
+        column("metric_data.finish"),
+        column("metric_data.duration"),
+        column("woi.window_begin"),
+        column("woi.window_finish"),
+    )
+    .0
+}
+
+/// A metric_data column scaled by the unit conversion factor, if any.
+fn scaled(column: &str, factor: Option<&str>) -> String {
+    match factor {
+        Some(f) => format!("(metric_data.{} * {})", column, f),
+        None => format!("metric_data.{}", column),
+    }
+}
+
+/// The SQL expression for the aggregated value. When `rollup` is set,
+/// `metric_data` refers to the rollup subquery and the aggregates are
+/// recombined from the per-bucket min/max/sum/sum_sq/count rather than the
+/// raw values.
+fn aggregate_sql(agg: &Aggregator, overlap: Overlap, factor: Option<&str>, rollup: bool) -> String {
+    let value = scaled("value", factor);
+    match agg {
+        Aggregator::None => value,
+        Aggregator::Auto => {
+            // Rates of concurrent throughput metrics add up, so their
+            // overlap-weighted values are summed over the window's length
+            let clip = overlap_weight_sql(&Overlap::Clip);
+            let weight = overlap_weight_sql(&overlap);
+            format!(
+                "(CASE WHEN metric_desc.class = 'throughput' THEN SUM({} * {} ) / NULLIF(EXTRACT(EPOCH FROM (woi.window_finish - woi.window_begin)) * 1000, 0) ELSE SUM({} * {} ) / NULLIF(SUM( {} ), 0) END)",
+                value, clip, value, weight, weight
+            )
         }
+        Aggregator::Avg if rollup => format!(
+            "SUM({}) / NULLIF(SUM(metric_data.count), 0)",
+            scaled("sum", factor)
+        ),
+        Aggregator::Avg => format!("AVG({})", value),
         Aggregator::WeightedAvg => {
-            let duration_correction = r#"
-                        (
-                            metric_data.duration
-                                - (EXTRACT(EPOCH FROM (metric_data.begin))::bigint * 1000 - EXTRACT(EPOCH FROM (woi.window_begin))::bigint * 1000)
-                                - (EXTRACT(EPOCH FROM (woi.window_finish))::bigint * 1000 - EXTRACT(EPOCH FROM (metric_data.finish))::bigint * 1000)
-                        )
-                        "#;
-            qb.push("SUM(metric_data.value * ");
-            qb.push(&duration_correction);
-            qb.push(" ) / SUM( ");
-            qb.push(duration_correction);
-            qb.push(" ) as weighted_avg");
+            let weight = overlap_weight_sql(&overlap);
+            format!(
+                "SUM({} * {} ) / NULLIF(SUM( {} ), 0)",
+                value, weight, weight
+            )
         }
-        Aggregator::Stddev => {
-            qb.push("STDDEV(metric_data.value) as stddev");
+        Aggregator::Stddev if rollup => {
+            let sum_sq = match factor {
+                Some(f) => format!("(metric_data.sum_sq * {} * {})", f, f),
+                None => "metric_data.sum_sq".to_string(),
+            };
+            format!(
+                "SQRT(GREATEST((SUM({}) - SUM({}) ^ 2 / NULLIF(SUM(metric_data.count), 0)) / NULLIF(SUM(metric_data.count) - 1, 0), 0))",
+                sum_sq,
+                scaled("sum", factor)
+            )
         }
+        Aggregator::Stddev => format!("STDDEV({})", value),
         Aggregator::Min => {
-            qb.push("MIN(metric_data.value) as min");
+            let column = if rollup { "min" } else { "value" };
+            format!("MIN({})", scaled(column, factor))
         }
         Aggregator::Max => {
-            qb.push("MAX(metric_data.value) as max");
+            let column = if rollup { "max" } else { "value" };
+            format!("MAX({})", scaled(column, factor))
         }
-    };
+    }
 }
 
-fn push_metric_subquery(
-    qb: &mut QueryBuilder<Postgres>,
-    maybe_name: Option<String>,
-    maybe_value: Option<String>,
-) {
-    let subquery_part: &str = r#"
-        LEFT JOIN
-        (SELECT
-            name.metric_desc_uuid as metric_desc_uuid,
-            metric_desc.metric_type as metric_type,
-            name.val as name_value,
-            metric_data.value as metric_value
-        FROM metric_desc, name, metric_data
-        WHERE
-            metric_desc.metric_desc_uuid = name.metric_desc_uuid AND
-            name.metric_desc_uuid = metric_data.metric_desc_uuid
-    "#;
-    qb.push(subquery_part);
-    if let Some(name) = maybe_name.clone() {
-        qb.push(" AND name.name = ");
-        qb.push_bind(name.clone());
+/// The name of the aggregated value column
+fn aggregate_column(agg: &Aggregator) -> &'static str {
+    match agg {
+        Aggregator::None => "value",
+        Aggregator::Auto => "value",
+        Aggregator::Avg => "avg",
+        Aggregator::WeightedAvg => "weighted_avg",
+        Aggregator::Stddev => "stddev",
+        Aggregator::Min => "min",
+        Aggregator::Max => "max",
     }
-    if let Some(value) = maybe_value {
-        qb.push(" AND name.val = ");
-        qb.push_bind(value.clone());
+}
+
+fn uses_ref_periods(metric_args: &MetricArgs) -> bool {
+    metric_args.ref_period.is_some() || metric_args.ref_periods_from_iteration.is_some()
+}
+
+/// The width of each window in milliseconds, if the query is windowed.
+async fn window_ms(pool: &PgPool, metric_args: &MetricArgs) -> Result<Option<i64>, QueryError> {
+    let resolution = metric_args.resolution.max(1) as i64;
+    if uses_ref_periods(metric_args) {
+        // Windows are only as coarse as those of the shortest period
+        let raw_query: &str = r#"
+            SELECT MIN((EXTRACT(EPOCH FROM (finish - begin)) * 1000)::bigint)
+            FROM period
+            WHERE
+                period_uuid = ANY($1) OR
+                sample_uuid IN (SELECT sample_uuid FROM sample WHERE iteration_uuid = $2)
+        "#;
+        let period_ms: Option<i64> = sqlx::query_scalar(raw_query)
+            .bind(metric_args.ref_period.clone().unwrap_or_default())
+            .bind(metric_args.ref_periods_from_iteration)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| QueryError::MetricError(format!("{}", e)))?;
+        Ok(period_ms.map(|ms| ms / resolution))
+    } else if let (Some(begin), Some(finish)) = (metric_args.begin, metric_args.finish) {
+        Ok(Some((finish - begin).num_milliseconds() / resolution))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Picks a rollup interval to aggregate from instead of the raw data, when
+/// the requested windows are coarse enough and the aggregation allows it.
+async fn choose_rollup(pool: &PgPool, metric_args: &MetricArgs) -> Result<Option<i64>> {
+    if metric_args.no_rollup
+        || matches!(metric_args.aggregator, Aggregator::None)
+        // Rollup buckets don't keep the durations throughput rates are summed over
+        || matches!(metric_args.aggregator, Aggregator::Auto)
+        || metric_args.value_eq.is_some()
+        || metric_args.value_lt.is_some()
+        || metric_args.value_gt.is_some()
+    {
+        return Ok(None);
+    }
+    match window_ms(pool, metric_args).await? {
+        Some(window) => Ok(rollup::find_interval(pool, window).await?),
+        None => Ok(None),
     }
-    qb.push(format!(
-        ") as \"{}\"",
-        maybe_name.unwrap_or("base".to_string())
-    ));
 }
 
-pub async fn query_metric(pool: &PgPool, metric_args: MetricArgs) -> Result<()> {
-    let mut names: Vec<(String, Option<String>)> = Vec::new();
-    for name in metric_args.name.clone().unwrap_or(vec![]) {
-        let parts: Vec<String> = name.split("=").map(|s| s.to_string()).collect();
-        let n = parts
-            .get(0)
+#[derive(Clone, Debug)]
+pub enum BreakoutKind {
+    /// A name of the metric_desc the data belongs to
+    Name,
+    /// A tag of the run the data belongs to
+    Tag,
+    /// A param of the iteration the data belongs to
+    Param,
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakout {
+    pub kind: BreakoutKind,
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Breakout {
+    /// Parses "<name>" or "<name>=<value>" for the given kind
+    fn new(kind: BreakoutKind, spec: &str) -> Result<Self, QueryError> {
+        let mut parts = spec.splitn(2, '=');
+        let name = parts
+            .next()
+            .filter(|n| !n.is_empty())
             .ok_or(QueryError::MetricError(format!(
-                "invalid name, {:?}",
-                parts
-            )))?
-            .to_string();
-        let v = parts.get(1);
-        names.push((n, v.cloned()));
+                "invalid breakout, {}",
+                spec
+            )))?;
+        Ok(Breakout {
+            kind,
+            name: name.to_string(),
+            value: parts.next().map(|v| v.to_string()),
+        })
+    }
+
+    /// Parses "name:<name>[=<value>]", "tag:<name>[=<value>]" or
+    /// "param:<arg>[=<value>]"
+    fn parse(spec: &str) -> Result<Self, QueryError> {
+        match spec.split_once(':') {
+            Some(("name", rest)) => Breakout::new(BreakoutKind::Name, rest),
+            Some(("tag", rest)) => Breakout::new(BreakoutKind::Tag, rest),
+            Some(("param", rest)) => Breakout::new(BreakoutKind::Param, rest),
+            _ => Err(QueryError::MetricError(format!(
+                "invalid breakout, {} (expected name:<name>, tag:<name> or param:<arg>)",
+                spec
+            ))),
+        }
+    }
+
+    /// The name of the result column holding the breakout's value
+    fn column(&self) -> String {
+        match self.kind {
+            BreakoutKind::Name => format!("{}_v", self.name),
+            BreakoutKind::Tag => format!("tag_{}", self.name),
+            BreakoutKind::Param => format!("param_{}", self.name),
+        }
+    }
+}
+
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Joins the table holding the breakout's values as `alias`. Data without
+/// the name, tag or param (or with a different value, if given) is excluded.
+fn push_breakout_join(qb: &mut QueryBuilder<Postgres>, breakout: &Breakout, alias: &str) {
+    match breakout.kind {
+        BreakoutKind::Name => {
+            qb.push(format!(
+                " INNER JOIN name {} ON {}.metric_desc_uuid = metric_data.metric_desc_uuid",
+                alias, alias
+            ));
+            qb.push(format!(" AND {}.name = ", alias));
+        }
+        BreakoutKind::Tag => {
+            qb.push(format!(
+                " INNER JOIN tag {} ON {}.run_uuid = run.run_uuid",
+                alias, alias
+            ));
+            qb.push(format!(" AND {}.name = ", alias));
+        }
+        BreakoutKind::Param => {
+            qb.push(format!(
+                " INNER JOIN param {} ON {}.iteration_uuid = iteration.iteration_uuid",
+                alias, alias
+            ));
+            qb.push(format!(" AND {}.arg = ", alias));
+        }
+    }
+    qb.push_bind(breakout.name.clone());
+    if let Some(value) = &breakout.value {
+        qb.push(format!(" AND {}.val = ", alias));
+        qb.push_bind(value.clone());
+    }
+}
+
+pub struct MetricQuery {
+    pub qb: QueryBuilder<'static, Postgres>,
+    /// Identifies the query and its arguments in the local result cache
+    pub cache_key: String,
+}
+
+/// Builds the SQL for a metric query
+pub async fn build_metric_query(
+    pool: &PgPool,
+    metric_args: &mut MetricArgs,
+) -> Result<MetricQuery> {
+    let mut breakouts: Vec<Breakout> = Vec::new();
+    for name in metric_args.name.clone().unwrap_or_default() {
+        breakouts.push(Breakout::new(BreakoutKind::Name, &name)?);
+    }
+    for spec in metric_args.breakout.clone().unwrap_or_default() {
+        breakouts.push(Breakout::parse(&spec)?);
+    }
+    let aliases: Vec<String> = (0..breakouts.len()).map(|i| format!("b{}", i)).collect();
+    let by_class = matches!(metric_args.aggregator, Aggregator::Auto);
+
+    if let Some(unit) = &metric_args.unit {
+        unit::lookup(unit).ok_or(QueryError::MetricError(format!("unknown unit, {}", unit)))?;
     }
 
-    let base_name = match names.clone().first() {
-        Some(first) => first.clone().0,
-        None => "base".to_string(),
-    };
     let select_part: &str = r#"
         SELECT
             run.run_uuid as run_uuid,
@@ -180,17 +502,94 @@ pub async fn query_metric(pool: &PgPool, metric_args: MetricArgs) -> Result<()>
             metric_desc.metric_type as metric_type,
     "#;
 
-    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(select_part);
-    for (name, _) in &names {
-        qb.push(format!(" \"{}\".name_value as \"{}_v\" ", name, name));
-        qb.push(", ");
+    let mut qb: QueryBuilder<'static, Postgres> = QueryBuilder::new(select_part);
+    if by_class {
+        qb.push(" metric_desc.class as class, ");
+    }
+    for (breakout, alias) in breakouts.iter().zip(&aliases) {
+        qb.push(format!(
+            " {}.val as {}, ",
+            alias,
+            quote_ident(&breakout.column())
+        ));
+    }
+    if uses_ref_periods(metric_args) {
+        qb.push(" woi.ref_period_uuid, ");
     }
     qb.push(" woi.window_begin, woi.window_finish, ");
 
-    push_choose_aggregator(&mut qb, metric_args.aggregator.clone());
+    let factor = metric_args.unit.as_deref().map(unit::conversion_factor_sql);
+    let value = scaled("value", factor.as_deref());
+    let rollup_interval = choose_rollup(pool, metric_args).await?;
+    let aggregate = aggregate_sql(
+        &metric_args.aggregator,
+        metric_args.overlap.clone(),
+        factor.as_deref(),
+        rollup_interval.is_some(),
+    );
+    qb.push(format!(
+        "{} as {}",
+        aggregate,
+        aggregate_column(&metric_args.aggregator)
+    ));
+
+    qb.push(" FROM ");
+    match rollup_interval {
+        Some(interval) => {
+            qb.push(
+                r#"
+            (SELECT
+                metric_desc_uuid,
+                avg as value,
+                begin,
+                finish,
+                (EXTRACT(EPOCH FROM (finish - begin)) * 1000)::bigint as duration,
+                min,
+                max,
+                sum,
+                sum_sq,
+                count
+            FROM metric_data_rollup
+            WHERE interval_ms =
+            "#,
+            );
+            qb.push_bind(interval);
+            // Downsampled runs may only be rolled up at 1 minute
+            qb.push(format!(
+                r#" OR (interval_ms = {} AND NOT EXISTS (
+                SELECT 1 FROM metric_data_rollup kept
+                WHERE kept.metric_desc_uuid = metric_data_rollup.metric_desc_uuid AND kept.interval_ms = "#,
+                retention::DOWNSAMPLE_INTERVAL_MS
+            ));
+            qb.push_bind(interval);
+            qb.push("))) as metric_data");
+        }
+        None => {
+            // Runs downsampled by the retention policy have no raw data left,
+            // so their 1 minute rollups stand in for it
+            qb.push(format!(
+                r#"
+            (SELECT metric_desc_uuid, value, begin, finish, duration
+            FROM metric_data
+            UNION ALL
+            SELECT
+                metric_desc_uuid,
+                avg as value,
+                begin,
+                finish,
+                (EXTRACT(EPOCH FROM (finish - begin)) * 1000)::bigint as duration
+            FROM metric_data_rollup
+            WHERE interval_ms = {} AND NOT EXISTS (
+                SELECT 1 FROM metric_data raw
+                WHERE raw.metric_desc_uuid = metric_data_rollup.metric_desc_uuid
+            )) as metric_data
+            "#,
+                retention::DOWNSAMPLE_INTERVAL_MS
+            ));
+        }
+    }
 
     let join_part: &str = r#"
-        FROM metric_data
         LEFT JOIN metric_desc
             ON metric_desc.metric_desc_uuid = metric_data.metric_desc_uuid
         LEFT JOIN period
@@ -205,38 +604,39 @@ pub async fn query_metric(pool: &PgPool, metric_args: MetricArgs) -> Result<()>
 
     qb.push(join_part);
 
-    let mut last_name = base_name.clone();
-    for (i, (name, maybe_value)) in names.clone().into_iter().enumerate() {
-        push_metric_subquery(&mut qb, Some(name.clone()), maybe_value);
-        qb.push(" ON ");
-        qb.push(format!(
-            " \"{}\".metric_desc_uuid = \"{}\".metric_desc_uuid",
-            last_name, name
-        ));
-        last_name = name;
-        if i < names.len() - 1 {
-            qb.push(" LEFT JOIN ");
-        }
+    for (breakout, alias) in breakouts.iter().zip(&aliases) {
+        push_breakout_join(&mut qb, breakout, alias);
     }
 
-    if let Some(ref_period) = metric_args.ref_period {
+    if uses_ref_periods(metric_args) {
+        // Every ref period is split into `resolution` windows of its own
         qb.push(format!(r#"
             CROSS JOIN
             (
-                SELECT
-                    window_begin,
-                    window_begin + window_duration as window_finish
+                SELECT ref_period_uuid, window_begin, window_finish
                 FROM
                     (
                         SELECT
-                            (period.finish - period.begin)/{} as window_duration,
-                            generate_series(period.begin, period.finish, (period.finish - period.begin)/{}) as window_begin
-                        FROM period
-                        WHERE period_uuid =
+                            ref_period_uuid,
+                            window_begin,
+                            window_begin + window_duration as window_finish,
+                            ROW_NUMBER() OVER (PARTITION BY ref_period_uuid ORDER BY window_begin) as window_num
+                        FROM
+                            (
+                                SELECT
+                                    period.period_uuid as ref_period_uuid,
+                                    (period.finish - period.begin)/{} as window_duration,
+                                    generate_series(period.begin, period.finish, (period.finish - period.begin)/{}) as window_begin
+                                FROM period
+                                WHERE period.period_uuid = ANY(
         "#, metric_args.resolution, metric_args.resolution));
-        qb.push_bind(ref_period);
+        qb.push_bind(metric_args.ref_period.clone().unwrap_or_default());
+        qb.push(
+            ") OR period.sample_uuid IN (SELECT sample_uuid FROM sample WHERE iteration_uuid = ",
+        );
+        qb.push_bind(metric_args.ref_periods_from_iteration);
         qb.push(format!(
-            " ) as windows ORDER BY window_begin, window_finish LIMIT {} ) woi",
+            ") ) as windows ) as numbered_windows WHERE window_num <= {} ) woi",
             metric_args.resolution
         ));
     } else if let (Some(begin), Some(finish)) = (metric_args.begin, metric_args.finish) {
@@ -276,6 +676,7 @@ pub async fn query_metric(pool: &PgPool, metric_args: MetricArgs) -> Result<()>
     qb.push(" WHERE ");
     let mut sep = qb.separated(" AND ");
     sep.push(" TRUE ");
+    sep.push(" run.deleted_at IS NULL ");
     if let Some(run_uuid) = metric_args.run_uuid {
         sep.push(" run.run_uuid = ");
         sep.push_bind_unseparated(run_uuid);
@@ -288,24 +689,32 @@ pub async fn query_metric(pool: &PgPool, metric_args: MetricArgs) -> Result<()>
         sep.push(" metric_data.metric_desc_uuid = ");
         sep.push_bind_unseparated(metric_desc_uuid);
     }
-    if let Some(metric_type) = metric_args.metric_type {
+    if let Some(metric_type) = &metric_args.metric_type {
         sep.push(" metric_desc.metric_type = ");
         sep.push_bind_unseparated(metric_type.clone());
     }
     if let Some(value_eq) = metric_args.value_eq {
-        sep.push(" metric_data.value = ");
+        sep.push(format!(" {} = ", value));
         sep.push_bind_unseparated(value_eq);
     }
     if let Some(value_lt) = metric_args.value_lt {
-        sep.push(" metric_data.value < ");
+        sep.push(format!(" {} < ", value));
         sep.push_bind_unseparated(value_lt);
     }
     if let Some(value_gt) = metric_args.value_gt {
-        sep.push(" metric_data.value > ");
+        sep.push(format!(" {} > ", value));
         sep.push_bind_unseparated(value_gt);
     }
 
-    if metric_args.ref_period.is_some() {
+    if let Some(unit) = &metric_args.unit {
+        // Drop data that can't be expressed in the requested unit
+        sep.push(format!(
+            " {} IS NOT NULL ",
+            unit::conversion_factor_sql(unit)
+        ));
+    }
+
+    if uses_ref_periods(metric_args) {
         sep.push(
             r#"
         (
@@ -339,37 +748,177 @@ pub async fn query_metric(pool: &PgPool, metric_args: MetricArgs) -> Result<()>
         sep.push_unseparated(" ) ");
     }
 
-    if metric_args.name.is_some() && !matches!(metric_args.aggregator, Aggregator::None) {
+    let grouped = !matches!(metric_args.aggregator, Aggregator::None);
+    if !grouped && (metric_args.agg_gt.is_some() || metric_args.agg_lt.is_some()) {
+        return Err(QueryError::MetricError(
+            "--agg-gt and --agg-lt need an aggregator".to_string(),
+        )
+        .into());
+    }
+    if grouped {
         qb.push(" GROUP BY ");
         let mut sep = qb.separated(", ");
         sep.push("run.run_uuid");
         sep.push("iteration.iteration_uuid");
         sep.push("metric_desc.metric_type");
+        if by_class {
+            sep.push("metric_desc.class");
+        }
+        if uses_ref_periods(metric_args) {
+            sep.push("woi.ref_period_uuid");
+        }
         sep.push("woi.window_begin");
         sep.push("woi.window_finish");
-        for (name, _) in names.clone() {
-            sep.push(format!("\"{}\".name_value", name));
+        for alias in &aliases {
+            sep.push(format!("{}.val", alias));
         }
     }
 
-    if metric_args.name.is_some() && !matches!(metric_args.aggregator, Aggregator::None) {
+    if grouped && (metric_args.agg_gt.is_some() || metric_args.agg_lt.is_some()) {
+        qb.push(" HAVING ");
+        let mut sep = qb.separated(" AND ");
+        if let Some(agg_gt) = metric_args.agg_gt {
+            sep.push(format!(" {} > ", aggregate));
+            sep.push_bind_unseparated(agg_gt);
+        }
+        if let Some(agg_lt) = metric_args.agg_lt {
+            sep.push(format!(" {} < ", aggregate));
+            sep.push_bind_unseparated(agg_lt);
+        }
+    }
+
+    if grouped {
         qb.push(" ORDER BY ");
         let mut sep = qb.separated(", ");
-        for (name, _) in &names {
-            sep.push(format!("\"{}\".name_value", name));
+        for (breakout, alias) in breakouts.iter().zip(&aliases) {
+            if let BreakoutKind::Param = breakout.kind {
+                // Swept params are usually numbers, so order them as such
+                sep.push(format!(
+                    "(CASE WHEN {}.val ~ '^-?[0-9]+(\\.[0-9]+)?$' THEN {}.val::numeric END)",
+                    alias, alias
+                ));
+            }
+            sep.push(format!("{}.val", alias));
+        }
+        if uses_ref_periods(metric_args) {
+            sep.push("woi.ref_period_uuid");
         }
         sep.push("woi.window_begin");
         sep.push("woi.window_finish");
     }
 
-    let query = qb.build();
-    let res = query
+    let connect_options = pool.connect_options();
+    // The same query reads other tables as another user or in another schema
+    let (db_user, search_path): (String, String) =
+        sqlx::query_as("SELECT current_user::text, current_setting('search_path')")
+            .fetch_one(pool)
+            .await
+            .map_err(|e| QueryError::MetricError(format!("{}", e)))?;
+    let cache_key = cache::key(&[
+        connect_options.get_host(),
+        &connect_options.get_port().to_string(),
+        connect_options.get_database().unwrap_or_default(),
+        &db_user,
+        &search_path,
+        qb.sql(),
+        &format!(
+            "{:?}",
+            (
+                (
+                    metric_args.run_uuid,
+                    metric_args.iteration_uuid,
+                    metric_args.metric_desc_uuid,
+                    &metric_args.metric_type,
+                ),
+                (
+                    &metric_args.ref_period,
+                    metric_args.ref_periods_from_iteration,
+                    metric_args.begin,
+                    metric_args.finish,
+                ),
+                metric_args.value_eq,
+                metric_args.value_lt,
+                metric_args.value_gt,
+                (metric_args.agg_gt, metric_args.agg_lt),
+                &breakouts,
+                rollup_interval,
+            )
+        ),
+    ]);
+    Ok(MetricQuery { qb, cache_key })
+}
+
+/// Runs a metric query, returning its header and its rows with the
+/// timestamps in RFC 3339 and UTC, as the cache keeps them, and which
+/// columns hold timestamps
+async fn fetch_raw_rows(
+    pool: &PgPool,
+    qb: &mut QueryBuilder<'_, Postgres>,
+) -> Result<CachedResult> {
+    let res = qb
+        .build()
         .fetch_all(pool)
         .await
         .map_err(|e| QueryError::MetricError(format!("{}", e)))?;
+    let times = res
+        .first()
+        .map(|pg_row| {
+            pg_row
+                .columns()
+                .iter()
+                .filter(|column| column.type_info().name() == "TIMESTAMPTZ")
+                .map(|column| column.ordinal())
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(CachedResult {
+        header: res.first().map(row_header).unwrap_or_default(),
+        rows: res
+            .iter()
+            .map(|pg_row| unpack_row_with(pg_row, |t| t.to_rfc3339()))
+            .collect(),
+        times,
+    })
+}
 
-    let (header, rows) = unpack_rows(res, &names);
-    let out_string = match metric_args.output {
+/// Renders the timestamps of a cached result in the timezone and time
+/// format of the options
+fn render_times(result: CachedResult, opts: &DisplayOpts) -> (Vec<String>, Vec<Vec<String>>) {
+    let mut rows = result.rows;
+    for row in rows.iter_mut() {
+        for i in &result.times {
+            if let Some(t) = row
+                .get(*i)
+                .and_then(|cell| DateTime::parse_from_rfc3339(cell).ok())
+            {
+                row[*i] = render::format_time(&t.with_timezone(&Utc), opts);
+            }
+        }
+    }
+    (result.header, rows)
+}
+
+/// Runs a metric query, returning its header and rendered rows
+pub async fn fetch_rows(
+    pool: &PgPool,
+    qb: &mut QueryBuilder<'_, Postgres>,
+    opts: &DisplayOpts,
+) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    let res = qb
+        .build()
+        .fetch_all(pool)
+        .await
+        .map_err(|e| QueryError::MetricError(format!("{}", e)))?;
+    Ok(unpack_rows(res, opts))
+}
+
+pub fn format_rows(
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    output: Option<OutputFormat>,
+    opts: &DisplayOpts,
+) -> Result<String> {
+    let out_string = match output {
         Some(o_fmt) => match o_fmt {
             OutputFormat::CSV => {
                 let mut writer = csv::Writer::from_writer(vec![]);
@@ -378,28 +927,216 @@ pub async fn query_metric(pool: &PgPool, metric_args: MetricArgs) -> Result<()>
                     writer.write_record(&row)?;
                 }
                 String::from_utf8(
-                    writer.into_inner().map_err(|e| {
-                        QueryError::SerializeError(format!("CSV ({})", e.to_string()))
-                    })?,
+                    writer
+                        .into_inner()
+                        .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e)))?,
                 )
-                .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e.to_string())))?
+                .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e)))?
             }
             OutputFormat::JSON => {
                 let results: Vec<HashMap<String, String>> = rows
                     .into_iter()
-                    .map(|r| HashMap::from_iter(header.clone().into_iter().zip(r.into_iter())))
+                    .map(|r| HashMap::from_iter(header.clone().into_iter().zip(r)))
                     .collect();
-                serde_json::to_string_pretty::<Vec<HashMap<String, String>>>(&results)
-                    .map_err(|e| QueryError::SerializeError(format!("JSON ({})", e.to_string())))?
+                render::json(&results, true, opts)
+                    .map_err(|e| QueryError::SerializeError(format!("JSON ({})", e)))?
+            }
+            OutputFormat::NDJSON => {
+                let mut lines: Vec<String> = Vec::new();
+                for row in rows {
+                    let result: HashMap<&String, String> =
+                        HashMap::from_iter(header.iter().zip(row));
+                    let line = render::json(&result, false, opts)
+                        .map_err(|e| QueryError::SerializeError(format!("NDJSON ({})", e)))?;
+                    if !line.is_empty() {
+                        lines.push(line);
+                    }
+                }
+                lines.join("\n")
+            }
+            OutputFormat::Vega => {
+                serde_json::to_string_pretty(&vega::spec(&header, rows, None)?)
+                    .map_err(|e| QueryError::SerializeError(format!("Vega ({})", e)))?
+            }
+            OutputFormat::Xlsx => {
+                return Err(QueryError::UnknownFormat(
+                    "xlsx, only query get and query metric write workbooks".to_string(),
+                )
+                .into());
             }
         },
         None => {
             let mut table = Table::from_iter(vec![header].into_iter().chain(rows));
-            table.with(Style::modern());
+            render::style(&mut table, opts);
             table.to_string()
         }
     };
+    Ok(out_string)
+}
+
+/// A sheet per breakout group of the rows, named by the metric type and
+/// breakout values, ex: "Gbps w1 client"
+fn breakout_sheets(header: Vec<String>, rows: Vec<Vec<String>>) -> Vec<Sheet> {
+    let key_columns: Vec<usize> = match (
+        header.iter().position(|c| c == "metric_type"),
+        header.iter().position(|c| c == "window_begin"),
+    ) {
+        (Some(start), Some(end)) => (start..end)
+            .filter(|i| !matches!(header[*i].as_str(), "class" | "ref_period_uuid"))
+            .collect(),
+        _ => vec![],
+    };
+    let mut sheets: Vec<Sheet> = vec![];
+    let mut sheet_of: HashMap<Vec<String>, usize> = HashMap::new();
+    for row in rows {
+        let key: Vec<String> = key_columns.iter().map(|i| row[*i].clone()).collect();
+        let i = *sheet_of.entry(key.clone()).or_insert_with(|| {
+            let name = match key.is_empty() {
+                true => "metric".to_string(),
+                false => key.join(" "),
+            };
+            sheets.push(Sheet {
+                name,
+                header: header.clone(),
+                rows: vec![],
+            });
+            sheets.len() - 1
+        });
+        let values = row
+            .into_iter()
+            .map(|cell| match cell.as_str() {
+                "null" => Value::Null,
+                _ => Value::String(cell),
+            })
+            .collect();
+        sheets[i].rows.push(values);
+    }
+    sheets
+}
+
+/// Plain listings of the data don't aggregate unless they're asked to
+fn default_aggregator(metric_args: &mut MetricArgs) {
+    if metric_args.name.is_none()
+        && metric_args.breakout.is_none()
+        && matches!(metric_args.aggregator, Aggregator::Auto)
+    {
+        metric_args.aggregator = Aggregator::None;
+    }
+}
+
+/// The header and rows of a metric query, from the cache when it's enabled,
+/// with the timestamps shown as its display options say
+pub async fn metric_rows(
+    pool: &PgPool,
+    mut metric_args: MetricArgs,
+) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    default_aggregator(&mut metric_args);
+    let MetricQuery { mut qb, cache_key } = build_metric_query(pool, &mut metric_args).await?;
+    let use_cache = metric_args.cache && !metric_args.no_cache;
+    let ttl = Duration::from_millis(metric_args.cache_ttl as u64);
+
+    if let Some(cached) = use_cache.then(|| cache::load(&cache_key, ttl)).flatten() {
+        return Ok(render_times(cached, &metric_args.display));
+    }
+    let result = fetch_raw_rows(pool, &mut qb).await?;
+    if use_cache {
+        cache::store(&cache_key, &result)?;
+    }
+    Ok(render_times(result, &metric_args.display))
+}
+
+pub async fn query_metric(pool: &PgPool, mut metric_args: MetricArgs) -> Result<()> {
+    if let Some(MetricCommand::Diff(diff_args)) = metric_args.command {
+        return diff::metric_diff(pool, diff_args).await;
+    }
+    let use_cache = metric_args.cache && !metric_args.no_cache;
+    if let (false, Some(o_fmt @ (OutputFormat::CSV | OutputFormat::NDJSON))) =
+        (use_cache, metric_args.output.clone())
+    {
+        default_aggregator(&mut metric_args);
+        let MetricQuery { mut qb, .. } = build_metric_query(pool, &mut metric_args).await?;
+        return stream_rows(pool, &mut qb, &o_fmt, &metric_args.display).await;
+    }
+
+    let display = metric_args.display.clone();
+    let output = metric_args.output.clone();
+    let data_url = metric_args.data_url.clone();
+    let out = metric_args.out.clone();
+    let unit = metric_args.unit.clone();
+    let (header, mut rows) = metric_rows(pool, metric_args).await?;
+    if let Some(OutputFormat::Xlsx) = output {
+        let out = out.ok_or(XlsxError::NoOut)?;
+        let num_rows = rows.len();
+        xlsx::write(&out, breakout_sheets(header, rows), &display)?;
+        report!("wrote {} rows to {}", num_rows, out);
+        return Ok(());
+    }
+    let out_string = match (output, data_url) {
+        (Some(OutputFormat::Vega), Some(data_url)) => {
+            serde_json::to_string_pretty(&vega::spec(&header, rows, Some(&data_url))?)
+                .map_err(|e| QueryError::SerializeError(format!("Vega ({})", e)))?
+        }
+        (None, _) if render::is_human(&display) => {
+            render::humanize(&header, &mut rows, unit.as_deref());
+            format_rows(header, rows, None, &display)?
+        }
+        (output, _) => format_rows(header, rows, output, &display)?,
+    };
 
     println!("{}", out_string);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The window the rows are weighed in, from 1000 to 2000 ms
+    const WINDOW: (f64, f64) = (1000.0, 2000.0);
+
+    /// The weight of the row from `begin` to `finish` in `WINDOW`
+    fn weight(overlap: Overlap, begin: f64, finish: f64) -> f64 {
+        overlap_weight(&overlap, begin, finish, finish - begin, WINDOW.0, WINDOW.1)
+    }
+
+    #[test]
+    fn clip_weighs_the_part_inside_the_window() {
+        // Straddling the window's begin, then its finish
+        assert_eq!(weight(Overlap::Clip, 800.0, 1200.0), 200.0);
+        assert_eq!(weight(Overlap::Clip, 1900.0, 2400.0), 100.0);
+        assert_eq!(weight(Overlap::Clip, 1500.0, 1500.0), 0.0);
+        assert_eq!(weight(Overlap::Clip, 2500.0, 3000.0), 0.0);
+    }
+
+    #[test]
+    fn proportional_weighs_the_fraction_inside_the_window() {
+        assert_eq!(weight(Overlap::Proportional, 800.0, 1200.0), 0.5);
+        assert_eq!(weight(Overlap::Proportional, 1900.0, 2400.0), 0.2);
+        assert_eq!(weight(Overlap::Proportional, 1500.0, 1500.0), 0.0);
+        assert_eq!(weight(Overlap::Proportional, 2500.0, 3000.0), 0.0);
+    }
+
+    #[test]
+    fn whole_point_weighs_the_whole_row() {
+        assert_eq!(weight(Overlap::WholePoint, 800.0, 1200.0), 400.0);
+        assert_eq!(weight(Overlap::WholePoint, 1900.0, 2400.0), 500.0);
+        assert_eq!(weight(Overlap::WholePoint, 1500.0, 1500.0), 0.0);
+        assert_eq!(weight(Overlap::WholePoint, 2500.0, 3000.0), 500.0);
+    }
+
+    #[test]
+    fn the_sql_weighs_the_rows_the_same_way() {
+        assert_eq!(
+            overlap_weight_sql(&Overlap::Proportional),
+            "GREATEST(EXTRACT(EPOCH FROM (LEAST(metric_data.finish, woi.window_finish) - GREATEST(metric_data.begin, woi.window_begin))) * 1000, 0) / GREATEST(metric_data.duration, 1)"
+        );
+    }
+
+    #[test]
+    fn weighted_avg_divides_by_the_total_weight() {
+        assert_eq!(
+            aggregate_sql(&Aggregator::WeightedAvg, Overlap::WholePoint, None, false),
+            "SUM(metric_data.value * metric_data.duration ) / NULLIF(SUM( metric_data.duration ), 0)"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::add;
+use crate::args::{NatsArgs, StatusMode};
+use crate::parser::{self, BodyJson, IngestOpts};
+use anyhow::Result;
+use async_nats::jetstream::AckKind;
+use async_nats::jetstream::consumer::{AckPolicy, PullConsumer, pull};
+use async_nats::{Client, ConnectOptions};
+use futures_util::StreamExt;
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+
+#[derive(Error, Debug)]
+pub enum NatsError {
+    #[error("Couldn't read the NATS credentials file {0}, {1}")]
+    InvalidCreds(String, String),
+    #[error("Couldn't connect to NATS at {0}, {1}")]
+    ConnectFailed(String, String),
+    #[error("Couldn't subscribe to {0}, {1}")]
+    SubscribeFailed(String, String),
+    #[error("Couldn't consume the stream {0}, {1}")]
+    ConsumeFailed(String, String),
+}
+
+/// How long JetStream waits before redelivering a message that failed to ingest
+const REDELIVER_DELAY: Duration = Duration::from_secs(10);
+
+/// The documents of a message, an error means it can never be ingested
+fn documents(
+    subject: &str,
+    payload: &[u8],
+    status_mode: StatusMode,
+    opts: &IngestOpts,
+) -> Result<Vec<BodyJson>> {
+    let mut records = add::parse_run_nodes(subject, payload, opts.uuid_v7)?;
+    parser::normalize_statuses(parser::document_statuses(&mut records), status_mode)?;
+    Ok(records)
+}
+
+async fn ingest(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    subject: &str,
+    records: &Vec<BodyJson>,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let num_new = parser::ingest(
+        pool,
+        replica,
+        records,
+        "nats",
+        &json!({ "subject": subject }),
+        opts,
+    )
+    .await?;
+    info!("added {} rows from {}", num_new, subject);
+    Ok(num_new)
+}
+
+/// Plain NATS, messages published while scdm isn't subscribed are missed
+async fn subscribe(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: &NatsArgs,
+    client: Client,
+    opts: &IngestOpts,
+) -> Result<()> {
+    let mut subscriber = client
+        .subscribe(args.subject.clone())
+        .await
+        .map_err(|e| NatsError::SubscribeFailed(args.subject.clone(), e.to_string()))?;
+    info!("subscribed to {}", args.subject);
+    loop {
+        let message = tokio::select! {
+            message = subscriber.next() => message,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let Some(message) = message else {
+            break;
+        };
+        let subject = message.subject.as_str();
+        let ingested = match documents(subject, &message.payload, args.status.mode(), opts) {
+            Ok(records) => ingest(pool, replica, subject, &records, opts).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = ingested {
+            warn!("dropped a message on {}, {}", subject, e);
+        }
+    }
+    Ok(())
+}
+
+/// A durable JetStream consumer, which only acks a message once it's
+/// ingested. Messages that can't be parsed are terminated, those that failed
+/// to ingest are redelivered.
+async fn consume(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: &NatsArgs,
+    client: Client,
+    stream: &str,
+    opts: &IngestOpts,
+) -> Result<()> {
+    let cerr = |e: String| NatsError::ConsumeFailed(stream.to_string(), e);
+    let jetstream = async_nats::jetstream::new(client);
+    let consumer: PullConsumer = jetstream
+        .get_stream(stream)
+        .await
+        .map_err(|e| cerr(e.to_string()))?
+        .get_or_create_consumer(
+            &args.durable,
+            pull::Config {
+                durable_name: Some(args.durable.clone()),
+                filter_subject: args.subject.clone(),
+                ack_policy: AckPolicy::Explicit,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| cerr(e.to_string()))?;
+    let mut messages = consumer.messages().await.map_err(|e| cerr(e.to_string()))?;
+    info!(
+        "consuming {} from stream {} as {}",
+        args.subject, stream, args.durable
+    );
+    loop {
+        let message = tokio::select! {
+            message = messages.next() => message,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let message = match message {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                warn!("couldn't receive from stream {}, {}", stream, e);
+                continue;
+            }
+            None => break,
+        };
+        let subject = message.subject.as_str();
+        let ack = match documents(subject, &message.payload, args.status.mode(), opts) {
+            Ok(records) => match ingest(pool, replica, subject, &records, opts).await {
+                Ok(_) => AckKind::Ack,
+                Err(e) => {
+                    warn!("couldn't ingest a message on {}, retrying, {}", subject, e);
+                    AckKind::Nak(Some(REDELIVER_DELAY))
+                }
+            },
+            Err(e) => {
+                warn!("dropped a message on {}, {}", subject, e);
+                AckKind::Term
+            }
+        };
+        if let Err(e) = message.ack_with(ack).await {
+            warn!("couldn't acknowledge a message on {}, {}", subject, e);
+        }
+    }
+    Ok(())
+}
+
+/// Ingests the results published to the subject until interrupted
+pub async fn nats(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: NatsArgs,
+    opts: &IngestOpts,
+) -> Result<()> {
+    let mut options = ConnectOptions::new().name("scdm");
+    if let Some(creds) = &args.creds {
+        options = options
+            .credentials_file(creds)
+            .await
+            .map_err(|e| NatsError::InvalidCreds(creds.clone(), e.to_string()))?;
+    }
+    if let Some(token) = &args.token {
+        options = options.token(token.clone());
+    }
+    let client = options
+        .connect(&args.url)
+        .await
+        .map_err(|e| NatsError::ConnectFailed(args.url.clone(), e.to_string()))?;
+
+    match &args.stream {
+        Some(stream) => consume(pool, replica, &args, client, stream, opts).await,
+        None => subscribe(pool, replica, &args, client, opts).await,
+    }
+}
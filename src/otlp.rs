@@ -0,0 +1,194 @@
+use crate::args::OtlpArgs;
+use crate::cdm::Run;
+use crate::query::QueryGet;
+use crate::report;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value, json};
+use sqlx::PgPool;
+use sqlx::types::Json;
+use std::collections::BTreeMap;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum OtlpError {
+    #[error("Invalid header {0}, expected name=value")]
+    InvalidHeader(String),
+    #[error("Couldn't send the metrics to {0}, {1}")]
+    SendFailed(String, String),
+    #[error("The collector refused the metrics, {0} {1}")]
+    Rejected(u16, String),
+}
+
+/// The duration weighted average of each metric_desc in each period of a run
+const SQL_PERIOD_METRICS: &str = r#"
+    SELECT
+        iteration.iteration_uuid,
+        sample.sample_uuid,
+        period.name,
+        period.begin,
+        period.finish,
+        metric_desc.source,
+        metric_desc.metric_type,
+        metric_desc.unit,
+        metric_desc.names,
+        SUM(metric_data.value * metric_data.duration) / NULLIF(SUM(metric_data.duration), 0)
+    FROM iteration
+        JOIN sample USING (iteration_uuid)
+        JOIN period USING (sample_uuid)
+        JOIN metric_desc USING (period_uuid)
+        JOIN metric_data USING (metric_desc_uuid)
+    WHERE
+        iteration.run_uuid = $1 AND
+        ($2::text IS NULL OR period.name = $2) AND
+        ($3::text IS NULL OR metric_desc.metric_type = $3)
+    GROUP BY iteration.iteration_uuid, sample.sample_uuid, period.period_uuid, metric_desc.metric_desc_uuid
+"#;
+
+type PeriodMetric = (
+    Uuid,
+    Uuid,
+    String,
+    DateTime<Utc>,
+    DateTime<Utc>,
+    String,
+    String,
+    Option<String>,
+    Option<Json<Map<String, Value>>>,
+    Option<f64>,
+);
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn unix_nanos(t: &DateTime<Utc>) -> String {
+    t.timestamp_nanos_opt().unwrap_or_default().to_string()
+}
+
+/// An OTLP/JSON ExportMetricsServiceRequest with a gauge per metric type,
+/// named source.metric_type, and a point per period and metric_desc
+fn export_request(run: &Run, period_metrics: Vec<PeriodMetric>) -> Value {
+    let mut gauges: BTreeMap<(String, String), (Option<String>, Vec<Value>)> = BTreeMap::new();
+    for (
+        iteration_uuid,
+        sample_uuid,
+        period,
+        begin,
+        finish,
+        source,
+        metric_type,
+        unit,
+        names,
+        value,
+    ) in period_metrics
+    {
+        let Some(value) = value else {
+            continue;
+        };
+        let mut attributes = vec![
+            attribute("scdm.iteration_uuid", &iteration_uuid.to_string()),
+            attribute("scdm.sample_uuid", &sample_uuid.to_string()),
+            attribute("scdm.period", &period),
+        ];
+        for (name, val) in names.map(|n| n.0).unwrap_or_default() {
+            let val = match val {
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+            attributes.push(attribute(&name, &val));
+        }
+        let gauge = gauges
+            .entry((source, metric_type))
+            .or_insert((unit, vec![]));
+        gauge.1.push(json!({
+            "attributes": attributes,
+            "startTimeUnixNano": unix_nanos(&begin),
+            "timeUnixNano": unix_nanos(&finish),
+            "asDouble": value,
+        }));
+    }
+    let metrics: Vec<Value> = gauges
+        .into_iter()
+        .map(|((source, metric_type), (unit, points))| {
+            json!({
+                "name": format!("{}.{}", source, metric_type),
+                "unit": unit.unwrap_or_default(),
+                "gauge": { "dataPoints": points },
+            })
+        })
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", "scdm"),
+                    attribute("scdm.run_uuid", &run.run_uuid.to_string()),
+                    attribute("scdm.benchmark", &run.benchmark),
+                    attribute("scdm.run_name", &run.name),
+                    attribute("scdm.source", &run.source),
+                ],
+            },
+            "scopeMetrics": [{
+                "scope": { "name": "scdm", "version": env!("CARGO_PKG_VERSION") },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+/// Sends each run's metrics to the collector in a request of its own
+pub async fn otlp(pool: &PgPool, args: OtlpArgs) -> Result<()> {
+    let url = format!("{}/v1/metrics", args.endpoint.trim_end_matches('/'));
+    let mut headers = reqwest::header::HeaderMap::new();
+    for header in &args.headers {
+        let (name, value) = header
+            .split_once('=')
+            .ok_or(OtlpError::InvalidHeader(header.clone()))?;
+        headers.insert(
+            reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| OtlpError::InvalidHeader(header.clone()))?,
+            value
+                .trim()
+                .parse()
+                .map_err(|_| OtlpError::InvalidHeader(header.clone()))?,
+        );
+    }
+    let client = reqwest::Client::new();
+
+    let runs = args.runs.query_get(pool).await?;
+    let mut points = 0;
+    for run in &runs {
+        let period_metrics: Vec<PeriodMetric> = sqlx::query_as(SQL_PERIOD_METRICS)
+            .bind(run.run_uuid)
+            .bind(args.period_name.clone())
+            .bind(args.metric_type.clone())
+            .fetch_all(pool)
+            .await?;
+        points += period_metrics.len();
+        let request = export_request(run, period_metrics);
+        if args.dry_run {
+            println!("{}", serde_json::to_string(&request)?);
+            continue;
+        }
+        let response = client
+            .post(&url)
+            .headers(headers.clone())
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| OtlpError::SendFailed(url.clone(), e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(OtlpError::Rejected(status.as_u16(), body).into());
+        }
+        info!("sent the metrics of run {}", run.run_uuid);
+    }
+    if !args.dry_run {
+        report!("sent {} data points of {} runs", points, runs.len());
+    }
+    Ok(())
+}
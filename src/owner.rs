@@ -0,0 +1,78 @@
+use crate::args::GlobalOpts;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum OwnerError {
+    #[error(
+        "Refusing to {0} {1} runs owned by someone other than {2}, ex: {3} owned by {4}. Pass --admin to override"
+    )]
+    NotOwned(&'static str, usize, String, Uuid, String),
+}
+
+/// Who's changing the runs. The runs they ingest are theirs, and the ones
+/// someone else owns can't be deleted or restored unless they're an admin.
+/// Without an owner runs belong to their email, and anyone can change them
+#[derive(Debug, Clone, Default)]
+pub struct Actor {
+    pub owner: Option<String>,
+    pub admin: bool,
+}
+
+impl Actor {
+    pub fn new(owner: Option<String>, admin: bool) -> Self {
+        Actor {
+            owner: owner.filter(|owner| !owner.is_empty()),
+            admin,
+        }
+    }
+
+    /// The --owner and --admin of the global options
+    pub fn from_global_opts(global_opts: &GlobalOpts) -> Self {
+        Actor::new(global_opts.owner.clone(), global_opts.admin)
+    }
+}
+
+/// The owner of a run being ingested, the ingest's owner or else its email
+pub fn run_owner(owner: Option<&str>, email: &str) -> Option<String> {
+    owner
+        .filter(|owner| !owner.is_empty())
+        .or((!email.is_empty()).then_some(email))
+        .map(str::to_string)
+}
+
+/// Fails unless every run changed, by its owner, is the actor's. Runs
+/// without an owner are anyone's, and nothing is refused when the actor has
+/// no owner. Check before committing the change, so a refused one is rolled
+/// back
+pub fn check(
+    actor: &Actor,
+    action: &'static str,
+    runs: &[(Uuid, Option<String>)],
+) -> Result<(), OwnerError> {
+    if actor.admin {
+        return Ok(());
+    }
+    let Some(owner) = &actor.owner else {
+        return Ok(());
+    };
+    let mut others: Vec<(Uuid, &String)> = runs
+        .iter()
+        .filter_map(|(run_uuid, run_owner)| match run_owner {
+            Some(run_owner) if run_owner != owner => Some((*run_uuid, run_owner)),
+            _ => None,
+        })
+        .collect();
+    others.sort();
+    others.dedup();
+    match others.first() {
+        Some((run_uuid, run_owner)) => Err(OwnerError::NotOwned(
+            action,
+            others.len(),
+            owner.clone(),
+            *run_uuid,
+            run_owner.to_string(),
+        )),
+        None => Ok(()),
+    }
+}
@@ -1,19 +1,31 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, TryStreamExt, stream};
 use serde::{Deserialize, Deserializer, Serialize, de};
-use serde_json::Value;
-use sqlx::{Execute, PgPool, Postgres, QueryBuilder, Transaction};
-use std::collections::HashMap;
+use serde_json::{Value, json};
+use sqlx::types::Json;
+use sqlx::{Connection, Execute, PgConnection, PgPool, Postgres, QueryBuilder, Transaction};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::fs;
 use std::fs::File;
 use std::io::{BufReader, prelude::*};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Instant;
 use thiserror::Error;
+use tracing::{Instrument, debug_span, instrument, warn};
 use uuid::Uuid;
 
-use crate::cdm::Name;
+use crate::adapter;
+use crate::args::{GlobalOpts, ParseArgs, StatusMode};
+use crate::audit;
+use crate::cancel::Cancellation;
+use crate::cdm::{self, Name};
+use crate::owner;
+use crate::query::PG_VAR_NUM_LIMIT;
+use crate::report;
+use crate::spill::{self, SpillError};
 
 #[derive(Error, Debug)]
 pub enum ParseError {
@@ -27,6 +39,14 @@ pub enum ParseError {
     TimestampParseFailed(String),
     #[error("Couldn't insert row into CDM table {0}")]
     InsertFailed(String),
+    #[error("{0} documents have invalid timestamps:\n{1}")]
+    InvalidTimestamps(usize, String),
+    #[error("Added {0} rows, but couldn't write them to the replica, {1}")]
+    ReplicaFailed(u64, String),
+    #[error("{0} documents have an unknown status, pass --coerce to record them as error:\n{1}")]
+    InvalidStatuses(usize, String),
+    #[error("Run {0} is being ingested by another process")]
+    RunBusy(Uuid),
 }
 
 #[derive(Debug, Clone)]
@@ -64,7 +84,17 @@ where
     F: FromStr,
     F::Err: Display,
 {
-    let s: String = Deserialize::deserialize(deserializer)?;
+    // Crucible writes numbers as strings, but plain numbers are taken too
+    let s = match Value::deserialize(deserializer)? {
+        Value::String(s) => s,
+        Value::Number(n) => n.to_string(),
+        other => {
+            return Err(de::Error::custom(format!(
+                "expected a number, got {}",
+                other
+            )));
+        }
+    };
     s.parse().map_err(de::Error::custom)
 }
 
@@ -78,7 +108,7 @@ fn is_ndjson(path: &str) -> bool {
 }
 
 fn index_name_to_type(name: String) -> Option<IndexType> {
-    match name.split("dev-").nth(1)?.split("@").nth(0)? {
+    match name.split("dev-").nth(1)?.split("@").next()? {
         "iteration" => Some(IndexType::Iteration),
         "metric_data" => Some(IndexType::MetricData),
         "metric_desc" => Some(IndexType::MetricDesc),
@@ -219,6 +249,8 @@ pub struct MetricDescSpecJson {
     pub source: String,
     #[serde(rename = "type")]
     pub metric_type: String,
+    #[serde(default)]
+    pub unit: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -240,6 +272,7 @@ impl Global for MetricDescJson {
                 names_list: Vec::new(),
                 source: "global".to_string(),
                 metric_type: "global".to_string(),
+                unit: None,
             },
             iteration: None,
             period: Some(PeriodFKJson {
@@ -337,6 +370,10 @@ pub struct RunSpecJson {
     pub name: String,
     pub description: Option<String>,
     pub source: String,
+    /// Who the run belongs to, only in backups. Otherwise it's worked out
+    /// from the email when the run is ingested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -404,7 +441,7 @@ pub struct TagSpecJson {
     pub val: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum IndexType {
     Iteration,
     MetricData,
@@ -429,7 +466,7 @@ pub enum BodyJson {
     Name(Name),
 }
 
-fn parse_body(index_type: IndexType, body_jsonl: String) -> Result<BodyJson> {
+pub(crate) fn parse_body(index_type: IndexType, body_jsonl: String) -> Result<BodyJson> {
     Ok(match index_type {
         IndexType::Iteration => {
             BodyJson::Iteration(serde_json::from_str(&body_jsonl).map_err(|e| {
@@ -464,10 +501,14 @@ fn parse_body(index_type: IndexType, body_jsonl: String) -> Result<BodyJson> {
     })
 }
 
+// The span wraps the body, which clippy then finds the return type in
+#[allow(clippy::type_complexity)]
+#[instrument(level = "debug", skip_all, fields(rows = runs.len()))]
 pub async fn insert_runs(
     txn: &mut Transaction<'_, Postgres>,
     globals: &mut HashMap<Uuid, GlobalResource>,
     runs: &Vec<&RunJson>,
+    opts: &IngestOpts,
 ) -> Result<(
     u64,
     Vec<IterationJson>,
@@ -492,50 +533,63 @@ pub async fn insert_runs(
         ));
     }
 
-    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
-        "INSERT INTO run
-        (run_uuid, begin, finish, benchmark, email, name, description, source) ",
-    );
-    qb.push_values(runs, |mut b, run| {
-        let iteration_uuid = Uuid::new_v4();
-        let global_iteration = IterationJson::global(run.run.run_uuid, iteration_uuid);
-        let sample_uuid = Uuid::new_v4();
-        let global_sample = SampleJson::global(iteration_uuid, sample_uuid);
-        let period_uuid = Uuid::new_v4();
-        let global_period = PeriodJson::global(sample_uuid, period_uuid);
-        let metric_desc_uuid = Uuid::new_v4();
-        let global_metric_desc = MetricDescJson::global(period_uuid, metric_desc_uuid);
-        let global_metric_data = MetricDataJson::global(metric_desc_uuid, Uuid::nil());
-        global_iterations.push(global_iteration.clone());
-        global_samples.push(global_sample.clone());
-        global_periods.push(global_period.clone());
-        global_metric_descs.push(global_metric_desc.clone());
-        global_metric_datas.push(global_metric_data.clone());
-        let global_resource = GlobalResource {
-            iteration: global_iteration,
-            sample: global_sample,
-            period: global_period,
-            metric_desc: global_metric_desc,
-            metric_data: global_metric_data,
-        };
-        globals.insert(run.run.run_uuid, global_resource);
-        b.push_bind(run.run.run_uuid)
-            .push_bind(run.run.begin)
-            .push_bind(run.run.end)
-            .push_bind(&run.run.benchmark)
-            .push_bind(&run.run.email)
-            .push_bind(&run.run.name)
-            .push_bind(&run.run.description)
-            .push_bind(&run.run.source);
-    });
-    let query = qb.build();
-    let s = query.sql();
-    let res = query
-        .execute(&mut **txn)
-        .await
-        .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
+    let mut rows_affected = 0;
+    for group in runs.chunks(opts.chunk_size(9)) {
+        opts.cancellation.check()?;
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO run
+        (run_uuid, begin, finish, benchmark, email, name, description, source, owner) ",
+        );
+        qb.push_values(group, |mut b, run| {
+            let iteration_uuid = cdm::new_uuid(opts.uuid_v7);
+            let global_iteration = IterationJson::global(run.run.run_uuid, iteration_uuid);
+            let sample_uuid = cdm::new_uuid(opts.uuid_v7);
+            let global_sample = SampleJson::global(iteration_uuid, sample_uuid);
+            let period_uuid = cdm::new_uuid(opts.uuid_v7);
+            let global_period = PeriodJson::global(sample_uuid, period_uuid);
+            let metric_desc_uuid = cdm::new_uuid(opts.uuid_v7);
+            let global_metric_desc = MetricDescJson::global(period_uuid, metric_desc_uuid);
+            let global_metric_data = MetricDataJson::global(metric_desc_uuid, Uuid::nil());
+            global_iterations.push(global_iteration.clone());
+            global_samples.push(global_sample.clone());
+            global_periods.push(global_period.clone());
+            global_metric_descs.push(global_metric_desc.clone());
+            global_metric_datas.push(global_metric_data.clone());
+            let global_resource = GlobalResource {
+                iteration: global_iteration,
+                sample: global_sample,
+                period: global_period,
+                metric_desc: global_metric_desc,
+                metric_data: global_metric_data,
+            };
+            globals.insert(run.run.run_uuid, global_resource);
+            b.push_bind(run.run.run_uuid)
+                .push_bind(run.run.begin)
+                .push_bind(run.run.end)
+                .push_bind(&run.run.benchmark)
+                .push_bind(&run.run.email)
+                .push_bind(&run.run.name)
+                .push_bind(&run.run.description)
+                .push_bind(&run.run.source)
+                .push_bind(
+                    run.run
+                        .owner
+                        .clone()
+                        .or_else(|| owner::run_owner(opts.owner.as_deref(), &run.run.email)),
+                );
+        });
+        let query = qb.build();
+        let s = query.sql();
+        let res = query
+            .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
+            .await
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
+        rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
+    }
     Ok((
-        res.rows_affected(),
+        rows_affected,
         global_iterations,
         global_samples,
         global_periods,
@@ -544,155 +598,206 @@ pub async fn insert_runs(
     ))
 }
 
-pub async fn insert_tags(txn: &mut Transaction<'_, Postgres>, tags: &Vec<&TagJson>) -> Result<u64> {
+#[instrument(level = "debug", skip_all, fields(rows = tags.len()))]
+pub async fn insert_tags(
+    txn: &mut Transaction<'_, Postgres>,
+    tags: &Vec<&TagJson>,
+    opts: &IngestOpts,
+) -> Result<u64> {
     if tags.is_empty() {
         return Ok(0);
     }
 
-    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
-        "INSERT INTO tag
+    let mut rows_affected = 0;
+    for group in tags.chunks(opts.chunk_size(3)) {
+        opts.cancellation.check()?;
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO tag
         (run_uuid, name, val) ",
-    );
-    qb.push_values(tags, |mut b, tag| {
-        b.push_bind(tag.run.run_uuid)
-            .push_bind(&tag.tag.name)
-            .push_bind(&tag.tag.val);
-    });
-    let query = qb.build();
-    let s = query.sql();
-    let res = query
-        .execute(&mut **txn)
-        .await
-        .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
-    Ok(res.rows_affected())
+        );
+        qb.push_values(group, |mut b, tag| {
+            b.push_bind(tag.run.run_uuid)
+                .push_bind(&tag.tag.name)
+                .push_bind(&tag.tag.val);
+        });
+        let query = qb.build();
+        let s = query.sql();
+        let res = query
+            .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
+            .await
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
+        rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
+    }
+    Ok(rows_affected)
 }
 
+#[instrument(level = "debug", skip_all, fields(rows = iterations.len()))]
 pub async fn insert_iterations(
     txn: &mut Transaction<'_, Postgres>,
     iterations: &Vec<&IterationJson>,
+    opts: &IngestOpts,
 ) -> Result<u64> {
     if iterations.is_empty() {
         return Ok(0);
     }
 
-    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
-        "INSERT INTO iteration
+    let mut rows_affected = 0;
+    for group in iterations.chunks(opts.chunk_size(7)) {
+        opts.cancellation.check()?;
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO iteration
         (iteration_uuid, run_uuid, num, status, path, primary_metric, primary_period) ",
-    );
-    qb.push_values(iterations, |mut b, iteration| {
-        b.push_bind(iteration.iteration.iteration_uuid)
-            .push_bind(iteration.run.run_uuid)
-            .push_bind(iteration.iteration.num)
-            .push_bind(&iteration.iteration.status)
-            .push_bind(&iteration.iteration.path)
-            .push_bind(&iteration.iteration.primary_metric)
-            .push_bind(&iteration.iteration.primary_period);
-    });
-    let query = qb.build();
-    let s = query.sql();
-    let res = query
-        .execute(&mut **txn)
-        .await
-        .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
-    Ok(res.rows_affected())
+        );
+        qb.push_values(group, |mut b, iteration| {
+            b.push_bind(iteration.iteration.iteration_uuid)
+                .push_bind(iteration.run.run_uuid)
+                .push_bind(iteration.iteration.num)
+                .push_bind(&iteration.iteration.status)
+                .push_bind(&iteration.iteration.path)
+                .push_bind(&iteration.iteration.primary_metric)
+                .push_bind(&iteration.iteration.primary_period);
+        });
+        let query = qb.build();
+        let s = query.sql();
+        let res = query
+            .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
+            .await
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
+        rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
+    }
+    Ok(rows_affected)
 }
 
+#[instrument(level = "debug", skip_all, fields(rows = params.len()))]
 pub async fn insert_params(
     txn: &mut Transaction<'_, Postgres>,
     params: &Vec<&ParamJson>,
+    opts: &IngestOpts,
 ) -> Result<u64> {
     if params.is_empty() {
         return Ok(0);
     }
 
-    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
-        "INSERT INTO param
+    let mut rows_affected = 0;
+    for group in params.chunks(opts.chunk_size(3)) {
+        opts.cancellation.check()?;
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO param
         (iteration_uuid, arg, val) ",
-    );
-    qb.push_values(params, |mut b, param| {
-        b.push_bind(param.iteration.iteration_uuid)
-            .push_bind(&param.param.arg)
-            .push_bind(&param.param.val);
-    });
-    let query = qb.build();
-    let s = query.sql();
-    let res = query
-        .execute(&mut **txn)
-        .await
-        .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
-    Ok(res.rows_affected())
+        );
+        qb.push_values(group, |mut b, param| {
+            b.push_bind(param.iteration.iteration_uuid)
+                .push_bind(&param.param.arg)
+                .push_bind(&param.param.val);
+        });
+        let query = qb.build();
+        let s = query.sql();
+        let res = query
+            .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
+            .await
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
+        rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
+    }
+    Ok(rows_affected)
 }
 
+#[instrument(level = "debug", skip_all, fields(rows = samples.len()))]
 pub async fn insert_samples(
     txn: &mut Transaction<'_, Postgres>,
     samples: &Vec<&SampleJson>,
+    opts: &IngestOpts,
 ) -> Result<u64> {
     if samples.is_empty() {
         return Ok(0);
     }
 
-    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
-        "INSERT INTO sample
+    let mut rows_affected = 0;
+    for group in samples.chunks(opts.chunk_size(5)) {
+        opts.cancellation.check()?;
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO sample
         (sample_uuid, iteration_uuid, num, status, path) ",
-    );
-    qb.push_values(samples, |mut b, sample| {
-        b.push_bind(sample.sample.sample_uuid)
-            .push_bind(&sample.iteration.iteration_uuid)
-            .push_bind(sample.sample.num)
-            .push_bind(&sample.sample.status)
-            .push_bind(&sample.sample.path);
-    });
-    let query = qb.build();
-    let s = query.sql();
-    let res = query
-        .execute(&mut **txn)
-        .await
-        .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
-    Ok(res.rows_affected())
+        );
+        qb.push_values(group, |mut b, sample| {
+            b.push_bind(sample.sample.sample_uuid)
+                .push_bind(sample.iteration.iteration_uuid)
+                .push_bind(sample.sample.num)
+                .push_bind(&sample.sample.status)
+                .push_bind(&sample.sample.path);
+        });
+        let query = qb.build();
+        let s = query.sql();
+        let res = query
+            .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
+            .await
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
+        rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
+    }
+    Ok(rows_affected)
 }
 
+#[instrument(level = "debug", skip_all, fields(rows = periods.len()))]
 pub async fn insert_periods(
     txn: &mut Transaction<'_, Postgres>,
     periods: &Vec<&PeriodJson>,
+    opts: &IngestOpts,
 ) -> Result<u64> {
     if periods.is_empty() {
         return Ok(0);
     }
 
-    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
-        "INSERT INTO period
+    let mut rows_affected = 0;
+    for group in periods.chunks(opts.chunk_size(5)) {
+        opts.cancellation.check()?;
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO period
         (period_uuid, sample_uuid, begin, finish, name) ",
-    );
-    qb.push_values(periods, |mut b, period| {
-        b.push_bind(period.period.period_uuid)
-            .push_bind(period.sample.sample_uuid)
-            .push_bind(period.period.begin)
-            .push_bind(period.period.end)
-            .push_bind(&period.period.name);
-    });
-    let query = qb.build();
-    let s = query.sql();
-    let res = query
-        .execute(&mut **txn)
-        .await
-        .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
-    Ok(res.rows_affected())
+        );
+        qb.push_values(group, |mut b, period| {
+            b.push_bind(period.period.period_uuid)
+                .push_bind(period.sample.sample_uuid)
+                .push_bind(period.period.begin)
+                .push_bind(period.period.end)
+                .push_bind(&period.period.name);
+        });
+        let query = qb.build();
+        let s = query.sql();
+        let res = query
+            .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
+            .await
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
+        rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
+    }
+    Ok(rows_affected)
 }
 
+#[instrument(level = "debug", skip_all, fields(rows = metric_descs.len()))]
 pub async fn insert_metric_descs(
     txn: &mut Transaction<'_, Postgres>,
     globals: &HashMap<Uuid, GlobalResource>,
     metric_descs: &Vec<&MetricDescJson>,
+    opts: &IngestOpts,
 ) -> Result<u64> {
     if metric_descs.is_empty() {
         return Ok(0);
     }
 
     let mut rows_affected = 0;
-    for group in metric_descs.chunks(1024) {
+    for group in metric_descs.chunks(opts.chunk_size(8)) {
+        opts.cancellation.check()?;
         let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
             "INSERT INTO metric_desc
-        (metric_desc_uuid, period_uuid, class, metric_type, source, names_list, names) ",
+        (metric_desc_uuid, period_uuid, class, metric_type, source, names_list, names, unit) ",
         );
         qb.push_values(group, |mut b, metric_desc| {
             b.push_bind(metric_desc.metric_desc.metric_desc_uuid)
@@ -711,26 +816,37 @@ pub async fn insert_metric_descs(
                 .push_bind(&metric_desc.metric_desc.metric_type)
                 .push_bind(&metric_desc.metric_desc.source)
                 .push_bind(&metric_desc.metric_desc.names_list)
-                .push_bind(serde_json::to_string(&metric_desc.metric_desc.names).ok());
+                .push_bind(Json(&metric_desc.metric_desc.names))
+                .push_bind(&metric_desc.metric_desc.unit);
         });
+        // Another batch or ingest may have inserted it already
+        qb.push(" ON CONFLICT DO NOTHING");
         let query = qb.build();
         let s = query.sql();
         let res = query
             .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
             .await
-            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
         rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
     }
     Ok(rows_affected)
 }
 
-pub async fn insert_names(txn: &mut Transaction<'_, Postgres>, names: &Vec<&Name>) -> Result<u64> {
+#[instrument(level = "debug", skip_all, fields(rows = names.len()))]
+pub async fn insert_names(
+    txn: &mut Transaction<'_, Postgres>,
+    names: &Vec<&Name>,
+    opts: &IngestOpts,
+) -> Result<u64> {
     if names.is_empty() {
         return Ok(0);
     }
 
     let mut rows_affected = 0;
-    for group in names.chunks(1024) {
+    for group in names.chunks(opts.chunk_size(3)) {
+        opts.cancellation.check()?;
         let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
             "INSERT INTO name
         (metric_desc_uuid, name, val) ",
@@ -740,26 +856,33 @@ pub async fn insert_names(txn: &mut Transaction<'_, Postgres>, names: &Vec<&Name
                 .push_bind(&name.name)
                 .push_bind(&name.val);
         });
+        // Another batch or ingest may have inserted it already
+        qb.push(" ON CONFLICT DO NOTHING");
         let query = qb.build();
         let s = query.sql();
         let res = query
             .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
             .await
-            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
         rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
     }
     Ok(rows_affected)
 }
 
+#[instrument(level = "debug", skip_all, fields(rows = metric_datas.len()))]
 pub async fn insert_metric_datas(
     txn: &mut Transaction<'_, Postgres>,
     metric_datas: &Vec<&MetricDataJson>,
+    opts: &IngestOpts,
 ) -> Result<u64> {
     if metric_datas.is_empty() {
         return Ok(0);
     }
     let mut rows_affected = 0;
-    for group in metric_datas.chunks(1024) {
+    for group in metric_datas.chunks(opts.chunk_size(5)) {
+        opts.cancellation.check()?;
         let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
             "INSERT INTO metric_data
         (metric_desc_uuid, value, begin, finish, duration) ",
@@ -775,97 +898,509 @@ pub async fn insert_metric_datas(
         let s = query.sql();
         let res = query
             .execute(&mut **txn)
+            .instrument(debug_span!("batch", rows = group.len()))
             .await
-            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e.to_string(), s)))?;
+            .map_err(|e| ParseError::InsertFailed(format!("{} ({})", e, s)))?;
         rows_affected += res.rows_affected();
+        opts.cancellation.add_inserted(res.rows_affected());
     }
     Ok(rows_affected)
 }
 
-pub fn extract_names(metric_desc: &MetricDescJson) -> Vec<Name> {
-    metric_desc
-        .metric_desc
-        .names
-        .iter()
-        .filter_map(|md| match md.1.as_str() {
-            Some(val) => Some(Name {
-                metric_desc_uuid: metric_desc.metric_desc.metric_desc_uuid,
-                name: md.0.to_string(),
-                val: val.to_string(),
-            }),
-            None => None,
+pub fn extract_names(metric_desc: &MetricDescJson) -> impl Iterator<Item = Name> + '_ {
+    metric_desc.metric_desc.names.iter().filter_map(|md| {
+        md.1.as_str().map(|val| Name {
+            metric_desc_uuid: metric_desc.metric_desc.metric_desc_uuid,
+            name: md.0.to_string(),
+            val: val.to_string(),
         })
-        .collect()
+    })
+}
+
+/// The status a spelling of pass, fail, skip or error stands for
+fn map_status(status: &str) -> Option<&'static str> {
+    match status.trim().to_lowercase().as_str() {
+        "pass" | "passed" | "ok" | "success" | "succeeded" => Some("pass"),
+        "fail" | "failed" | "failure" => Some("fail"),
+        "skip" | "skipped" => Some("skip"),
+        "error" | "errored" => Some("error"),
+        _ => None,
+    }
+}
+
+/// Rewrites the statuses into pass, fail, skip or error as `mode` allows,
+/// reporting those that can't be. Each status comes with a description of
+/// the document it's from.
+pub fn normalize_statuses<'a>(
+    statuses: impl Iterator<Item = (String, &'a mut String)>,
+    mode: StatusMode,
+) -> Result<(), ParseError> {
+    let mut problems = Vec::new();
+    for (document, status) in statuses {
+        let normalized = match mode {
+            StatusMode::Strict => ["pass", "fail", "skip", "error"]
+                .into_iter()
+                .find(|s| s == status),
+            StatusMode::Map => map_status(status),
+            StatusMode::Coerce => Some(map_status(status).unwrap_or("error")),
+        };
+        match normalized {
+            Some(normalized) => *status = normalized.to_string(),
+            None => problems.push(format!("{}: unknown status \"{}\"", document, status)),
+        }
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let num_problems = problems.len();
+    if num_problems > 20 {
+        problems.truncate(20);
+        problems.push(format!("... and {} more", num_problems - 20));
+    }
+    Err(ParseError::InvalidStatuses(
+        num_problems,
+        problems.join("\n"),
+    ))
+}
+
+/// The statuses of the iteration and sample documents
+pub fn document_statuses(records: &mut [BodyJson]) -> impl Iterator<Item = (String, &mut String)> {
+    records.iter_mut().filter_map(|record| match record {
+        BodyJson::Iteration(iteration) => Some((
+            format!(
+                "iteration {} (run {})",
+                iteration.iteration.iteration_uuid, iteration.run.run_uuid
+            ),
+            &mut iteration.iteration.status,
+        )),
+        BodyJson::Sample(sample) => Some((
+            format!(
+                "sample {} (run {})",
+                sample.sample.sample_uuid, sample.run.run_uuid
+            ),
+            &mut sample.sample.status,
+        )),
+        _ => None,
+    })
+}
+
+/// Catches the documents that a bad clock left with times out of order,
+/// rather than letting them into the aggregates
+pub fn validate_timestamps(
+    runs: &[&RunJson],
+    periods: &[&PeriodJson],
+    metric_datas: &[&MetricDataJson],
+) -> Result<(), ParseError> {
+    let mut problems = Vec::new();
+    for run in runs.iter().filter(|r| r.run.begin > r.run.end) {
+        problems.push(format!(
+            "run {}: begins at {} after it ends at {}",
+            run.run.run_uuid, run.run.begin, run.run.end
+        ));
+    }
+    for period in periods.iter().filter(|p| p.period.begin > p.period.end) {
+        problems.push(format!(
+            "period {} (run {}): begins at {} after it ends at {}",
+            period.period.period_uuid, period.run.run_uuid, period.period.begin, period.period.end
+        ));
+    }
+    for metric_data in metric_datas {
+        let data = &metric_data.metric_data;
+        if data.begin > data.end {
+            problems.push(format!(
+                "metric_data of metric_desc {} (run {}): begins at {} after it ends at {}",
+                metric_data.metric_desc.metric_desc_uuid,
+                metric_data.run.run_uuid,
+                data.begin,
+                data.end
+            ));
+        } else if data.duration < 0 {
+            problems.push(format!(
+                "metric_data of metric_desc {} (run {}): negative duration {}",
+                metric_data.metric_desc.metric_desc_uuid, metric_data.run.run_uuid, data.duration
+            ));
+        }
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let num_problems = problems.len();
+    if num_problems > 20 {
+        problems.truncate(20);
+        problems.push(format!("... and {} more", num_problems - 20));
+    }
+    Err(ParseError::InvalidTimestamps(
+        num_problems,
+        problems.join("\n"),
+    ))
+}
+
+/// The documents of an ingest by the table they go into
+#[derive(Default)]
+pub(crate) struct Tables<'a> {
+    pub(crate) runs: Vec<&'a RunJson>,
+    tags: Vec<&'a TagJson>,
+    iterations: Vec<&'a IterationJson>,
+    params: Vec<&'a ParamJson>,
+    samples: Vec<&'a SampleJson>,
+    periods: Vec<&'a PeriodJson>,
+    metric_descs: Vec<&'a MetricDescJson>,
+    metric_datas: Vec<&'a MetricDataJson>,
+    names: Vec<Name>,
+}
+
+impl<'a> Tables<'a> {
+    pub(crate) fn new(records: &'a Vec<BodyJson>) -> Result<Self> {
+        let mut tables = Tables::default();
+        let mut seen_metric_descs = HashSet::new();
+        // A name is unique to its metric_desc, the first value given is kept
+        let mut seen_names = HashSet::new();
+        for record in records {
+            match record {
+                BodyJson::Run(run) => tables.runs.push(run),
+                BodyJson::Tag(tag) => tables.tags.push(tag),
+                BodyJson::Iteration(iteration) => tables.iterations.push(iteration),
+                BodyJson::Param(param) => tables.params.push(param),
+                BodyJson::Sample(sample) => tables.samples.push(sample),
+                BodyJson::Period(period) => tables.periods.push(period),
+                // A metric_desc can be repeated across the files of a run
+                BodyJson::MetricDesc(metric_desc) => {
+                    if seen_metric_descs.insert(metric_desc.metric_desc.metric_desc_uuid) {
+                        tables.metric_descs.push(metric_desc)
+                    }
+                }
+                BodyJson::MetricData(metric_data) => tables.metric_datas.push(metric_data),
+                BodyJson::Name(name) => {
+                    if seen_names.insert((name.metric_desc_uuid, name.name.clone())) {
+                        tables.names.push(name.clone())
+                    }
+                }
+            };
+        }
+
+        validate_timestamps(&tables.runs, &tables.periods, &tables.metric_datas)?;
+
+        // Name documents can repeat the names their metric_desc carries
+        for name in tables.metric_descs.iter().copied().flat_map(extract_names) {
+            if seen_names.insert((name.metric_desc_uuid, name.name.clone())) {
+                tables.names.push(name)
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Adds the runs' global resources to the tables they go into
+    pub(crate) fn add_globals(&mut self, globals: &'a Globals) {
+        self.iterations.extend(&globals.iterations);
+        self.samples.extend(&globals.samples);
+        self.periods.extend(&globals.periods);
+        self.metric_descs.extend(&globals.metric_descs);
+        self.metric_datas.extend(&globals.metric_datas);
+    }
+}
+
+/// The resources scdm makes up for data that is scoped to a run
+pub(crate) struct Globals {
+    pub(crate) resources: HashMap<Uuid, GlobalResource>,
+    iterations: Vec<IterationJson>,
+    samples: Vec<SampleJson>,
+    periods: Vec<PeriodJson>,
+    metric_descs: Vec<MetricDescJson>,
+    metric_datas: Vec<MetricDataJson>,
+}
+
+/// Inserts the runs, and makes up their global resources
+pub(crate) async fn insert_runs_and_globals(
+    txn: &mut Transaction<'_, Postgres>,
+    runs: &Vec<&RunJson>,
+    opts: &IngestOpts,
+) -> Result<(u64, Globals)> {
+    let mut resources: HashMap<Uuid, GlobalResource> = HashMap::new();
+    let (new_run_rows, iterations, samples, periods, metric_descs, metric_datas) =
+        insert_runs(txn, &mut resources, runs, opts).await?;
+    let globals = Globals {
+        resources,
+        iterations,
+        samples,
+        periods,
+        metric_descs,
+        metric_datas,
+    };
+    Ok((new_run_rows, globals))
 }
 
 pub async fn insert_records(
     txn: &mut Transaction<'_, Postgres>,
     records: &Vec<BodyJson>,
+    opts: &IngestOpts,
 ) -> Result<u64> {
-    let mut num_new = 0;
-    let mut runs = Vec::new();
-    let mut tags = Vec::new();
-    let mut iterations = Vec::new();
-    let mut params = Vec::new();
-    let mut samples = Vec::new();
-    let mut periods = Vec::new();
-    let mut metric_descs = Vec::new();
-    let mut metric_datas = Vec::new();
-    let mut names = Vec::new();
-
-    for record in records {
-        match record {
-            BodyJson::Run(run) => runs.push(run),
-            BodyJson::Tag(tag) => tags.push(tag),
-            BodyJson::Iteration(iteration) => iterations.push(iteration),
-            BodyJson::Param(param) => params.push(param),
-            BodyJson::Sample(sample) => samples.push(sample),
-            BodyJson::Period(period) => periods.push(period),
-            BodyJson::MetricDesc(metric_desc) => metric_descs.push(metric_desc),
-            BodyJson::MetricData(metric_data) => metric_datas.push(metric_data),
-            BodyJson::Name(name) => names.push(name.clone()),
-        };
+    let mut tables = Tables::new(records)?;
+    let (num_new, globals) = insert_runs_and_globals(txn, &tables.runs, opts).await?;
+    tables.add_globals(&globals);
+    Ok(num_new + insert_tables(txn, &tables, &globals.resources, opts).await?)
+}
+
+/// Inserts everything but the runs, which have to be in already along with
+/// the global resources of the runs the metric_descs are scoped to
+pub(crate) async fn insert_tables(
+    txn: &mut Transaction<'_, Postgres>,
+    tables: &Tables<'_>,
+    resources: &HashMap<Uuid, GlobalResource>,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let mut num_new = insert_tags(txn, &tables.tags, opts).await?;
+    num_new += insert_iterations(txn, &tables.iterations, opts).await?;
+    num_new += insert_params(txn, &tables.params, opts).await?;
+    num_new += insert_samples(txn, &tables.samples, opts).await?;
+    num_new += insert_periods(txn, &tables.periods, opts).await?;
+    num_new += insert_metric_descs(txn, resources, &tables.metric_descs, opts).await?;
+    num_new += insert_names(txn, &tables.names.iter().collect(), opts).await?;
+    num_new += insert_metric_datas(txn, &tables.metric_datas, opts).await?;
+    Ok(num_new)
+}
+
+/// The rows of names or metric_data each concurrent transaction inserts
+const CONCURRENT_BATCH: usize = 16 * 1024;
+
+/// How an ingest inserts the documents
+#[derive(Debug, Clone)]
+pub struct IngestOpts {
+    /// How many connections an ingest that creates runs inserts over at
+    /// once, one inserts everything in a single transaction
+    pub jobs: usize,
+    /// Caps the rows each INSERT sends, by default as many as fit under
+    /// Postgres's limit on bind parameters
+    pub batch_size: Option<usize>,
+    /// Generate time ordered UUIDv7s for the resources scdm makes up
+    pub uuid_v7: bool,
+    /// Who the ingested runs belong to instead of whoever their email says
+    pub owner: Option<String>,
+    /// Stops the ingest once it's interrupted, and counts the rows it added
+    pub cancellation: Cancellation,
+}
+
+impl Default for IngestOpts {
+    fn default() -> Self {
+        IngestOpts {
+            jobs: 1,
+            batch_size: None,
+            uuid_v7: false,
+            owner: None,
+            cancellation: Cancellation::default(),
+        }
+    }
+}
+
+impl IngestOpts {
+    /// The ingest settings of the global options
+    pub fn new(global_opts: &GlobalOpts) -> Self {
+        IngestOpts {
+            jobs: global_opts.ingest_jobs.max(1),
+            batch_size: global_opts.batch_size.map(|rows| rows as usize),
+            uuid_v7: global_opts.uuid_v7,
+            owner: global_opts.owner.clone(),
+            cancellation: Cancellation::default(),
+        }
+    }
+
+    /// The rows an INSERT of this many columns sends at once
+    fn chunk_size(&self, columns: usize) -> usize {
+        let fit = PG_VAR_NUM_LIMIT as usize / columns;
+        match self.batch_size {
+            Some(rows) => rows.min(fit),
+            None => fit,
+        }
+    }
+}
+
+/// A transaction's worth of the rows that hang off of the runs' resources
+enum Batch<'a> {
+    Tags(&'a Vec<&'a TagJson>),
+    Params(&'a Vec<&'a ParamJson>),
+    Names(Vec<&'a Name>),
+    MetricDatas(Vec<&'a MetricDataJson>),
+}
+
+async fn insert_batch(pool: &PgPool, batch: Batch<'_>, opts: &IngestOpts) -> Result<u64> {
+    let mut txn = pool.begin().await?;
+    let rows = match batch {
+        Batch::Tags(tags) => insert_tags(&mut txn, tags, opts).await?,
+        Batch::Params(params) => insert_params(&mut txn, params, opts).await?,
+        Batch::Names(names) => insert_names(&mut txn, &names, opts).await?,
+        Batch::MetricDatas(metric_datas) => {
+            insert_metric_datas(&mut txn, &metric_datas, opts).await?
+        }
+    };
+    txn.commit().await?;
+    Ok(rows)
+}
+
+/// Removes the runs an ingest that failed part way through committed
+async fn discard_runs(pool: &PgPool, run_uuids: &[Uuid]) {
+    let discarded = sqlx::query("DELETE FROM run WHERE run_uuid = ANY($1)")
+        .bind(run_uuids)
+        .execute(pool)
+        .await;
+    if let Err(e) = discarded {
+        warn!(
+            "couldn't remove the partly ingested runs {:?}, they're soft deleted for `scdm purge` ({})",
+            run_uuids, e
+        );
+    }
+}
+
+/// Inserts the records over up to `opts.jobs` connections at once. The runs and
+/// the resources everything else hangs off of go first, committed with the
+/// runs soft deleted so nothing sees them half ingested. The tags, params,
+/// names and metric_data chunks are then inserted concurrently, each in a
+/// transaction of its own. The returned transaction brings the runs back,
+/// committing it finishes the ingest. If the ingest fails the runs are
+/// deleted again, and if it's killed they're left soft deleted
+async fn insert_records_concurrently<'a>(
+    pool: &'a PgPool,
+    records: &Vec<BodyJson>,
+    opts: &IngestOpts,
+) -> Result<(u64, Transaction<'a, Postgres>, Vec<Uuid>)> {
+    let mut tables = Tables::new(records)?;
+    let run_uuids: Vec<Uuid> = tables.runs.iter().map(|run| run.run.run_uuid).collect();
+
+    let mut txn = pool.begin().await?;
+    let (mut num_new, globals) = insert_runs_and_globals(&mut txn, &tables.runs, opts).await?;
+    sqlx::query("UPDATE run SET deleted_at = now() WHERE run_uuid = ANY($1)")
+        .bind(&run_uuids)
+        .execute(&mut *txn)
+        .await?;
+    tables.add_globals(&globals);
+    num_new += insert_iterations(&mut txn, &tables.iterations, opts).await?;
+    num_new += insert_samples(&mut txn, &tables.samples, opts).await?;
+    num_new += insert_periods(&mut txn, &tables.periods, opts).await?;
+    num_new +=
+        insert_metric_descs(&mut txn, &globals.resources, &tables.metric_descs, opts).await?;
+    txn.commit().await?;
+
+    let mut batches = vec![Batch::Tags(&tables.tags), Batch::Params(&tables.params)];
+    let names: Vec<&Name> = tables.names.iter().collect();
+    batches.extend(
+        names
+            .chunks(CONCURRENT_BATCH)
+            .map(|group| Batch::Names(group.to_vec())),
+    );
+    batches.extend(
+        tables
+            .metric_datas
+            .chunks(CONCURRENT_BATCH)
+            .map(|group| Batch::MetricDatas(group.to_vec())),
+    );
+    // Made up front, a closure in the stream trips up the Send check of the
+    // server's handlers
+    let inserts: Vec<_> = batches
+        .into_iter()
+        .map(|batch| insert_batch(pool, batch, opts))
+        .collect();
+    let inserted: Result<Vec<u64>> = stream::iter(inserts)
+        .buffer_unordered(opts.jobs)
+        .try_collect()
+        .await;
+    match inserted {
+        Ok(rows) => num_new += rows.iter().sum::<u64>(),
+        Err(e) => {
+            discard_runs(pool, &run_uuids).await;
+            return Err(e);
+        }
     }
 
-    let extracted_names = metric_descs
-        .clone()
+    let mut txn = pool.begin().await?;
+    sqlx::query("UPDATE run SET deleted_at = NULL WHERE run_uuid = ANY($1)")
+        .bind(&run_uuids)
+        .execute(&mut *txn)
+        .await?;
+    Ok((num_new, txn, run_uuids))
+}
+
+/// The runs the records belong to, in order and without repeats
+fn record_run_uuids<'a>(records: impl IntoIterator<Item = &'a BodyJson>) -> Vec<Uuid> {
+    let mut run_uuids: Vec<Uuid> = records
         .into_iter()
-        .map(extract_names)
-        .flatten();
+        .filter_map(|record| match record {
+            BodyJson::Iteration(iteration) => Some(iteration.run.run_uuid),
+            BodyJson::MetricData(metric_data) => Some(metric_data.run.run_uuid),
+            BodyJson::MetricDesc(metric_desc) => Some(metric_desc.run.run_uuid),
+            BodyJson::Param(param) => Some(param.run.run_uuid),
+            BodyJson::Period(period) => Some(period.run.run_uuid),
+            BodyJson::Run(run) => Some(run.run.run_uuid),
+            BodyJson::Sample(sample) => Some(sample.run.run_uuid),
+            BodyJson::Tag(tag) => Some(tag.run.run_uuid),
+            BodyJson::Name(_) => None,
+        })
+        .collect();
+    run_uuids.sort();
+    run_uuids.dedup();
+    run_uuids
+}
 
-    names.extend(extracted_names);
+/// Advisory locks on the runs being ingested. They're held by a connection
+/// taken out of the pool, so they last across all of the ingest's
+/// transactions and go away with it however the ingest ends
+pub struct RunLocks(PgConnection);
+
+/// Locks the runs for an ingest, failing on the first one another process
+/// is ingesting rather than waiting on it
+pub async fn lock_runs(pool: &PgPool, run_uuids: &[Uuid]) -> Result<RunLocks> {
+    let mut conn = pool.acquire().await?.detach();
+    for &run_uuid in run_uuids {
+        let locked: bool =
+            sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtextextended($1::text, 0))")
+                .bind(run_uuid)
+                .fetch_one(&mut conn)
+                .await?;
+        if !locked {
+            RunLocks(conn).release().await;
+            return Err(ParseError::RunBusy(run_uuid).into());
+        }
+    }
+    Ok(RunLocks(conn))
+}
 
-    // Default resources for data that is scoped to the run
-    let mut globals: HashMap<Uuid, GlobalResource> = HashMap::new();
+impl RunLocks {
+    /// Unlocks the runs by ending the session that holds the locks
+    pub async fn release(self) {
+        if let Err(e) = self.0.close().await {
+            warn!(
+                "couldn't cleanly close the connection locking the runs ({})",
+                e
+            );
+        }
+    }
+}
 
-    let (
-        new_run_rows,
-        global_iterations,
-        global_samples,
-        global_periods,
-        global_metric_descs,
-        global_metric_datas,
-    ) = insert_runs(txn, &mut globals, &runs).await?;
-    iterations.append(&mut global_iterations.iter().collect());
-    samples.append(&mut global_samples.iter().collect());
-    periods.append(&mut global_periods.iter().collect());
-    metric_descs.append(&mut global_metric_descs.iter().collect());
-    metric_datas.append(&mut global_metric_datas.iter().collect());
-    num_new += new_run_rows;
-
-    num_new += insert_tags(txn, &tags).await?;
-    num_new += insert_iterations(txn, &iterations).await?;
-    num_new += insert_params(txn, &params).await?;
-    num_new += insert_samples(txn, &samples).await?;
-    num_new += insert_periods(txn, &periods).await?;
-    num_new += insert_metric_descs(txn, &globals, &metric_descs).await?;
-    num_new += insert_names(txn, &names.iter().collect()).await?;
-    num_new += insert_metric_datas(txn, &metric_datas).await?;
-    Ok(num_new)
+/// The index type and unparsed body line of each document in CDM ndjson,
+/// where each document is an index line followed by the body line
+pub fn ndjson_bodies<R: BufRead>(reader: R) -> impl Iterator<Item = Result<(IndexType, String)>> {
+    let mut lines = reader.lines();
+    std::iter::from_fn(move || match (lines.next(), lines.next()) {
+        (Some(Ok(index_jsonl)), Some(Ok(body_jsonl))) => Some((index_jsonl, body_jsonl)),
+        _ => None,
+    })
+    .map(|(index_jsonl, body_jsonl)| {
+        let index: IndexJson = serde_json::from_str(&index_jsonl)
+            .map_err(|e| ParseError::JSONParseFailed("IndexJSON".to_string(), e.to_string()))?;
+        let index_type = index_name_to_type(index.index._index.clone())
+            .ok_or(ParseError::UnknownIndex(index.index._index))?;
+
+        Ok((index_type, body_jsonl))
+    })
 }
 
-pub async fn parse(pool: &PgPool, dir_path: &Path) -> Result<()> {
-    // Read all of the ndjson files
+/// Reads the documents out of CDM ndjson
+pub fn ndjson_documents<R: BufRead>(reader: R) -> impl Iterator<Item = Result<BodyJson>> {
+    ndjson_bodies(reader).map(|body| {
+        let (index_type, body_jsonl) = body?;
+        parse_body(index_type, body_jsonl)
+    })
+}
+
+/// The ndjson files in the directory
+pub fn ndjson_paths(dir_path: &Path) -> Result<Vec<PathBuf>> {
     let files = fs::read_dir(dir_path).map_err(|_| {
         ParseError::InvalidPath(
             dir_path
@@ -881,39 +1416,139 @@ pub async fn parse(pool: &PgPool, dir_path: &Path) -> Result<()> {
         .filter_map(|f| f.ok())
         .map(|d| d.path());
 
-    let ndjson_paths: Vec<PathBuf> = paths
+    Ok(paths
         .filter(|p| p.to_str().map(is_ndjson).unwrap_or(false))
-        .collect();
+        .collect())
+}
+
+/// Opens an ndjson file, naming it if it can't be
+pub fn open_ndjson(ndjson_path: &Path) -> Result<File, ParseError> {
+    File::open(ndjson_path).map_err(|_| {
+        ParseError::InvalidPath(format!(
+            "Couldn't open file {}",
+            ndjson_path.to_str().unwrap_or("path")
+        ))
+    })
+}
 
+/// Reads the documents out of every ndjson file in the directory
+#[instrument(skip_all, fields(path = %dir_path.display()))]
+pub fn read_ndjson_dir(dir_path: &Path) -> Result<Vec<BodyJson>> {
     let mut records: Vec<BodyJson> = Vec::new();
+    for ndjson_path in ndjson_paths(dir_path)? {
+        for record in ndjson_documents(BufReader::new(open_ndjson(&ndjson_path)?)) {
+            records.push(record?);
+        }
+    }
+    Ok(records)
+}
 
-    for ndjson_path in ndjson_paths {
-        let f = File::open(ndjson_path.clone()).map_err(|_| {
-            ParseError::InvalidPath(format!(
-                "Couldn't open file {}",
-                ndjson_path.to_str().unwrap_or("path")
-            ))
-        })?;
-
-        let reader = BufReader::new(f);
-        let mut lines = reader.lines();
-        while let (Some(Ok(index_jsonl)), Some(Ok(body_jsonl))) = (lines.next(), lines.next()) {
-            let index: IndexJson = serde_json::from_str(&index_jsonl)
-                .map_err(|e| ParseError::JSONParseFailed("IndexJSON".to_string(), e.to_string()))?;
-            let index_type = index_name_to_type(index.index._index.clone())
-                .ok_or(ParseError::UnknownIndex(index.index._index))?;
-
-            records.push(parse_body(index_type, body_jsonl)?);
+/// Ingests the documents in one transaction, and then again into the replica
+/// when there's one. The number of rows added to the primary is returned.
+pub async fn ingest(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    records: &Vec<BodyJson>,
+    command: &str,
+    filters: &Value,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let total_records = ingest_into(pool, records, command, filters, opts).await?;
+
+    if let Some(replica) = replica {
+        ingest_into(replica, records, command, filters, opts)
+            .await
+            .map_err(|e| ParseError::ReplicaFailed(total_records, format!("{}", e)))?;
+    }
+    Ok(total_records)
+}
+
+/// Inserts the records with their runs locked against other ingests
+async fn ingest_into(
+    pool: &PgPool,
+    records: &Vec<BodyJson>,
+    command: &str,
+    filters: &Value,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let locks = lock_runs(pool, &record_run_uuids(records)).await?;
+    let ingested = ingest_locked(pool, records, command, filters, opts).await;
+    locks.release().await;
+    ingested
+}
+
+/// Inserts the records and audits it, concurrently when they create runs
+/// and more than one ingest job is allowed
+async fn ingest_locked(
+    pool: &PgPool,
+    records: &Vec<BodyJson>,
+    command: &str,
+    filters: &Value,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let start = Instant::now();
+    let creates_runs = records
+        .iter()
+        .any(|record| matches!(record, BodyJson::Run(_)));
+    if opts.jobs > 1 && creates_runs {
+        let (total_records, mut txn, run_uuids) =
+            insert_records_concurrently(pool, records, opts).await?;
+        let finished = async {
+            audit::record_timed(&mut *txn, command, filters, total_records, start.elapsed())
+                .await?;
+            txn.commit().await?;
+            opts.cancellation.add_committed(total_records);
+            anyhow::Ok(())
+        };
+        if let Err(e) = finished.await {
+            discard_runs(pool, &run_uuids).await;
+            return Err(e);
         }
+        return Ok(total_records);
     }
-    // Ingest the documents in one transaction
+
     let mut txn = pool.begin().await?;
+    let total_records = insert_records(&mut txn, records, opts).await?;
+    audit::record_timed(&mut *txn, command, filters, total_records, start.elapsed()).await?;
+    txn.commit().await?;
+    opts.cancellation.add_committed(total_records);
+    Ok(total_records)
+}
 
-    let total_records = insert_records(&mut txn, &records).await?;
+/// Reads the results in the format given and ingests them
+pub async fn parse(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    mut args: ParseArgs,
+    opts: &IngestOpts,
+) -> Result<()> {
+    let path = Path::new(&args.path);
+    let filters = json!({ "path": path, "format": args.format });
+    if let Some(max_memory) = args.max_memory {
+        if args.format != "cdm" {
+            return Err(SpillError::UnsupportedFormat(args.format).into());
+        }
+        let max_bytes = max_memory as usize * 1024 * 1024;
+        let total_records = spill::ingest(
+            pool,
+            replica,
+            path,
+            max_bytes,
+            args.status.mode(),
+            &filters,
+            opts,
+        )
+        .await?;
+        report!("added {} rows", total_records);
+        return Ok(());
+    }
+    args.adapter.uuid_v7 = opts.uuid_v7;
+    let mut records = adapter::find(&args.format)?.parse(path, &args.adapter)?;
+    normalize_statuses(document_statuses(&mut records), args.status.mode())?;
 
-    txn.commit().await?;
+    let total_records = ingest(pool, replica, &records, "parse", &filters, opts).await?;
 
-    println!("added {} rows", total_records);
+    report!("added {} rows", total_records);
 
     Ok(())
 }
@@ -0,0 +1,231 @@
+use crate::cdm;
+use crate::init::{init_convenience_views, init_views, merr};
+use crate::report;
+use anyhow::Result;
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use sqlx::Transaction;
+use sqlx::postgres::{PgConnection, PgPool, Postgres};
+
+/// Whether metric_data has been converted into a partitioned table
+pub async fn is_partitioned(pool: &PgPool) -> Result<bool> {
+    let partitioned: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pg_partitioned_table WHERE partrelid = to_regclass('metric_data'))",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(merr)?;
+    Ok(partitioned)
+}
+
+fn month_start(ts: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(ts.year(), ts.month(), 1, 0, 0, 0)
+        .single()
+        .unwrap_or(ts)
+}
+
+fn next_month(month: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = match month.month() {
+        12 => (month.year() + 1, 1),
+        m => (month.year(), m + 1),
+    };
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0)
+        .single()
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+fn partition_name(month: DateTime<Utc>) -> String {
+    format!("metric_data_p{}", month.format("%Y%m"))
+}
+
+/// Creates the partition holding `month`'s metric data, moving any of its
+/// rows out of the default partition first.
+async fn create_month_partition(
+    txn: &mut Transaction<'_, Postgres>,
+    month: DateTime<Utc>,
+) -> Result<bool> {
+    let name = partition_name(month);
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(&name)
+        .fetch_one(&mut **txn)
+        .await
+        .map_err(merr)?;
+    if exists {
+        return Ok(false);
+    }
+    sqlx::query(&format!(
+        "CREATE TABLE {} (LIKE metric_data INCLUDING DEFAULTS INCLUDING CONSTRAINTS)",
+        name
+    ))
+    .execute(&mut **txn)
+    .await
+    .map_err(merr)?;
+    sqlx::query(&format!(
+        r#"
+        WITH moved AS (
+            DELETE FROM metric_data_default
+            WHERE begin >= $1 AND begin < $2
+            RETURNING *
+        )
+        INSERT INTO {} SELECT * FROM moved
+        "#,
+        name
+    ))
+    .bind(month)
+    .bind(next_month(month))
+    .execute(&mut **txn)
+    .await
+    .map_err(merr)?;
+    sqlx::query(&format!(
+        "ALTER TABLE metric_data ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}')",
+        name,
+        month.to_rfc3339(),
+        next_month(month).to_rfc3339()
+    ))
+    .execute(&mut **txn)
+    .await
+    .map_err(merr)?;
+    Ok(true)
+}
+
+/// Creates partitions for the coming `months_ahead` months, and for every
+/// month that has data sitting in the default partition.
+pub async fn maintain_partitions(pool: &PgPool, months_ahead: u32) -> Result<Vec<String>> {
+    let mut txn = pool.begin().await.map_err(merr)?;
+    let mut months: Vec<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT DISTINCT date_trunc('month', begin, 'UTC') FROM metric_data_default",
+    )
+    .fetch_all(&mut *txn)
+    .await
+    .map_err(merr)?;
+    let mut month = month_start(Utc::now());
+    for _ in 0..=months_ahead {
+        months.push(month);
+        month = next_month(month);
+    }
+
+    let mut created = Vec::new();
+    for month in months {
+        if create_month_partition(&mut txn, month).await? {
+            created.push(partition_name(month));
+        }
+    }
+    txn.commit().await.map_err(merr)?;
+    Ok(created)
+}
+
+/// Drops the monthly partitions that end before `before`, returning their
+/// names. Pass the transaction deleting their runs, so the data is only gone
+/// if the runs are
+pub async fn drop_partitions_before(
+    conn: &mut PgConnection,
+    before: DateTime<Utc>,
+) -> Result<Vec<String>> {
+    let raw_query: &str = r#"
+        SELECT child.relname
+        FROM pg_inherits
+        JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+        WHERE
+            pg_inherits.inhparent = to_regclass('metric_data') AND
+            child.relname LIKE 'metric_data_p%'
+    "#;
+    let partitions: Vec<String> = sqlx::query_scalar(raw_query)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(merr)?;
+
+    let mut dropped = Vec::new();
+    for name in partitions {
+        let Some(month) = name
+            .strip_prefix("metric_data_p")
+            .and_then(|ym| chrono::NaiveDate::parse_from_str(&format!("{}01", ym), "%Y%m%d").ok())
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .map(|d| d.and_utc())
+        else {
+            continue;
+        };
+        if next_month(month) <= before {
+            sqlx::query(&format!("DROP TABLE {}", name))
+                .execute(&mut *conn)
+                .await
+                .map_err(merr)?;
+            dropped.push(name);
+        }
+    }
+    Ok(dropped)
+}
+
+/// Converts metric_data into a table partitioned by month of the data's
+/// begin. Data that falls outside of the monthly partitions lands in
+/// metric_data_default until `maintain_partitions` gives it its own.
+pub async fn partition_metric_data(pool: &PgPool) -> Result<()> {
+    if is_partitioned(pool).await? {
+        return Ok(());
+    }
+
+    let mut views_exist = false;
+    for view in cdm::MATERIALIZED_VIEWS {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(view)
+            .fetch_one(pool)
+            .await
+            .map_err(merr)?;
+        views_exist |= exists;
+    }
+
+    let mut txn = pool.begin().await.map_err(merr)?;
+    // The views read metric_data, so they're recreated afterwards
+    for view in cdm::MATERIALIZED_VIEWS {
+        sqlx::query(&format!("DROP MATERIALIZED VIEW IF EXISTS {}", view))
+            .execute(&mut *txn)
+            .await
+            .map_err(merr)?;
+    }
+    for (view, _) in cdm::CONVENIENCE_VIEWS.iter().rev() {
+        sqlx::query(&format!("DROP VIEW IF EXISTS {}", view))
+            .execute(&mut *txn)
+            .await
+            .map_err(merr)?;
+    }
+    for statement in [
+        "ALTER TABLE metric_data RENAME TO metric_data_unpartitioned",
+        "ALTER TABLE metric_data_unpartitioned RENAME CONSTRAINT metric_data_pkey TO metric_data_unpartitioned_pkey",
+        r#"
+        CREATE TABLE metric_data (
+            metric_data_id bigint NOT NULL DEFAULT nextval('metric_data_metric_data_id_seq'),
+            metric_desc_uuid uuid REFERENCES metric_desc ON DELETE CASCADE,
+            value double precision NOT NULL,
+            begin timestamptz NOT NULL,
+            finish timestamptz NOT NULL,
+            duration bigint NOT NULL,
+            PRIMARY KEY (metric_data_id, metric_desc_uuid, begin)
+        ) PARTITION BY RANGE (begin)
+        "#,
+        // Keep the sequence from being dropped along with the old table
+        "ALTER SEQUENCE metric_data_metric_data_id_seq OWNED BY metric_data.metric_data_id",
+        "CREATE TABLE metric_data_default PARTITION OF metric_data DEFAULT",
+        r#"
+        INSERT INTO metric_data_default (metric_data_id, metric_desc_uuid, value, begin, finish, duration)
+        SELECT metric_data_id, metric_desc_uuid, value, begin, finish, duration
+        FROM metric_data_unpartitioned
+        "#,
+        "DROP TABLE metric_data_unpartitioned",
+        "ALTER TABLE metric_data ADD CONSTRAINT metric_data_begin_finish_check CHECK (begin <= finish) NOT VALID",
+        "ALTER TABLE metric_data ADD CONSTRAINT metric_data_duration_check CHECK (duration >= 0) NOT VALID",
+        "CREATE INDEX metric_data_metric_desc_uuid_begin_idx ON metric_data (metric_desc_uuid, begin)",
+        "CREATE INDEX metric_data_begin_finish_brin_idx ON metric_data USING brin (begin, finish)",
+    ] {
+        sqlx::query(statement)
+            .execute(&mut *txn)
+            .await
+            .map_err(merr)?;
+    }
+    txn.commit().await.map_err(merr)?;
+
+    let created = maintain_partitions(pool, 1).await?;
+    report!("partitioned metric_data into {}", created.join(", "));
+    init_convenience_views(pool).await?;
+    if views_exist {
+        init_views(pool).await?;
+    }
+    Ok(())
+}
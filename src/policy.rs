@@ -0,0 +1,166 @@
+use crate::args::{
+    DisplayOpts, PolicyArgs, PolicyCommand, PolicyListArgs, PolicyScopeArgs, PolicySetArgs,
+};
+use crate::audit;
+use crate::cdm::RetentionPolicy;
+use crate::query::{self, QueryError, QueryGet};
+use crate::report;
+use anyhow::Result;
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PolicyError {
+    #[error("No retention policy for {0}")]
+    NotFound(String),
+    #[error("Couldn't prune the runs past their retention policy, {0}")]
+    PruneFailed(String),
+}
+
+/// The policy covering each run that one covers. A policy for one of its
+/// tags wins over one for its benchmark, which wins over the default. When
+/// several of its tags have one, the longest keep wins
+pub const SQL_RUN_POLICIES: &str = r#"
+    SELECT DISTINCT ON (run.run_uuid) run.run_uuid, policy.keep
+    FROM run
+    JOIN retention_policy AS policy
+        ON (policy.benchmark IS NULL OR policy.benchmark = run.benchmark) AND (
+            policy.tag_name IS NULL OR EXISTS (
+                SELECT 1 FROM tag
+                WHERE tag.run_uuid = run.run_uuid
+                    AND tag.name = policy.tag_name
+                    AND tag.val = policy.tag_val
+            )
+        )
+    ORDER BY
+        run.run_uuid,
+        policy.tag_name IS NULL,
+        policy.benchmark IS NULL,
+        policy.keep DESC NULLS FIRST
+"#;
+
+/// The runs and tags a policy covers, as `policy list` shows them
+fn describe(scope: &PolicyScopeArgs) -> String {
+    let benchmark = match &scope.benchmark {
+        Some(benchmark) => format!("benchmark {}", benchmark),
+        None => "every benchmark".to_string(),
+    };
+    match &scope.tag {
+        Some((name, val)) => format!("{} tagged {}={}", benchmark, name, val),
+        None => benchmark,
+    }
+}
+
+async fn set(pool: &PgPool, args: PolicySetArgs) -> Result<()> {
+    let raw_query: &str = r#"
+        INSERT INTO retention_policy (benchmark, tag_name, tag_val, keep)
+        VALUES ($1, $2, $3, $4 * INTERVAL '1 millisecond')
+        ON CONFLICT ((COALESCE(benchmark, '')), (COALESCE(tag_name, '')), (COALESCE(tag_val, '')))
+        DO UPDATE SET keep = EXCLUDED.keep, updated_at = now()
+    "#;
+    let (tag_name, tag_val) = args.scope.tag.clone().unzip();
+    let mut txn = pool.begin().await?;
+    let res = sqlx::query(raw_query)
+        .bind(&args.scope.benchmark)
+        .bind(tag_name)
+        .bind(tag_val)
+        .bind(args.keep)
+        .execute(&mut *txn)
+        .await?;
+    audit::record(&mut *txn, "policy set", &args, res.rows_affected()).await?;
+    txn.commit().await?;
+    match args.keep {
+        Some(_) => report!("set the retention policy for {}", describe(&args.scope)),
+        None => report!("keeping the runs of {} forever", describe(&args.scope)),
+    }
+    Ok(())
+}
+
+async fn unset(pool: &PgPool, args: PolicyScopeArgs) -> Result<()> {
+    let raw_query: &str = r#"
+        DELETE FROM retention_policy
+        WHERE benchmark IS NOT DISTINCT FROM $1
+            AND tag_name IS NOT DISTINCT FROM $2
+            AND tag_val IS NOT DISTINCT FROM $3
+    "#;
+    let (tag_name, tag_val) = args.tag.clone().unzip();
+    let mut txn = pool.begin().await?;
+    let res = sqlx::query(raw_query)
+        .bind(&args.benchmark)
+        .bind(tag_name)
+        .bind(tag_val)
+        .execute(&mut *txn)
+        .await?;
+    if res.rows_affected() == 0 {
+        return Err(PolicyError::NotFound(describe(&args)).into());
+    }
+    audit::record(&mut *txn, "policy unset", &args, res.rows_affected()).await?;
+    txn.commit().await?;
+    report!("removed the retention policy for {}", describe(&args));
+    Ok(())
+}
+
+impl QueryGet<RetentionPolicy> for PolicyListArgs {
+    async fn query_get(&self, pool: &PgPool) -> Result<Vec<RetentionPolicy>, QueryError> {
+        let raw_query: &str = r#"
+            SELECT
+                benchmark,
+                tag_name || '=' || tag_val AS tag,
+                justify_hours(keep)::text AS keep,
+                updated_at
+            FROM retention_policy
+            ORDER BY benchmark NULLS FIRST, tag_name NULLS FIRST, tag_val
+        "#;
+        sqlx::query_as(raw_query)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
+    }
+}
+
+/// Deletes the runs that finished longer ago than their policy keeps them
+pub async fn apply(pool: &PgPool) -> Result<()> {
+    let has_policy: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM retention_policy)")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| PolicyError::PruneFailed(format!("{}", e)))?;
+    if !has_policy {
+        return Ok(());
+    }
+
+    let perr = |e: sqlx::Error| PolicyError::PruneFailed(format!("{}", e));
+    let mut txn = pool.begin().await.map_err(perr)?;
+    // No one in particular prunes, so the runs' owners aren't checked
+    let res = sqlx::query(&format!(
+        "DELETE FROM run USING ({}) AS policy
+        WHERE run.run_uuid = policy.run_uuid AND run.finish < now() - policy.keep",
+        SQL_RUN_POLICIES
+    ))
+    .execute(&mut *txn)
+    .await
+    .map_err(perr)?;
+    audit::record(
+        &mut *txn,
+        "prune policy",
+        &serde_json::Value::Null,
+        res.rows_affected(),
+    )
+    .await?;
+    txn.commit().await.map_err(perr)?;
+    report!(
+        "pruned {} runs past their retention policy",
+        res.rows_affected()
+    );
+    Ok(())
+}
+
+pub async fn policy(pool: &PgPool, args: PolicyArgs) -> Result<()> {
+    match args.command {
+        PolicyCommand::Set(set_args) => set(pool, set_args).await,
+        PolicyCommand::List(list_args) => {
+            let output = list_args.output.clone();
+            query::query_get(pool, list_args, output, None, None, &DisplayOpts::default()).await
+        }
+        PolicyCommand::Unset(scope_args) => unset(pool, scope_args).await,
+    }
+}
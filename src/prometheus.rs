@@ -0,0 +1,196 @@
+use crate::serve::{ApiError, ServeState};
+use axum::Router;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use sqlx::PgPool;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+/// Runs that haven't been deleted, by benchmark
+const SQL_RUNS: &str = r#"
+    SELECT benchmark, COUNT(*)::float8,
+        EXTRACT(EPOCH FROM (now() - MAX(finish)))::float8
+    FROM run
+    WHERE deleted_at IS NULL
+    GROUP BY benchmark
+"#;
+
+/// Counting the data points scans metric_data, so it's done in the
+/// background rather than on every scrape
+const SQL_DATA_POINTS: &str = r#"
+    SELECT run.benchmark, COUNT(*)::float8
+    FROM metric_data
+        JOIN metric_desc USING (metric_desc_uuid)
+        JOIN period USING (period_uuid)
+        JOIN sample USING (sample_uuid)
+        JOIN iteration USING (iteration_uuid)
+        JOIN run USING (run_uuid)
+    WHERE run.deleted_at IS NULL
+    GROUP BY run.benchmark
+"#;
+
+const SQL_TAG_AGES: &str = r#"
+    SELECT tag.name, tag.val, EXTRACT(EPOCH FROM (now() - MAX(run.finish)))::float8
+    FROM tag
+        JOIN run USING (run_uuid)
+    WHERE run.deleted_at IS NULL
+    GROUP BY tag.name, tag.val
+"#;
+
+/// The ingests the audit log has durations for
+const SQL_INGESTS: &str = r#"
+    SELECT command, COUNT(*)::float8, SUM(duration_ms)::float8 / 1000,
+        EXTRACT(EPOCH FROM MAX(at))::float8
+    FROM audit_log
+    WHERE duration_ms IS NOT NULL
+    GROUP BY command
+"#;
+
+/// The data points by benchmark as of the latest count, none until the
+/// first one finishes
+#[derive(Clone, Default)]
+pub(crate) struct DataPoints(Arc<RwLock<Vec<(String, f64)>>>);
+
+impl DataPoints {
+    /// Counts the data points now and then every `interval` after
+    pub fn count_every(pool: PgPool, interval: Duration) -> Self {
+        let data_points = DataPoints::default();
+        let counted = data_points.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                match sqlx::query_as(SQL_DATA_POINTS).fetch_all(&pool).await {
+                    Ok(counts) => *counted.0.write().unwrap() = counts,
+                    Err(e) => warn!("couldn't count the data points, {}", e),
+                }
+            }
+        });
+        data_points
+    }
+
+    fn latest(&self) -> Vec<(String, f64)> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+/// A label value, escaped as the text exposition format requires
+fn label(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+async fn exposition(pool: &PgPool, data_points: &DataPoints) -> anyhow::Result<String> {
+    let runs: Vec<(String, f64, Option<f64>)> = sqlx::query_as(SQL_RUNS).fetch_all(pool).await?;
+    let data_points = data_points.latest();
+    let tag_ages: Vec<(String, String, Option<f64>)> =
+        sqlx::query_as(SQL_TAG_AGES).fetch_all(pool).await?;
+    let ingests: Vec<(String, f64, Option<f64>, Option<f64>)> =
+        sqlx::query_as(SQL_INGESTS).fetch_all(pool).await?;
+
+    let mut out = String::new();
+    family(
+        &mut out,
+        "scdm_runs",
+        "gauge",
+        "Runs in the database",
+        runs.iter()
+            .map(|(benchmark, count, _)| (format!("benchmark={}", label(benchmark)), *count))
+            .collect(),
+    );
+    family(
+        &mut out,
+        "scdm_metric_data_points",
+        "gauge",
+        "Metric data points in the database, as of the latest count",
+        data_points
+            .into_iter()
+            .map(|(benchmark, count)| (format!("benchmark={}", label(&benchmark)), count))
+            .collect(),
+    );
+    family(
+        &mut out,
+        "scdm_latest_run_age_seconds",
+        "gauge",
+        "Seconds since the latest run of the benchmark finished",
+        runs.iter()
+            .filter_map(|(benchmark, _, age)| {
+                age.map(|age| (format!("benchmark={}", label(benchmark)), age))
+            })
+            .collect(),
+    );
+    family(
+        &mut out,
+        "scdm_latest_tagged_run_age_seconds",
+        "gauge",
+        "Seconds since the latest run with the tag finished",
+        tag_ages
+            .into_iter()
+            .filter_map(|(name, val, age)| {
+                age.map(|age| (format!("tag={},value={}", label(&name), label(&val)), age))
+            })
+            .collect(),
+    );
+    let _ = writeln!(
+        out,
+        "# HELP scdm_ingest_duration_seconds How long the ingests took"
+    );
+    let _ = writeln!(out, "# TYPE scdm_ingest_duration_seconds summary");
+    for (command, count, seconds, _) in &ingests {
+        let command = label(command);
+        let _ = writeln!(
+            out,
+            "scdm_ingest_duration_seconds_sum{{command={}}} {}",
+            command,
+            seconds.unwrap_or(0.0)
+        );
+        let _ = writeln!(
+            out,
+            "scdm_ingest_duration_seconds_count{{command={}}} {}",
+            command, count
+        );
+    }
+    family(
+        &mut out,
+        "scdm_last_ingest_timestamp_seconds",
+        "gauge",
+        "When the latest ingest finished, as a Unix timestamp",
+        ingests
+            .into_iter()
+            .filter_map(|(command, _, _, at)| {
+                at.map(|at| (format!("command={}", label(&command)), at))
+            })
+            .collect(),
+    );
+    Ok(out)
+}
+
+async fn metrics(State(state): State<ServeState>) -> Result<impl IntoResponse, ApiError> {
+    let body = exposition(&state.pool, &state.data_points).await?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
+
+/// Gauges about the data for Prometheus to scrape, so alerts can tell when
+/// results stop arriving
+pub(crate) fn routes() -> Router<ServeState> {
+    Router::new().route("/metrics", get(metrics))
+}
@@ -0,0 +1,80 @@
+use crate::args::PruneArgs;
+use crate::report;
+use crate::{audit, partition, policy, retention};
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum PruneError {
+    #[error("Couldn't prune the runs, {0}")]
+    PruneFailed(String),
+}
+
+/// Deletes the runs past their retention policy, and the ones no policy
+/// covers that finished longer than `older_than` ago, then applies the
+/// retention tiers to the rest. When metric_data is partitioned, whole
+/// months that only hold data of deleted runs are dropped instead of deleted
+/// row by row, and the partitions for the coming months are created.
+pub async fn prune(pool: &PgPool, args: PruneArgs) -> Result<()> {
+    let partitioned = partition::is_partitioned(pool).await?;
+
+    policy::apply(pool).await?;
+
+    if let Some(older_than) = args.older_than {
+        let cutoff = Utc::now() - Duration::milliseconds(older_than);
+        let perr = |e: sqlx::Error| PruneError::PruneFailed(format!("{}", e));
+        let mut txn = pool.begin().await.map_err(perr)?;
+        // No one in particular prunes, so the runs' owners aren't checked
+        let expired: Vec<Uuid> = sqlx::query_scalar(&format!(
+            "SELECT run_uuid FROM run
+            WHERE finish < $1 AND run_uuid NOT IN (SELECT run_uuid FROM ({}) AS policy)",
+            policy::SQL_RUN_POLICIES
+        ))
+        .bind(cutoff)
+        .fetch_all(&mut *txn)
+        .await
+        .map_err(perr)?;
+
+        if partitioned {
+            // Partitions can only go once none of the remaining runs have data in them.
+            // The runs a policy covers are all kept by now, it was applied above.
+            // They're dropped on the transaction deleting the runs, so a failed
+            // delete brings them back
+            let earliest_kept: Option<chrono::DateTime<Utc>> = sqlx::query_scalar(&format!(
+                "SELECT MIN(begin) FROM run
+                WHERE finish >= $1 OR run_uuid IN (SELECT run_uuid FROM ({}) AS policy)",
+                policy::SQL_RUN_POLICIES
+            ))
+            .bind(cutoff)
+            .fetch_one(&mut *txn)
+            .await
+            .map_err(perr)?;
+            let before = earliest_kept.map_or(cutoff, |kept| kept.min(cutoff));
+            for name in partition::drop_partitions_before(&mut txn, before).await? {
+                info!("dropped partition {}", name);
+            }
+        }
+
+        let res = sqlx::query("DELETE FROM run WHERE run_uuid = ANY($1)")
+            .bind(&expired)
+            .execute(&mut *txn)
+            .await
+            .map_err(perr)?;
+        audit::record(&mut *txn, "prune", &args, res.rows_affected()).await?;
+        txn.commit().await.map_err(perr)?;
+        report!("pruned {} runs", res.rows_affected());
+    }
+
+    retention::apply(pool).await?;
+
+    if partitioned {
+        for name in partition::maintain_partitions(pool, args.months_ahead).await? {
+            info!("created partition {}", name);
+        }
+    }
+    Ok(())
+}
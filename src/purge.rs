@@ -0,0 +1,35 @@
+use crate::args::PurgeArgs;
+use crate::audit;
+use crate::owner::{self, Actor};
+use crate::report;
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum PurgeError {
+    #[error("Couldn't purge the runs, {0}")]
+    PurgeFailed(String),
+}
+
+/// Permanently deletes the soft deleted runs, along with all of their data
+pub async fn purge(pool: &PgPool, args: PurgeArgs, actor: &Actor) -> Result<()> {
+    let cutoff = Utc::now() - Duration::milliseconds(args.older_than.unwrap_or(0));
+    let perr = |e: sqlx::Error| PurgeError::PurgeFailed(format!("{}", e));
+    let mut txn = pool.begin().await.map_err(perr)?;
+    let purged: Vec<(Uuid, Option<String>)> = sqlx::query_as(
+        "DELETE FROM run WHERE deleted_at IS NOT NULL AND deleted_at <= $1
+        RETURNING run_uuid, owner",
+    )
+    .bind(cutoff)
+    .fetch_all(&mut *txn)
+    .await
+    .map_err(perr)?;
+    owner::check(actor, "purge", &purged)?;
+    audit::record(&mut *txn, "purge", &args, purged.len() as u64).await?;
+    txn.commit().await.map_err(perr)?;
+    report!("purged {} runs", purged.len());
+    Ok(())
+}
@@ -1,18 +1,23 @@
 use crate::args::{
-    DeleteCommand, DeleteRunArgs, DeleteTagArgs, GetCommand, GetIterationArgs, GetMetricDataArgs,
-    GetMetricDescArgs, GetNameArgs, GetParamArgs, GetPeriodArgs, GetRunArgs, GetSampleArgs,
-    GetTagArgs, OutputFormat, QueryArgs, QueryCommand,
+    DeleteCommand, DeleteRunArgs, DeleteTagArgs, DisplayOpts, GetCommand, GetIterationArgs,
+    GetMetricDataArgs, GetMetricDescArgs, GetNameArgs, GetOptions, GetParamArgs, GetPeriodArgs,
+    GetRunArgs, GetRunLinkArgs, GetSampleArgs, GetTagArgs, OutputFormat, QueryArgs, QueryCommand,
 };
+use crate::audit;
 use crate::cdm::*;
 use crate::metric::query_metric;
+use crate::owner::{self, Actor};
+use crate::render;
+use crate::report;
+use crate::xlsx::{self, Sheet, XlsxError};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::Serialize;
-use sqlx::PgPool;
+use serde_json::Value;
 use sqlx::prelude::FromRow;
+use sqlx::{PgConnection, PgPool};
+use tabled::Tabled;
 use tabled::derive::display;
-use tabled::settings::Style;
-use tabled::{Table, Tabled};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -32,6 +37,23 @@ pub enum QueryError {
     MetricError(String),
 }
 
+fn csv_field(value: &Value, opts: &DisplayOpts) -> String {
+    match value {
+        Value::Null => String::new(),
+        // Timestamps in the timezone and time format of the options
+        Value::String(s) => match s.parse::<DateTime<Utc>>() {
+            Ok(t) => render::rfc3339_time(&t, opts),
+            Err(_) => s.clone(),
+        },
+        Value::Array(values) => values
+            .iter()
+            .map(|value| csv_field(value, opts))
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
 pub trait QueryGet<T>
 where
     T: Serialize + Tabled,
@@ -40,56 +62,95 @@ where
         &self,
         pool: &PgPool,
     ) -> impl std::future::Future<Output = Result<Vec<T>, QueryError>>;
+}
 
-    fn query_json(
-        &self,
-        pool: &PgPool,
-    ) -> impl std::future::Future<Output = Result<String, QueryError>> {
-        async {
-            let results: Vec<T> = self.query_get(pool).await?;
-            Ok(serde_json::to_string_pretty::<Vec<T>>(&results)
-                .map_err(|e| QueryError::SerializeError(format!("JSON ({})", e.to_string())))?)
+pub fn results_json<T: Serialize>(results: &[T], opts: &DisplayOpts) -> Result<String, QueryError> {
+    render::json(&results, true, opts)
+        .map_err(|e| QueryError::SerializeError(format!("JSON ({})", e)))
+}
+
+pub fn results_csv<T: Serialize>(results: &[T], opts: &DisplayOpts) -> Result<String, QueryError> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for (i, result) in results.iter().enumerate() {
+        // Going through a JSON value lets list columns share one CSV field
+        let Value::Object(fields) = serde_json::to_value(result)
+            .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e)))?
+        else {
+            return Err(QueryError::SerializeError(
+                "CSV (rows have to be structs)".to_string(),
+            ));
+        };
+        if i == 0 {
+            writer
+                .write_record(fields.keys())
+                .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e)))?;
         }
+        writer
+            .write_record(fields.values().map(|value| csv_field(value, opts)))
+            .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e)))?;
     }
+    String::from_utf8(
+        writer
+            .into_inner()
+            .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e)))?,
+    )
+    .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e)))
+}
 
-    fn query_csv(
-        &self,
-        pool: &PgPool,
-    ) -> impl std::future::Future<Output = Result<String, QueryError>> {
-        async {
-            let results: Vec<T> = self.query_get(pool).await?;
-            let mut writer = csv::Writer::from_writer(vec![]);
-            for result in &results {
-                writer
-                    .serialize(result)
-                    .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e.to_string())))?;
-            }
-            Ok(String::from_utf8(
-                writer
-                    .into_inner()
-                    .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e.to_string())))?,
-            )
-            .map_err(|e| QueryError::SerializeError(format!("CSV ({})", e.to_string())))?)
+pub fn results_ndjson<T: Serialize>(
+    results: &[T],
+    opts: &DisplayOpts,
+) -> Result<String, QueryError> {
+    let mut lines: Vec<String> = Vec::new();
+    for result in results {
+        let line = render::json(result, false, opts)
+            .map_err(|e| QueryError::SerializeError(format!("NDJSON ({})", e)))?;
+        // A filter can drop the line altogether
+        if !line.is_empty() {
+            lines.push(line);
         }
     }
+    Ok(lines.join("\n"))
+}
 
-    fn query_table(
-        &self,
-        pool: &PgPool,
-    ) -> impl std::future::Future<Output = Result<String, QueryError>> {
-        async {
-            let results: Vec<T> = self.query_get(pool).await?;
-            let mut table = Table::new(results);
-            table.with(Style::modern());
-            Ok(table.to_string())
+/// The results as a sheet named for their resource, ex: "MetricDesc"
+fn results_sheet<T: Serialize>(results: &[T]) -> Result<Sheet, QueryError> {
+    let mut header = vec![];
+    let mut rows = vec![];
+    for (i, result) in results.iter().enumerate() {
+        let Value::Object(fields) = serde_json::to_value(result)
+            .map_err(|e| QueryError::SerializeError(format!("XLSX ({})", e)))?
+        else {
+            return Err(QueryError::SerializeError(
+                "XLSX (rows have to be structs)".to_string(),
+            ));
+        };
+        if i == 0 {
+            header = fields.keys().cloned().collect();
         }
+        rows.push(fields.into_iter().map(|(_, value)| value).collect());
     }
+    let name = std::any::type_name::<T>()
+        .rsplit("::")
+        .next()
+        .unwrap_or("results");
+    Ok(Sheet {
+        name: name.to_string(),
+        header,
+        rows,
+    })
+}
+
+fn results_table<T: Tabled>(results: Vec<T>, opts: &DisplayOpts) -> String {
+    render::table(results, opts).to_string()
 }
 
 impl QueryGet<Run> for GetRunArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<Run>, QueryError> {
         let raw_query: &str = r#"
-            SELECT DISTINCT(run.*) FROM run LEFT JOIN tag ON run.run_uuid = tag.run_uuid
+            SELECT DISTINCT run.*,
+                (EXTRACT(EPOCH FROM (run.finish - run.begin)) * 1000)::bigint AS duration_ms
+            FROM run LEFT JOIN tag ON run.run_uuid = tag.run_uuid
             WHERE
                 ($1 IS NULL OR run.run_uuid = $1) AND
                 ($2 IS NULL OR begin <= $2) AND
@@ -101,13 +162,16 @@ impl QueryGet<Run> for GetRunArgs {
                 ($8 IS NULL OR run.name = $8) AND
                 ($9 IS NULL OR source = $9) AND
                 ($10 IS NULL OR tag.name = $10) AND
-                ($11 IS NULL OR tag.val = $11)
+                ($11 IS NULL OR tag.val = $11) AND
+                (run.deleted_at IS NOT NULL) = $12 AND
+                ($13::bigint IS NULL OR EXTRACT(EPOCH FROM (finish - begin)) * 1000 >= $13) AND
+                ($14::bigint IS NULL OR EXTRACT(EPOCH FROM (finish - begin)) * 1000 <= $14)
             "#;
 
         let (tag_name, tag_value): (Option<String>, Option<String>) =
             if let Some(maybe_tag) = self.tag.clone() {
                 let parts: Vec<String> = maybe_tag.split("=").map(|s| s.to_string()).collect();
-                (parts.get(0).cloned(), parts.get(1).cloned())
+                (parts.first().cloned(), parts.get(1).cloned())
             } else {
                 (None, None)
             };
@@ -122,28 +186,33 @@ impl QueryGet<Run> for GetRunArgs {
             .bind(self.name.clone())
             .bind(self.source.clone())
             .bind(tag_name)
-            .bind(tag_value);
-        Ok(query
+            .bind(tag_value)
+            .bind(self.deleted)
+            .bind(self.min_duration)
+            .bind(self.max_duration);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
 impl QueryGet<Tag> for GetTagArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<Tag>, QueryError> {
         let raw_query: &str = r#"
-            SELECT * FROM tag
+            SELECT tag.* FROM tag
+            JOIN run ON run.run_uuid = tag.run_uuid
             WHERE
-                ($1 IS NULL OR run_uuid = $1) AND
-                ($2 IS NULL OR name = $2) AND
-                ($3 IS NULL OR val = $3)
+                ($1 IS NULL OR tag.run_uuid = $1) AND
+                ($2 IS NULL OR tag.name = $2) AND
+                ($3 IS NULL OR tag.val = $3) AND
+                (run.deleted_at IS NOT NULL) = $4
             "#;
 
         let (tag_name, tag_value): (Option<String>, Option<String>) =
             if let Some(maybe_tag) = self.tag.clone() {
                 let parts: Vec<String> = maybe_tag.split("=").map(|s| s.to_string()).collect();
-                (parts.get(0).cloned(), parts.get(1).cloned())
+                (parts.first().cloned(), parts.get(1).cloned())
             } else {
                 (None, None)
             };
@@ -151,34 +220,43 @@ impl QueryGet<Tag> for GetTagArgs {
         let query = sqlx::query_as(raw_query)
             .bind(self.run_uuid)
             .bind(tag_name)
-            .bind(tag_value);
-        Ok(query
+            .bind(tag_value)
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
 impl QueryGet<Iteration> for GetIterationArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<Iteration>, QueryError> {
         let raw_query: &str = r#"
-            SELECT iteration.* FROM iteration
+            SELECT iteration.*, (
+                SELECT (EXTRACT(EPOCH FROM (MAX(period.finish) - MIN(period.begin))) * 1000)::bigint
+                FROM sample JOIN period ON period.sample_uuid = sample.sample_uuid
+                WHERE sample.iteration_uuid = iteration.iteration_uuid
+            ) AS duration_ms
+            FROM iteration
+            JOIN run ON run.run_uuid = iteration.run_uuid
             WHERE
-                ($1 IS NULL OR iteration_uuid = $1) AND
-                ($2 IS NULL OR run_uuid = $2) AND
-                ($3 IS NULL OR num = $3) AND
-                ($4 IS NULL OR status = $4)
+                ($1 IS NULL OR iteration.iteration_uuid = $1) AND
+                ($2 IS NULL OR iteration.run_uuid = $2) AND
+                ($3 IS NULL OR iteration.num = $3) AND
+                ($4 IS NULL OR iteration.status = $4) AND
+                (run.deleted_at IS NOT NULL) = $5
             "#;
 
         let query = sqlx::query_as(raw_query)
             .bind(self.iteration_uuid)
             .bind(self.run_uuid)
             .bind(self.num)
-            .bind(self.status.clone());
-        Ok(query
+            .bind(self.status.clone())
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
@@ -186,20 +264,24 @@ impl QueryGet<Param> for GetParamArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<Param>, QueryError> {
         let raw_query: &str = r#"
             SELECT param.* FROM param
+            JOIN iteration ON iteration.iteration_uuid = param.iteration_uuid
+            JOIN run ON run.run_uuid = iteration.run_uuid
             WHERE
-                ($1 IS NULL OR iteration_uuid = $1) AND
-                ($2 IS NULL OR arg = $2) AND
-                ($3 IS NULL OR val = $3)
+                ($1 IS NULL OR param.iteration_uuid = $1) AND
+                ($2 IS NULL OR param.arg = $2) AND
+                ($3 IS NULL OR param.val = $3) AND
+                (run.deleted_at IS NOT NULL) = $4
             "#;
 
         let query = sqlx::query_as(raw_query)
             .bind(self.iteration_uuid)
             .bind(self.arg.clone())
-            .bind(self.val.clone());
-        Ok(query
+            .bind(self.val.clone())
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
@@ -207,37 +289,51 @@ impl QueryGet<Sample> for GetSampleArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<Sample>, QueryError> {
         let raw_query: &str = r#"
             SELECT sample.* FROM sample
+            JOIN iteration ON iteration.iteration_uuid = sample.iteration_uuid
+            JOIN run ON run.run_uuid = iteration.run_uuid
             WHERE
-                ($1 IS NULL OR sample_uuid = $1) AND
-                ($2 IS NULL OR iteration_uuid = $2) AND
-                ($3 IS NULL OR num = $3) AND
-                ($3 IS NULL OR status = $4)
+                ($1 IS NULL OR sample.sample_uuid = $1) AND
+                ($2 IS NULL OR sample.iteration_uuid = $2) AND
+                ($3 IS NULL OR sample.num = $3) AND
+                ($4 IS NULL OR sample.status = $4) AND
+                (run.deleted_at IS NOT NULL) = $5
             "#;
 
         let query = sqlx::query_as(raw_query)
             .bind(self.sample_uuid)
             .bind(self.iteration_uuid)
             .bind(self.num)
-            .bind(self.status.clone());
-        Ok(query
+            .bind(self.status.clone())
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
 impl QueryGet<Period> for GetPeriodArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<Period>, QueryError> {
         let raw_query: &str = r#"
-            SELECT period.* FROM period
+            SELECT period.*,
+                (EXTRACT(EPOCH FROM (period.finish - period.begin)) * 1000)::bigint AS duration_ms
+            FROM period
+            JOIN sample ON sample.sample_uuid = period.sample_uuid
+            JOIN iteration ON iteration.iteration_uuid = sample.iteration_uuid
+            JOIN run ON run.run_uuid = iteration.run_uuid
             WHERE
-                ($1 IS NULL OR period_uuid = $1) AND
-                ($2 IS NULL OR sample_uuid = $2) AND
-                ($3 IS NULL OR begin <= $3) AND
-                ($4 IS NULL OR begin >= $4) AND
-                ($5 IS NULL OR finish <= $5) AND
-                ($6 IS NULL OR finish >= $6) AND
-                ($7 IS NULL OR name = $7)
+                ($1 IS NULL OR period.period_uuid = $1) AND
+                ($2 IS NULL OR period.sample_uuid = $2) AND
+                ($3 IS NULL OR period.begin <= $3) AND
+                ($4 IS NULL OR period.begin >= $4) AND
+                ($5 IS NULL OR period.finish <= $5) AND
+                ($6 IS NULL OR period.finish >= $6) AND
+                ($7 IS NULL OR period.name = $7) AND
+                ($8::bigint IS NULL OR
+                    EXTRACT(EPOCH FROM (period.finish - period.begin)) * 1000 >= $8) AND
+                ($9::bigint IS NULL OR
+                    EXTRACT(EPOCH FROM (period.finish - period.begin)) * 1000 <= $9) AND
+                (run.deleted_at IS NOT NULL) = $10
             "#;
 
         let query = sqlx::query_as(raw_query)
@@ -247,11 +343,14 @@ impl QueryGet<Period> for GetPeriodArgs {
             .bind(self.begin_after)
             .bind(self.finish_before)
             .bind(self.finish_after)
-            .bind(self.name.clone());
-        Ok(query
+            .bind(self.name.clone())
+            .bind(self.min_duration)
+            .bind(self.max_duration)
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
@@ -259,12 +358,20 @@ impl QueryGet<MetricDesc> for GetMetricDescArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<MetricDesc>, QueryError> {
         let raw_query: &str = r#"
             SELECT metric_desc.* FROM metric_desc
+            JOIN period ON period.period_uuid = metric_desc.period_uuid
+            JOIN sample ON sample.sample_uuid = period.sample_uuid
+            JOIN iteration ON iteration.iteration_uuid = sample.iteration_uuid
+            JOIN run ON run.run_uuid = iteration.run_uuid
             WHERE
-                ($1 IS NULL OR metric_desc_uuid = $1) AND
-                ($2 IS NULL OR period_uuid = $2) AND
-                ($3 IS NULL OR class = $3) AND
-                ($4 IS NULL OR metric_type = $4) AND
-                ($5 IS NULL OR source = $5)
+                ($1 IS NULL OR metric_desc.metric_desc_uuid = $1) AND
+                ($2 IS NULL OR metric_desc.period_uuid = $2) AND
+                ($3 IS NULL OR metric_desc.class = $3) AND
+                ($4 IS NULL OR metric_desc.metric_type = $4) AND
+                ($5 IS NULL OR metric_desc.source = $5) AND
+                ($6::jsonb IS NULL OR metric_desc.names @> $6::jsonb) AND
+                ($7::jsonpath IS NULL OR metric_desc.names @? $7::jsonpath) AND
+                ($8::text IS NULL OR metric_desc.names_list @> ARRAY[$8::text]) AND
+                (run.deleted_at IS NOT NULL) = $9
             "#;
 
         let query = sqlx::query_as(raw_query)
@@ -272,11 +379,15 @@ impl QueryGet<MetricDesc> for GetMetricDescArgs {
             .bind(self.period_uuid)
             .bind(self.class.clone())
             .bind(self.metric_type.clone())
-            .bind(self.source.clone());
-        Ok(query
+            .bind(self.source.clone())
+            .bind(self.names_contain.clone())
+            .bind(self.names_path.clone())
+            .bind(self.has_name.clone())
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
@@ -284,20 +395,27 @@ impl QueryGet<Name> for GetNameArgs {
     async fn query_get(&self, pool: &PgPool) -> Result<Vec<Name>, QueryError> {
         let raw_query: &str = r#"
             SELECT name.* FROM name
+            JOIN metric_desc ON metric_desc.metric_desc_uuid = name.metric_desc_uuid
+            JOIN period ON period.period_uuid = metric_desc.period_uuid
+            JOIN sample ON sample.sample_uuid = period.sample_uuid
+            JOIN iteration ON iteration.iteration_uuid = sample.iteration_uuid
+            JOIN run ON run.run_uuid = iteration.run_uuid
             WHERE
-                ($1 IS NULL OR metric_desc_uuid = $1) AND
-                ($2 IS NULL OR name = $2) AND
-                ($3 IS NULL OR val = $3)
+                ($1 IS NULL OR name.metric_desc_uuid = $1) AND
+                ($2 IS NULL OR name.name = $2) AND
+                ($3 IS NULL OR name.val = $3) AND
+                (run.deleted_at IS NOT NULL) = $4
             "#;
 
         let query = sqlx::query_as(raw_query)
             .bind(self.metric_desc_uuid)
             .bind(self.name.clone())
-            .bind(self.val.clone());
-        Ok(query
+            .bind(self.val.clone())
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
@@ -309,9 +427,13 @@ pub struct Data {
     pub iteration_uuid: Option<Uuid>,
     pub metric_desc_uuid: Uuid,
     pub metric_type: String,
+    #[tabled(display = "render::time")]
     pub begin: DateTime<Utc>,
+    #[tabled(display = "render::time")]
     pub finish: DateTime<Utc>,
+    #[tabled(display = "render::millis")]
     pub duration: i64,
+    #[tabled(display = "render::value")]
     pub value: f64,
 }
 
@@ -345,7 +467,9 @@ impl QueryGet<Data> for GetMetricDataArgs {
                 ($8 IS NULL OR metric_data.finish >= $8) AND
                 ($9 IS NULL OR metric_data.value = $9) AND
                 ($10 IS NULL OR metric_data.value < $10) AND
-                ($11 IS NULL OR metric_data.value > $11)
+                ($11 IS NULL OR metric_data.value > $11) AND
+                -- Data without a run isn't a deleted run's
+                ((run.run_uuid IS NULL AND NOT $12) OR (run.deleted_at IS NOT NULL) = $12)
             "#;
 
         let query = sqlx::query_as(raw_query)
@@ -359,11 +483,64 @@ impl QueryGet<Data> for GetMetricDataArgs {
             .bind(self.finish_after)
             .bind(self.value_eq)
             .bind(self.value_lt)
-            .bind(self.value_gt);
-        Ok(query
+            .bind(self.value_gt)
+            .bind(self.deleted);
+        query
             .fetch_all(pool)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?)
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
+    }
+}
+
+impl QueryGet<RunLink> for GetRunLinkArgs {
+    async fn query_get(&self, pool: &PgPool) -> Result<Vec<RunLink>, QueryError> {
+        let raw_query: &str = if self.lineage {
+            r#"
+            WITH RECURSIVE lineage AS (
+                SELECT run_link.*, ARRAY[run_link.from_run_uuid] as seen
+                FROM run_link
+                JOIN run AS from_run ON from_run.run_uuid = run_link.from_run_uuid
+                JOIN run AS to_run ON to_run.run_uuid = run_link.to_run_uuid
+                WHERE
+                    run_link.from_run_uuid = $1 AND
+                    ($2::text IS NULL OR run_link.relation = $2) AND
+                    (from_run.deleted_at IS NOT NULL OR to_run.deleted_at IS NOT NULL) = $3
+                UNION ALL
+                SELECT run_link.*, lineage.seen || run_link.from_run_uuid
+                FROM run_link
+                JOIN lineage
+                    ON run_link.from_run_uuid = lineage.to_run_uuid
+                JOIN run AS from_run ON from_run.run_uuid = run_link.from_run_uuid
+                JOIN run AS to_run ON to_run.run_uuid = run_link.to_run_uuid
+                WHERE
+                    run_link.from_run_uuid <> ALL(lineage.seen) AND
+                    ($2::text IS NULL OR run_link.relation = $2) AND
+                    (from_run.deleted_at IS NOT NULL OR to_run.deleted_at IS NOT NULL) = $3
+            )
+            SELECT DISTINCT from_run_uuid, relation, to_run_uuid, created
+            FROM lineage
+            ORDER BY created
+            "#
+        } else {
+            r#"
+            SELECT run_link.from_run_uuid, run_link.relation, run_link.to_run_uuid, run_link.created
+            FROM run_link
+            JOIN run AS from_run ON from_run.run_uuid = run_link.from_run_uuid
+            JOIN run AS to_run ON to_run.run_uuid = run_link.to_run_uuid
+            WHERE
+                ($1::uuid IS NULL OR run_link.from_run_uuid = $1 OR run_link.to_run_uuid = $1) AND
+                ($2::text IS NULL OR run_link.relation = $2) AND
+                (from_run.deleted_at IS NOT NULL OR to_run.deleted_at IS NOT NULL) = $3
+            ORDER BY run_link.created
+            "#
+        };
+        sqlx::query_as(raw_query)
+            .bind(self.run_uuid)
+            .bind(self.relation.map(|r| r.as_str()))
+            .bind(self.deleted)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| QueryError::GetError(format!("{}", e)))
     }
 }
 
@@ -371,13 +548,31 @@ pub async fn query_get<T: Serialize + Tabled, U: QueryGet<T>>(
     pool: &PgPool,
     resource: U,
     format: Option<OutputFormat>,
+    limit: Option<usize>,
+    out: Option<String>,
+    display: &DisplayOpts,
 ) -> Result<()> {
+    let mut results = resource.query_get(pool).await?;
+    if let Some(limit) = limit {
+        results.truncate(limit);
+    }
+
     let result: String = match format {
         Some(format_type) => match format_type {
-            OutputFormat::JSON => resource.query_json(pool).await,
-            OutputFormat::CSV => resource.query_csv(pool).await,
+            OutputFormat::JSON => results_json(&results, display),
+            OutputFormat::CSV => results_csv(&results, display),
+            OutputFormat::NDJSON => results_ndjson(&results, display),
+            OutputFormat::Vega => Err(QueryError::UnknownFormat(
+                "vega, it only charts the windows of query metric".to_string(),
+            )),
+            OutputFormat::Xlsx => {
+                let out = out.ok_or(XlsxError::NoOut)?;
+                xlsx::write(&out, vec![results_sheet(&results)?], display)?;
+                report!("wrote {} rows to {}", results.len(), out);
+                return Ok(());
+            }
         },
-        None => resource.query_table(pool).await,
+        None => Ok(results_table(results, display)),
     }?;
 
     println!("{}", result);
@@ -385,21 +580,40 @@ pub async fn query_get<T: Serialize + Tabled, U: QueryGet<T>>(
 }
 
 pub trait QueryDelete {
+    /// Deletes on the caller's transaction, which commits it
     fn query_delete(
         &self,
-        pool: &PgPool,
+        conn: &mut PgConnection,
+        actor: &Actor,
     ) -> impl std::future::Future<Output = Result<u64, QueryError>>;
 }
 
 impl QueryDelete for DeleteRunArgs {
-    async fn query_delete(&self, pool: &PgPool) -> Result<u64, QueryError> {
-        let raw_query: &str = r#"
+    async fn query_delete(
+        &self,
+        conn: &mut PgConnection,
+        actor: &Actor,
+    ) -> Result<u64, QueryError> {
+        let action: &str = if self.soft {
+            r#"
+            UPDATE run SET deleted_at = now()
+            FROM run AS r
+            "#
+        } else {
+            r#"
             DELETE FROM run
             USING run AS r
+            "#
+        };
+        let raw_query = format!(
+            "{}{}",
+            action,
+            r#"
             LEFT JOIN tag as t ON
                 r.run_uuid = t.run_uuid
             WHERE
                 (run.run_uuid = r.run_uuid) AND
+                (run.deleted_at IS NULL OR NOT $12) AND
                 ($1 IS NULL OR run.run_uuid = $1) AND
                 ($2 IS NULL OR run.begin <= $2) AND
                 ($3 IS NULL OR run.begin >= $3) AND
@@ -411,16 +625,18 @@ impl QueryDelete for DeleteRunArgs {
                 ($9 IS NULL OR run.source = $9) AND
                 ($10 IS NULL OR t.name = $10) AND
                 ($11 IS NULL OR t.val = $11)
-            "#;
+            RETURNING run.run_uuid, run.owner
+            "#
+        );
 
         let (tag_name, tag_value): (Option<String>, Option<String>) =
             if let Some(maybe_tag) = self.tag.clone() {
                 let parts: Vec<String> = maybe_tag.split("=").map(|s| s.to_string()).collect();
-                (parts.get(0).cloned(), parts.get(1).cloned())
+                (parts.first().cloned(), parts.get(1).cloned())
             } else {
                 (None, None)
             };
-        let query = sqlx::query(raw_query)
+        let query = sqlx::query_as(&raw_query)
             .bind(self.run_uuid)
             .bind(self.begin_before)
             .bind(self.begin_after)
@@ -431,68 +647,124 @@ impl QueryDelete for DeleteRunArgs {
             .bind(self.name.clone())
             .bind(self.source.clone())
             .bind(tag_name)
-            .bind(tag_value);
+            .bind(tag_value)
+            .bind(self.soft);
 
-        let results = query
-            .execute(pool)
+        let deleted: Vec<(Uuid, Option<String>)> = query
+            .fetch_all(&mut *conn)
             .await
             .map_err(|e| QueryError::DeleteError(format!("{}", e)))?;
-        Ok(results.rows_affected())
+        owner::check(actor, "delete", &deleted)
+            .map_err(|e| QueryError::DeleteError(format!("{}", e)))?;
+        Ok(deleted.len() as u64)
     }
 }
 
 impl QueryDelete for DeleteTagArgs {
-    async fn query_delete(&self, pool: &PgPool) -> Result<u64, QueryError> {
+    async fn query_delete(
+        &self,
+        conn: &mut PgConnection,
+        actor: &Actor,
+    ) -> Result<u64, QueryError> {
         let raw_query: &str = r#"
-            DELETE FROM tag
-            WHERE
-                ($1 IS NULL OR run_uuid = $1) AND
-                ($2 IS NULL OR name = $2) AND
-                ($3 IS NULL OR val = $3)
+            WITH deleted AS (
+                DELETE FROM tag
+                WHERE
+                    ($1 IS NULL OR run_uuid = $1) AND
+                    ($2 IS NULL OR name = $2) AND
+                    ($3 IS NULL OR val = $3)
+                RETURNING run_uuid
+            )
+            SELECT run.run_uuid, run.owner FROM deleted JOIN run USING (run_uuid)
             "#;
 
         let (tag_name, tag_value): (Option<String>, Option<String>) =
             if let Some(maybe_tag) = self.tag.clone() {
                 let parts: Vec<String> = maybe_tag.split("=").map(|s| s.to_string()).collect();
-                (parts.get(0).cloned(), parts.get(1).cloned())
+                (parts.first().cloned(), parts.get(1).cloned())
             } else {
                 (None, None)
             };
 
-        let query = sqlx::query(raw_query)
+        let query = sqlx::query_as(raw_query)
             .bind(self.run_uuid)
             .bind(tag_name)
             .bind(tag_value);
-        let results = query
-            .execute(pool)
+        let deleted: Vec<(Uuid, Option<String>)> = query
+            .fetch_all(&mut *conn)
             .await
-            .map_err(|e| QueryError::GetError(format!("{}", e)))?;
-        Ok(results.rows_affected())
+            .map_err(|e| QueryError::DeleteError(format!("{}", e)))?;
+        owner::check(actor, "delete the tags of", &deleted)
+            .map_err(|e| QueryError::DeleteError(format!("{}", e)))?;
+        Ok(deleted.len() as u64)
     }
 }
 
-pub async fn query_delete<U: QueryDelete>(pool: &PgPool, resource: U) -> Result<()> {
-    let num_deletes = resource.query_delete(pool).await?;
-    println!("deleted {} rows", num_deletes);
+/// Deletes the resource and records the delete in the audit log, in one
+/// transaction
+pub async fn delete_audited<U: QueryDelete + Serialize>(
+    pool: &PgPool,
+    command: &str,
+    resource: U,
+    actor: &Actor,
+) -> Result<u64> {
+    let mut txn = pool.begin().await?;
+    let num_deletes = resource.query_delete(&mut txn, actor).await?;
+    audit::record(&mut *txn, command, &resource, num_deletes).await?;
+    txn.commit().await?;
+    Ok(num_deletes)
+}
+
+pub async fn query_delete<U: QueryDelete + Serialize>(
+    pool: &PgPool,
+    command: &str,
+    resource: U,
+    actor: &Actor,
+) -> Result<()> {
+    let num_deletes = delete_audited(pool, command, resource, actor).await?;
+    report!("deleted {} rows", num_deletes);
     Ok(())
 }
 
-pub async fn query(pool: &PgPool, args: QueryArgs) -> Result<()> {
+pub async fn query(pool: &PgPool, args: QueryArgs, actor: &Actor) -> Result<()> {
     match args.command {
-        QueryCommand::Get(get) => match get.resource {
-            GetCommand::Run(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::Tag(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::Iteration(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::Param(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::Sample(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::Period(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::MetricDesc(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::MetricData(args) => query_get(pool, args, get.get_options.output).await,
-            GetCommand::Name(args) => query_get(pool, args, get.get_options.output).await,
-        },
+        QueryCommand::Get(get) => {
+            let GetOptions {
+                output,
+                limit,
+                out,
+                display,
+            } = get.get_options;
+            match get.resource {
+                GetCommand::Run(args) => query_get(pool, args, output, limit, out, &display).await,
+                GetCommand::Tag(args) => query_get(pool, args, output, limit, out, &display).await,
+                GetCommand::Iteration(args) => {
+                    query_get(pool, args, output, limit, out, &display).await
+                }
+                GetCommand::Param(args) => {
+                    query_get(pool, args, output, limit, out, &display).await
+                }
+                GetCommand::Sample(args) => {
+                    query_get(pool, args, output, limit, out, &display).await
+                }
+                GetCommand::Period(args) => {
+                    query_get(pool, args, output, limit, out, &display).await
+                }
+                GetCommand::MetricDesc(args) => {
+                    query_get(pool, args, output, limit, out, &display).await
+                }
+                GetCommand::MetricData(args) => {
+                    query_get(pool, args, output, limit, out, &display).await
+                }
+                GetCommand::Name(args) => query_get(pool, args, output, limit, out, &display).await,
+                GetCommand::RunLink(args) => {
+                    query_get(pool, args, output, limit, out, &display).await
+                }
+            }
+        }
         QueryCommand::Delete(del) => match del.resource {
-            DeleteCommand::Run(args) => query_delete(pool, args).await,
-            DeleteCommand::Tag(args) => query_delete(pool, args).await,
+            DeleteCommand::Run(args) => query_delete(pool, "query delete run", args, actor).await,
+            DeleteCommand::Tag(args) => query_delete(pool, "query delete tag", args, actor).await,
         },
         QueryCommand::Metric(metric_args) => query_metric(pool, metric_args).await,
     }
@@ -0,0 +1,28 @@
+use crate::args::RefreshArgs;
+use crate::cdm;
+use anyhow::Result;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum RefreshError {
+    #[error("Couldn't refresh the view {0}, {1}")]
+    RefreshFailed(String, String),
+}
+
+pub async fn refresh(pool: &PgPool, args: RefreshArgs) -> Result<()> {
+    for view in cdm::MATERIALIZED_VIEWS {
+        let raw_query = if args.concurrently {
+            format!("REFRESH MATERIALIZED VIEW CONCURRENTLY {}", view)
+        } else {
+            format!("REFRESH MATERIALIZED VIEW {}", view)
+        };
+        sqlx::query(&raw_query)
+            .execute(pool)
+            .await
+            .map_err(|e| RefreshError::RefreshFailed(view.to_string(), format!("{}", e)))?;
+        info!("refreshed {}", view);
+    }
+    Ok(())
+}
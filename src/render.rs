@@ -0,0 +1,559 @@
+use crate::args::{DisplayOpts, TableStyle};
+use crate::unit::{self, Dimension};
+use chrono::format::StrftimeItems;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Vars, data, unwrap_valr};
+use jaq_json::Val;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::io::{self, IsTerminal};
+use std::sync::Arc;
+use tabled::settings::object::Segment;
+use tabled::settings::{Color, Format, Style, Width};
+use tabled::{Table, Tabled};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error(
+        "Unknown timezone {0}, expected utc, local, a name like Europe/Berlin or an offset like +02:00"
+    )]
+    UnknownTimezone(String),
+    #[error("Invalid time format {0}, expected strftime specifiers like %Y-%m-%d %H:%M")]
+    InvalidTimeFormat(String),
+    #[error("Invalid highlight {0}, expected <column><op><value>=<color>, ex: value>1000=red: {1}")]
+    InvalidHighlight(String, String),
+    #[error("Invalid filter {0}: {1}")]
+    InvalidFilter(String, String),
+    #[error("The filter failed: {0}")]
+    FilterFailed(String),
+    #[error("Couldn't serialize the results to JSON ({0})")]
+    JsonFailed(String),
+}
+
+/// A jq filter, compiled once for all the output it goes through
+#[derive(Clone)]
+pub struct JsonFilter {
+    code: String,
+    filter: Arc<jaq_core::Filter<data::JustLut<Val>>>,
+}
+
+impl fmt::Debug for JsonFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.code)
+    }
+}
+
+/// Compiles a jq filter with jq's standard library, ex: `.[] | .run_uuid`
+pub fn compile_filter(code: &str) -> Result<JsonFilter, RenderError> {
+    let invalid = |e: String| RenderError::InvalidFilter(code.to_string(), e);
+    let defs = jaq_core::defs()
+        .chain(jaq_std::defs())
+        .chain(jaq_json::defs());
+    let funs = jaq_core::funs()
+        .chain(jaq_std::funs())
+        .chain(jaq_json::funs());
+    let arena = Arena::default();
+    let modules = Loader::new(defs)
+        .load(&arena, File { code, path: () })
+        .map_err(|errs| {
+            invalid(format!(
+                "{:?}",
+                errs.into_iter().map(|(_, e)| e).collect::<Vec<_>>()
+            ))
+        })?;
+    Compiler::default()
+        .with_funs(funs)
+        .compile(modules)
+        .map(|filter| JsonFilter {
+            code: code.to_string(),
+            filter: Arc::new(filter),
+        })
+        .map_err(|errs| {
+            invalid(format!(
+                "{:?}",
+                errs.into_iter().map(|(_, e)| e).collect::<Vec<_>>()
+            ))
+        })
+}
+
+/// The timezone timestamps are shown in
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Timezone {
+    #[default]
+    Utc,
+    Local,
+    Offset(FixedOffset),
+    /// A timezone of the IANA database, which follows its daylight saving
+    Named(Tz),
+}
+
+impl Timezone {
+    pub fn parse(arg: &str) -> Result<Self, RenderError> {
+        match arg.to_lowercase().as_str() {
+            "utc" | "z" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            _ => arg
+                .parse::<FixedOffset>()
+                .map(Timezone::Offset)
+                .or_else(|_| arg.parse::<Tz>().map(Timezone::Named))
+                .map_err(|_| RenderError::UnknownTimezone(arg.to_string())),
+        }
+    }
+
+    /// The instant a wall clock time in the timezone stands for, the
+    /// earlier one when a daylight saving change makes it ambiguous
+    pub fn to_utc(&self, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Timezone::Utc => Some(naive.and_utc()),
+            Timezone::Local => naive
+                .and_local_timezone(Local)
+                .earliest()
+                .map(|t| t.to_utc()),
+            Timezone::Offset(offset) => naive
+                .and_local_timezone(*offset)
+                .earliest()
+                .map(|t| t.to_utc()),
+            Timezone::Named(tz) => naive.and_local_timezone(*tz).earliest().map(|t| t.to_utc()),
+        }
+    }
+}
+
+/// Checks the format has only specifiers chrono knows, as formatting with
+/// any other panics
+pub fn parse_time_format(arg: &str) -> Result<String, RenderError> {
+    StrftimeItems::new(arg)
+        .parse()
+        .map(|_| arg.to_string())
+        .map_err(|_| RenderError::InvalidTimeFormat(arg.to_string()))
+}
+
+thread_local! {
+    /// The display options of the table being built. The display functions
+    /// of the rows' `Tabled` derives only get the value they show, so
+    /// `table` lends them the options while it builds the rows
+    static BUILDING: RefCell<DisplayOpts> = RefCell::default();
+}
+
+/// The fewest characters of a UUID a table shows
+const SHORT_ID_LEN: usize = 8;
+
+/// How a highlight compares a cell to its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+/// Colors the cells of a column that compare to a value, ex: value>1000=red
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    column: String,
+    comparison: Comparison,
+    value: String,
+    color: Color,
+}
+
+impl Highlight {
+    /// Whether the cell compares to the value, as numbers if they both are
+    fn matches(&self, cell: &str) -> bool {
+        use std::cmp::Ordering;
+        let ordering = match (cell.parse::<f64>(), self.value.parse::<f64>()) {
+            (Ok(cell), Ok(value)) => cell.partial_cmp(&value),
+            (Err(_), Ok(_)) => None,
+            _ => Some(cell.cmp(self.value.as_str())),
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self.comparison {
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+        }
+    }
+}
+
+/// Highlights as comma separated rules, ex: 'value>1000=red,status==fail=yellow'
+pub fn parse_highlights(arg: &str) -> Result<Vec<Highlight>, RenderError> {
+    arg.split(',')
+        .filter(|rule| !rule.trim().is_empty())
+        .map(parse_highlight)
+        .collect()
+}
+
+/// A rule of --highlight, ex: value>1000=red
+pub fn parse_highlight(rule: &str) -> Result<Highlight, RenderError> {
+    let invalid = |e: &str| RenderError::InvalidHighlight(rule.to_string(), e.to_string());
+    let (condition, color) = rule.trim().rsplit_once('=').ok_or(invalid("no color"))?;
+    let color = match color.to_lowercase().as_str() {
+        "red" => Color::FG_RED,
+        "green" => Color::FG_GREEN,
+        "yellow" => Color::FG_YELLOW,
+        "blue" => Color::FG_BLUE,
+        "magenta" => Color::FG_MAGENTA,
+        "cyan" => Color::FG_CYAN,
+        "white" => Color::FG_WHITE,
+        "bold" => Color::BOLD,
+        _ => return Err(invalid("unknown color")),
+    };
+    let at = condition
+        .find(['<', '>', '=', '!'])
+        .ok_or(invalid("no comparison"))?;
+    let (column, rest) = condition.split_at(at);
+    let (comparison, value) = [
+        (">=", Comparison::Ge),
+        ("<=", Comparison::Le),
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        (">", Comparison::Gt),
+        ("<", Comparison::Lt),
+    ]
+    .into_iter()
+    .find_map(|(op, comparison)| rest.strip_prefix(op).map(|value| (comparison, value)))
+    .ok_or(invalid("unknown comparison"))?;
+    if column.trim().is_empty() || value.is_empty() {
+        return Err(invalid("no column or value"));
+    }
+    Ok(Highlight {
+        column: column.trim().to_string(),
+        comparison,
+        value: value.to_string(),
+        color,
+    })
+}
+
+/// The JSON of the value, or with a --filter, each of its outputs on a line
+/// of their own like jq prints them
+pub fn json<T: Serialize>(
+    value: &T,
+    pretty: bool,
+    opts: &DisplayOpts,
+) -> Result<String, RenderError> {
+    fn to_string<T: Serialize>(value: &T, pretty: bool) -> Result<String, RenderError> {
+        match pretty {
+            true => serde_json::to_string_pretty(value),
+            false => serde_json::to_string(value),
+        }
+        .map_err(|e| RenderError::JsonFailed(e.to_string()))
+    }
+    let Some(JsonFilter { filter, .. }) = &opts.filter else {
+        return to_string(value, pretty);
+    };
+    let input = to_string(value, false)?;
+    let input = jaq_json::read::parse_single(input.as_bytes())
+        .map_err(|e| RenderError::JsonFailed(e.to_string()))?;
+    let ctx = Ctx::<data::JustLut<Val>>::new(&filter.lut, Vars::new([]));
+    let mut outputs = vec![];
+    for output in filter.id.run((ctx, input)).map(unwrap_valr) {
+        let output = output.map_err(|e| RenderError::FilterFailed(e.to_string()))?;
+        let output: serde_json::Value = serde_json::from_str(&output.to_string())
+            .map_err(|e| RenderError::JsonFailed(e.to_string()))?;
+        outputs.push(to_string(&output, pretty)?);
+    }
+    Ok(outputs.join("\n"))
+}
+
+/// The timestamp in the timezone of the options
+pub fn in_timezone(t: &DateTime<Utc>, opts: &DisplayOpts) -> DateTime<FixedOffset> {
+    match opts.timezone.unwrap_or_default() {
+        Timezone::Utc => t.fixed_offset(),
+        Timezone::Local => t.with_timezone(&Local).fixed_offset(),
+        Timezone::Offset(offset) => t.with_timezone(&offset),
+        Timezone::Named(tz) => t.with_timezone(&tz).fixed_offset(),
+    }
+}
+
+/// The timestamp in the time format, chrono's by default
+fn formatted<T: TimeZone>(t: DateTime<T>, time_format: Option<&str>) -> String
+where
+    T::Offset: Display,
+{
+    match time_format {
+        Some(time_format) => t.format(time_format).to_string(),
+        None => t.to_string(),
+    }
+}
+
+/// A timestamp in the timezone and time format of the options
+pub fn format_time(t: &DateTime<Utc>, opts: &DisplayOpts) -> String {
+    let time_format = opts.time_format.as_deref();
+    match opts.timezone.unwrap_or_default() {
+        Timezone::Utc => formatted(*t, time_format),
+        Timezone::Local => formatted(t.with_timezone(&Local), time_format),
+        Timezone::Offset(offset) => formatted(t.with_timezone(&offset), time_format),
+        Timezone::Named(tz) => formatted(t.with_timezone(&tz), time_format),
+    }
+}
+
+/// A timestamp of output for programs to read, like CSV, in the timezone of
+/// the options and RFC 3339 unless a time format was given
+pub fn rfc3339_time(t: &DateTime<Utc>, opts: &DisplayOpts) -> String {
+    match opts.time_format.is_some() {
+        true => format_time(t, opts),
+        false => in_timezone(t, opts).to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    }
+}
+
+/// A timestamp of a table, in the timezone and time format of the table
+pub fn time(t: &DateTime<Utc>) -> String {
+    BUILDING.with_borrow(|opts| format_time(t, opts))
+}
+
+/// Like `tabled::derive::display::option`, for timestamps
+pub fn option_time(t: &Option<DateTime<Utc>>, default: impl Display) -> String {
+    match t {
+        Some(t) => time(t),
+        None => default.to_string(),
+    }
+}
+
+/// Whether tables show values for people to read rather than as they're stored
+pub fn is_human(opts: &DisplayOpts) -> bool {
+    opts.human.unwrap_or(false)
+}
+
+/// Whether the table being built shows values for people to read
+fn building_human() -> bool {
+    BUILDING.with_borrow(is_human)
+}
+
+/// A number with at most `decimals` decimals, without trailing zeros
+fn trimmed(value: f64, decimals: usize) -> String {
+    let s = format!("{:.*}", decimals, value);
+    match s.contains('.') {
+        true => s.trim_end_matches('0').trim_end_matches('.').to_string(),
+        false => s,
+    }
+}
+
+/// A number shortened with an SI suffix, ex: 1.2M, or an IEC one of powers of
+/// 1024, ex: 3.4Gi
+fn scaled(value: f64, iec: bool) -> String {
+    let (base, suffixes) = match iec {
+        true => (1024.0, ["", "Ki", "Mi", "Gi", "Ti", "Pi", "Ei"]),
+        false => (1000.0, ["", "k", "M", "G", "T", "P", "E"]),
+    };
+    let mut scaled = value;
+    let mut i = 0;
+    while scaled.abs() >= base && i < suffixes.len() - 1 {
+        scaled /= base;
+        i += 1;
+    }
+    match i {
+        0 => trimmed(scaled, 2),
+        _ => format!("{}{}", trimmed(scaled, 1), suffixes[i]),
+    }
+}
+
+/// Seconds as the units of time they add up to, ex: 2m 13s, or below a
+/// second in ms or us, ex: 1.5ms
+pub fn duration(secs: f64) -> String {
+    let abs = secs.abs();
+    if abs < 1e-3 {
+        return format!("{}us", trimmed(secs * 1e6, 1));
+    }
+    if abs < 1.0 {
+        return format!("{}ms", trimmed(secs * 1e3, 1));
+    }
+    if abs < 60.0 {
+        return format!("{}s", trimmed(secs, 1));
+    }
+    let sign = if secs < 0.0 { "-" } else { "" };
+    let mut left = abs.round() as u64;
+    let mut parts = vec![];
+    for (unit, length) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if left >= length {
+            parts.push(format!("{}{}", left / length, unit));
+            left %= length;
+        }
+    }
+    format!("{}{}", sign, parts.join(" "))
+}
+
+/// A metric value for people to read, scaled to the suffix that suits its
+/// unit: durations for time, IEC bytes for data, SI bits per second for data
+/// rates and a bare SI suffix otherwise
+pub fn human(value: f64, unit: Option<&str>) -> String {
+    match unit.and_then(unit::lookup) {
+        Some((Dimension::Time, factor)) => duration(value * factor),
+        Some((Dimension::Data, factor)) => format!("{}B", scaled(value * factor / 8.0, true)),
+        Some((Dimension::DataRate, factor)) => format!("{}bps", scaled(value * factor, false)),
+        None => scaled(value, false),
+    }
+}
+
+/// A value of a table, with a SI suffix with --human
+pub fn value(value: &f64) -> String {
+    match building_human() {
+        true => human(*value, None),
+        false => value.to_string(),
+    }
+}
+
+/// Milliseconds of a table, as a duration with --human
+pub fn millis(ms: &i64) -> String {
+    match building_human() {
+        true => duration(*ms as f64 / 1000.0),
+        false => ms.to_string(),
+    }
+}
+
+/// Milliseconds of a table that are how long something lasted, always as a
+/// duration, ex: 2m 13s
+pub fn elapsed(ms: &i64) -> String {
+    duration(*ms as f64 / 1000.0)
+}
+
+/// Like `tabled::derive::display::option`, for how long something lasted
+pub fn option_elapsed(ms: &Option<i64>, default: impl Display) -> String {
+    match ms {
+        Some(ms) => elapsed(ms),
+        None => default.to_string(),
+    }
+}
+
+/// Like `tabled::derive::display::option`, for milliseconds
+pub fn option_millis(ms: &Option<i64>, default: impl Display) -> String {
+    match ms {
+        Some(ms) => millis(ms),
+        None => default.to_string(),
+    }
+}
+
+/// Like `tabled::derive::display::option`, for a size in bytes, with an IEC
+/// suffix with --human
+pub fn option_bytes(bytes: &Option<i64>, default: impl Display) -> String {
+    match bytes {
+        Some(bytes) if building_human() => human(*bytes as f64, Some("B")),
+        Some(bytes) => bytes.to_string(),
+        None => default.to_string(),
+    }
+}
+
+/// Rewrites the value column of a metric query's rows for people to read,
+/// in the unit values were converted to or else the one their metric type
+/// names, if it is one, ex: Gbps
+pub fn humanize(header: &[String], rows: &mut [Vec<String>], unit: Option<&str>) {
+    let Some(value_idx) = header.iter().position(|c| c == "value") else {
+        return;
+    };
+    let metric_type_idx = header.iter().position(|c| c == "metric_type");
+    for row in rows {
+        let Ok(value) = row[value_idx].parse::<f64>() else {
+            continue;
+        };
+        let unit = unit.or(metric_type_idx.map(|i| row[i].as_str()));
+        row[value_idx] = human(value, unit);
+    }
+}
+
+/// Colors the cells the highlights match, when writing to a terminal that
+/// doesn't ask for NO_COLOR
+fn highlight(table: &mut Table, highlights: &[Highlight]) {
+    if highlights.is_empty()
+        || !io::stdout().is_terminal()
+        || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+    {
+        return;
+    }
+    let records = table.get_records();
+    let Some(header) = records.first() else {
+        return;
+    };
+    let mut colored = vec![];
+    for highlight in highlights.iter() {
+        let Some(col) = header
+            .iter()
+            .position(|cell| cell.as_ref() == highlight.column)
+        else {
+            continue;
+        };
+        for (row, cells) in records.iter().enumerate().skip(1) {
+            if highlight.matches(cells[col].as_ref()) {
+                colored.push(((row, col), highlight.color.clone()));
+            }
+        }
+    }
+    for (position, color) in colored {
+        table.modify(position, color);
+    }
+}
+
+/// Cuts the table's UUIDs short, to the same length for all of them and long
+/// enough that no two share it, like git shortens commit hashes. UUIDv7s
+/// begin with their time so those made together need more of it
+fn shorten_ids(table: &mut Table) {
+    let is_id = |cell: &str| cell.len() == 36 && Uuid::parse_str(cell).is_ok();
+    let ids: HashSet<String> = table
+        .get_records()
+        .iter()
+        .flatten()
+        .map(|cell| cell.as_ref().to_string())
+        .filter(|cell| is_id(cell))
+        .collect();
+    if ids.is_empty() {
+        return;
+    }
+    let len = (SHORT_ID_LEN..36)
+        .find(|len| {
+            ids.iter()
+                .map(|id| &id[..*len])
+                .collect::<HashSet<_>>()
+                .len()
+                == ids.len()
+        })
+        .unwrap_or(36);
+    table.modify(
+        Segment::all(),
+        Format::content(move |cell| match is_id(cell) {
+            true => cell[..len].to_string(),
+            false => cell.to_string(),
+        }),
+    );
+}
+
+/// Draws the table in the table style of the options, its cells no wider
+/// than the max column width and its UUIDs short unless --full-ids was given
+pub fn style(table: &mut Table, opts: &DisplayOpts) {
+    if !opts.full_ids.unwrap_or(false) {
+        shorten_ids(table);
+    }
+    highlight(table, &opts.highlight);
+    if let Some(width) = opts.max_col_width {
+        match opts.wrap {
+            true => table.modify(Segment::all(), Width::wrap(width)),
+            false => table.modify(Segment::all(), Width::truncate(width).suffix("…")),
+        };
+    }
+    match opts.table_style.unwrap_or(TableStyle::Modern) {
+        TableStyle::Modern => table.with(Style::modern()),
+        TableStyle::Rounded => table.with(Style::rounded()),
+        TableStyle::Ascii => table.with(Style::ascii()),
+        TableStyle::Psql => table.with(Style::psql()),
+        TableStyle::Markdown => table.with(Style::markdown()),
+        TableStyle::Blank => table.with(Style::blank()),
+    };
+}
+
+/// A table of the rows, their timestamps and values shown as the options say
+/// and the table styled by them
+pub fn table<T: Tabled>(rows: impl IntoIterator<Item = T>, opts: &DisplayOpts) -> Table {
+    let lent = BUILDING.replace(opts.clone());
+    let mut table = Table::new(rows);
+    BUILDING.set(lent);
+    style(&mut table, opts);
+    table
+}
@@ -0,0 +1,246 @@
+use crate::args::{QueryArgs, QueryCommand, ReplArgs};
+use crate::owner::Actor;
+use crate::{query, uuid_prefix};
+use anyhow::Result;
+use clap::{CommandFactory, Parser};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ReplError {
+    #[error("Couldn't start the line editor, {0}")]
+    EditorFailed(String),
+    #[error("Couldn't read the line, {0}")]
+    ReadFailed(String),
+}
+
+const HELP: &str = r#"Commands are those of `scdm query`, with or without the `query`:
+    get run --benchmark fio
+    metric --metric-type iops --breakout tag:kernel
+\set NAME VALUE  add --NAME VALUE to the commands that take it
+\unset NAME      stop adding --NAME
+\set             show what's set
+\refresh         reload the completions from the database
+\q               quit"#;
+
+/// A line of the repl, parsed as `scdm query` parses its arguments
+#[derive(Parser)]
+#[command(no_binary_name = true, name = "scdm>")]
+struct ReplLine {
+    #[clap(subcommand)]
+    command: QueryCommand,
+}
+
+/// Completes subcommands, options, UUIDs and metric types
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ReplHelper {
+    words: BTreeSet<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        let candidates = self
+            .words
+            .range(prefix.to_string()..)
+            .take_while(|word| word.starts_with(prefix))
+            .cloned()
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+/// The subcommand names and long options of the line's commands
+fn command_words(command: &clap::Command, words: &mut BTreeSet<String>) {
+    for arg in command.get_arguments() {
+        if let Some(long) = arg.get_long() {
+            words.insert(format!("--{}", long));
+        }
+    }
+    for subcommand in command.get_subcommands() {
+        words.insert(subcommand.get_name().to_string());
+        command_words(subcommand, words);
+    }
+}
+
+async fn completions(pool: &PgPool) -> Result<BTreeSet<String>> {
+    let raw_query: &str = r#"
+        SELECT run_uuid::text FROM run
+        UNION SELECT iteration_uuid::text FROM iteration
+        UNION SELECT DISTINCT metric_type FROM metric_desc
+    "#;
+    let mut words: BTreeSet<String> = sqlx::query_scalar(raw_query)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+    command_words(&ReplLine::command(), &mut words);
+    Ok(words)
+}
+
+/// $XDG_STATE_HOME/scdm/history, or ~/.local/state/scdm/history
+fn history_path() -> Option<PathBuf> {
+    let state = match env::var_os("XDG_STATE_HOME") {
+        Some(state) if !state.is_empty() => PathBuf::from(state),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("scdm").join("history"))
+}
+
+/// Adds each `\set` default after the deepest command of the line that takes
+/// it, unless the line already gives it
+fn with_defaults(words: &mut Vec<String>, defaults: &BTreeMap<String, String>) {
+    let mut path = vec![(0, ReplLine::command())];
+    for (i, word) in words.iter().enumerate() {
+        if word.starts_with('-') {
+            continue;
+        }
+        let Some(subcommand) = path.last().unwrap().1.find_subcommand(word).cloned() else {
+            continue;
+        };
+        path.push((i + 1, subcommand));
+    }
+    let mut inserts = vec![];
+    for (name, value) in defaults {
+        let found = path.iter().rev().find_map(|(at, command)| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name.as_str()))
+                .map(|arg| (*at, arg.get_short()))
+        });
+        let Some((at, short)) = found else {
+            continue;
+        };
+        let given = words.iter().any(|word| {
+            word == &format!("--{}", name)
+                || word.starts_with(&format!("--{}=", name))
+                || short.is_some_and(|short| word.starts_with(&format!("-{}", short)))
+        });
+        if !given {
+            inserts.push((at, name, value));
+        }
+    }
+    inserts.sort_by_key(|(at, _, _)| std::cmp::Reverse(*at));
+    for (at, name, value) in inserts {
+        words.splice(at..at, [format!("--{}", name), value.clone()]);
+    }
+}
+
+async fn run_line(
+    pool: &PgPool,
+    mut words: Vec<String>,
+    defaults: &BTreeMap<String, String>,
+    actor: &Actor,
+) -> Result<()> {
+    if words.first().is_some_and(|word| word == "query") {
+        words.remove(0);
+    }
+    with_defaults(&mut words, defaults);
+    let uuid_args = uuid_prefix::uuid_args(&ReplLine::command(), &words, 0);
+    let words = uuid_prefix::resolve(pool, &words, &uuid_args).await?;
+    let line = match ReplLine::try_parse_from(words) {
+        Ok(line) => line,
+        Err(e) => {
+            let _ = e.print();
+            return Ok(());
+        }
+    };
+    let args = QueryArgs {
+        command: line.command,
+    };
+    query::query(pool, args, actor).await
+}
+
+/// Reads `scdm query` commands until \q or the end of the input, so an
+/// analysis session connects once
+pub async fn repl(pool: &PgPool, args: ReplArgs, actor: &Actor) -> Result<()> {
+    let mut editor: Editor<ReplHelper, DefaultHistory> =
+        Editor::new().map_err(|e| ReplError::EditorFailed(e.to_string()))?;
+    editor.set_helper(Some(ReplHelper {
+        words: completions(pool).await?,
+    }));
+    let history = if args.no_history {
+        None
+    } else {
+        history_path()
+    };
+    if let Some(history) = &history {
+        // There's no history yet the first time
+        let _ = editor.load_history(history);
+    }
+
+    let mut defaults: BTreeMap<String, String> = BTreeMap::new();
+    loop {
+        let line = match tokio::task::block_in_place(|| editor.readline("scdm> ")) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(ReplError::ReadFailed(e.to_string()).into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        // shlex would take the backslash of \set for an escape
+        if line.starts_with('\\') || matches!(line, "quit" | "exit" | "help") {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["\\q"] | ["quit"] | ["exit"] => break,
+                ["\\?"] | ["help"] => println!("{}", HELP),
+                ["\\set"] => {
+                    for (name, value) in &defaults {
+                        println!("{} = {}", name, value);
+                    }
+                }
+                ["\\set", name, value] => {
+                    defaults.insert(name.trim_start_matches('-').to_string(), value.to_string());
+                }
+                ["\\unset", name] => {
+                    defaults.remove(name.trim_start_matches('-'));
+                }
+                ["\\refresh"] => match completions(pool).await {
+                    Ok(words) => editor.set_helper(Some(ReplHelper { words })),
+                    Err(e) => eprintln!("Error: {:#}", e),
+                },
+                ["\\set", ..] | ["\\unset", ..] => {
+                    eprintln!("Usage: \\set NAME VALUE, \\unset NAME")
+                }
+                [command, ..] => eprintln!("Unknown command {}, \\? lists them", command),
+                [] => {}
+            }
+            continue;
+        }
+        let Some(words) = shlex::split(line) else {
+            eprintln!("Unterminated quote");
+            continue;
+        };
+        if let Err(e) = run_line(pool, words, &defaults, actor).await {
+            eprintln!("Error: {:#}", e);
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Some(dir) = history.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(e) = editor.save_history(history) {
+            tracing::warn!("couldn't save the history to {}, {}", history.display(), e);
+        }
+    }
+    Ok(())
+}
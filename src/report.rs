@@ -0,0 +1,504 @@
+use crate::args::{
+    DisplayOpts, GetIterationArgs, GetParamArgs, GetRunArgs, GetTagArgs, ReportArgs,
+};
+use crate::cdm::{Iteration, Param, Run, Tag};
+use crate::query::QueryGet;
+use crate::render;
+use crate::report;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("No run {0}")]
+    NotFound(Uuid),
+    #[error("Couldn't write the report to {0}, {1}")]
+    WriteFailed(String, String),
+}
+
+/// The primary metric of each of the run's iterations, summarized across
+/// samples like mv_iteration_primary_metric. Iterations whose raw data was
+/// downsampled away fall back to their iteration_summary.
+pub(crate) const SQL_PRIMARY_SUMMARIES: &str = r#"
+    WITH desc_values AS (
+        SELECT
+            sample.iteration_uuid,
+            sample.sample_uuid,
+            SUM(metric_data.value * metric_data.duration) / NULLIF(SUM(metric_data.duration), 0) as value
+        FROM iteration
+        JOIN sample
+            ON sample.iteration_uuid = iteration.iteration_uuid
+        JOIN period
+            ON period.sample_uuid = sample.sample_uuid AND period.name = iteration.primary_period
+        JOIN metric_desc
+            ON metric_desc.period_uuid = period.period_uuid AND (
+                metric_desc.metric_type = iteration.primary_metric OR
+                metric_desc.source || '::' || metric_desc.metric_type = iteration.primary_metric
+            )
+        JOIN metric_data
+            ON metric_data.metric_desc_uuid = metric_desc.metric_desc_uuid
+        WHERE iteration.run_uuid = $1
+        GROUP BY sample.iteration_uuid, sample.sample_uuid, metric_desc.metric_desc_uuid
+    ), sample_values AS (
+        SELECT iteration_uuid, sample_uuid, SUM(value) as value
+        FROM desc_values
+        GROUP BY iteration_uuid, sample_uuid
+    ), live AS (
+        SELECT
+            iteration.iteration_uuid,
+            COUNT(sample_values.sample_uuid) as samples,
+            AVG(sample_values.value) as mean,
+            STDDEV(sample_values.value) as stddev,
+            MIN(sample_values.value) as min,
+            MAX(sample_values.value) as max
+        FROM iteration
+        LEFT JOIN sample_values
+            ON sample_values.iteration_uuid = iteration.iteration_uuid
+        WHERE iteration.run_uuid = $1 AND iteration.primary_period <> 'global'
+        GROUP BY iteration.iteration_uuid
+    )
+    SELECT
+        live.iteration_uuid,
+        CASE WHEN live.samples = 0 AND summary.iteration_uuid IS NOT NULL
+            THEN summary.samples ELSE live.samples END,
+        CASE WHEN live.samples = 0 THEN summary.mean ELSE live.mean END,
+        CASE WHEN live.samples = 0 THEN summary.stddev ELSE live.stddev END,
+        CASE WHEN live.samples = 0 THEN summary.min ELSE live.min END,
+        CASE WHEN live.samples = 0 THEN summary.max ELSE live.max END
+    FROM live
+    LEFT JOIN iteration_summary summary
+        ON summary.iteration_uuid = live.iteration_uuid
+"#;
+
+/// The primary metric of each sample over its primary period, summing the
+/// metric_descs that end at the same time
+const SQL_PRIMARY_SERIES: &str = r#"
+    SELECT iteration.iteration_uuid, sample.num, metric_data.finish, SUM(metric_data.value)
+    FROM iteration
+    JOIN sample
+        ON sample.iteration_uuid = iteration.iteration_uuid
+    JOIN period
+        ON period.sample_uuid = sample.sample_uuid AND period.name = iteration.primary_period
+    JOIN metric_desc
+        ON metric_desc.period_uuid = period.period_uuid AND (
+            metric_desc.metric_type = iteration.primary_metric OR
+            metric_desc.source || '::' || metric_desc.metric_type = iteration.primary_metric
+        )
+    JOIN metric_data
+        ON metric_data.metric_desc_uuid = metric_desc.metric_desc_uuid
+    WHERE iteration.run_uuid = $1 AND iteration.primary_period <> 'global'
+    GROUP BY iteration.iteration_uuid, sample.num, metric_data.finish
+    ORDER BY iteration.iteration_uuid, sample.num, metric_data.finish
+"#;
+
+pub(crate) type Summary = (
+    Uuid,
+    i64,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+);
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em auto; max-width: 60em; color: #222; }
+h1 { font-size: 1.5em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+th { background: #f4f4f4; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+svg text { font-size: 11px; fill: #444; }
+.footer { margin-top: 3em; color: #888; font-size: 0.8em; }
+"#;
+
+/// The (finish, value) points of each sample's primary metric, by sample num
+type SampleSeries = BTreeMap<i64, Vec<(DateTime<Utc>, f64)>>;
+
+/// Series colors, repeated when there are more samples
+const COLORS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+];
+
+const CHART_WIDTH: f64 = 720.0;
+const CHART_HEIGHT: f64 = 240.0;
+const MARGIN: f64 = 60.0;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn number(value: Option<f64>) -> String {
+    match value {
+        Some(value) => format!("{:.3}", value),
+        None => "null".to_string(),
+    }
+}
+
+fn table(out: &mut String, header: &[&str], rows: Vec<Vec<String>>) {
+    if rows.is_empty() {
+        out.push_str("<p>None</p>\n");
+        return;
+    }
+    out.push_str("<table>\n");
+    if !header.is_empty() {
+        out.push_str("<tr>");
+        for column in header {
+            let _ = write!(out, "<th>{}</th>", escape(column));
+        }
+        out.push_str("</tr>\n");
+    }
+    for row in rows {
+        out.push_str("<tr>");
+        for value in row {
+            let class = if value.parse::<f64>().is_ok() {
+                " class=\"num\""
+            } else {
+                ""
+            };
+            let _ = write!(out, "<td{}>{}</td>", class, escape(&value));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n");
+}
+
+/// The y axis from 0, or from the minimum when there are negative values
+fn y_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((0.0_f64, 0.0_f64), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if max > min {
+        (min, max)
+    } else {
+        (min, min + 1.0)
+    }
+}
+
+fn y_axis(out: &mut String, min: f64, max: f64) {
+    let _ = write!(
+        out,
+        "<line x1=\"{m}\" y1=\"{t}\" x2=\"{m}\" y2=\"{b}\" stroke=\"#888\"/>\
+         <line x1=\"{m}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#888\"/>\
+         <text x=\"{l}\" y=\"{t}\" text-anchor=\"end\">{max}</text>\
+         <text x=\"{l}\" y=\"{b}\" text-anchor=\"end\">{min}</text>",
+        m = MARGIN,
+        l = MARGIN - 4.0,
+        t = MARGIN / 2.0,
+        b = MARGIN / 2.0 + CHART_HEIGHT,
+        r = MARGIN + CHART_WIDTH,
+        max = number(Some(max)),
+        min = number(Some(min)),
+    );
+}
+
+/// A bar per iteration at its mean, with a line from its min to its max
+fn summary_chart(out: &mut String, bars: &[(i64, f64, Option<f64>, Option<f64>)]) {
+    let (min, max) = y_range(
+        bars.iter()
+            .flat_map(|(_, mean, lo, hi)| [Some(*mean), *lo, *hi])
+            .flatten(),
+    );
+    let y = |v: f64| MARGIN / 2.0 + CHART_HEIGHT * (max - v) / (max - min);
+    let _ = write!(
+        out,
+        "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        CHART_WIDTH + MARGIN * 2.0,
+        CHART_HEIGHT + MARGIN
+    );
+    y_axis(out, min, max);
+    let slot = CHART_WIDTH / bars.len() as f64;
+    for (i, (num, mean, lo, hi)) in bars.iter().enumerate() {
+        let x = MARGIN + slot * i as f64;
+        let (top, bottom) = (y(mean.max(0.0)), y(mean.min(0.0)));
+        let _ = write!(
+            out,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\">\
+             <title>iteration {}: {}</title></rect>",
+            x + slot * 0.15,
+            top,
+            slot * 0.7,
+            bottom - top,
+            COLORS[0],
+            num,
+            number(Some(*mean))
+        );
+        if let (Some(lo), Some(hi)) = (lo, hi) {
+            let _ = write!(
+                out,
+                "<line x1=\"{c:.1}\" y1=\"{:.1}\" x2=\"{c:.1}\" y2=\"{:.1}\" stroke=\"#222\"/>",
+                y(*lo),
+                y(*hi),
+                c = x + slot / 2.0
+            );
+        }
+        let _ = write!(
+            out,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            x + slot / 2.0,
+            MARGIN / 2.0 + CHART_HEIGHT + 14.0,
+            num
+        );
+    }
+    out.push_str("</svg>\n");
+}
+
+/// A line per sample of the value over time since the sample's period began
+fn series_chart(out: &mut String, samples: &SampleSeries) {
+    let (min, max) = y_range(samples.values().flatten().map(|(_, v)| *v));
+    let span = samples
+        .values()
+        .filter_map(|points| Some((points.last()?.0 - points.first()?.0).num_milliseconds()))
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let y = |v: f64| MARGIN / 2.0 + CHART_HEIGHT * (max - v) / (max - min);
+    let _ = write!(
+        out,
+        "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        CHART_WIDTH + MARGIN * 2.0,
+        CHART_HEIGHT + MARGIN
+    );
+    y_axis(out, min, max);
+    for (i, (num, points)) in samples.iter().enumerate() {
+        let Some((start, _)) = points.first() else {
+            continue;
+        };
+        let path: Vec<String> = points
+            .iter()
+            .map(|(t, v)| {
+                let x = MARGIN + CHART_WIDTH * (*t - *start).num_milliseconds() as f64 / span;
+                format!("{:.1},{:.1}", x, y(*v))
+            })
+            .collect();
+        let _ = write!(
+            out,
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\">\
+             <title>sample {}</title></polyline>",
+            path.join(" "),
+            COLORS[i % COLORS.len()],
+            num
+        );
+    }
+    let _ = writeln!(
+        out,
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{:.1}s</text></svg>",
+        MARGIN + CHART_WIDTH,
+        MARGIN / 2.0 + CHART_HEIGHT + 14.0,
+        span / 1000.0
+    );
+}
+
+async fn render_run(pool: &PgPool, run: &Run) -> Result<String> {
+    let tags: Vec<Tag> = GetTagArgs {
+        run_uuid: Some(run.run_uuid),
+        tag: None,
+        deleted: false,
+    }
+    .query_get(pool)
+    .await?;
+    let mut iterations: Vec<Iteration> = GetIterationArgs {
+        iteration_uuid: None,
+        run_uuid: Some(run.run_uuid),
+        num: None,
+        status: None,
+        deleted: false,
+    }
+    .query_get(pool)
+    .await?;
+    iterations.sort_by_key(|iteration| iteration.num);
+    let summaries: BTreeMap<Uuid, Summary> = sqlx::query_as(SQL_PRIMARY_SUMMARIES)
+        .bind(run.run_uuid)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|summary: Summary| (summary.0, summary))
+        .collect();
+    let mut series: BTreeMap<Uuid, SampleSeries> = BTreeMap::new();
+    let points: Vec<(Uuid, i64, DateTime<Utc>, f64)> = sqlx::query_as(SQL_PRIMARY_SERIES)
+        .bind(run.run_uuid)
+        .fetch_all(pool)
+        .await?;
+    for (iteration_uuid, sample, finish, value) in points {
+        series
+            .entry(iteration_uuid)
+            .or_default()
+            .entry(sample)
+            .or_default()
+            .push((finish, value));
+    }
+
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{} {}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        escape(&run.benchmark),
+        run.run_uuid,
+        STYLE
+    );
+    let _ = writeln!(
+        out,
+        "<h1>{} run {}</h1>",
+        escape(&run.benchmark),
+        run.run_uuid
+    );
+    table(
+        &mut out,
+        &[],
+        vec![
+            vec!["run_uuid".to_string(), run.run_uuid.to_string()],
+            vec!["benchmark".to_string(), run.benchmark.clone()],
+            vec!["name".to_string(), run.name.clone()],
+            vec!["email".to_string(), run.email.clone()],
+            vec!["source".to_string(), run.source.clone()],
+            vec![
+                "description".to_string(),
+                run.description.clone().unwrap_or_default(),
+            ],
+            vec![
+                "begin".to_string(),
+                render::format_time(&run.begin, &DisplayOpts::default()),
+            ],
+            vec![
+                "finish".to_string(),
+                render::format_time(&run.finish, &DisplayOpts::default()),
+            ],
+            vec![
+                "duration".to_string(),
+                format!(
+                    "{:.1}s",
+                    (run.finish - run.begin).num_milliseconds() as f64 / 1000.0
+                ),
+            ],
+        ],
+    );
+
+    out.push_str("<h2>Tags</h2>\n");
+    table(
+        &mut out,
+        &["name", "value"],
+        tags.into_iter()
+            .map(|tag| vec![tag.name, tag.val])
+            .collect(),
+    );
+
+    out.push_str("<h2>Primary metrics</h2>\n");
+    let mut rows = vec![];
+    let mut bars = vec![];
+    for iteration in &iterations {
+        let Some((_, samples, mean, stddev, min, max)) = summaries.get(&iteration.iteration_uuid)
+        else {
+            continue;
+        };
+        if let Some(mean) = mean {
+            bars.push((iteration.num, *mean, *min, *max));
+        }
+        rows.push(vec![
+            iteration.num.to_string(),
+            iteration.status.clone().unwrap_or_default(),
+            iteration.primary_metric.clone().unwrap_or_default(),
+            iteration.primary_period.clone().unwrap_or_default(),
+            samples.to_string(),
+            number(*mean),
+            number(*stddev),
+            number(*min),
+            number(*max),
+        ]);
+    }
+    table(
+        &mut out,
+        &[
+            "iteration",
+            "status",
+            "metric",
+            "period",
+            "samples",
+            "mean",
+            "stddev",
+            "min",
+            "max",
+        ],
+        rows,
+    );
+    if !bars.is_empty() {
+        summary_chart(&mut out, &bars);
+    }
+
+    for iteration in &iterations {
+        let _ = writeln!(
+            out,
+            "<h2>Iteration {}</h2>\n<p>{}</p>",
+            iteration.num, iteration.iteration_uuid
+        );
+        let params: Vec<Param> = GetParamArgs {
+            iteration_uuid: Some(iteration.iteration_uuid),
+            arg: None,
+            val: None,
+            deleted: false,
+        }
+        .query_get(pool)
+        .await?;
+        table(
+            &mut out,
+            &["param", "value"],
+            params
+                .into_iter()
+                .map(|param| vec![param.arg, param.val])
+                .collect(),
+        );
+        if let Some(samples) = series.get(&iteration.iteration_uuid) {
+            let _ = writeln!(
+                out,
+                "<p>{} in the {} period of each sample</p>",
+                escape(&iteration.primary_metric.clone().unwrap_or_default()),
+                escape(&iteration.primary_period.clone().unwrap_or_default())
+            );
+            series_chart(&mut out, samples);
+        }
+    }
+
+    let _ = write!(
+        out,
+        "<p class=\"footer\">Generated by scdm {} at {}</p>\n</body>\n</html>\n",
+        env!("CARGO_PKG_VERSION"),
+        render::format_time(&Utc::now(), &DisplayOpts::default())
+    );
+    Ok(out)
+}
+
+/// Writes a self-contained HTML report of the run, with its charts inline
+pub async fn report(pool: &PgPool, args: ReportArgs) -> Result<()> {
+    let run = GetRunArgs {
+        run_uuid: Some(args.run_uuid),
+        tag: None,
+        begin_before: None,
+        begin_after: None,
+        finish_before: None,
+        finish_after: None,
+        benchmark: None,
+        email: None,
+        name: None,
+        source: None,
+        deleted: false,
+        min_duration: None,
+        max_duration: None,
+    }
+    .query_get(pool)
+    .await?
+    .pop()
+    .ok_or(ReportError::NotFound(args.run_uuid))?;
+    let html = render_run(pool, &run).await?;
+    fs::write(&args.out, html)
+        .map_err(|e| ReportError::WriteFailed(args.out.clone(), e.to_string()))?;
+    report!("wrote the report of run {} to {}", run.run_uuid, args.out);
+    Ok(())
+}
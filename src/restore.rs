@@ -0,0 +1,107 @@
+use crate::args::RestoreArgs;
+use crate::audit;
+use crate::owner::{self, Actor};
+use crate::parser::{self, BodyJson, IngestOpts};
+use crate::report;
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use std::fs::File;
+use std::io::BufReader;
+use thiserror::Error;
+use tracing::warn;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum RestoreError {
+    #[error("Couldn't restore the runs, {0}")]
+    RestoreFailed(String),
+    #[error("Couldn't read the backup {0}, {1}")]
+    ReadFailed(String, String),
+}
+
+/// Ingests one run's documents like `scdm parse` would, unless the run is
+/// already in the database
+async fn restore_run(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: &RestoreArgs,
+    filters: &Value,
+    mut records: Vec<BodyJson>,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let Some(BodyJson::Run(run)) = records.first() else {
+        return Ok(0);
+    };
+    let run_uuid = run.run.run_uuid;
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM run WHERE run_uuid = $1)")
+        .bind(run_uuid)
+        .fetch_one(pool)
+        .await?;
+    if exists {
+        warn!("skipping run {}, it already exists", run_uuid);
+        return Ok(0);
+    }
+    parser::normalize_statuses(parser::document_statuses(&mut records), args.status.mode())?;
+    parser::ingest(pool, replica, &records, "restore", filters, opts).await
+}
+
+/// Loads the runs in a backup, each in its own transaction
+async fn restore_backup(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: &RestoreArgs,
+    path: &str,
+    opts: &IngestOpts,
+) -> Result<()> {
+    let f = File::open(path)
+        .map_err(|e| RestoreError::ReadFailed(path.to_string(), format!("{}", e)))?;
+    let reader = BufReader::new(GzDecoder::new(f));
+    let filters = json!({ "file": path });
+
+    let mut total_records = 0;
+    let mut records = Vec::new();
+    // Each run's documents follow its run document
+    for record in parser::ndjson_documents(reader) {
+        let record = record?;
+        if matches!(record, BodyJson::Run(_)) {
+            let run = std::mem::take(&mut records);
+            total_records += restore_run(pool, replica, args, &filters, run, opts).await?;
+        }
+        records.push(record);
+    }
+    total_records += restore_run(pool, replica, args, &filters, records, opts).await?;
+
+    report!("added {} rows", total_records);
+    Ok(())
+}
+
+/// Brings back soft deleted runs, or loads the runs in a backup
+pub async fn restore(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: RestoreArgs,
+    opts: &IngestOpts,
+    actor: &Actor,
+) -> Result<()> {
+    if let Some(path) = &args.file {
+        return restore_backup(pool, replica, &args, path, opts).await;
+    }
+
+    let rerr = |e: sqlx::Error| RestoreError::RestoreFailed(format!("{}", e));
+    let mut txn = pool.begin().await.map_err(rerr)?;
+    let restored: Vec<(Uuid, Option<String>)> = sqlx::query_as(
+        "UPDATE run SET deleted_at = NULL WHERE run_uuid = ANY($1) AND deleted_at IS NOT NULL
+        RETURNING run_uuid, owner",
+    )
+    .bind(&args.run_uuid)
+    .fetch_all(&mut *txn)
+    .await
+    .map_err(rerr)?;
+    owner::check(actor, "restore", &restored)?;
+    audit::record(&mut *txn, "restore", &args, restored.len() as u64).await?;
+    txn.commit().await.map_err(rerr)?;
+    report!("restored {} runs", restored.len());
+    Ok(())
+}
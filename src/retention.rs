@@ -0,0 +1,238 @@
+use crate::args::{RetentionArgs, RetentionCommand, RetentionSetArgs};
+use crate::report;
+use crate::{audit, rollup};
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+/// The width of the rollups kept once the raw data is gone
+pub const DOWNSAMPLE_INTERVAL_MS: i64 = 60 * 1000;
+
+#[derive(Error, Debug)]
+pub enum RetentionError {
+    #[error("Rollups have to be kept at least as long as the raw data")]
+    RollupShorterThanRaw,
+    #[error("Couldn't apply the retention policy, {0}")]
+    ApplyFailed(String),
+}
+
+fn rerr<T: std::error::Error>(err: T) -> RetentionError {
+    RetentionError::ApplyFailed(err.to_string())
+}
+
+/// The primary metric of a run's iterations, computed the same way as
+/// mv_iteration_primary_metric
+const SQL_SUMMARIZE_ITERATIONS: &str = r#"
+    INSERT INTO iteration_summary
+        (iteration_uuid, primary_metric, primary_period, samples, mean, stddev, min, max)
+    WITH desc_values AS (
+        SELECT
+            sample.iteration_uuid,
+            sample.sample_uuid,
+            SUM(metric_data.value * metric_data.duration) / NULLIF(SUM(metric_data.duration), 0) as value
+        FROM iteration
+        JOIN sample
+            ON sample.iteration_uuid = iteration.iteration_uuid
+        JOIN period
+            ON period.sample_uuid = sample.sample_uuid AND period.name = iteration.primary_period
+        JOIN metric_desc
+            ON metric_desc.period_uuid = period.period_uuid AND (
+                metric_desc.metric_type = iteration.primary_metric OR
+                metric_desc.source || '::' || metric_desc.metric_type = iteration.primary_metric
+            )
+        JOIN metric_data
+            ON metric_data.metric_desc_uuid = metric_desc.metric_desc_uuid
+        WHERE iteration.run_uuid = $1
+        GROUP BY sample.iteration_uuid, sample.sample_uuid, metric_desc.metric_desc_uuid
+    ), sample_values AS (
+        SELECT iteration_uuid, sample_uuid, SUM(value) as value
+        FROM desc_values
+        GROUP BY iteration_uuid, sample_uuid
+    )
+    SELECT
+        iteration.iteration_uuid,
+        iteration.primary_metric,
+        iteration.primary_period,
+        COUNT(sample_values.sample_uuid),
+        AVG(sample_values.value),
+        STDDEV(sample_values.value),
+        MIN(sample_values.value),
+        MAX(sample_values.value)
+    FROM iteration
+    LEFT JOIN sample_values
+        ON sample_values.iteration_uuid = iteration.iteration_uuid
+    WHERE iteration.run_uuid = $1 AND iteration.primary_period <> 'global'
+    GROUP BY iteration.iteration_uuid
+    ON CONFLICT (iteration_uuid) DO NOTHING
+"#;
+
+/// The metric_descs of a run
+const SQL_RUN_METRIC_DESCS: &str = r#"
+    SELECT metric_desc.metric_desc_uuid
+    FROM metric_desc
+        JOIN period USING (period_uuid)
+        JOIN sample USING (sample_uuid)
+        JOIN iteration USING (iteration_uuid)
+    WHERE iteration.run_uuid = $1
+"#;
+
+async fn set(pool: &PgPool, args: RetentionSetArgs) -> Result<()> {
+    if args.rollup < args.raw {
+        return Err(RetentionError::RollupShorterThanRaw.into());
+    }
+    let raw_query: &str = r#"
+        INSERT INTO retention_tiers (raw_for, rollup_for)
+        VALUES ($1 * INTERVAL '1 millisecond', $2 * INTERVAL '1 millisecond')
+        ON CONFLICT (only_row) DO UPDATE SET
+            raw_for = EXCLUDED.raw_for,
+            rollup_for = EXCLUDED.rollup_for,
+            updated_at = now()
+    "#;
+    let mut txn = pool.begin().await?;
+    let res = sqlx::query(raw_query)
+        .bind(args.raw)
+        .bind(args.rollup)
+        .execute(&mut *txn)
+        .await?;
+    audit::record(&mut *txn, "retention set", &args, res.rows_affected()).await?;
+    txn.commit().await?;
+    show(pool).await
+}
+
+async fn show(pool: &PgPool) -> Result<()> {
+    let tiers: Option<(String, String)> = sqlx::query_as(
+        "SELECT justify_hours(raw_for)::text, justify_hours(rollup_for)::text FROM retention_tiers",
+    )
+    .fetch_optional(pool)
+    .await?;
+    match tiers {
+        Some((raw_for, rollup_for)) => {
+            println!("raw metric_data: {}", raw_for);
+            println!("1 minute rollups: {}", rollup_for);
+            println!("iteration summaries: forever");
+        }
+        None => println!("no retention policy, data is kept until it's pruned"),
+    }
+    Ok(())
+}
+
+async fn clear(pool: &PgPool) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    let res = sqlx::query("DELETE FROM retention_tiers")
+        .execute(&mut *txn)
+        .await?;
+    audit::record(
+        &mut *txn,
+        "retention clear",
+        &Value::Null,
+        res.rows_affected(),
+    )
+    .await?;
+    txn.commit().await?;
+    report!("cleared the retention policy");
+    Ok(())
+}
+
+/// Downsamples the runs that have outlived the raw tier to 1 minute rollups
+/// and iteration summaries, then drops the rollups of the runs that have
+/// outlived the rollup tier
+pub async fn apply(pool: &PgPool) -> Result<()> {
+    let has_policy: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM retention_tiers)")
+        .fetch_one(pool)
+        .await
+        .map_err(rerr)?;
+    if !has_policy {
+        return Ok(());
+    }
+
+    let raw_query: &str = r#"
+        SELECT run.run_uuid FROM run, retention_tiers
+        WHERE run.finish < now() - retention_tiers.raw_for AND EXISTS (
+            SELECT 1 FROM metric_data
+            WHERE metric_data.metric_desc_uuid IN (
+                SELECT metric_desc.metric_desc_uuid
+                FROM metric_desc
+                    JOIN period USING (period_uuid)
+                    JOIN sample USING (sample_uuid)
+                    JOIN iteration USING (iteration_uuid)
+                WHERE iteration.run_uuid = run.run_uuid
+            )
+        )
+        ORDER BY run.finish
+    "#;
+    let expired_raw: Vec<Uuid> = sqlx::query_scalar(raw_query)
+        .fetch_all(pool)
+        .await
+        .map_err(rerr)?;
+    // Each run is downsampled on its own, so an interrupted prune loses no data
+    let mut downsampled_rows = 0;
+    for run_uuid in &expired_raw {
+        let mut txn = pool.begin().await?;
+        sqlx::query(rollup::SQL_ROLLUP)
+            .bind(DOWNSAMPLE_INTERVAL_MS)
+            .bind(run_uuid)
+            .execute(&mut *txn)
+            .await
+            .map_err(rerr)?;
+        sqlx::query(SQL_SUMMARIZE_ITERATIONS)
+            .bind(run_uuid)
+            .execute(&mut *txn)
+            .await
+            .map_err(rerr)?;
+        let res = sqlx::query(&format!(
+            "DELETE FROM metric_data WHERE metric_desc_uuid IN ({})",
+            SQL_RUN_METRIC_DESCS
+        ))
+        .bind(run_uuid)
+        .execute(&mut *txn)
+        .await
+        .map_err(rerr)?;
+        txn.commit().await?;
+        downsampled_rows += res.rows_affected();
+        info!("downsampled run {}", run_uuid);
+    }
+
+    let raw_query: &str = r#"
+        DELETE FROM metric_data_rollup
+        WHERE metric_desc_uuid IN (
+            SELECT metric_desc.metric_desc_uuid
+            FROM metric_desc
+                JOIN period USING (period_uuid)
+                JOIN sample USING (sample_uuid)
+                JOIN iteration USING (iteration_uuid)
+                JOIN run USING (run_uuid)
+                CROSS JOIN retention_tiers
+            WHERE run.finish < now() - retention_tiers.rollup_for
+        )
+    "#;
+    let mut txn = pool.begin().await?;
+    let res = sqlx::query(raw_query)
+        .execute(&mut *txn)
+        .await
+        .map_err(rerr)?;
+    audit::record(
+        &mut *txn,
+        "prune retention",
+        &serde_json::json!({ "downsampled_runs": expired_raw.len() }),
+        downsampled_rows + res.rows_affected(),
+    )
+    .await?;
+    txn.commit().await?;
+    report!(
+        "removed {} raw metric_data rows and {} expired rollups",
+        downsampled_rows,
+        res.rows_affected()
+    );
+    Ok(())
+}
+
+pub async fn retention(pool: &PgPool, args: RetentionArgs) -> Result<()> {
+    match args.command {
+        RetentionCommand::Set(set_args) => set(pool, set_args).await,
+        RetentionCommand::Show => show(pool).await,
+        RetentionCommand::Clear => clear(pool).await,
+    }
+}
@@ -0,0 +1,154 @@
+use crate::args::RollupArgs;
+use crate::report;
+use anyhow::Result;
+use sqlx::{PgConnection, PgPool};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum RollupError {
+    #[error("Couldn't roll up the metric data, {0}")]
+    RollupFailed(String),
+}
+
+/// Buckets every metric_data row by `interval_ms` (aligned to the Unix epoch,
+/// keyed on the data's begin) and upserts per-descriptor aggregates.
+pub const SQL_ROLLUP: &str = r#"
+    INSERT INTO metric_data_rollup
+        (metric_desc_uuid, interval_ms, begin, finish, min, max, avg, sum, sum_sq, count)
+    SELECT
+        bucketed.metric_desc_uuid,
+        $1,
+        bucketed.bucket,
+        bucketed.bucket + ($1 * INTERVAL '1 millisecond'),
+        MIN(bucketed.value),
+        MAX(bucketed.value),
+        AVG(bucketed.value),
+        SUM(bucketed.value),
+        SUM(bucketed.value * bucketed.value),
+        COUNT(*)
+    FROM
+        (SELECT
+            metric_data.metric_desc_uuid,
+            metric_data.value,
+            date_bin($1 * INTERVAL '1 millisecond', metric_data.begin, 'epoch'::timestamptz) as bucket
+        FROM metric_data
+        LEFT JOIN metric_desc
+            ON metric_desc.metric_desc_uuid = metric_data.metric_desc_uuid
+        LEFT JOIN period
+            ON period.period_uuid = metric_desc.period_uuid
+        LEFT JOIN sample
+            ON sample.sample_uuid = period.sample_uuid
+        LEFT JOIN iteration
+            ON iteration.iteration_uuid = sample.iteration_uuid
+        WHERE
+            ($2::uuid IS NULL OR iteration.run_uuid = $2)
+        ) as bucketed
+    GROUP BY bucketed.metric_desc_uuid, bucketed.bucket
+    ON CONFLICT (metric_desc_uuid, interval_ms, begin) DO UPDATE SET
+        finish = EXCLUDED.finish,
+        min = EXCLUDED.min,
+        max = EXCLUDED.max,
+        avg = EXCLUDED.avg,
+        sum = EXCLUDED.sum,
+        sum_sq = EXCLUDED.sum_sq,
+        count = EXCLUDED.count
+"#;
+
+/// Finds the coarsest rollup interval that still fits at least ten buckets
+/// into a window of `window_ms` and covers all of the ingested metric data.
+pub async fn find_interval(pool: &PgPool, window_ms: i64) -> Result<Option<i64>, RollupError> {
+    let raw_query: &str = r#"
+        SELECT interval_ms FROM metric_data_rollup_state
+        WHERE
+            interval_ms * 10 <= $1 AND
+            max_metric_data_id >= (SELECT COALESCE(MAX(metric_data_id), 0) FROM metric_data)
+        ORDER BY interval_ms DESC
+        LIMIT 1
+    "#;
+    sqlx::query_scalar(raw_query)
+        .bind(window_ms)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| RollupError::RollupFailed(format!("{}", e)))
+}
+
+/// Rolls up the runs of the metric_descs again at each interval they're
+/// rolled up at, after some of their data was deleted. Their buckets still
+/// hold data, so each one is overwritten. Call on the transaction of the
+/// delete, so the rollups never disagree with the data
+pub async fn refresh(
+    conn: &mut PgConnection,
+    metric_desc_uuids: &[Uuid],
+) -> Result<u64, RollupError> {
+    let intervals: Vec<i64> = sqlx::query_scalar(
+        "SELECT DISTINCT interval_ms FROM metric_data_rollup WHERE metric_desc_uuid = ANY($1)",
+    )
+    .bind(metric_desc_uuids)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| RollupError::RollupFailed(format!("{}", e)))?;
+    if intervals.is_empty() {
+        return Ok(0);
+    }
+    let run_uuids: Vec<Uuid> = sqlx::query_scalar(
+        "SELECT DISTINCT iteration.run_uuid
+        FROM metric_desc
+            JOIN period USING (period_uuid)
+            JOIN sample USING (sample_uuid)
+            JOIN iteration USING (iteration_uuid)
+        WHERE metric_desc.metric_desc_uuid = ANY($1)",
+    )
+    .bind(metric_desc_uuids)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| RollupError::RollupFailed(format!("{}", e)))?;
+    let mut rows = 0;
+    for interval_ms in &intervals {
+        for run_uuid in &run_uuids {
+            rows += sqlx::query(SQL_ROLLUP)
+                .bind(interval_ms)
+                .bind(run_uuid)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| RollupError::RollupFailed(format!("{}", e)))?
+                .rows_affected();
+        }
+    }
+    Ok(rows)
+}
+
+pub async fn rollup(pool: &PgPool, args: RollupArgs) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    let max_metric_data_id: i64 =
+        sqlx::query_scalar("SELECT COALESCE(MAX(metric_data_id), 0) FROM metric_data")
+            .fetch_one(&mut *txn)
+            .await
+            .map_err(|e| RollupError::RollupFailed(format!("{}", e)))?;
+    let res = sqlx::query(SQL_ROLLUP)
+        .bind(args.interval)
+        .bind(args.run_uuid)
+        .execute(&mut *txn)
+        .await
+        .map_err(|e| RollupError::RollupFailed(format!("{}", e)))?;
+    // Rolling up a single run doesn't make the interval complete
+    if args.run_uuid.is_none() {
+        sqlx::query(
+            r#"
+            INSERT INTO metric_data_rollup_state (interval_ms, max_metric_data_id, rolled_up_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT (interval_ms) DO UPDATE SET
+                max_metric_data_id = EXCLUDED.max_metric_data_id,
+                rolled_up_at = EXCLUDED.rolled_up_at
+            "#,
+        )
+        .bind(args.interval)
+        .bind(max_metric_data_id)
+        .execute(&mut *txn)
+        .await
+        .map_err(|e| RollupError::RollupFailed(format!("{}", e)))?;
+    }
+    txn.commit().await?;
+    report!("rolled up {} rows", res.rows_affected());
+    Ok(())
+}
@@ -0,0 +1,366 @@
+use crate::args::{DeleteCommand, GetCommand, MetricArgs, OutputFormat, ServeArgs, StatusMode};
+use crate::owner::Actor;
+use crate::parser::{self, BodyJson, IngestOpts};
+use crate::query::{self, QueryDelete, QueryError, QueryGet};
+use crate::{grafana, metric, prometheus};
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use clap::Parser;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Duration;
+use tabled::Tabled;
+use thiserror::Error;
+use tracing::{info, warn};
+
+#[derive(Error, Debug)]
+pub enum ServeError {
+    #[error("Couldn't listen on {0}, {1}")]
+    BindFailed(String, String),
+    #[error("The server stopped, {0}")]
+    ServeFailed(String),
+}
+
+/// The largest push `scdm agent` can make, axum's default of 2MB is too
+/// small for the data points of a run
+const MAX_PUSH_BYTES: usize = 1 << 30;
+
+#[derive(Clone)]
+pub(crate) struct ServeState {
+    pub pool: PgPool,
+    /// Where pushed results are also written, like every other ingest's
+    replica: Option<PgPool>,
+    token: Option<String>,
+    owner_tokens: Vec<(String, String)>,
+    actor: Actor,
+    pub data_points: prometheus::DataPoints,
+    ingest_opts: IngestOpts,
+}
+
+/// A failed request, sent back as {"error": "..."}
+pub(crate) struct ApiError(StatusCode, String);
+
+impl ApiError {
+    pub fn bad_request(message: impl ToString) -> Self {
+        ApiError(
+            StatusCode::BAD_REQUEST,
+            message.to_string().trim().to_string(),
+        )
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        warn!("request failed, {}", e);
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+/// Only the first line of clap's message, its usage is for the command line
+impl From<clap::Error> for ApiError {
+    fn from(e: clap::Error) -> Self {
+        let message = e.to_string();
+        let message = message.lines().next().unwrap_or_default();
+        ApiError::bad_request(message.trim_start_matches("error: "))
+    }
+}
+
+impl From<QueryError> for ApiError {
+    fn from(e: QueryError) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// The filters of a get, parsed as `scdm query get` parses them
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct GetRequest {
+    #[clap(subcommand)]
+    resource: GetCommand,
+}
+
+/// The filters of a delete, parsed as `scdm query delete` parses them
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct DeleteRequest {
+    #[clap(subcommand)]
+    resource: DeleteCommand,
+}
+
+/// The options of a metric query, parsed as `scdm query metric` parses them
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+pub(crate) struct MetricRequest {
+    #[clap(flatten)]
+    pub metric: MetricArgs,
+}
+
+/// Turns the query string into command line arguments, `name=value` into
+/// `--name value` and a bare `name` into the flag `--name`
+pub(crate) fn push_params(args: &mut Vec<String>, params: Vec<(String, String)>) {
+    for (name, value) in params {
+        args.push(format!("--{}", name));
+        if !value.is_empty() {
+            args.push(value);
+        }
+    }
+}
+
+async fn results<T: Serialize + Tabled, U: QueryGet<T>>(
+    pool: &PgPool,
+    resource: U,
+    limit: Option<usize>,
+) -> Result<Response, ApiError> {
+    let mut results = resource.query_get(pool).await?;
+    if let Some(limit) = limit {
+        results.truncate(limit);
+    }
+    Ok(Json(results).into_response())
+}
+
+async fn get_resource(
+    State(state): State<ServeState>,
+    Path(resource): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Response, ApiError> {
+    let (limit, params): (Vec<_>, Vec<_>) = params.into_iter().partition(|(n, _)| n == "limit");
+    let limit = match limit.last() {
+        Some((_, limit)) => Some(
+            limit
+                .parse::<usize>()
+                .map_err(|_| ApiError::bad_request(format!("Invalid limit {}", limit)))?,
+        ),
+        None => None,
+    };
+    let mut args = vec![resource];
+    push_params(&mut args, params);
+    let request = GetRequest::try_parse_from(args)?;
+
+    let pool = &state.pool;
+    match request.resource {
+        GetCommand::Run(args) => results(pool, args, limit).await,
+        GetCommand::Tag(args) => results(pool, args, limit).await,
+        GetCommand::Iteration(args) => results(pool, args, limit).await,
+        GetCommand::Param(args) => results(pool, args, limit).await,
+        GetCommand::Sample(args) => results(pool, args, limit).await,
+        GetCommand::Period(args) => results(pool, args, limit).await,
+        GetCommand::MetricDesc(args) => results(pool, args, limit).await,
+        GetCommand::MetricData(args) => results(pool, args, limit).await,
+        GetCommand::Name(args) => results(pool, args, limit).await,
+        GetCommand::RunLink(args) => results(pool, args, limit).await,
+    }
+}
+
+async fn get_metric(
+    State(state): State<ServeState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Response, ApiError> {
+    let mut args = vec![];
+    push_params(&mut args, params);
+    let request = MetricRequest::try_parse_from(args)?;
+    let display = request.metric.display.clone();
+    let (header, rows) = metric::metric_rows(&state.pool, request.metric).await?;
+    let body = metric::format_rows(header, rows, Some(OutputFormat::JSON), &display)?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
+
+/// Compares the whole token whatever the first difference is
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Who the request acts as, by its bearer token. The server's token acts
+/// as the server's --owner and --admin, an owner's token as that owner
+fn authorize(state: &ServeState, headers: &HeaderMap) -> Result<Actor, ApiError> {
+    if state.token.is_none() && state.owner_tokens.is_empty() {
+        return Err(ApiError(
+            StatusCode::FORBIDDEN,
+            "Deletes and pushes are disabled, the server was started without a token".to_string(),
+        ));
+    }
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let unauthorized = || {
+        ApiError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong bearer token".to_string(),
+        )
+    };
+    let given = given.ok_or_else(unauthorized)?;
+    if state
+        .token
+        .as_deref()
+        .is_some_and(|token| token_matches(given, token))
+    {
+        return Ok(state.actor.clone());
+    }
+    state
+        .owner_tokens
+        .iter()
+        .find(|(_, token)| token_matches(given, token))
+        .map(|(owner, _)| Actor::new(Some(owner.clone()), false))
+        .ok_or_else(unauthorized)
+}
+
+async fn deleted<U: QueryDelete + Serialize>(
+    pool: &PgPool,
+    command: &str,
+    resource: U,
+    actor: &Actor,
+) -> Result<Response, ApiError> {
+    let num_deletes = query::delete_audited(pool, command, resource, actor).await?;
+    Ok(Json(json!({ "deleted": num_deletes })).into_response())
+}
+
+/// Whether the delete is limited to some of the resources. `soft` only
+/// changes how they're deleted
+fn filtered(resource: &DeleteCommand) -> bool {
+    match resource {
+        DeleteCommand::Run(args) => {
+            args.run_uuid.is_some()
+                || args.tag.is_some()
+                || args.begin_before.is_some()
+                || args.begin_after.is_some()
+                || args.finish_before.is_some()
+                || args.finish_after.is_some()
+                || args.benchmark.is_some()
+                || args.email.is_some()
+                || args.name.is_some()
+                || args.source.is_some()
+        }
+        DeleteCommand::Tag(args) => args.run_uuid.is_some() || args.tag.is_some(),
+    }
+}
+
+async fn delete_resource(
+    State(state): State<ServeState>,
+    Path(resource): Path<String>,
+    headers: HeaderMap,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Response, ApiError> {
+    let actor = authorize(&state, &headers)?;
+    let mut args = vec![resource];
+    push_params(&mut args, params);
+    let request = DeleteRequest::try_parse_from(args)?;
+    // Unlike the command line, a request can't delete everything by accident
+    if !filtered(&request.resource) {
+        return Err(ApiError::bad_request("A delete needs at least one filter"));
+    }
+
+    match request.resource {
+        DeleteCommand::Run(args) => deleted(&state.pool, "serve delete run", args, &actor).await,
+        DeleteCommand::Tag(args) => deleted(&state.pool, "serve delete tag", args, &actor).await,
+    }
+}
+
+/// Ingests the CDM ndjson pushed by `scdm agent`, the agent has already
+/// normalized the statuses so only the exact ones are taken
+async fn ingest(
+    State(state): State<ServeState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let actor = authorize(&state, &headers)?;
+    let mut records = parser::ndjson_documents(&body[..])
+        .collect::<Result<Vec<BodyJson>>>()
+        .map_err(ApiError::bad_request)?;
+    if records.is_empty() {
+        return Err(ApiError::bad_request("No documents to ingest"));
+    }
+    parser::normalize_statuses(parser::document_statuses(&mut records), StatusMode::Strict)
+        .map_err(ApiError::bad_request)?;
+    let agent = headers
+        .get("X-Scdm-Agent")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown");
+    // The runs pushed are the pusher's
+    let opts = IngestOpts {
+        owner: actor.owner,
+        ..state.ingest_opts.clone()
+    };
+    let num_new = parser::ingest(
+        &state.pool,
+        state.replica.as_ref(),
+        &records,
+        "serve ingest",
+        &json!({ "agent": agent }),
+        &opts,
+    )
+    .await?;
+    info!("added {} rows pushed by {}", num_new, agent);
+    Ok(Json(json!({ "added": num_new })).into_response())
+}
+
+/// GET /api/v1/metric takes the options of `scdm query metric`,
+/// GET /api/v1/{resource} those of `scdm query get {resource}` and
+/// DELETE /api/v1/{resource} those of `scdm query delete {resource}`.
+/// POST /api/v1/ingest takes the results pushed by `scdm agent`.
+/// Grafana's JSON datasource is served under /grafana, and Prometheus can
+/// scrape /metrics
+fn router(state: ServeState) -> Router {
+    Router::new()
+        .merge(grafana::routes())
+        .merge(prometheus::routes())
+        .route("/api/v1/metric", get(get_metric))
+        .route(
+            "/api/v1/ingest",
+            post(ingest).layer(DefaultBodyLimit::max(MAX_PUSH_BYTES)),
+        )
+        .route(
+            "/api/v1/{resource}",
+            get(get_resource).delete(delete_resource),
+        )
+        .with_state(state)
+}
+
+pub async fn serve(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: ServeArgs,
+    ingest_opts: &IngestOpts,
+    actor: &Actor,
+) -> Result<()> {
+    let state = ServeState {
+        pool: pool.clone(),
+        replica: replica.cloned(),
+        token: args.token,
+        owner_tokens: args.owner_tokens,
+        actor: actor.clone(),
+        ingest_opts: ingest_opts.clone(),
+        data_points: prometheus::DataPoints::count_every(
+            pool.clone(),
+            Duration::from_millis(args.count_interval as u64),
+        ),
+    };
+    let listener = tokio::net::TcpListener::bind(&args.listen)
+        .await
+        .map_err(|e| ServeError::BindFailed(args.listen.clone(), e.to_string()))?;
+    info!("listening on {}", args.listen);
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+        .map_err(|e| ServeError::ServeFailed(e.to_string()))?;
+    Ok(())
+}
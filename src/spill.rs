@@ -0,0 +1,282 @@
+use crate::args::StatusMode;
+use crate::audit;
+use crate::parser::{
+    self, BodyJson, GlobalResource, IndexType, IngestOpts, ParseError, Tables, document_statuses,
+    insert_runs_and_globals, insert_tables, normalize_statuses,
+};
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use thiserror::Error;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum SpillError {
+    #[error("Couldn't spill the documents to {0}, {1}")]
+    WriteFailed(String, String),
+    #[error("Couldn't read the spilled documents back from {0}, {1}")]
+    ReadFailed(String, String),
+    #[error("--max-memory streams CDM ndjson, {0} results are read whole")]
+    UnsupportedFormat(String),
+}
+
+/// The tables in the order they're inserted, each after the ones it refers to
+const TABLES: [IndexType; 8] = [
+    IndexType::Run,
+    IndexType::Tag,
+    IndexType::Iteration,
+    IndexType::Param,
+    IndexType::Sample,
+    IndexType::Period,
+    IndexType::MetricDesc,
+    IndexType::MetricData,
+];
+
+/// The documents of one table, the ones spilled to its file and the rest
+#[derive(Default)]
+struct Bucket {
+    lines: Vec<String>,
+    file: Option<BufWriter<File>>,
+}
+
+/// The CDM documents of an ingest by table, unparsed. Past the memory budget
+/// they're spilled to files in a temporary directory, so any number of them
+/// can be inserted a table at a time
+pub struct Spill {
+    dir: PathBuf,
+    budget: usize,
+    buffered: usize,
+    buckets: Vec<Bucket>,
+    run_uuids: Vec<Uuid>,
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn slot(index_type: IndexType) -> usize {
+    TABLES.iter().position(|t| *t == index_type).unwrap()
+}
+
+impl Spill {
+    /// Reads the documents of every ndjson file in the directory, holding
+    /// about `max_bytes` of them in memory at most. Half of it buffers the
+    /// documents as they're read, the other half is for the parsed chunks
+    /// they're inserted in, which take a few times their size in JSON
+    #[instrument(skip_all, fields(path = %dir_path.display()))]
+    pub fn read_dir(dir_path: &Path, max_bytes: usize) -> Result<Spill> {
+        let mut spill = Spill {
+            dir: std::env::temp_dir().join(format!("scdm-spill-{}", Uuid::new_v4())),
+            budget: max_bytes / 2,
+            buffered: 0,
+            buckets: TABLES.iter().map(|_| Bucket::default()).collect(),
+            run_uuids: vec![],
+        };
+        for ndjson_path in parser::ndjson_paths(dir_path)? {
+            let f = parser::open_ndjson(&ndjson_path)?;
+            for body in parser::ndjson_bodies(BufReader::new(f)) {
+                let (index_type, body_jsonl) = body?;
+                spill.push(index_type, body_jsonl)?;
+            }
+        }
+        for (slot, bucket) in spill.buckets.iter_mut().enumerate() {
+            if let Some(file) = &mut bucket.file {
+                file.flush().map_err(|e| {
+                    SpillError::WriteFailed(Self::path(&spill.dir, slot), e.to_string())
+                })?;
+            }
+        }
+        spill.run_uuids.sort();
+        spill.run_uuids.dedup();
+        Ok(spill)
+    }
+
+    fn path(dir: &Path, slot: usize) -> String {
+        dir.join(format!("{:?}.ndjson", TABLES[slot]))
+            .display()
+            .to_string()
+    }
+
+    fn push(&mut self, index_type: IndexType, body_jsonl: String) -> Result<()> {
+        // The runs are few, and their UUIDs are needed up front to lock them
+        if index_type == IndexType::Run
+            && let BodyJson::Run(run) = parser::parse_body(index_type, body_jsonl.clone())?
+        {
+            self.run_uuids.push(run.run.run_uuid);
+        }
+        self.buffered += body_jsonl.len();
+        self.buckets[slot(index_type)].lines.push(body_jsonl);
+        if self.buffered > self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the buffered documents out to the tables' files
+    fn spill(&mut self) -> Result<(), SpillError> {
+        debug!("spilling {} bytes of documents", self.buffered);
+        for (slot, bucket) in self.buckets.iter_mut().enumerate() {
+            if bucket.lines.is_empty() {
+                continue;
+            }
+            let werr = |e: std::io::Error| {
+                SpillError::WriteFailed(Self::path(&self.dir, slot), e.to_string())
+            };
+            let file = match &mut bucket.file {
+                Some(file) => file,
+                None => {
+                    fs::create_dir_all(&self.dir).map_err(werr)?;
+                    bucket.file.insert(BufWriter::new(
+                        File::create(Self::path(&self.dir, slot)).map_err(werr)?,
+                    ))
+                }
+            };
+            for line in bucket.lines.drain(..) {
+                writeln!(file, "{}", line).map_err(werr)?;
+            }
+        }
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Inserts the table's documents a chunk at a time, spilled ones first
+    async fn insert_table(
+        &self,
+        txn: &mut Transaction<'_, Postgres>,
+        index_type: IndexType,
+        status_mode: StatusMode,
+        inserted: &mut Inserted,
+        opts: &IngestOpts,
+    ) -> Result<u64> {
+        let slot = slot(index_type);
+        let path = Self::path(&self.dir, slot);
+        let spilled = match self.buckets[slot].file {
+            Some(_) => Some(
+                BufReader::new(
+                    File::open(&path)
+                        .map_err(|e| SpillError::ReadFailed(path.clone(), e.to_string()))?,
+                )
+                .lines(),
+            ),
+            None => None,
+        };
+        let mut lines = spilled
+            .into_iter()
+            .flatten()
+            .map(|line| line.map_err(|e| SpillError::ReadFailed(path.clone(), e.to_string())))
+            .chain(self.buckets[slot].lines.iter().cloned().map(Ok));
+
+        let mut num_new = 0;
+        loop {
+            let mut records = vec![];
+            let mut bytes = 0;
+            while bytes < self.budget / 4 {
+                let Some(line) = lines.next() else {
+                    break;
+                };
+                let line = line?;
+                bytes += line.len();
+                records.push(parser::parse_body(index_type, line)?);
+            }
+            if records.is_empty() {
+                return Ok(num_new);
+            }
+            num_new += insert_chunk(txn, records, status_mode, inserted, opts).await?;
+        }
+    }
+}
+
+/// What the chunks inserted so far leave to the ones after them
+#[derive(Default)]
+struct Inserted {
+    /// The global resources of the runs, which metric_descs can be scoped to
+    resources: HashMap<Uuid, GlobalResource>,
+    metric_descs: HashSet<Uuid>,
+}
+
+/// Inserts the documents, leaving out the metric_descs an earlier chunk
+/// inserted
+async fn insert_chunk(
+    txn: &mut Transaction<'_, Postgres>,
+    mut records: Vec<BodyJson>,
+    status_mode: StatusMode,
+    inserted: &mut Inserted,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    normalize_statuses(document_statuses(&mut records), status_mode)?;
+    records.retain(|record| match record {
+        BodyJson::MetricDesc(metric_desc) => inserted
+            .metric_descs
+            .insert(metric_desc.metric_desc.metric_desc_uuid),
+        _ => true,
+    });
+    let mut tables = Tables::new(&records)?;
+    let (num_new, globals) = insert_runs_and_globals(txn, &tables.runs, opts).await?;
+    tables.add_globals(&globals);
+    inserted.resources.extend(
+        globals
+            .resources
+            .iter()
+            .map(|(run_uuid, resource)| (*run_uuid, resource.clone())),
+    );
+    Ok(num_new + insert_tables(txn, &tables, &inserted.resources, opts).await?)
+}
+
+/// Inserts the documents in one transaction with their runs locked
+async fn ingest_into(
+    pool: &PgPool,
+    spill: &Spill,
+    status_mode: StatusMode,
+    filters: &Value,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let locks = parser::lock_runs(pool, &spill.run_uuids).await?;
+    let ingested = async {
+        let start = Instant::now();
+        let mut txn = pool.begin().await?;
+        let mut inserted = Inserted::default();
+        let mut num_new = 0;
+        for index_type in TABLES {
+            num_new += spill
+                .insert_table(&mut txn, index_type, status_mode, &mut inserted, opts)
+                .await?;
+        }
+        audit::record_timed(&mut *txn, "parse", filters, num_new, start.elapsed()).await?;
+        txn.commit().await?;
+        opts.cancellation.add_committed(num_new);
+        anyhow::Ok(num_new)
+    }
+    .await;
+    locks.release().await;
+    ingested
+}
+
+/// Ingests the directory of CDM ndjson like `parser::ingest`, but without
+/// ever holding much more than `max_bytes` of it in memory
+pub async fn ingest(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    dir_path: &Path,
+    max_bytes: usize,
+    status_mode: StatusMode,
+    filters: &Value,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let spill = Spill::read_dir(dir_path, max_bytes)?;
+    let total_records = ingest_into(pool, &spill, status_mode, filters, opts).await?;
+
+    if let Some(replica) = replica {
+        ingest_into(replica, &spill, status_mode, filters, opts)
+            .await
+            .map_err(|e| ParseError::ReplicaFailed(total_records, format!("{}", e)))?;
+    }
+    Ok(total_records)
+}
@@ -0,0 +1,143 @@
+use crate::args::{DisplayOpts, StatsArgs, StatsSection};
+use crate::{cdm, metric};
+use anyhow::Result;
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StatsError {
+    #[error("Couldn't gather the stats, {0}")]
+    StatsFailed(String),
+}
+
+fn serr<T: std::error::Error>(err: T) -> StatsError {
+    StatsError::StatsFailed(err.to_string())
+}
+
+/// Row counts and disk usage of the tables, counting the partitions of a
+/// partitioned metric_data towards it
+async fn tables(pool: &PgPool) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    let mut rows = Vec::new();
+    for table in cdm::DATA_TABLES {
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(pool)
+            .await
+            .map_err(serr)?;
+        let raw_query: &str = r#"
+            SELECT
+                COALESCE(SUM(pg_total_relation_size(relid)), 0)::bigint,
+                COALESCE(SUM(pg_indexes_size(relid)), 0)::bigint
+            FROM (
+                SELECT relid FROM pg_partition_tree(to_regclass($1))
+                UNION
+                SELECT to_regclass($1)
+            ) as tree
+        "#;
+        let (total, indexes): (i64, i64) = sqlx::query_as(raw_query)
+            .bind(table)
+            .fetch_one(pool)
+            .await
+            .map_err(serr)?;
+        rows.push(vec![
+            table.to_string(),
+            count.to_string(),
+            total.to_string(),
+            indexes.to_string(),
+        ]);
+    }
+    let header = ["table", "rows", "total_bytes", "index_bytes"];
+    Ok((header.iter().map(|c| c.to_string()).collect(), rows))
+}
+
+async fn benchmarks(pool: &PgPool) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    let raw_query: &str = r#"
+        SELECT benchmark, COUNT(*), MIN(begin)::text, MAX(finish)::text
+        FROM run
+        GROUP BY benchmark
+        ORDER BY COUNT(*) DESC, benchmark
+    "#;
+    let rows: Vec<(Option<String>, i64, String, String)> = sqlx::query_as(raw_query)
+        .fetch_all(pool)
+        .await
+        .map_err(serr)?;
+    let header = ["benchmark", "runs", "first_begin", "last_finish"];
+    Ok((
+        header.iter().map(|c| c.to_string()).collect(),
+        rows.into_iter()
+            .map(|(benchmark, runs, begin, finish)| {
+                vec![
+                    benchmark.unwrap_or_default(),
+                    runs.to_string(),
+                    begin,
+                    finish,
+                ]
+            })
+            .collect(),
+    ))
+}
+
+async fn runs(pool: &PgPool) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    let raw_query: &str = r#"
+        SELECT run.run_uuid::text, run.benchmark, run.begin::text, COUNT(metric_data.metric_data_id)
+        FROM run
+        LEFT JOIN iteration
+            ON iteration.run_uuid = run.run_uuid
+        LEFT JOIN sample
+            ON sample.iteration_uuid = iteration.iteration_uuid
+        LEFT JOIN period
+            ON period.sample_uuid = sample.sample_uuid
+        LEFT JOIN metric_desc
+            ON metric_desc.period_uuid = period.period_uuid
+        LEFT JOIN metric_data
+            ON metric_data.metric_desc_uuid = metric_desc.metric_desc_uuid
+        GROUP BY run.run_uuid
+        ORDER BY COUNT(metric_data.metric_data_id) DESC, run.begin
+    "#;
+    let rows: Vec<(String, Option<String>, String, i64)> = sqlx::query_as(raw_query)
+        .fetch_all(pool)
+        .await
+        .map_err(serr)?;
+    let header = ["run_uuid", "benchmark", "begin", "data_points"];
+    Ok((
+        header.iter().map(|c| c.to_string()).collect(),
+        rows.into_iter()
+            .map(|(run_uuid, benchmark, begin, points)| {
+                vec![
+                    run_uuid,
+                    benchmark.unwrap_or_default(),
+                    begin,
+                    points.to_string(),
+                ]
+            })
+            .collect(),
+    ))
+}
+
+pub async fn stats(pool: &PgPool, args: StatsArgs) -> Result<()> {
+    let sections = match args.section {
+        Some(section) => vec![section],
+        None if args.output.is_some() => {
+            return Err(StatsError::StatsFailed(
+                "pick a --section to output as json or csv".to_string(),
+            )
+            .into());
+        }
+        None => vec![
+            StatsSection::Tables,
+            StatsSection::Benchmarks,
+            StatsSection::Runs,
+        ],
+    };
+    for section in sections {
+        let (header, rows) = match section {
+            StatsSection::Tables => tables(pool).await?,
+            StatsSection::Benchmarks => benchmarks(pool).await?,
+            StatsSection::Runs => runs(pool).await?,
+        };
+        println!(
+            "{}",
+            metric::format_rows(header, rows, args.output.clone(), &DisplayOpts::default())?
+        );
+    }
+    Ok(())
+}
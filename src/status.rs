@@ -0,0 +1,215 @@
+use crate::args::{DisplayOpts, OutputFormat, StatusArgs};
+use crate::query::{self, QueryError};
+use crate::render;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use sqlx::prelude::FromRow;
+use tabled::Tabled;
+use tabled::derive::display;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum StatusError {
+    #[error("Couldn't gather the statuses, {0}")]
+    StatusFailed(String),
+    #[error("No runs match")]
+    NoRuns,
+    #[error("Failed, {0} of the iterations and samples failed or errored")]
+    Failed(usize),
+}
+
+fn serr<T: std::error::Error>(err: T) -> StatusError {
+    StatusError::StatusFailed(err.to_string())
+}
+
+/// How many of a run's iterations and samples failed or errored, and how
+/// many were skipped
+#[derive(Clone, Debug, FromRow, Tabled, Serialize)]
+pub struct RunStatus {
+    pub run_uuid: Uuid,
+    #[tabled(display("display::option", "null"))]
+    pub benchmark: Option<String>,
+    #[tabled(display = "render::time")]
+    pub begin: DateTime<Utc>,
+    pub iterations: i64,
+    pub failed_iterations: i64,
+    pub skipped_iterations: i64,
+    pub samples: i64,
+    pub failed_samples: i64,
+    pub skipped_samples: i64,
+}
+
+/// An iteration that failed or errored or has a sample that did, with its
+/// params
+#[derive(Clone, Debug, FromRow, Tabled, Serialize)]
+pub struct Failure {
+    pub run_uuid: Uuid,
+    pub iteration: i64,
+    pub iteration_uuid: Uuid,
+    #[tabled(display("display::option", "null"))]
+    pub iteration_status: Option<String>,
+    #[tabled(display("display::option", "null"))]
+    pub sample: Option<i64>,
+    #[tabled(display("display::option", "null"))]
+    pub sample_status: Option<String>,
+    #[tabled(display("display::option", "null"))]
+    pub params: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Status<'a> {
+    runs: &'a [RunStatus],
+    failures: &'a [Failure],
+}
+
+/// The runs matching the filters, leaving out the soft deleted ones. Each
+/// run's global iteration, which holds its run-wide telemetry, isn't counted
+async fn run_statuses(pool: &PgPool, args: &StatusArgs) -> Result<Vec<RunStatus>, StatusError> {
+    let raw_query: &str = r#"
+        SELECT
+            run.run_uuid,
+            run.benchmark,
+            run.begin,
+            COUNT(DISTINCT iteration.iteration_uuid) AS iterations,
+            COUNT(DISTINCT iteration.iteration_uuid)
+                FILTER (WHERE iteration.status IN ('fail', 'error')) AS failed_iterations,
+            COUNT(DISTINCT iteration.iteration_uuid)
+                FILTER (WHERE iteration.status = 'skip') AS skipped_iterations,
+            COUNT(DISTINCT sample.sample_uuid) AS samples,
+            COUNT(DISTINCT sample.sample_uuid)
+                FILTER (WHERE sample.status IN ('fail', 'error')) AS failed_samples,
+            COUNT(DISTINCT sample.sample_uuid)
+                FILTER (WHERE sample.status = 'skip') AS skipped_samples
+        FROM run
+        LEFT JOIN iteration
+            ON iteration.run_uuid = run.run_uuid
+            AND iteration.primary_period IS DISTINCT FROM 'global'
+        LEFT JOIN sample ON sample.iteration_uuid = iteration.iteration_uuid
+        WHERE
+            run.deleted_at IS NULL AND
+            ($1::uuid[] IS NULL OR run.run_uuid = ANY($1)) AND
+            ($2::text IS NULL OR run.benchmark = $2) AND
+            ($3::text IS NULL OR EXISTS (
+                SELECT 1 FROM tag
+                WHERE tag.run_uuid = run.run_uuid
+                    AND tag.name = $3
+                    AND ($4::text IS NULL OR tag.val = $4)
+            )) AND
+            ($5::timestamptz IS NULL OR run.begin >= $5)
+        GROUP BY run.run_uuid
+        ORDER BY run.begin
+    "#;
+    let (tag_name, tag_value) = match args.tag.as_deref().map(|tag| tag.split_once('=')) {
+        Some(Some((name, value))) => (Some(name), Some(value)),
+        Some(None) => (args.tag.as_deref(), None),
+        None => (None, None),
+    };
+    sqlx::query_as(raw_query)
+        .bind(args.run_uuid.clone())
+        .bind(args.benchmark.clone())
+        .bind(tag_name)
+        .bind(tag_value)
+        .bind(args.begin_after)
+        .fetch_all(pool)
+        .await
+        .map_err(serr)
+}
+
+/// The failed iterations of the runs, a row per failed sample or one for an
+/// iteration that failed without any. Skipped ones aren't failures
+async fn failures(pool: &PgPool, run_uuids: Vec<Uuid>) -> Result<Vec<Failure>, StatusError> {
+    let raw_query: &str = r#"
+        SELECT
+            iteration.run_uuid,
+            iteration.num AS iteration,
+            iteration.iteration_uuid,
+            iteration.status AS iteration_status,
+            sample.num AS sample,
+            sample.status AS sample_status,
+            (
+                SELECT string_agg(param.arg || '=' || param.val, ' ' ORDER BY param.arg)
+                FROM param WHERE param.iteration_uuid = iteration.iteration_uuid
+            ) AS params
+        FROM iteration
+        LEFT JOIN sample
+            ON sample.iteration_uuid = iteration.iteration_uuid
+            AND sample.status IN ('fail', 'error')
+        WHERE
+            iteration.run_uuid = ANY($1) AND
+            iteration.primary_period IS DISTINCT FROM 'global' AND
+            (iteration.status IN ('fail', 'error') OR sample.sample_uuid IS NOT NULL)
+        ORDER BY iteration.run_uuid, iteration.num, sample.num
+    "#;
+    sqlx::query_as(raw_query)
+        .bind(run_uuids)
+        .fetch_all(pool)
+        .await
+        .map_err(serr)
+}
+
+fn table<T: Tabled>(rows: &[T], display: &DisplayOpts) -> String {
+    render::table(rows, display).to_string()
+}
+
+/// Prints how many of the runs' iterations and samples passed and which
+/// failed, failing itself if any did so CI can gate on it
+pub async fn status(pool: &PgPool, args: StatusArgs) -> Result<()> {
+    let runs = run_statuses(pool, &args).await?;
+    if runs.is_empty() {
+        return Err(StatusError::NoRuns.into());
+    }
+    let failures = failures(pool, runs.iter().map(|run| run.run_uuid).collect()).await?;
+
+    match args.output {
+        None => {
+            let display = DisplayOpts {
+                highlight: render::parse_highlights(
+                    "failed_iterations>0=red,failed_samples>0=red,\
+                     skipped_iterations>0=yellow,skipped_samples>0=yellow",
+                )?,
+                ..Default::default()
+            };
+            println!("{}", table(&runs, &display));
+            if !failures.is_empty() {
+                println!("{}", table(&failures, &display));
+            }
+        }
+        Some(OutputFormat::JSON) => {
+            let status = Status {
+                runs: &runs,
+                failures: &failures,
+            };
+            println!(
+                "{}",
+                render::json(&status, true, &DisplayOpts::default())
+                    .map_err(|e| QueryError::SerializeError(format!("JSON ({})", e)))?
+            );
+        }
+        Some(OutputFormat::CSV) => println!(
+            "{}",
+            query::results_csv(&failures, &DisplayOpts::default())?
+        ),
+        Some(OutputFormat::NDJSON) => println!(
+            "{}",
+            query::results_ndjson(&failures, &DisplayOpts::default())?
+        ),
+        Some(OutputFormat::Vega | OutputFormat::Xlsx) => {
+            return Err(QueryError::UnknownFormat(
+                "vega or xlsx, status writes a table, json, csv or ndjson".to_string(),
+            )
+            .into());
+        }
+    }
+
+    let failed: i64 = runs
+        .iter()
+        .map(|run| run.failed_iterations + run.failed_samples)
+        .sum();
+    if failed > 0 {
+        return Err(StatusError::Failed(failed as usize).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::args::{ImportArgs, PruneArgs, StatusOpts, SyncArgs};
+use crate::import::{self, OpenSearchOpts};
+use crate::parser::IngestOpts;
+use crate::{policy, prune};
+use anyhow::Result;
+use sqlx::PgPool;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("Another sync is running as pid {0}, see {1}")]
+    AlreadyRunning(String, String),
+    #[error("Couldn't write the pid file {0}, {1}")]
+    PidFileFailed(String, String),
+}
+
+/// Holds the pid file for as long as the sync runs
+struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Creates the pid file, taking over one left by a sync that's gone
+    fn acquire(path: PathBuf) -> Result<Self, SyncError> {
+        let name = path.display().to_string();
+        let ferr = |e: std::io::Error| SyncError::PidFileFailed(name.clone(), e.to_string());
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut f) => {
+                    writeln!(f, "{}", std::process::id()).map_err(ferr)?;
+                    return Ok(PidFile { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let pid = fs::read_to_string(&path).map_err(ferr)?;
+                    let pid = pid.trim();
+                    if !pid.is_empty() && Path::new("/proc").join(pid).exists() {
+                        return Err(SyncError::AlreadyRunning(pid.to_string(), name));
+                    }
+                    warn!("removing the stale pid file {}", name);
+                    fs::remove_file(&path).map_err(ferr)?;
+                }
+                Err(e) => return Err(ferr(e)),
+            }
+        }
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Imports the runs that aren't in the database yet, then prunes the runs
+/// past their retention policy, and the older ones too if asked
+async fn sync_once(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: &SyncArgs,
+    opensearch: &OpenSearchOpts,
+    opts: &IngestOpts,
+) -> Result<u64> {
+    let import_args = ImportArgs {
+        run_uuid: None,
+        all: true,
+        skip_existing: true,
+        status: StatusOpts {
+            strict: args.status.strict,
+            coerce: args.status.coerce,
+        },
+    };
+    let num_new = import::import_runs(pool, replica, &import_args, opensearch, opts).await?;
+    if let Some(older_than) = args.prune_older_than {
+        prune::prune(
+            pool,
+            PruneArgs {
+                older_than: Some(older_than),
+                months_ahead: 1,
+            },
+        )
+        .await?;
+    } else {
+        policy::apply(pool).await?;
+    }
+    Ok(num_new)
+}
+
+/// Imports the new runs once, or every --interval with --daemon. A failed
+/// sync in daemon mode is logged and retried at the next interval.
+pub async fn sync(
+    pool: &PgPool,
+    replica: Option<&PgPool>,
+    args: SyncArgs,
+    opensearch: &OpenSearchOpts,
+    opts: &IngestOpts,
+) -> Result<()> {
+    let pid_file = args
+        .pid_file
+        .clone()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("scdm-sync.pid"));
+    let _pid_file = PidFile::acquire(pid_file)?;
+
+    if !args.daemon {
+        sync_once(pool, replica, &args, opensearch, opts).await?;
+        return Ok(());
+    }
+
+    let interval = Duration::from_millis(args.interval as u64);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut failures = 0;
+    info!("syncing every {}s", interval.as_secs_f64());
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        let start = Instant::now();
+        match sync_once(pool, replica, &args, opensearch, opts).await {
+            Ok(num_new) => {
+                failures = 0;
+                info!(
+                    "sync added {} rows in {:.1}s",
+                    num_new,
+                    start.elapsed().as_secs_f64()
+                );
+            }
+            Err(e) => {
+                failures += 1;
+                warn!("sync failed, {} in a row, {}", failures, e);
+            }
+        }
+    }
+    info!("stopped syncing");
+    Ok(())
+}
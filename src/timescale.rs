@@ -0,0 +1,62 @@
+use crate::SCDMError;
+use crate::init::merr;
+use crate::partition;
+use crate::report;
+use anyhow::Result;
+use sqlx::PgPool;
+
+/// Turns metric_data into a TimescaleDB hypertable chunked on the data's
+/// begin, with chunks older than `compress_after_ms` compressed per
+/// metric_desc.
+pub async fn hypertable_metric_data(pool: &PgPool, compress_after_ms: i64) -> Result<()> {
+    let available: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb')",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(merr)?;
+    if !available {
+        return Err(SCDMError::FailedTableInit(
+            "the timescaledb extension isn't available on this server".to_string(),
+        )
+        .into());
+    }
+    if partition::is_partitioned(pool).await? {
+        return Err(SCDMError::FailedTableInit(
+            "metric_data is already partitioned, it can't also be a hypertable".to_string(),
+        )
+        .into());
+    }
+
+    let mut txn = pool.begin().await.map_err(merr)?;
+    for statement in [
+        "CREATE EXTENSION IF NOT EXISTS timescaledb",
+        // Unique indexes of a hypertable have to include the time column
+        "ALTER TABLE metric_data DROP CONSTRAINT IF EXISTS metric_data_pkey",
+        "ALTER TABLE metric_data ADD PRIMARY KEY (metric_data_id, metric_desc_uuid, begin)",
+        "SELECT create_hypertable('metric_data', 'begin', migrate_data => true, if_not_exists => true)",
+        r#"
+        ALTER TABLE metric_data SET (
+            timescaledb.compress,
+            timescaledb.compress_segmentby = 'metric_desc_uuid',
+            timescaledb.compress_orderby = 'begin'
+        )
+        "#,
+    ] {
+        sqlx::query(statement)
+            .execute(&mut *txn)
+            .await
+            .map_err(merr)?;
+    }
+    sqlx::query(
+        "SELECT add_compression_policy('metric_data', $1 * INTERVAL '1 millisecond', if_not_exists => true)",
+    )
+    .bind(compress_after_ms)
+    .execute(&mut *txn)
+    .await
+    .map_err(merr)?;
+    txn.commit().await.map_err(merr)?;
+
+    report!("metric_data is now a hypertable");
+    Ok(())
+}
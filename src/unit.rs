@@ -0,0 +1,126 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dimension {
+    DataRate,
+    Data,
+    Time,
+}
+
+/// Known units, their dimension, and the factor to convert them to the
+/// base unit of that dimension (bits/s, bits and seconds).
+const UNITS: &[(&str, Dimension, f64)] = &[
+    ("bps", Dimension::DataRate, 1.0),
+    ("Kbps", Dimension::DataRate, 1e3),
+    ("Mbps", Dimension::DataRate, 1e6),
+    ("Gbps", Dimension::DataRate, 1e9),
+    ("Tbps", Dimension::DataRate, 1e12),
+    ("B/s", Dimension::DataRate, 8.0),
+    ("KB/s", Dimension::DataRate, 8e3),
+    ("MB/s", Dimension::DataRate, 8e6),
+    ("GB/s", Dimension::DataRate, 8e9),
+    ("KiB/s", Dimension::DataRate, 8.0 * 1024.0),
+    ("MiB/s", Dimension::DataRate, 8.0 * 1024.0 * 1024.0),
+    ("GiB/s", Dimension::DataRate, 8.0 * 1024.0 * 1024.0 * 1024.0),
+    ("b", Dimension::Data, 1.0),
+    ("B", Dimension::Data, 8.0),
+    ("KB", Dimension::Data, 8e3),
+    ("MB", Dimension::Data, 8e6),
+    ("GB", Dimension::Data, 8e9),
+    ("KiB", Dimension::Data, 8.0 * 1024.0),
+    ("MiB", Dimension::Data, 8.0 * 1024.0 * 1024.0),
+    ("GiB", Dimension::Data, 8.0 * 1024.0 * 1024.0 * 1024.0),
+    ("ns", Dimension::Time, 1e-9),
+    ("nsec", Dimension::Time, 1e-9),
+    ("us", Dimension::Time, 1e-6),
+    ("usec", Dimension::Time, 1e-6),
+    ("ms", Dimension::Time, 1e-3),
+    ("msec", Dimension::Time, 1e-3),
+    ("s", Dimension::Time, 1.0),
+    ("sec", Dimension::Time, 1.0),
+];
+
+pub fn lookup(unit: &str) -> Option<(Dimension, f64)> {
+    UNITS
+        .iter()
+        .find(|(name, _, _)| *name == unit)
+        .map(|(_, dimension, factor)| (*dimension, *factor))
+}
+
+/// The factor to multiply a value in `from` by to get a value in `to`.
+pub fn conversion_factor(from: &str, to: &str) -> Option<f64> {
+    let (from_dimension, from_factor) = lookup(from)?;
+    let (to_dimension, to_factor) = lookup(to)?;
+    if from_dimension != to_dimension {
+        return None;
+    }
+    Some(from_factor / to_factor)
+}
+
+/// The unit a metric_desc's values are in. Descriptors without an explicit
+/// unit fall back to their metric type, which is often the unit itself (Gbps).
+pub const SQL_DESC_UNIT: &str = "COALESCE(metric_desc.unit, metric_desc.metric_type)";
+
+/// A CASE expression that evaluates to the factor converting the current
+/// metric_desc's values to `target`, or NULL when they can't be converted.
+pub fn conversion_factor_sql(target: &str) -> String {
+    let mut sql = format!("(CASE {}", SQL_DESC_UNIT);
+    for (name, _, _) in UNITS {
+        if let Some(factor) = conversion_factor(name, target) {
+            sql.push_str(&format!(
+                " WHEN '{}' THEN {:e}::double precision",
+                name, factor
+            ));
+        }
+    }
+    sql.push_str(" END)");
+    sql
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the factor is the expected one, within rounding
+    fn converts(from: &str, to: &str, expected: f64) -> bool {
+        conversion_factor(from, to).is_some_and(|factor| (factor / expected - 1.0).abs() < 1e-12)
+    }
+
+    #[test]
+    fn factors_convert_within_a_dimension() {
+        assert!(converts("MiB/s", "Gbps", 0.008388608));
+        assert!(converts("Gbps", "MB/s", 125.0));
+        assert!(converts("ns", "ms", 1e-6));
+        assert!(converts("sec", "usec", 1e6));
+        assert!(converts("GiB", "KiB", 1024.0 * 1024.0));
+        assert!(converts("Mbps", "Mbps", 1.0));
+    }
+
+    #[test]
+    fn incompatible_units_are_refused() {
+        assert_eq!(conversion_factor("ms", "Gbps"), None);
+        assert_eq!(conversion_factor("GB", "GB/s"), None);
+        assert_eq!(conversion_factor("furlongs", "ms"), None);
+        assert_eq!(conversion_factor("ms", "furlongs"), None);
+    }
+
+    #[test]
+    fn the_sql_falls_back_to_the_metric_type() {
+        let sql = conversion_factor_sql("ms");
+        assert!(
+            sql.starts_with("(CASE COALESCE(metric_desc.unit, metric_desc.metric_type) WHEN "),
+            "{}",
+            sql
+        );
+        assert!(
+            sql.contains(" WHEN 's' THEN 1e3::double precision"),
+            "{}",
+            sql
+        );
+        assert!(
+            sql.contains(" WHEN 'ms' THEN 1e0::double precision"),
+            "{}",
+            sql
+        );
+        // Units of other dimensions are left to the CASE's NULL
+        assert!(!sql.contains("'Gbps'"), "{}", sql);
+    }
+}
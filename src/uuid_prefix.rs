@@ -0,0 +1,382 @@
+use clap::Command;
+use sqlx::PgPool;
+use std::any::TypeId;
+use std::collections::HashMap;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum UuidPrefixError {
+    #[error("No {0} has a UUID starting with {1}")]
+    NotFound(&'static str, String),
+    #[error("{1} starts the UUIDs of more than one {0}, ex: {2} and {3}, give more of it")]
+    Ambiguous(&'static str, String, Uuid, Uuid),
+    #[error("Couldn't look up the {0} UUID starting with {1}: {2}")]
+    LookupFailed(&'static str, String, String),
+    #[error("{1} is too short to look up a {0} by, give at least {2} characters of its UUID")]
+    TooShort(&'static str, String, usize),
+}
+
+/// The fewest hex digits a prefix is looked up by, as fewer would start the
+/// UUIDs of most rows of a table
+const MIN_PREFIX_LEN: usize = 4;
+
+/// A UUID option of the command line given one or more prefixes, ex:
+/// `--run-uuid 4c17`
+pub struct UuidArg {
+    /// The command line word holding the value, and where in it the value starts
+    word: usize,
+    start: usize,
+    delimiter: Option<char>,
+    table: &'static str,
+}
+
+/// The table whose UUIDs an option names, by the option's id
+fn table_of(id: &str) -> Option<&'static str> {
+    match id {
+        "run_uuid" | "baseline" | "from" | "to" => Some("run"),
+        "iteration_uuid" | "ref_periods_from_iteration" => Some("iteration"),
+        "sample_uuid" => Some("sample"),
+        "period_uuid" | "ref_period" | "period" => Some("period"),
+        "metric_desc_uuid" => Some("metric_desc"),
+        "artifact_uuid" => Some("artifact"),
+        _ => None,
+    }
+}
+
+/// Whether the value is the start of a UUID rather than all of it
+fn is_prefix(value: &str) -> bool {
+    !value.is_empty()
+        && Uuid::parse_str(value).is_err()
+        && value.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
+
+/// The values of the option, split on its delimiter
+fn items(value: &str, delimiter: Option<char>) -> Vec<&str> {
+    match delimiter {
+        Some(delimiter) => value.split(delimiter).collect(),
+        None => vec![value],
+    }
+}
+
+/// The UUID options of the words that were given a prefix. The words are
+/// walked like clap does, from `skip`, so an option is found on whichever
+/// subcommand of the path declares it
+pub fn uuid_args(command: &Command, words: &[String], skip: usize) -> Vec<UuidArg> {
+    let mut path = vec![command.clone()];
+    let mut positional = 0;
+    let mut found = vec![];
+    let mut i = skip;
+    while i < words.len() {
+        let word = &words[i];
+        if word == "--" {
+            break;
+        }
+        let find = |matches: &dyn Fn(&clap::Arg) -> bool| {
+            path.iter()
+                .rev()
+                .find_map(|command| command.get_arguments().find(|arg| matches(arg)).cloned())
+        };
+        // The option and where its value is, in this word or the next
+        let (arg, at) = if let Some(long) = word.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, _)) => (name, Some(word.len() - long.len() + name.len() + 1)),
+                None => (long, None),
+            };
+            let Some(arg) = find(&|arg| {
+                arg.get_long() == Some(name)
+                    || arg
+                        .get_all_aliases()
+                        .is_some_and(|aliases| aliases.contains(&name))
+            }) else {
+                i += 1;
+                continue;
+            };
+            (arg, inline)
+        } else if word.len() > 1 && word.starts_with('-') {
+            // Flags can be bunched up, ex: -vq, with the last taking a value
+            let mut taking = None;
+            for (at, c) in word.char_indices().skip(1) {
+                let Some(arg) = find(&|arg| arg.get_short() == Some(c)) else {
+                    break;
+                };
+                if arg.get_action().takes_values() {
+                    let at = at + c.len_utf8();
+                    let at = at + usize::from(word[at..].starts_with('='));
+                    taking = Some((arg, (at < word.len()).then_some(at)));
+                    break;
+                }
+            }
+            let Some(taking) = taking else {
+                i += 1;
+                continue;
+            };
+            taking
+        } else if let Some(subcommand) = path.last().unwrap().find_subcommand(word).cloned() {
+            path.push(subcommand);
+            positional = 0;
+            i += 1;
+            continue;
+        } else {
+            let arg = path
+                .last()
+                .unwrap()
+                .get_positionals()
+                .nth(positional)
+                .cloned();
+            positional += 1;
+            match arg {
+                Some(arg) => (arg, Some(0)),
+                None => {
+                    i += 1;
+                    continue;
+                }
+            }
+        };
+        let (word, start) = match at {
+            Some(start) => (i, start),
+            None if arg.get_action().takes_values() => (i + 1, 0),
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        i = word + 1;
+        let Some(value) = words.get(word).map(|word| &word[start..]) else {
+            break;
+        };
+        let Some(table) = table_of(arg.get_id().as_str()) else {
+            continue;
+        };
+        if arg.get_value_parser().type_id() != TypeId::of::<Uuid>() {
+            continue;
+        }
+        let delimiter = arg.get_value_delimiter();
+        if items(value, delimiter).into_iter().any(is_prefix) {
+            found.push(UuidArg {
+                word,
+                start,
+                delimiter,
+                table,
+            });
+        }
+    }
+    found
+}
+
+/// The words with each prefix replaced by the UUID it stands for
+fn replace(
+    words: &[String],
+    args: &[UuidArg],
+    uuids: &HashMap<(&'static str, String), Uuid>,
+) -> Vec<String> {
+    let mut words = words.to_vec();
+    for arg in args {
+        let word = &words[arg.word];
+        let replaced: Vec<String> = items(&word[arg.start..], arg.delimiter)
+            .into_iter()
+            .map(|item| match uuids.get(&(arg.table, item.to_string())) {
+                Some(uuid) => uuid.to_string(),
+                None => item.to_string(),
+            })
+            .collect();
+        let delimiter = arg.delimiter.map(String::from).unwrap_or_default();
+        words[arg.word] = format!("{}{}", &word[..arg.start], replaced.join(&delimiter));
+    }
+    words
+}
+
+/// The prefixes the options were given, with the table of each
+fn prefixes(words: &[String], args: &[UuidArg]) -> Vec<(&'static str, String)> {
+    let mut prefixes = vec![];
+    for arg in args {
+        for item in items(&words[arg.word][arg.start..], arg.delimiter) {
+            if is_prefix(item) {
+                prefixes.push((arg.table, item.to_string()));
+            }
+        }
+    }
+    prefixes
+}
+
+/// The words with a nil UUID standing in for each prefix, so they can be
+/// parsed before there's a database to look the prefixes up in
+pub fn with_placeholders(words: &[String], args: &[UuidArg]) -> Vec<String> {
+    let uuids = prefixes(words, args)
+        .into_iter()
+        .map(|prefix| (prefix, Uuid::nil()))
+        .collect();
+    replace(words, args, &uuids)
+}
+
+/// Fails when the prefix has too few hex digits to look up
+fn check_len(table: &'static str, prefix: &str) -> Result<(), UuidPrefixError> {
+    let digits = prefix.chars().filter(char::is_ascii_hexdigit).count();
+    match digits < MIN_PREFIX_LEN {
+        true => Err(UuidPrefixError::TooShort(
+            table,
+            prefix.to_string(),
+            MIN_PREFIX_LEN,
+        )),
+        false => Ok(()),
+    }
+}
+
+/// The one UUID of those of the table starting with the prefix
+fn only(table: &'static str, prefix: &str, uuids: &[Uuid]) -> Result<Uuid, UuidPrefixError> {
+    match uuids {
+        [uuid] => Ok(*uuid),
+        [] => Err(UuidPrefixError::NotFound(table, prefix.to_string())),
+        [a, b, ..] => Err(UuidPrefixError::Ambiguous(
+            table,
+            prefix.to_string(),
+            *a,
+            *b,
+        )),
+    }
+}
+
+/// The one UUID of the table that starts with the prefix
+async fn lookup(pool: &PgPool, table: &'static str, prefix: &str) -> Result<Uuid, UuidPrefixError> {
+    check_len(table, prefix)?;
+    let failed =
+        |e: sqlx::Error| UuidPrefixError::LookupFailed(table, prefix.to_string(), e.to_string());
+    let uuids: Vec<Uuid> = sqlx::query_scalar(&format!(
+        "SELECT {table}_uuid FROM {table} WHERE {table}_uuid::text LIKE $1 || '%' ORDER BY 1 LIMIT 2"
+    ))
+    .bind(prefix.to_lowercase())
+    .fetch_all(pool)
+    .await
+    .map_err(failed)?;
+    only(table, prefix, &uuids)
+}
+
+/// The words with each prefix replaced by the UUID it's the start of, which
+/// must be the only one of its table that is
+pub async fn resolve(
+    pool: &PgPool,
+    words: &[String],
+    args: &[UuidArg],
+) -> Result<Vec<String>, UuidPrefixError> {
+    let mut uuids = HashMap::new();
+    for (table, prefix) in prefixes(words, args) {
+        if !uuids.contains_key(&(table, prefix.clone())) {
+            let uuid = lookup(pool, table, &prefix).await?;
+            uuids.insert((table, prefix), uuid);
+        }
+    }
+    Ok(replace(words, args, &uuids))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, value_parser};
+
+    const RUN: &str = "4c17e5d2-9a4b-4d6e-8f10-2b3c4d5e6f70";
+    const OTHER_RUN: &str = "4c17a0b1-1111-4222-8333-444455556666";
+
+    fn command() -> Command {
+        Command::new("scdm").subcommand(
+            Command::new("compare")
+                .arg(
+                    Arg::new("run_uuid")
+                        .long("run-uuid")
+                        .value_parser(value_parser!(Uuid)),
+                )
+                .arg(
+                    Arg::new("period_uuid")
+                        .long("period-uuid")
+                        .value_delimiter(',')
+                        .value_parser(value_parser!(Uuid)),
+                )
+                .arg(Arg::new("name").long("name")),
+        )
+    }
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    /// The words with the prefixes of the options replaced by these UUIDs
+    fn resolved(words: &[String], uuids: &[(&'static str, &str, &str)]) -> Vec<String> {
+        let args = uuid_args(&command(), words, 1);
+        let uuids = uuids
+            .iter()
+            .map(|(table, prefix, uuid)| ((*table, prefix.to_string()), uuid.parse().unwrap()))
+            .collect();
+        replace(words, &args, &uuids)
+    }
+
+    #[test]
+    fn both_argv_forms_are_resolved() {
+        let uuids = [("run", "4c17e5", RUN)];
+        assert_eq!(
+            resolved(&words(&["scdm", "compare", "--run-uuid", "4c17e5"]), &uuids),
+            words(&["scdm", "compare", "--run-uuid", RUN])
+        );
+        assert_eq!(
+            resolved(&words(&["scdm", "compare", "--run-uuid=4c17e5"]), &uuids),
+            words(&["scdm", "compare", &format!("--run-uuid={}", RUN)])
+        );
+    }
+
+    #[test]
+    fn delimited_values_are_resolved_each() {
+        let argv = words(&[
+            "scdm",
+            "compare",
+            &format!("--period-uuid={},4c17e5", OTHER_RUN),
+        ]);
+        assert_eq!(
+            resolved(&argv, &[("period", "4c17e5", RUN)]),
+            words(&[
+                "scdm",
+                "compare",
+                &format!("--period-uuid={},{}", OTHER_RUN, RUN)
+            ])
+        );
+        assert_eq!(
+            with_placeholders(&argv, &uuid_args(&command(), &argv, 1)),
+            words(&[
+                "scdm",
+                "compare",
+                &format!("--period-uuid={},{}", OTHER_RUN, Uuid::nil())
+            ])
+        );
+    }
+
+    #[test]
+    fn full_uuids_and_other_options_are_left_alone() {
+        let argv = words(&["scdm", "compare", "--run-uuid", RUN, "--name", "4c17"]);
+        assert!(uuid_args(&command(), &argv, 1).is_empty());
+        let argv = words(&["scdm", "compare", &format!("--run-uuid={}", RUN)]);
+        assert!(uuid_args(&command(), &argv, 1).is_empty());
+    }
+
+    #[test]
+    fn short_prefixes_are_refused() {
+        assert!(matches!(
+            check_len("run", "4c1"),
+            Err(UuidPrefixError::TooShort("run", _, MIN_PREFIX_LEN))
+        ));
+        // The dashes of a prefix aren't counted
+        assert!(check_len("run", "4c1-").is_err());
+        assert!(check_len("run", "4c17").is_ok());
+    }
+
+    #[test]
+    fn a_prefix_must_start_only_one_uuid() {
+        let run: Uuid = RUN.parse().unwrap();
+        let other: Uuid = OTHER_RUN.parse().unwrap();
+        assert_eq!(only("run", "4c17", &[run]).unwrap(), run);
+        assert!(matches!(
+            only("run", "4c17", &[other, run]),
+            Err(UuidPrefixError::Ambiguous("run", _, a, b)) if a == other && b == run
+        ));
+        assert!(matches!(
+            only("run", "4c17", &[]),
+            Err(UuidPrefixError::NotFound("run", _))
+        ));
+    }
+}
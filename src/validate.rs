@@ -0,0 +1,48 @@
+use crate::args::ValidateArgs;
+use crate::parser::{self, BodyJson, document_statuses, normalize_statuses, validate_timestamps};
+use crate::report;
+use crate::{add, backup};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Reads results in either format scdm ingests, a directory of CDM ndjson
+/// or the JSON results `scdm add` takes
+pub fn read_results(path: &Path, uuid_v7: bool) -> Result<Vec<BodyJson>> {
+    if path.is_dir() {
+        let records = parser::read_ndjson_dir(path)?;
+        if !records.is_empty() {
+            return Ok(records);
+        }
+    }
+    add::read_run_nodes(path, uuid_v7)
+}
+
+/// Runs the checks `parse` and `add` make before ingesting, without a database
+pub fn validate(args: ValidateArgs) -> Result<()> {
+    let mut records = read_results(Path::new(&args.path), false)?;
+    normalize_statuses(document_statuses(&mut records), args.status.mode())?;
+
+    let mut runs = Vec::new();
+    let mut periods = Vec::new();
+    let mut metric_datas = Vec::new();
+    let mut num_documents: BTreeMap<&str, u64> = BTreeMap::new();
+    for record in &records {
+        match record {
+            BodyJson::Run(run) => runs.push(run),
+            BodyJson::Period(period) => periods.push(period),
+            BodyJson::MetricData(metric_data) => metric_datas.push(metric_data),
+            _ => {}
+        }
+        if let Some(index) = backup::index_name(record) {
+            *num_documents.entry(index).or_default() += 1;
+        }
+    }
+    validate_timestamps(&runs, &periods, &metric_datas)?;
+
+    for (index, n) in &num_documents {
+        report!("{}: {}", index, n);
+    }
+    report!("{} is valid", args.path);
+    Ok(())
+}
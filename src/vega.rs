@@ -0,0 +1,140 @@
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use serde_json::{Map, Value, json};
+use std::collections::HashSet;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum VegaError {
+    #[error("Vega output charts the windows of `query metric`, these rows have no window_begin")]
+    NoWindows,
+}
+
+const SCHEMA: &str = "https://vega.github.io/schema/vega-lite/v5.json";
+
+/// The columns that place a row in time rather than identify its series
+const WINDOW_COLUMNS: [&str; 2] = ["window_begin", "window_finish"];
+
+/// A rendered cell as the JSON Vega reads, timestamps as RFC 3339 in
+/// whatever offset they were rendered in and numbers as numbers
+fn cell(column: &str, cell: String) -> Value {
+    if cell == "null" {
+        return Value::Null;
+    }
+    if WINDOW_COLUMNS.contains(&column)
+        && let Ok(t) = cell.parse::<DateTime<FixedOffset>>()
+    {
+        return Value::String(t.to_rfc3339_opts(SecondsFormat::AutoSi, true));
+    }
+    match cell.parse::<f64>() {
+        Ok(n) if n.is_finite() => json!(n),
+        _ => Value::String(cell),
+    }
+}
+
+/// A Vega-Lite spec of a line per series of the windowed rows of a metric
+/// query, with the rows inlined or, given a URL, read from there instead.
+/// The series are told apart by the columns before the windows that differ
+/// between rows, the value is the last column
+pub fn spec(
+    header: &[String],
+    rows: Vec<Vec<String>>,
+    data_url: Option<&str>,
+) -> Result<Value, VegaError> {
+    let window_idx = header
+        .iter()
+        .position(|c| c == "window_begin")
+        .ok_or(VegaError::NoWindows)?;
+    let value_column = header.last().cloned().unwrap_or_default();
+    let series_columns: Vec<&String> = header[..window_idx]
+        .iter()
+        .enumerate()
+        .filter(|(i, column)| {
+            *column != "class"
+                && rows
+                    .iter()
+                    .map(|row| &row[*i])
+                    .collect::<HashSet<_>>()
+                    .len()
+                    > 1
+        })
+        .map(|(_, column)| column)
+        .collect();
+    let metric_types: HashSet<&String> = header
+        .iter()
+        .position(|c| c == "metric_type")
+        .map(|i| rows.iter().map(|row| &row[i]).collect())
+        .unwrap_or_default();
+    let y_title = match metric_types.len() {
+        1 => metric_types.into_iter().next().cloned().unwrap_or_default(),
+        _ => value_column.clone(),
+    };
+
+    let data = match data_url {
+        Some(url) => {
+            let format_type = if url.ends_with(".csv") { "csv" } else { "json" };
+            json!({
+                "url": url,
+                "format": {
+                    "type": format_type,
+                    "parse": {
+                        "window_begin": "date",
+                        "window_finish": "date",
+                        value_column.clone(): "number",
+                    },
+                },
+            })
+        }
+        None => {
+            let values: Vec<Map<String, Value>> = rows
+                .into_iter()
+                .map(|row| {
+                    header
+                        .iter()
+                        .zip(row)
+                        .map(|(column, c)| (column.clone(), cell(column, c)))
+                        .collect()
+                })
+                .collect();
+            json!({ "values": values })
+        }
+    };
+
+    let tooltip: Vec<Value> = header
+        .iter()
+        .map(|column| match column.as_str() {
+            "window_begin" | "window_finish" => {
+                json!({"field": column, "type": "temporal", "format": "%Y-%m-%d %H:%M:%S"})
+            }
+            _ if *column == value_column => json!({"field": column, "type": "quantitative"}),
+            _ => json!({"field": column, "type": "nominal"}),
+        })
+        .collect();
+    let mut encoding = json!({
+        "x": {"field": "window_begin", "type": "temporal", "title": "time"},
+        "y": {"field": value_column, "type": "quantitative", "title": y_title},
+        "tooltip": tooltip,
+    });
+    let mut spec = json!({
+        "$schema": SCHEMA,
+        "description": "scdm query metric",
+        "width": 800,
+        "height": 400,
+        "data": data,
+        "mark": {"type": "line", "point": true, "interpolate": "step-after"},
+    });
+    if !series_columns.is_empty() {
+        // One label per series, ex: "metric_type=Gbps hostname=w1"
+        let label = series_columns
+            .iter()
+            .map(|column| {
+                let column = column.replace('\'', "\\'");
+                format!("'{}=' + datum['{}']", column, column)
+            })
+            .collect::<Vec<_>>()
+            .join(" + ' ' + ");
+        spec["transform"] = json!([{"calculate": label, "as": "series"}]);
+        encoding["color"] = json!({"field": "series", "type": "nominal", "title": null});
+    }
+    spec["encoding"] = encoding;
+    Ok(spec)
+}
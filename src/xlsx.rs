@@ -0,0 +1,136 @@
+use crate::args::DisplayOpts;
+use crate::render;
+use chrono::{DateTime, FixedOffset};
+use rust_xlsxwriter::{ColNum, Format, RowNum, Workbook, Worksheet};
+use serde_json::Value;
+use std::collections::HashSet;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum XlsxError {
+    #[error("Couldn't write the workbook {0}: {1}")]
+    WriteFailed(String, String),
+    #[error("XLSX output needs a file to write to, pass --out")]
+    NoOut,
+}
+
+/// The longest sheet name Excel allows
+const MAX_SHEET_NAME: usize = 31;
+
+/// A sheet of the workbook, with a row per result
+pub struct Sheet {
+    pub name: String,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// A sheet name Excel accepts, without the characters it reserves and
+/// distinct from the names already taken
+fn sheet_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let clean: String = name
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            c => c,
+        })
+        .collect();
+    let clean = clean.trim_matches('\'');
+    let clean = if clean.is_empty() { "sheet" } else { clean };
+    let mut n = 1;
+    loop {
+        let suffix = if n == 1 {
+            String::new()
+        } else {
+            format!(" ({})", n)
+        };
+        let keep = MAX_SHEET_NAME - suffix.chars().count();
+        let candidate = format!("{}{}", clean.chars().take(keep).collect::<String>(), suffix);
+        // Excel compares sheet names case insensitively
+        if taken.insert(candidate.to_lowercase()) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Writes a cell keeping its type, so numbers and timestamps stay numbers
+/// and dates in the spreadsheet. Timestamps are in the options' timezone
+fn write_cell(
+    sheet: &mut Worksheet,
+    row: RowNum,
+    col: ColNum,
+    value: &Value,
+    datetime: &Format,
+    opts: &DisplayOpts,
+) -> Result<(), rust_xlsxwriter::XlsxError> {
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => {
+            sheet.write_boolean(row, col, *b)?;
+        }
+        Value::Number(n) => {
+            sheet.write_number(row, col, n.as_f64().unwrap_or_default())?;
+        }
+        Value::String(s) => {
+            if let Ok(t) = s.parse::<DateTime<FixedOffset>>() {
+                let t = render::in_timezone(&t.to_utc(), opts);
+                sheet.write_datetime_with_format(row, col, t.naive_local(), datetime)?;
+            } else if let Ok(n) = s.parse::<f64>()
+                && n.is_finite()
+            {
+                sheet.write_number(row, col, n)?;
+            } else {
+                sheet.write_string(row, col, s)?;
+            }
+        }
+        Value::Array(values) => {
+            let joined: Vec<String> = values
+                .iter()
+                .map(|v| v.as_str().map(str::to_string).unwrap_or(v.to_string()))
+                .collect();
+            sheet.write_string(row, col, joined.join(","))?;
+        }
+        Value::Object(_) => {
+            sheet.write_string(row, col, value.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the sheets to a new workbook at the path, each with a bold header
+/// row that stays in view while scrolling
+pub fn write(path: &str, sheets: Vec<Sheet>, opts: &DisplayOpts) -> Result<(), XlsxError> {
+    let failed =
+        |e: rust_xlsxwriter::XlsxError| XlsxError::WriteFailed(path.to_string(), e.to_string());
+    let bold = Format::new().set_bold();
+    let datetime = Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+    let mut workbook = Workbook::new();
+    let mut taken = HashSet::new();
+    for sheet in sheets {
+        let worksheet = workbook.add_worksheet();
+        worksheet
+            .set_name(sheet_name(&sheet.name, &mut taken))
+            .map_err(failed)?;
+        for (col, column) in sheet.header.iter().enumerate() {
+            worksheet
+                .write_string_with_format(0, col as ColNum, column, &bold)
+                .map_err(failed)?;
+        }
+        for (row, values) in sheet.rows.iter().enumerate() {
+            for (col, value) in values.iter().enumerate() {
+                write_cell(
+                    worksheet,
+                    row as RowNum + 1,
+                    col as ColNum,
+                    value,
+                    &datetime,
+                    opts,
+                )
+                .map_err(failed)?;
+            }
+        }
+        worksheet.set_freeze_panes(1, 0).map_err(failed)?;
+        worksheet.autofit();
+    }
+    workbook.save(path).map_err(failed)
+}